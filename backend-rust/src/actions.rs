@@ -0,0 +1,98 @@
+//! Triage action helpers
+//!
+//! Gives each model-suggested action a stable identity and a deterministic order,
+//! so the frontend can track actions across re-classifications, and strips actions a
+//! deployment has denied (`ACTION_DENYLIST`).
+
+use std::collections::HashSet;
+
+/// Known action prefixes, most important first. Actions are matched by prefix so that
+/// parameterized forms (e.g. `set-severity-S2`) sort with their family. Unknown actions
+/// keep the model's order after all known ones.
+const ACTION_PRIORITY: &[&str] = &[
+    "need-info",
+    "needinfo",
+    "set-has-str",
+    "set-severity",
+    "set-priority",
+    "assign-component",
+    "add-keyword",
+    "close-duplicate",
+    "close",
+];
+
+/// Stable id for an action, derived from its `action` and `reason` text.
+///
+/// Uses 64-bit FNV-1a rather than `DefaultHasher`, whose output may change between
+/// Rust releases.
+pub fn action_id(action: &str, reason: &str) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in action.bytes().chain([0]).chain(reason.bytes()) {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    format!("{:016x}", hash)
+}
+
+fn priority_rank(action: &str) -> usize {
+    ACTION_PRIORITY
+        .iter()
+        .position(|prefix| action.starts_with(prefix))
+        .unwrap_or(ACTION_PRIORITY.len())
+}
+
+/// Whether `action` is denied by an entry of `denylist`: the same action, or a
+/// parameterized form of it (`close` denies `close-as-wontfix` and `Close as dup`).
+/// Case-insensitive.
+pub fn is_denied(action: &str, denylist: &[String]) -> bool {
+    let action = action.trim().to_ascii_lowercase();
+    denylist.iter().any(|denied| {
+        action
+            .strip_prefix(denied.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with(['-', ' ']))
+    })
+}
+
+/// Remove items whose action is denied; returns how many were removed.
+/// `action` returns the action text of an item.
+pub fn remove_denied<T>(
+    items: &mut Vec<T>,
+    denylist: &[String],
+    action: impl Fn(&T) -> &str,
+) -> usize {
+    let before = items.len();
+    items.retain(|item| !is_denied(action(item), denylist));
+    before - items.len()
+}
+
+/// Drop repeated actions (same id) and sort the rest by the known priority order.
+/// `key` returns the `(id, action)` pair of an item.
+pub fn order_actions<T>(actions: Vec<T>, key: impl Fn(&T) -> (&str, &str)) -> Vec<T> {
+    let mut seen = HashSet::new();
+    let mut actions: Vec<T> = actions
+        .into_iter()
+        .filter(|item| seen.insert(key(item).0.to_string()))
+        .collect();
+    // Stable sort, so equally ranked actions keep the model's order
+    actions.sort_by_key(|item| priority_rank(key(item).1));
+    actions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn denylist_matches_actions_and_their_parameterized_forms() {
+        let denylist = vec!["close".to_string(), "set-priority-p1".to_string()];
+        assert!(is_denied("close", &denylist));
+        assert!(is_denied("Close-as-wontfix", &denylist));
+        assert!(is_denied("set-priority-P1", &denylist));
+        assert!(!is_denied("closed-loop-check", &denylist));
+        assert!(!is_denied("set-priority-P2", &denylist));
+
+        let mut actions = vec!["needinfo-reporter", "close-duplicate", "close"];
+        assert_eq!(remove_denied(&mut actions, &denylist, |a| a), 2);
+        assert_eq!(actions, ["needinfo-reporter"]);
+    }
+}
@@ -0,0 +1,312 @@
+//! Registry of in-flight API requests
+//!
+//! `track_requests` registers every `/api/*` request here for as long as it runs, and
+//! AI handlers [`annotate`] their entry with the provider, model and bug they work on.
+//! `/admin/requests` lists the entries and can cancel one; cancelling drops the request
+//! future, which kills any CLI process it spawned.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Instant;
+
+use serde::Serialize;
+use tokio::sync::{Notify, OwnedSemaphorePermit};
+
+use crate::claude_cli::RunMeta;
+
+tokio::task_local! {
+    /// Entry of the request being handled on this task
+    static CURRENT: Arc<ActiveRequest>;
+}
+
+#[derive(Debug, Default)]
+struct Details {
+    provider: Option<String>,
+    model: Option<String>,
+    bug_id: Option<u64>,
+    /// `"cli"` or `"api"`: how the result was produced
+    served_via: Option<&'static str>,
+    /// Reported cost and duration of the request's CLI runs so far
+    cli_meta: RunMeta,
+    /// Whether to keep the prompt sent to the provider, for `includePromptOnError`
+    capture_prompt: bool,
+    /// Last prompt sent to the provider, when captured
+    prompt_sent: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct ActiveRequest {
+    id: String,
+    endpoint: String,
+    started: Instant,
+    details: Mutex<Details>,
+    cancel: Notify,
+    registry: Weak<ActiveRequests>,
+    /// Set once a [`Detached`] has taken over the request from its handler
+    detached: AtomicBool,
+    /// Permits the request holds until it is done, see [`hold`]
+    permits: Mutex<Vec<OwnedSemaphorePermit>>,
+}
+
+impl ActiveRequest {
+    /// Stop listing the request and release its permits
+    fn finish(&self) {
+        if let Some(registry) = self.registry.upgrade() {
+            registry.requests.lock().unwrap().remove(&self.id);
+        }
+        self.permits.lock().unwrap().clear();
+    }
+}
+
+/// One row of `/admin/requests`
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ActiveRequestInfo {
+    pub request_id: String,
+    pub endpoint: String,
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    pub bug_id: Option<u64>,
+    pub elapsed_ms: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct ActiveRequests {
+    requests: Mutex<HashMap<String, Arc<ActiveRequest>>>,
+}
+
+impl ActiveRequests {
+    /// Run `fut` as request `id` on `endpoint`. Resolves to `None` if the request is
+    /// cancelled through [`ActiveRequests::cancel`] before it completes. The request is
+    /// done when `fut` is, unless `fut` hands it to a [`Detached`].
+    pub async fn run<T>(
+        self: &Arc<Self>,
+        id: &str,
+        endpoint: &str,
+        fut: impl Future<Output = T>,
+    ) -> Option<T> {
+        let entry = Arc::new(ActiveRequest {
+            id: id.to_string(),
+            endpoint: endpoint.to_string(),
+            started: Instant::now(),
+            details: Mutex::default(),
+            cancel: Notify::new(),
+            registry: Arc::downgrade(self),
+            detached: AtomicBool::new(false),
+            permits: Mutex::default(),
+        });
+        self.requests
+            .lock()
+            .unwrap()
+            .insert(id.to_string(), Arc::clone(&entry));
+        let _guard = Deregister(Arc::clone(&entry));
+
+        tokio::select! {
+            result = CURRENT.scope(Arc::clone(&entry), fut) => Some(result),
+            _ = entry.cancel.notified() => None,
+        }
+    }
+
+    /// Cancel request `id`; returns whether it was in flight
+    pub fn cancel(&self, id: &str) -> bool {
+        match self.requests.lock().unwrap().get(id) {
+            Some(entry) => {
+                entry.cancel.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// In-flight requests, longest-running first
+    pub fn snapshot(&self) -> Vec<ActiveRequestInfo> {
+        // Only hold the registry lock long enough to clone the entries out
+        let entries: Vec<Arc<ActiveRequest>> =
+            self.requests.lock().unwrap().values().cloned().collect();
+        let mut rows: Vec<ActiveRequestInfo> = entries
+            .iter()
+            .map(|entry| {
+                let details = entry.details.lock().unwrap();
+                ActiveRequestInfo {
+                    request_id: entry.id.clone(),
+                    endpoint: entry.endpoint.clone(),
+                    provider: details.provider.clone(),
+                    model: details.model.clone(),
+                    bug_id: details.bug_id,
+                    elapsed_ms: entry.started.elapsed().as_millis() as u64,
+                }
+            })
+            .collect();
+        rows.sort_by_key(|row| std::cmp::Reverse(row.elapsed_ms));
+        rows
+    }
+}
+
+struct Deregister(Arc<ActiveRequest>);
+
+impl Drop for Deregister {
+    fn drop(&mut self) {
+        if !self.0.detached.load(Ordering::Acquire) {
+            self.0.finish();
+        }
+    }
+}
+
+/// The current request, taken over by work that outlives its handler (an event
+/// stream's task). Until this is dropped the request stays listed, can be cancelled and
+/// keeps its permits.
+#[derive(Debug)]
+pub struct Detached(Arc<ActiveRequest>);
+
+impl Detached {
+    /// Run `fut` as the request; `None` if it is cancelled before it completes
+    pub async fn run<T>(&self, fut: impl Future<Output = T>) -> Option<T> {
+        tokio::select! {
+            result = CURRENT.scope(Arc::clone(&self.0), fut) => Some(result),
+            _ = self.0.cancel.notified() => None,
+        }
+    }
+}
+
+impl Drop for Detached {
+    fn drop(&mut self) {
+        self.0.finish();
+    }
+}
+
+/// Hand the current request over to a [`Detached`]; `None` outside a tracked request
+pub fn detach() -> Option<Detached> {
+    CURRENT
+        .try_with(|entry| {
+            entry.detached.store(true, Ordering::Release);
+            Detached(Arc::clone(entry))
+        })
+        .ok()
+}
+
+/// Keep `permit` until the current request is done, [`Detached`] part included.
+/// Outside a tracked request it is handed back.
+pub fn hold(permit: OwnedSemaphorePermit) -> Result<(), OwnedSemaphorePermit> {
+    match CURRENT.try_with(Arc::clone) {
+        Ok(entry) => {
+            entry.permits.lock().unwrap().push(permit);
+            Ok(())
+        }
+        Err(_) => Err(permit),
+    }
+}
+
+/// Record what the current request is working on. A no-op outside a tracked request.
+pub fn annotate(provider: &str, model: &str, bug_id: Option<u64>) {
+    let _ = CURRENT.try_with(|entry| {
+        let mut details = entry.details.lock().unwrap();
+        details.provider = Some(provider.to_string());
+        details.model = Some(model.to_string()).filter(|m| !m.is_empty());
+        details.bug_id = bug_id;
+    });
+}
+
+/// Record whether the current request's result came from the CLI or an HTTP API
+pub fn set_served_via(via: &'static str) {
+    let _ = CURRENT.try_with(|entry| entry.details.lock().unwrap().served_via = Some(via));
+}
+
+/// What [`set_served_via`] recorded for the current request
+pub fn served_via() -> Option<&'static str> {
+    CURRENT
+        .try_with(|entry| entry.details.lock().unwrap().served_via)
+        .ok()
+        .flatten()
+}
+
+/// Add a CLI run's reported cost and duration to the current request's totals
+pub fn add_cli_meta(meta: RunMeta) {
+    let _ = CURRENT.try_with(|entry| {
+        let mut details = entry.details.lock().unwrap();
+        details.cli_meta = details.cli_meta.add(meta);
+    });
+}
+
+/// Totals [`add_cli_meta`] collected for the current request, unless there are none
+pub fn cli_meta() -> Option<RunMeta> {
+    CURRENT
+        .try_with(|entry| entry.details.lock().unwrap().cli_meta)
+        .ok()
+        .filter(|meta| !meta.is_empty())
+}
+
+/// Keep the prompts the current request sends from now on, for [`prompt_sent`]
+pub fn capture_prompt() {
+    let _ = CURRENT.try_with(|entry| entry.details.lock().unwrap().capture_prompt = true);
+}
+
+/// Record the prompt about to be sent to a provider, if [`capture_prompt`] asked for it
+pub fn note_prompt_sent(prompt: &str) {
+    let _ = CURRENT.try_with(|entry| {
+        let mut details = entry.details.lock().unwrap();
+        if details.capture_prompt {
+            details.prompt_sent = Some(prompt.to_string());
+        }
+    });
+}
+
+/// The last prompt [`note_prompt_sent`] kept for the current request
+pub fn prompt_sent() -> Option<String> {
+    CURRENT
+        .try_with(|entry| entry.details.lock().unwrap().prompt_sent.clone())
+        .ok()
+        .flatten()
+}
+
+/// Provider, model and bug id annotated on the current request
+pub fn current_details() -> Option<(Option<String>, Option<String>, Option<u64>)> {
+    CURRENT
+        .try_with(|entry| {
+            let details = entry.details.lock().unwrap();
+            (details.provider.clone(), details.model.clone(), details.bug_id)
+        })
+        .ok()
+}
+
+/// The `id` of a bug object as sent by the frontend
+pub fn bug_id(bug: &serde_json::Value) -> Option<u64> {
+    bug.get("id").and_then(|id| id.as_u64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn lists_and_cancels_requests() {
+        let registry = Arc::new(ActiveRequests::default());
+        let running = {
+            let registry = Arc::clone(&registry);
+            tokio::spawn(async move {
+                registry
+                    .run("r1", "/api/ai/classify", async {
+                        annotate("claude", "sonnet", Some(12345));
+                        tokio::time::sleep(Duration::from_secs(30)).await;
+                    })
+                    .await
+            })
+        };
+        while registry.snapshot().first().and_then(|r| r.bug_id).is_none() {
+            tokio::task::yield_now().await;
+        }
+
+        let rows = registry.snapshot();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].request_id, "r1");
+        assert_eq!(rows[0].provider.as_deref(), Some("claude"));
+        assert_eq!(rows[0].bug_id, Some(12345));
+
+        assert!(registry.cancel("r1"));
+        assert_eq!(running.await.unwrap(), None);
+        assert!(registry.snapshot().is_empty());
+        assert!(!registry.cancel("r1"));
+    }
+}
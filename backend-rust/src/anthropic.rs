@@ -0,0 +1,326 @@
+//! Anthropic Messages API client (Claude HTTP API mode)
+//!
+//! Structured results are requested with the structured-outputs `output_format`. Models
+//! that don't support it reject the request with a 400; those are retried once with a
+//! single forced tool call whose `input_schema` is the requested schema, and the tool
+//! input becomes the result. The fallback can be turned off with
+//! `ANTHROPIC_TOOL_FALLBACK=false`.
+//!
+//! A cost budget is checked against an estimate before the first call and against the
+//! `usage` reported by each call, summed over the fallback.
+
+use std::time::Duration;
+
+use axum::http::StatusCode;
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::{active, budget, http_client, ErrorResponse};
+
+const API_VERSION: &str = "2023-06-01";
+const STRUCTURED_OUTPUTS_BETA: &str = "structured-outputs-2025-11-13";
+const MAX_TOKENS: u32 = 8192;
+/// Name of the forced tool in the tool-use fallback
+const RESULT_TOOL: &str = "emit_result";
+
+/// Error code for a model that can't produce structured output by either mechanism
+const STRUCTURED_OUTPUT_UNSUPPORTED: &str = "STRUCTURED_OUTPUT_UNSUPPORTED";
+
+/// Anthropic API settings
+#[derive(Debug, Clone)]
+pub struct AnthropicConfig {
+    pub base_url: String,
+    /// Retry with a forced tool call when structured outputs are unsupported
+    pub tool_fallback: bool,
+}
+
+impl Default for AnthropicConfig {
+    fn default() -> Self {
+        AnthropicConfig {
+            base_url: "https://api.anthropic.com".to_string(),
+            tool_fallback: true,
+        }
+    }
+}
+
+/// Ask `model` for a result matching `schema` (a JSON schema string). `timeout`
+/// replaces the shared client's timeout for each API call; `max_cost_usd` is the
+/// request's cost budget.
+#[allow(clippy::too_many_arguments)]
+pub async fn structured_request(
+    config: &AnthropicConfig,
+    client: &reqwest::Client,
+    api_key: &str,
+    model: &str,
+    prompt: &str,
+    schema: &str,
+    timeout: Option<Duration>,
+    max_cost_usd: Option<f32>,
+) -> Result<serde_json::Value, ErrorResponse> {
+    budget::check_estimate(max_cost_usd, model, prompt, schema)?;
+    let mut spent = 0.0;
+    let schema: serde_json::Value = serde_json::from_str(schema).map_err(|e| ErrorResponse {
+        error: "Invalid schema".to_string(),
+        details: Some(e.to_string()),
+        status: StatusCode::BAD_REQUEST,
+        ..Default::default()
+    })?;
+    let messages = json!([{ "role": "user", "content": prompt }]);
+    active::note_prompt_sent(prompt);
+
+    let body = json!({
+        "model": model,
+        "max_tokens": MAX_TOKENS,
+        "messages": messages,
+        "output_format": { "type": "json_schema", "schema": schema },
+    });
+    match send(config, client, api_key, &body, Some(STRUCTURED_OUTPUTS_BETA), timeout).await {
+        Ok(response) => {
+            info!("Anthropic result via structured outputs");
+            charge(&response, model, max_cost_usd, &mut spent)?;
+            return text_result(&response);
+        }
+        Err(e) if config.tool_fallback && is_structured_output_unsupported(&e) => {
+            warn!(
+                "Model {} doesn't support structured outputs, retrying with tool use",
+                model
+            );
+        }
+        Err(e) => return Err(e),
+    }
+
+    let body = json!({
+        "model": model,
+        "max_tokens": MAX_TOKENS,
+        "messages": messages,
+        "tools": [{
+            "name": RESULT_TOOL,
+            "description": "Report the result in the required structure",
+            "input_schema": schema,
+        }],
+        "tool_choice": { "type": "tool", "name": RESULT_TOOL },
+    });
+    let response = send(config, client, api_key, &body, None, timeout).await?;
+    info!("Anthropic result via forced tool use");
+    charge(&response, model, max_cost_usd, &mut spent)?;
+    tool_result(&response)
+}
+
+/// Add the cost of `response`'s reported `usage` to `spent` and check it against the budget
+fn charge(
+    response: &serde_json::Value,
+    model: &str,
+    max_cost_usd: Option<f32>,
+    spent: &mut f64,
+) -> Result<(), ErrorResponse> {
+    let tokens = |field: &str| response["usage"][field].as_u64().unwrap_or(0);
+    if let Some(cost) = budget::usage_usd(model, tokens("input_tokens"), tokens("output_tokens")) {
+        *spent += cost;
+    }
+    budget::check_spent(max_cost_usd, "Anthropic API", *spent)
+}
+
+async fn send(
+    config: &AnthropicConfig,
+    client: &reqwest::Client,
+    api_key: &str,
+    body: &serde_json::Value,
+    beta: Option<&str>,
+    timeout: Option<Duration>,
+) -> Result<serde_json::Value, ErrorResponse> {
+    let mut request = client
+        .post(format!("{}/v1/messages", config.base_url.trim_end_matches('/')))
+        .header("x-api-key", api_key)
+        .header("anthropic-version", API_VERSION)
+        .json(body);
+    if let Some(beta) = beta {
+        request = request.header("anthropic-beta", beta);
+    }
+    if let Some(timeout) = timeout {
+        request = request.timeout(timeout);
+    }
+    let response = request
+        .send()
+        .await
+        .map_err(|e| http_client::request_failed("Anthropic", e))?;
+
+    let status = response.status();
+    let body: serde_json::Value = http_client::read_json("Anthropic", response).await?;
+    if !status.is_success() {
+        let message = body
+            .pointer("/error/message")
+            .and_then(|m| m.as_str())
+            .unwrap_or("")
+            .to_string();
+        warn!("Anthropic API returned {}: {}", status, message);
+        return Err(ErrorResponse {
+            error: format!("Anthropic API returned {}", status),
+            details: Some(message).filter(|m| !m.is_empty()),
+            status: if status.is_client_error() {
+                status
+            } else {
+                StatusCode::BAD_GATEWAY
+            },
+            ..Default::default()
+        });
+    }
+    Ok(body)
+}
+
+/// A 400 complaining about `output_format` / structured outputs
+fn is_structured_output_unsupported(error: &ErrorResponse) -> bool {
+    if error.status != StatusCode::BAD_REQUEST {
+        return false;
+    }
+    let details = error.details.as_deref().unwrap_or("").to_ascii_lowercase();
+    details.contains("output_format") || details.contains("structured output")
+}
+
+fn missing_result(mechanism: &str) -> ErrorResponse {
+    ErrorResponse {
+        error: "Failed to parse Anthropic API output".to_string(),
+        details: Some(format!("No {} result in the response", mechanism)),
+        code: Some(STRUCTURED_OUTPUT_UNSUPPORTED),
+        status: StatusCode::BAD_GATEWAY,
+    }
+}
+
+/// The JSON text block of a structured-outputs response
+fn text_result(response: &serde_json::Value) -> Result<serde_json::Value, ErrorResponse> {
+    response
+        .get("content")
+        .and_then(|c| c.as_array())
+        .into_iter()
+        .flatten()
+        .filter(|block| block.get("type").and_then(|t| t.as_str()) == Some("text"))
+        .find_map(|block| serde_json::from_str(block.get("text")?.as_str()?).ok())
+        .ok_or_else(|| missing_result("structured output"))
+}
+
+/// The input of the forced tool call
+fn tool_result(response: &serde_json::Value) -> Result<serde_json::Value, ErrorResponse> {
+    response
+        .get("content")
+        .and_then(|c| c.as_array())
+        .into_iter()
+        .flatten()
+        .find(|block| {
+            block.get("type").and_then(|t| t.as_str()) == Some("tool_use")
+                && block.get("name").and_then(|n| n.as_str()) == Some(RESULT_TOOL)
+        })
+        .and_then(|block| block.get("input").cloned())
+        .ok_or_else(|| missing_result("tool use"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::post, Json, Router};
+
+    /// A fake Messages API that rejects `output_format` and answers tool calls
+    async fn mock_without_structured_outputs() -> String {
+        let app = Router::new().route(
+            "/v1/messages",
+            post(|Json(body): Json<serde_json::Value>| async move {
+                if body.get("output_format").is_some() {
+                    return (
+                        StatusCode::BAD_REQUEST,
+                        Json(json!({ "type": "error", "error": {
+                            "type": "invalid_request_error",
+                            "message": "output_format: not supported for this model"
+                        }})),
+                    );
+                }
+                (
+                    StatusCode::OK,
+                    Json(json!({
+                        "content": [{
+                            "type": "tool_use",
+                            "name": RESULT_TOOL,
+                            "input": { "summary": "via tool" }
+                        }],
+                        "usage": { "input_tokens": 100_000, "output_tokens": 10_000 }
+                    })),
+                )
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_tool_use() {
+        let config = AnthropicConfig {
+            base_url: mock_without_structured_outputs().await,
+            tool_fallback: true,
+        };
+        let result = structured_request(
+            &config,
+            &reqwest::Client::new(),
+            "key",
+            "old-model",
+            "prompt",
+            r#"{"type":"object"}"#,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(result, json!({ "summary": "via tool" }));
+    }
+
+    #[tokio::test]
+    async fn fallback_can_be_disabled() {
+        let config = AnthropicConfig {
+            base_url: mock_without_structured_outputs().await,
+            tool_fallback: false,
+        };
+        let err = structured_request(
+            &config,
+            &reqwest::Client::new(),
+            "key",
+            "old-model",
+            "prompt",
+            r#"{"type":"object"}"#,
+            None,
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn reported_usage_over_budget_is_rejected() {
+        let config = AnthropicConfig {
+            base_url: mock_without_structured_outputs().await,
+            tool_fallback: true,
+        };
+        let client = reqwest::Client::new();
+        let request = |budget| {
+            structured_request(
+                &config,
+                &client,
+                "key",
+                "claude-sonnet-4-5",
+                "prompt",
+                r#"{"type":"object"}"#,
+                None,
+                Some(budget),
+            )
+        };
+        // 100k input and 10k output tokens at Sonnet prices: $0.45
+        assert!(request(1.0).await.is_ok());
+        let err = request(0.4).await.unwrap_err();
+        assert_eq!(err.code, Some(budget::BUDGET_EXCEEDED));
+        assert_eq!(err.status, StatusCode::PAYMENT_REQUIRED);
+    }
+
+    #[test]
+    fn extracts_structured_text() {
+        let response = json!({ "content": [{ "type": "text", "text": "{\"summary\":\"ok\"}" }] });
+        assert_eq!(text_result(&response).unwrap(), json!({ "summary": "ok" }));
+    }
+}
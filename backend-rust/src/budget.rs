@@ -0,0 +1,160 @@
+//! Per-request cost budgets
+//!
+//! A request's `maxCostUsd`, capped by `MAX_COST_USD`, is enforced twice: before the
+//! call, against a cost estimated from the prompt's token estimate and list prices, and
+//! after it, against the cost the provider reports (the CLI's `total_cost_usd`, the
+//! Anthropic API's `usage`). The estimate can only stop calls whose prompt alone is too
+//! expensive; an overrun only visible afterwards is logged and the result rejected.
+//! Models without a known price skip the estimate.
+
+use axum::http::StatusCode;
+use tracing::{debug, warn};
+
+use crate::{metrics, tokens, ErrorResponse};
+
+/// Error code for a request whose (estimated or reported) cost is over its budget
+pub const BUDGET_EXCEEDED: &str = "BUDGET_EXCEEDED";
+
+/// Output tokens assumed when estimating a call's cost before making it
+const ASSUMED_OUTPUT_TOKENS: usize = 1024;
+
+/// List price in USD per million tokens
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Price {
+    input: f64,
+    output: f64,
+}
+
+/// Price by model family; CLI aliases (`opus`, `sonnet`, `haiku`) match too
+fn price_for(model: &str) -> Option<Price> {
+    let model = model.to_ascii_lowercase();
+    let (input, output) = if model.contains("opus") {
+        (15.0, 75.0)
+    } else if model.contains("sonnet") {
+        (3.0, 15.0)
+    } else if model.contains("haiku") {
+        (1.0, 5.0)
+    } else {
+        return None;
+    };
+    Some(Price { input, output })
+}
+
+/// The budget for one request: the smaller of the request's and the server's
+pub fn effective(requested: Option<f32>, cap: Option<f32>) -> Option<f32> {
+    match (requested, cap) {
+        (Some(requested), Some(cap)) => Some(requested.min(cap)),
+        (requested, cap) => requested.or(cap),
+    }
+}
+
+/// Estimated cost of sending `prompt` and `schema` to `model`, if its price is known
+pub fn estimate_usd(model: &str, prompt: &str, schema: &str) -> Option<f64> {
+    let price = price_for(model)?;
+    let estimator = tokens::estimator_for(model);
+    let input = estimator.estimate(prompt) + estimator.estimate(schema);
+    Some((input as f64 * price.input + ASSUMED_OUTPUT_TOKENS as f64 * price.output) / 1e6)
+}
+
+/// Reported cost of a call to `model` from its token usage, if its price is known
+pub fn usage_usd(model: &str, input_tokens: u64, output_tokens: u64) -> Option<f64> {
+    let price = price_for(model)?;
+    Some((input_tokens as f64 * price.input + output_tokens as f64 * price.output) / 1e6)
+}
+
+/// Refuse a call to `model` whose estimated cost is over `budget`
+pub fn check_estimate(
+    budget: Option<f32>,
+    model: &str,
+    prompt: &str,
+    schema: &str,
+) -> Result<(), ErrorResponse> {
+    let Some(budget) = budget else {
+        return Ok(());
+    };
+    let Some(estimate) = estimate_usd(model, prompt, schema) else {
+        debug!("No price for model {}, cost budget only checked afterwards", model);
+        return Ok(());
+    };
+    if estimate > budget as f64 {
+        warn!(
+            "Estimated cost ${:.4} for model {} exceeds budget ${:.4}, not calling it",
+            estimate, model, budget
+        );
+        metrics::inc_with("ai_budget_exceeded_total", &[("stage", "estimate")]);
+        return Err(exceeded(format!(
+            "Estimated cost ${:.4} exceeds the budget of ${:.4}",
+            estimate, budget
+        )));
+    }
+    Ok(())
+}
+
+/// Reject a result whose reported cost (USD) turned out to be over `budget`. The money
+/// is already spent, so the overrun is logged for follow-up.
+pub fn check_spent(budget: Option<f32>, provider: &str, spent: f64) -> Result<(), ErrorResponse> {
+    let Some(budget) = budget else {
+        return Ok(());
+    };
+    if spent > budget as f64 {
+        warn!(
+            "{} call cost ${:.4}, over the budget of ${:.4}; result rejected",
+            provider, spent, budget
+        );
+        metrics::inc_with("ai_budget_exceeded_total", &[("stage", "reported")]);
+        return Err(exceeded(format!(
+            "{} reported a cost of ${:.4}, over the budget of ${:.4}",
+            provider, spent, budget
+        )));
+    }
+    Ok(())
+}
+
+fn exceeded(details: String) -> ErrorResponse {
+    ErrorResponse {
+        error: "Cost budget exceeded".to_string(),
+        details: Some(details),
+        code: Some(BUDGET_EXCEEDED),
+        status: StatusCode::PAYMENT_REQUIRED,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_budget_is_capped_by_the_server() {
+        assert_eq!(effective(None, None), None);
+        assert_eq!(effective(Some(0.5), None), Some(0.5));
+        assert_eq!(effective(None, Some(1.0)), Some(1.0));
+        assert_eq!(effective(Some(5.0), Some(1.0)), Some(1.0));
+        assert_eq!(effective(Some(0.1), Some(1.0)), Some(0.1));
+    }
+
+    #[test]
+    fn estimate_is_checked_before_the_call() {
+        let prompt = "x".repeat(350_000); // ~100k tokens
+        // Sonnet: 100k * $3/M + 1024 * $15/M ~ $0.315
+        let estimate = estimate_usd("claude-sonnet-4-5", &prompt, "").unwrap();
+        assert!((estimate - 0.31536).abs() < 1e-4, "{}", estimate);
+
+        assert!(check_estimate(Some(1.0), "claude-sonnet-4-5", &prompt, "").is_ok());
+        let err = check_estimate(Some(0.1), "claude-sonnet-4-5", &prompt, "").unwrap_err();
+        assert_eq!(err.code, Some(BUDGET_EXCEEDED));
+        assert_eq!(err.status, StatusCode::PAYMENT_REQUIRED);
+
+        // Unknown price: left to the post-hoc check
+        assert!(check_estimate(Some(0.0), "some-local-model", &prompt, "").is_ok());
+        assert!(check_estimate(None, "opus", &prompt, "").is_ok());
+    }
+
+    #[test]
+    fn reported_cost_over_budget_is_rejected() {
+        assert!(check_spent(Some(0.5), "Claude CLI", 0.4).is_ok());
+        assert!(check_spent(None, "Claude CLI", 100.0).is_ok());
+        let err = check_spent(Some(0.5), "Claude CLI", 0.6).unwrap_err();
+        assert_eq!(err.code, Some(BUDGET_EXCEEDED));
+        assert_eq!(usage_usd("haiku", 1_000_000, 100_000), Some(1.5));
+    }
+}
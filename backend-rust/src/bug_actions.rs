@@ -0,0 +1,226 @@
+//! Triage action vocabulary
+//!
+//! Maps action names (as the model suggests them, e.g. `set-severity-S2` or
+//! `needinfo-reporter`) to the Bugzilla REST field changes that carry them out.
+//! `/api/bugzilla/bug/{id}/apply-actions` only applies actions listed here, so what the
+//! backend may change on a bug is fixed by configuration rather than by model output.
+//!
+//! The vocabulary is a JSON object of action name to update body; `BUGZILLA_ACTIONS_FILE`
+//! replaces the built-in one. String values may contain `{{reporter}}`, replaced with
+//! the bug's creator when the action is applied.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use axum::http::StatusCode;
+use serde_json::{json, Map, Value};
+
+use crate::ErrorResponse;
+
+/// Placeholder for the bug's reporter in update values
+pub const REPORTER: &str = "{{reporter}}";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActionVocabulary {
+    actions: BTreeMap<String, Map<String, Value>>,
+}
+
+impl Default for ActionVocabulary {
+    /// Severity, priority, has-STR and reporter needinfo
+    fn default() -> Self {
+        let mut actions = BTreeMap::new();
+        let mut add = |name: String, update: Value| {
+            if let Value::Object(update) = update {
+                actions.insert(name, update);
+            }
+        };
+        for severity in ["S1", "S2", "S3", "S4"] {
+            add(format!("set-severity-{}", severity), json!({ "severity": severity }));
+        }
+        for priority in ["P1", "P2", "P3", "P4", "P5"] {
+            add(format!("set-priority-{}", priority), json!({ "priority": priority }));
+        }
+        add("set-has-str".to_string(), json!({ "cf_has_str": "yes" }));
+        add(
+            "needinfo-reporter".to_string(),
+            json!({ "flags": [{ "name": "needinfo", "status": "?", "requestee": REPORTER }] }),
+        );
+        ActionVocabulary { actions }
+    }
+}
+
+impl ActionVocabulary {
+    /// Read a vocabulary from a JSON file: an object mapping each action name to the
+    /// object sent to Bugzilla's `PUT /rest/bug/{id}`
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::parse(&text)
+    }
+
+    fn parse(text: &str) -> Result<Self, String> {
+        let value: Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+        let Value::Object(entries) = value else {
+            return Err("expected an object of action name to update".to_string());
+        };
+        let mut actions = BTreeMap::new();
+        for (name, update) in entries {
+            match update {
+                Value::Object(update) if !name.is_empty() && !update.is_empty() => {
+                    actions.insert(name, update);
+                }
+                _ => return Err(format!("action {:?} must map to a non-empty object", name)),
+            }
+        }
+        Ok(ActionVocabulary { actions })
+    }
+
+    pub fn len(&self) -> usize {
+        self.actions.len()
+    }
+
+    /// The combined update for `actions`. Fails with 400 `UNKNOWN_ACTION` if any action
+    /// isn't in the vocabulary, and 400 `CONFLICTING_ACTIONS` if two set the same field
+    /// to different values. Arrays (e.g. `flags`) are concatenated and objects merged.
+    pub fn update_for(&self, actions: &[String]) -> Result<Map<String, Value>, ErrorResponse> {
+        let unknown: Vec<&str> = actions
+            .iter()
+            .filter(|action| !self.actions.contains_key(*action))
+            .map(String::as_str)
+            .collect();
+        if !unknown.is_empty() {
+            return Err(ErrorResponse {
+                error: format!("Unknown action: {}", unknown.join(", ")),
+                details: Some(format!(
+                    "Known actions: {}",
+                    self.actions.keys().cloned().collect::<Vec<_>>().join(", ")
+                )),
+                code: Some("UNKNOWN_ACTION"),
+                status: StatusCode::BAD_REQUEST,
+            });
+        }
+
+        let mut update = Map::new();
+        for action in actions {
+            merge(&mut update, &self.actions[action]).map_err(|field| ErrorResponse {
+                error: format!("Conflicting actions for field {}", field),
+                details: Some(format!("Actions: {}", actions.join(", "))),
+                code: Some("CONFLICTING_ACTIONS"),
+                status: StatusCode::BAD_REQUEST,
+            })?;
+        }
+        Ok(update)
+    }
+}
+
+/// Merge `from` into `into`; on a conflicting scalar returns the field name
+fn merge(into: &mut Map<String, Value>, from: &Map<String, Value>) -> Result<(), String> {
+    for (field, value) in from {
+        match (into.get_mut(field), value) {
+            (None, _) => {
+                into.insert(field.clone(), value.clone());
+            }
+            (Some(Value::Array(existing)), Value::Array(more)) => {
+                for item in more {
+                    if !existing.contains(item) {
+                        existing.push(item.clone());
+                    }
+                }
+            }
+            (Some(Value::Object(existing)), Value::Object(more)) => merge(existing, more)?,
+            (Some(existing), _) if existing == value => {}
+            _ => return Err(field.clone()),
+        }
+    }
+    Ok(())
+}
+
+/// Whether `update` contains `placeholder` in any string value
+pub fn uses_placeholder(update: &Map<String, Value>, placeholder: &str) -> bool {
+    fn walk(value: &Value, placeholder: &str) -> bool {
+        match value {
+            Value::String(text) => text.contains(placeholder),
+            Value::Array(items) => items.iter().any(|item| walk(item, placeholder)),
+            Value::Object(fields) => fields.values().any(|field| walk(field, placeholder)),
+            _ => false,
+        }
+    }
+    update.values().any(|value| walk(value, placeholder))
+}
+
+/// Replace `placeholder` with `replacement` in every string value of `update`
+pub fn fill_placeholder(update: &mut Map<String, Value>, placeholder: &str, replacement: &str) {
+    fn walk(value: &mut Value, placeholder: &str, replacement: &str) {
+        match value {
+            Value::String(text) => *text = text.replace(placeholder, replacement),
+            Value::Array(items) => {
+                items.iter_mut().for_each(|item| walk(item, placeholder, replacement))
+            }
+            Value::Object(fields) => fields
+                .values_mut()
+                .for_each(|field| walk(field, placeholder, replacement)),
+            _ => {}
+        }
+    }
+    update
+        .values_mut()
+        .for_each(|value| walk(value, placeholder, replacement));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn actions(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn actions_combine_into_one_update() {
+        let update = ActionVocabulary::default()
+            .update_for(&actions(&["set-severity-S2", "set-priority-P3", "needinfo-reporter"]))
+            .unwrap();
+        assert_eq!(
+            Value::Object(update),
+            json!({
+                "severity": "S2",
+                "priority": "P3",
+                "flags": [{ "name": "needinfo", "status": "?", "requestee": REPORTER }]
+            })
+        );
+    }
+
+    #[test]
+    fn unknown_and_conflicting_actions_are_rejected() {
+        let vocabulary = ActionVocabulary::default();
+        let err = vocabulary.update_for(&actions(&["set-severity-S2", "close"])).unwrap_err();
+        assert_eq!(err.code, Some("UNKNOWN_ACTION"));
+        assert_eq!(err.error, "Unknown action: close");
+
+        let err = vocabulary
+            .update_for(&actions(&["set-severity-S2", "set-severity-S3"]))
+            .unwrap_err();
+        assert_eq!(err.code, Some("CONFLICTING_ACTIONS"));
+        assert_eq!(err.status, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn vocabulary_is_parsed_and_validated() {
+        let vocabulary =
+            ActionVocabulary::parse(r#"{ "add-crash": { "keywords": { "add": ["crash"] } } }"#)
+                .unwrap();
+        assert_eq!(vocabulary.len(), 1);
+        assert!(ActionVocabulary::parse(r#"{ "close": "RESOLVED" }"#).is_err());
+        assert!(ActionVocabulary::parse("[]").is_err());
+    }
+
+    #[test]
+    fn reporter_placeholder_is_filled() {
+        let mut update = ActionVocabulary::default()
+            .update_for(&actions(&["needinfo-reporter"]))
+            .unwrap();
+        assert!(uses_placeholder(&update, REPORTER));
+        fill_placeholder(&mut update, REPORTER, "reporter@example.com");
+        assert_eq!(update["flags"][0]["requestee"], "reporter@example.com");
+        assert!(!uses_placeholder(&update, REPORTER));
+    }
+}
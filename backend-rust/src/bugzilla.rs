@@ -0,0 +1,673 @@
+//! Bugzilla REST proxy
+//!
+//! Fetches bug data server-side for clients whose direct requests are blocked by CORS,
+//! and applies triage actions from the configured vocabulary (`src/bug_actions.rs`).
+
+use std::io;
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use futures_util::{future, TryStreamExt};
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::bug_actions::{self, ActionVocabulary};
+use crate::{http_client, transcript, AppState, ErrorResponse};
+
+/// Attachment content types the proxy will forward. Anything else (archives, binaries,
+/// ...) is refused rather than proxied.
+const ALLOWED_ATTACHMENT_TYPES: &[&str] = &[
+    "text/plain",
+    "text/html",
+    "text/css",
+    "text/javascript",
+    "text/xml",
+    "application/javascript",
+    "application/json",
+    "application/xml",
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+];
+
+/// Bug fields fetched by the proxy - the same set as `BUG_FIELDS` in
+/// frontend/src/bugzilla.js
+const BUG_FIELDS: &[&str] = &[
+    "id",
+    "summary",
+    "status",
+    "resolution",
+    "product",
+    "component",
+    "severity",
+    "priority",
+    "keywords",
+    "cf_has_str",
+    "cf_crash_signature",
+    "flags",
+    "creator",
+    "assigned_to",
+    "creation_time",
+    "last_change_time",
+];
+
+/// Query parameters `/api/bugzilla/search` passes through to Bugzilla's `/rest/bug`.
+/// Anything else is refused, so the proxy can't be used to set `include_fields`,
+/// credentials or other request-shaping parameters.
+const SEARCH_PARAMS: &[&str] = &[
+    "product",
+    "component",
+    "status",
+    "resolution",
+    "severity",
+    "priority",
+    "keywords",
+    "creator",
+    "assigned_to",
+    "creation_time",
+    "last_change_time",
+    "quicksearch",
+    "order",
+    "limit",
+    "offset",
+];
+
+/// Bugzilla connection settings
+#[derive(Debug, Clone)]
+pub struct BugzillaConfig {
+    /// Base URL of the Bugzilla instance, e.g. `https://bugzilla.mozilla.org`
+    pub base_url: String,
+    /// API key sent as `X-BUGZILLA-API-KEY`, if configured
+    pub api_key: Option<String>,
+    /// Largest attachment body the proxy will forward
+    pub max_attachment_bytes: u64,
+    /// Most bugs a search may return; larger (or absent) `limit`s are clamped to it
+    pub search_max_limit: u32,
+    /// Most comments one `/comments` page may hold; larger (or absent) `limit`s are
+    /// clamped to it
+    pub comments_max_page: usize,
+    /// Actions `apply-actions` may carry out
+    pub actions: Arc<ActionVocabulary>,
+}
+
+impl BugzillaConfig {
+    fn get(&self, client: &reqwest::Client, path: &str) -> reqwest::RequestBuilder {
+        self.get_with_key(client, path, None)
+    }
+
+    /// GET request authenticated with `api_key`, or the configured key when `None`
+    fn get_with_key(
+        &self,
+        client: &reqwest::Client,
+        path: &str,
+        api_key: Option<&str>,
+    ) -> reqwest::RequestBuilder {
+        self.request(client, reqwest::Method::GET, path, api_key)
+    }
+
+    fn request(
+        &self,
+        client: &reqwest::Client,
+        method: reqwest::Method,
+        path: &str,
+        api_key: Option<&str>,
+    ) -> reqwest::RequestBuilder {
+        let url = format!("{}{}", self.base_url.trim_end_matches('/'), path);
+        let request = client.request(method, url);
+        match api_key.or(self.api_key.as_deref()) {
+            Some(key) => request.header("X-BUGZILLA-API-KEY", key),
+            None => request,
+        }
+    }
+
+    /// Request that changes Bugzilla, made with the caller's `api_key` only. The server's
+    /// key never authenticates writes: the API is open to any origin, so it would let any
+    /// web page change bugs as the server account.
+    fn write(
+        &self,
+        client: &reqwest::Client,
+        method: reqwest::Method,
+        path: &str,
+        api_key: &str,
+    ) -> reqwest::RequestBuilder {
+        let url = format!("{}{}", self.base_url.trim_end_matches('/'), path);
+        client.request(method, url).header("X-BUGZILLA-API-KEY", api_key)
+    }
+}
+
+fn request_failed(e: reqwest::Error) -> ErrorResponse {
+    http_client::request_failed("Bugzilla", e)
+}
+
+/// Most of an error response's body that is read
+const ERROR_BODY_LIMIT: usize = 64 * 1024;
+
+/// Most of an error response's body logged and kept in `details`
+const ERROR_DETAILS_BYTES: usize = 2048;
+
+/// Map a non-success upstream response to an error with the same status. The body, read
+/// up to [`ERROR_BODY_LIMIT`], becomes the details, truncated.
+async fn upstream_error(response: reqwest::Response) -> ErrorResponse {
+    let status = response.status();
+    let body = match http_client::read_body("Bugzilla", response, ERROR_BODY_LIMIT).await {
+        Ok(body) => String::from_utf8_lossy(&body).into_owned(),
+        Err(e) => e.details.unwrap_or(e.error),
+    };
+    let body = transcript::truncate_field(&body, ERROR_DETAILS_BYTES);
+    warn!("Bugzilla returned {}: {}", status, body);
+    ErrorResponse {
+        error: format!("Bugzilla returned {}", status),
+        details: Some(body).filter(|b| !b.is_empty()),
+        status,
+        ..Default::default()
+    }
+}
+
+/// GET a Bugzilla REST path and return its JSON body
+async fn get_json(
+    state: &AppState,
+    path: &str,
+    query: &[(&str, &str)],
+    api_key: Option<&str>,
+) -> Result<serde_json::Value, ErrorResponse> {
+    let response = state
+        .bugzilla
+        .get_with_key(&state.http_client, path, api_key)
+        .query(query)
+        .send()
+        .await
+        .map_err(request_failed)?;
+    if !response.status().is_success() {
+        return Err(upstream_error(response).await);
+    }
+    http_client::read_json("Bugzilla", response).await
+}
+
+/// Fetch a bug the way the frontend assembles it: the bug fields plus its
+/// `attachments` (metadata only) and `comments` (the first marked `isDescription`).
+/// `api_key` overrides the configured key, e.g. for private bugs.
+pub async fn fetch_bug(
+    state: &AppState,
+    bug_id: u64,
+    api_key: Option<&str>,
+) -> Result<serde_json::Value, ErrorResponse> {
+    let include_fields = BUG_FIELDS.join(",");
+    let bug_path = format!("/rest/bug/{}", bug_id);
+    let attachments_path = format!("/rest/bug/{}/attachment", bug_id);
+    let comments_path = format!("/rest/bug/{}/comment", bug_id);
+    let bug_query = [("include_fields", include_fields.as_str())];
+    let (bugs, attachments, comments) = tokio::try_join!(
+        get_json(state, &bug_path, &bug_query, api_key),
+        get_json(state, &attachments_path, &[("exclude_fields", "data")], api_key),
+        get_json(state, &comments_path, &[], api_key),
+    )?;
+
+    let mut bug = bugs
+        .get("bugs")
+        .and_then(|b| b.get(0))
+        .cloned()
+        .ok_or_else(|| ErrorResponse {
+            error: format!("Bug {} not found", bug_id),
+            details: None,
+            status: StatusCode::NOT_FOUND,
+            ..Default::default()
+        })?;
+
+    let key = bug_id.to_string();
+    let attachments = attachments
+        .pointer(&format!("/bugs/{}", key))
+        .cloned()
+        .unwrap_or_else(|| serde_json::json!([]));
+    let comments = bug_comments(comments, bug_id);
+
+    if let Some(obj) = bug.as_object_mut() {
+        obj.insert("attachments".to_string(), attachments);
+        obj.insert("comments".to_string(), comments.into());
+    }
+    Ok(bug)
+}
+
+/// The comments of `/rest/bug/{id}/comment`'s response, the first (the bug's
+/// description) marked `isDescription`
+fn bug_comments(mut body: serde_json::Value, bug_id: u64) -> Vec<serde_json::Value> {
+    let Some(serde_json::Value::Array(mut comments)) = body
+        .pointer_mut(&format!("/bugs/{}/comments", bug_id))
+        .map(serde_json::Value::take)
+    else {
+        return Vec::new();
+    };
+    for (index, comment) in comments.iter_mut().enumerate() {
+        if let Some(obj) = comment.as_object_mut() {
+            obj.insert("isDescription".to_string(), (index == 0).into());
+        }
+    }
+    comments
+}
+
+/// A bug with its attachments and comments
+pub async fn get_bug(
+    State(state): State<Arc<AppState>>,
+    Path(bug_id): Path<u64>,
+) -> Result<Json<serde_json::Value>, ErrorResponse> {
+    info!("Bugzilla bug request for bug {}", bug_id);
+    fetch_bug(&state, bug_id, None).await.map(Json)
+}
+
+/// `/api/bugzilla/bug/{id}/comments` query
+#[derive(Debug, Deserialize)]
+pub struct CommentsQuery {
+    /// Index of the first comment to return (default: 0)
+    #[serde(default)]
+    pub offset: usize,
+    /// Comments to return, clamped to `comments_max_page` (default and 0: the maximum)
+    pub limit: Option<usize>,
+}
+
+/// At most `limit` (clamped to `max_page`) of `comments` starting at `offset`, with the
+/// offset of the next page, `None` after the last one
+fn page(
+    comments: Vec<serde_json::Value>,
+    query: &CommentsQuery,
+    max_page: usize,
+) -> (Vec<serde_json::Value>, Option<usize>) {
+    let limit = query.limit.filter(|limit| *limit > 0).map_or(max_page, |l| l.min(max_page));
+    let total = comments.len();
+    let page: Vec<serde_json::Value> =
+        comments.into_iter().skip(query.offset).take(limit).collect();
+    let end = query.offset.saturating_add(page.len());
+    (page, (end < total).then_some(end))
+}
+
+/// One page of `comments` as `/api/bugzilla/bug/{id}/comments` returns it
+fn comment_page(
+    bug_id: u64,
+    comments: Vec<serde_json::Value>,
+    query: &CommentsQuery,
+    max_page: usize,
+) -> serde_json::Value {
+    let total = comments.len();
+    let (comments, next_offset) = page(comments, query, max_page);
+    serde_json::json!({
+        "bugId": bug_id,
+        "offset": query.offset,
+        "total": total,
+        "nextOffset": next_offset,
+        "comments": comments,
+    })
+}
+
+/// Cut `bug.comments` down to the page `query` selects, returning the offset of the
+/// next page. A bug without a `comments` array is left as it is.
+pub fn page_bug_comments(
+    bug: &mut serde_json::Value,
+    query: &CommentsQuery,
+    max_page: usize,
+) -> Option<usize> {
+    let comments = bug.get_mut("comments")?.as_array_mut()?;
+    let (comments_page, next_offset) = page(std::mem::take(comments), query, max_page);
+    *comments = comments_page;
+    next_offset
+}
+
+/// A page of a bug's comments, for loading long threads a piece at a time. Bugzilla
+/// has no comment paging, so the whole thread is still fetched upstream; only the
+/// page is sent on.
+pub async fn get_comments(
+    State(state): State<Arc<AppState>>,
+    Path(bug_id): Path<u64>,
+    Query(query): Query<CommentsQuery>,
+) -> Result<Json<serde_json::Value>, ErrorResponse> {
+    info!(
+        "Bugzilla comments request for bug {} (offset {}, limit {:?})",
+        bug_id, query.offset, query.limit
+    );
+    let path = format!("/rest/bug/{}/comment", bug_id);
+    let body = get_json(&state, &path, &[], None).await?;
+    if body.pointer(&format!("/bugs/{}", bug_id)).is_none() {
+        return Err(ErrorResponse {
+            error: format!("Bug {} not found", bug_id),
+            details: None,
+            status: StatusCode::NOT_FOUND,
+            ..Default::default()
+        });
+    }
+    let comments = bug_comments(body, bug_id);
+    Ok(Json(comment_page(bug_id, comments, &query, state.bugzilla.comments_max_page)))
+}
+
+/// Validate search parameters against [`SEARCH_PARAMS`] and clamp `limit` to
+/// `max_limit`. Repeated parameters (`status=NEW&status=ASSIGNED`) are kept.
+fn search_query(
+    params: Vec<(String, String)>,
+    max_limit: u32,
+) -> Result<Vec<(String, String)>, ErrorResponse> {
+    let mut query = Vec::with_capacity(params.len() + 1);
+    let mut limit = max_limit;
+    for (name, value) in params {
+        if !SEARCH_PARAMS.contains(&name.as_str()) {
+            return Err(ErrorResponse {
+                error: format!("Unsupported search parameter: {}", name),
+                details: Some(format!("Supported: {}", SEARCH_PARAMS.join(", "))),
+                status: StatusCode::BAD_REQUEST,
+                ..Default::default()
+            });
+        }
+        if name == "limit" {
+            let requested: u32 = value.parse().map_err(|_| ErrorResponse {
+                error: format!("Invalid limit: {}", value),
+                details: None,
+                status: StatusCode::BAD_REQUEST,
+                ..Default::default()
+            })?;
+            // Bugzilla treats 0 as "no limit"
+            if requested != 0 {
+                limit = requested.min(max_limit);
+            }
+            continue;
+        }
+        query.push((name, value));
+    }
+    query.push(("limit".to_string(), limit.to_string()));
+    Ok(query)
+}
+
+/// Bug search (`/rest/bug`) with whitelisted query parameters passed through.
+/// Returns Bugzilla's `{ bugs: [...] }` with the same fields as [`get_bug`].
+pub async fn search_bugs(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<Vec<(String, String)>>,
+) -> Result<Json<serde_json::Value>, ErrorResponse> {
+    let query = search_query(params, state.bugzilla.search_max_limit)?;
+    info!("Bugzilla search request: {:?}", query);
+
+    let include_fields = BUG_FIELDS.join(",");
+    let response = state
+        .bugzilla
+        .get(&state.http_client, "/rest/bug")
+        .query(&query)
+        .query(&[("include_fields", include_fields.as_str())])
+        .send()
+        .await
+        .map_err(request_failed)?;
+    if !response.status().is_success() {
+        return Err(upstream_error(response).await);
+    }
+
+    let body = http_client::read_json("Bugzilla", response).await?;
+    Ok(Json(body))
+}
+
+/// Error code for a Bugzilla write without the caller's API key
+const API_KEY_REQUIRED: &str = "API_KEY_REQUIRED";
+
+/// `/api/bugzilla/bug/{id}/apply-actions` request
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApplyActionsRequest {
+    /// Action names from the configured vocabulary, e.g. `set-severity-S2`
+    pub actions: Vec<String>,
+    /// Bugzilla API key to make the change with; required, `BUGZILLA_API_KEY` is never
+    /// used for writes
+    pub api_key: Option<String>,
+}
+
+/// Apply triage actions to a bug: the actions' field changes from the vocabulary are
+/// combined and sent as one `PUT /rest/bug/{id}`. Unknown or conflicting actions are
+/// rejected before anything is sent. Returns the update sent and Bugzilla's response.
+/// The caller's `apiKey` is required; without it the answer is 401 `API_KEY_REQUIRED`.
+pub async fn apply_actions(
+    State(state): State<Arc<AppState>>,
+    Path(bug_id): Path<u64>,
+    Json(request): Json<ApplyActionsRequest>,
+) -> Result<Json<serde_json::Value>, ErrorResponse> {
+    let Some(api_key) = request.api_key.as_deref().filter(|key| !key.trim().is_empty()) else {
+        return Err(ErrorResponse {
+            error: "Bugzilla API key required".to_string(),
+            details: Some(
+                "Send your own apiKey; changes are never made with the server's key".to_string(),
+            ),
+            code: Some(API_KEY_REQUIRED),
+            status: StatusCode::UNAUTHORIZED,
+        });
+    };
+    if request.actions.is_empty() {
+        return Err(ErrorResponse {
+            error: "No actions to apply".to_string(),
+            details: None,
+            status: StatusCode::BAD_REQUEST,
+            ..Default::default()
+        });
+    }
+    let mut update = state.bugzilla.actions.update_for(&request.actions)?;
+
+    if bug_actions::uses_placeholder(&update, bug_actions::REPORTER) {
+        let path = format!("/rest/bug/{}", bug_id);
+        let query = [("include_fields", "creator")];
+        let bugs = get_json(&state, &path, &query, Some(api_key)).await?;
+        let reporter = bugs
+            .pointer("/bugs/0/creator")
+            .and_then(|c| c.as_str())
+            .ok_or_else(|| ErrorResponse {
+                error: format!("Bug {} not found", bug_id),
+                details: None,
+                status: StatusCode::NOT_FOUND,
+                ..Default::default()
+            })?;
+        bug_actions::fill_placeholder(&mut update, bug_actions::REPORTER, reporter);
+    }
+
+    info!(
+        "Applying actions {:?} to bug {}: {}",
+        request.actions,
+        bug_id,
+        serde_json::Value::Object(update.clone())
+    );
+    let response = state
+        .bugzilla
+        .write(
+            &state.http_client,
+            reqwest::Method::PUT,
+            &format!("/rest/bug/{}", bug_id),
+            api_key,
+        )
+        .json(&update)
+        .send()
+        .await
+        .map_err(request_failed)?;
+    if !response.status().is_success() {
+        return Err(upstream_error(response).await);
+    }
+    let result: serde_json::Value = http_client::read_json("Bugzilla", response).await?;
+
+    Ok(Json(serde_json::json!({
+        "bugId": bug_id,
+        "actions": request.actions,
+        "update": update,
+        "result": result,
+    })))
+}
+
+/// Attachment metadata for a bug, without the attachment data itself
+pub async fn get_attachments(
+    State(state): State<Arc<AppState>>,
+    Path(bug_id): Path<u64>,
+) -> Result<Json<serde_json::Value>, ErrorResponse> {
+    info!("Bugzilla attachments request for bug {}", bug_id);
+
+    let response = state
+        .bugzilla
+        .get(&state.http_client, &format!("/rest/bug/{}/attachment", bug_id))
+        .query(&[("exclude_fields", "data")])
+        .send()
+        .await
+        .map_err(request_failed)?;
+    if !response.status().is_success() {
+        return Err(upstream_error(response).await);
+    }
+
+    let body = http_client::read_json("Bugzilla", response).await?;
+    Ok(Json(body))
+}
+
+/// Raw content of a single attachment, streamed through with its content type.
+///
+/// Only allowlisted content types are forwarded, and the body is cut off once it
+/// exceeds `max_attachment_bytes`. The response is sandboxed so HTML testcases can't
+/// run script in the backend's origin.
+pub async fn get_attachment_content(
+    State(state): State<Arc<AppState>>,
+    Path(attachment_id): Path<u64>,
+) -> Result<Response, ErrorResponse> {
+    info!("Bugzilla attachment content request for attachment {}", attachment_id);
+
+    let response = state
+        .bugzilla
+        .get(&state.http_client, "/attachment.cgi")
+        .query(&[("id", attachment_id)])
+        .send()
+        .await
+        .map_err(request_failed)?;
+    if !response.status().is_success() {
+        return Err(upstream_error(response).await);
+    }
+
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .cloned()
+        .unwrap_or_else(|| HeaderValue::from_static("application/octet-stream"));
+    let essence = content_type
+        .to_str()
+        .unwrap_or("")
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+    if !ALLOWED_ATTACHMENT_TYPES.contains(&essence.as_str()) {
+        return Err(ErrorResponse {
+            error: "Attachment content type not allowed".to_string(),
+            details: Some(format!("Content type: {}", essence)),
+            status: StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ..Default::default()
+        });
+    }
+
+    let max_bytes = state.bugzilla.max_attachment_bytes;
+    if let Some(length) = response.content_length().filter(|len| *len > max_bytes) {
+        return Err(ErrorResponse {
+            error: "Attachment too large".to_string(),
+            details: Some(format!("{} bytes exceeds the {} byte limit", length, max_bytes)),
+            status: StatusCode::PAYLOAD_TOO_LARGE,
+            ..Default::default()
+        });
+    }
+
+    // Content-Length may be absent or wrong, so also count bytes as they stream
+    let mut streamed: u64 = 0;
+    let stream = response
+        .bytes_stream()
+        .map_err(io::Error::other)
+        .and_then(move |chunk| {
+            streamed += chunk.len() as u64;
+            future::ready(if streamed > max_bytes {
+                warn!("Attachment {} exceeded {} bytes, aborting", attachment_id, max_bytes);
+                Err(io::Error::other("attachment exceeds size limit"))
+            } else {
+                Ok(chunk)
+            })
+        });
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type),
+            (header::CONTENT_SECURITY_POLICY, HeaderValue::from_static("sandbox")),
+            (header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff")),
+        ],
+        Body::from_stream(stream),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn search_passes_whitelisted_params_through() {
+        let query = search_query(
+            params(&[("product", "Core"), ("status", "NEW"), ("status", "ASSIGNED")]),
+            100,
+        )
+        .unwrap();
+        assert_eq!(
+            query,
+            params(&[
+                ("product", "Core"),
+                ("status", "NEW"),
+                ("status", "ASSIGNED"),
+                ("limit", "100"),
+            ])
+        );
+    }
+
+    #[test]
+    fn search_clamps_limit() {
+        let limit = |value: &str| {
+            let query = search_query(params(&[("limit", value)]), 100).unwrap();
+            query.last().unwrap().1.clone()
+        };
+        assert_eq!(limit("20"), "20");
+        assert_eq!(limit("5000"), "100");
+        assert_eq!(limit("0"), "100");
+    }
+
+    #[test]
+    fn comments_are_paged() {
+        let comments: Vec<_> = (0..5).map(|i| serde_json::json!({ "count": i })).collect();
+        let body = serde_json::json!({ "bugs": { "7": { "comments": comments } } });
+        let comments = bug_comments(body, 7);
+        assert_eq!(comments[0]["isDescription"], true);
+        assert_eq!(comments[1]["isDescription"], false);
+
+        let page = |offset, limit| {
+            comment_page(7, comments.clone(), &CommentsQuery { offset, limit }, 2)
+        };
+        let first = page(0, None);
+        assert_eq!(first["comments"].as_array().unwrap().len(), 2);
+        assert_eq!(first["total"], 5);
+        assert_eq!(first["nextOffset"], 2);
+
+        let last = page(4, Some(100));
+        assert_eq!(last["comments"][0]["count"], 4);
+        assert_eq!(last["nextOffset"], serde_json::Value::Null);
+
+        let past_end = page(10, Some(1));
+        assert_eq!(past_end["comments"], serde_json::json!([]));
+        assert_eq!(past_end["nextOffset"], serde_json::Value::Null);
+
+        let mut bug = serde_json::json!({ "id": 7, "comments": comments });
+        let query = CommentsQuery { offset: 1, limit: None };
+        assert_eq!(page_bug_comments(&mut bug, &query, 2), Some(3));
+        assert_eq!(bug["comments"][0]["count"], 1);
+        assert_eq!(bug["comments"].as_array().unwrap().len(), 2);
+        assert_eq!(page_bug_comments(&mut serde_json::json!({ "id": 7 }), &query, 2), None);
+    }
+
+    #[test]
+    fn search_rejects_unknown_params() {
+        let err = search_query(params(&[("include_fields", "_all")]), 100).unwrap_err();
+        assert_eq!(err.status, StatusCode::BAD_REQUEST);
+        let err = search_query(params(&[("limit", "many")]), 100).unwrap_err();
+        assert_eq!(err.status, StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,94 @@
+//! Provider capability matrix
+//!
+//! `GET /api/ai/capabilities` tells the frontend which provider/endpoint combinations
+//! work, so it can disable the others instead of finding out from error responses.
+//! [`support`] mirrors how the handlers route each provider; a router test checks
+//! every combination against the handlers so the two can't drift apart.
+//!
+//! A combination is `native` when the endpoint has its own implementation for the
+//! provider, and `proxied` when it is handed on to another: `fastest` races the
+//! configured providers, and streaming classification without the Claude CLI runs a
+//! plain classify and only sends the final event. Stubs that answer "not yet
+//! implemented" (the Gemini and OpenAI proxies, Claude's API mode outside
+//! classification) are left out.
+
+use serde::Serialize;
+
+/// Providers in the matrix, including the `fastest` pseudo-provider
+pub const PROVIDERS: [&str; 4] = ["claude", "gemini", "openai", "fastest"];
+
+/// `/api/ai/*` endpoints that take a `provider`
+pub const ENDPOINTS: [&str; 12] = [
+    "classify",
+    "classify-by-id",
+    "classify-compare",
+    "classify-stream",
+    "stream-classify-by-id",
+    "suggest-response",
+    "generate",
+    "refine",
+    "testpage",
+    "explain",
+    "summarize-comments",
+    "rewrite-summary",
+];
+
+/// How an endpoint serves a provider
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Support {
+    Native,
+    Proxied,
+}
+
+/// How `endpoint` serves `provider`, or `None` when it answers with an error.
+/// `claude_cli` is whether Claude runs through the CLI (`CLAUDE_BACKEND_MODE=cli`)
+/// rather than the HTTP API.
+pub fn support(endpoint: &str, provider: &str, claude_cli: bool) -> Option<Support> {
+    let claude = provider == "claude";
+    match endpoint {
+        "classify" | "classify-by-id" if claude => Some(Support::Native),
+        "classify" | "classify-by-id" if provider == "fastest" => Some(Support::Proxied),
+        // Candidates are classified one by one; there is nothing to race
+        "classify-compare" if claude => Some(Support::Native),
+        // Only the CLI streams classifications field by field
+        "classify-stream" | "stream-classify-by-id" if claude && claude_cli => {
+            Some(Support::Native)
+        }
+        "classify-stream" | "stream-classify-by-id" if claude || provider == "fastest" => {
+            Some(Support::Proxied)
+        }
+        "suggest-response" | "generate" | "refine" | "testpage" | "explain"
+        | "summarize-comments" | "rewrite-summary"
+            if claude && claude_cli =>
+        {
+            Some(Support::Native)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn streaming_is_native_only_with_the_cli() {
+        assert_eq!(support("classify-stream", "claude", true), Some(Support::Native));
+        assert_eq!(support("classify-stream", "claude", false), Some(Support::Proxied));
+        assert_eq!(support("classify", "fastest", false), Some(Support::Proxied));
+        assert_eq!(support("classify-compare", "fastest", false), None);
+        assert_eq!(support("refine", "claude", true), Some(Support::Native));
+    }
+
+    #[test]
+    fn stubs_are_left_out() {
+        for endpoint in ENDPOINTS {
+            assert_eq!(support(endpoint, "gemini", true), None, "{}", endpoint);
+            assert_eq!(support(endpoint, "openai", false), None, "{}", endpoint);
+        }
+        assert_eq!(support("classify", "claude", false), Some(Support::Native));
+        assert_eq!(support("refine", "claude", false), None);
+        assert_eq!(support("rewrite-summary", "claude", false), None);
+    }
+}
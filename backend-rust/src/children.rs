@@ -0,0 +1,66 @@
+//! Registry of running CLI child processes
+//!
+//! Every spawned `claude` process is tracked here while it runs, so shutdown can kill
+//! whatever is still in flight instead of orphaning it, and `/health` can report how
+//! many are live.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use tokio::process::Child;
+use uuid::Uuid;
+
+#[derive(Debug, Default)]
+pub struct ChildRegistry {
+    children: Mutex<HashMap<Uuid, Child>>,
+}
+
+impl ChildRegistry {
+    /// Track `child` until the returned guard is taken or dropped
+    pub fn register(self: &Arc<Self>, child: Child) -> RegisteredChild {
+        let id = Uuid::new_v4();
+        self.children.lock().unwrap().insert(id, child);
+        RegisteredChild {
+            registry: Arc::clone(self),
+            id,
+        }
+    }
+
+    fn deregister(&self, id: Uuid) -> Option<Child> {
+        self.children.lock().unwrap().remove(&id)
+    }
+
+    /// Number of children currently running
+    pub fn len(&self) -> usize {
+        self.children.lock().unwrap().len()
+    }
+
+    /// Send a kill to every registered child; returns how many were signalled
+    pub fn kill_all(&self) -> usize {
+        let mut children = self.children.lock().unwrap();
+        for child in children.values_mut() {
+            let _ = child.start_kill();
+        }
+        children.len()
+    }
+}
+
+/// A registered child. Dropping it deregisters (and, with `kill_on_drop`, kills) the
+/// process, so a cancelled request never leaves an entry behind.
+pub struct RegisteredChild {
+    registry: Arc<ChildRegistry>,
+    id: Uuid,
+}
+
+impl RegisteredChild {
+    /// Deregister and take the child back, e.g. to wait for its exit status
+    pub fn take(self) -> Option<Child> {
+        self.registry.deregister(self.id)
+    }
+}
+
+impl Drop for RegisteredChild {
+    fn drop(&mut self) {
+        self.registry.deregister(self.id);
+    }
+}
@@ -4,55 +4,472 @@
 //! This is the preferred mode for Mozilla developers who have Claude Code installed.
 //!
 //! NOTE: All prompts and schemas are centralized in frontend/src/prompts.js.
-//! The backend requires the frontend to provide these values in requests.
+//! The backend requires the frontend to provide these values in requests, except that
+//! built-in schemas can be enabled with `ALLOW_DEFAULT_SCHEMAS`.
 
+use axum::http::StatusCode;
 use axum::Json;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::process::Stdio;
-use tokio::process::Command;
-use tracing::{debug, error, info};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::{ChildStderr, ChildStdout, Command};
+use tokio::sync::mpsc;
+use tracing::{debug, error, info, warn, Level};
 
-use crate::{ClassifyResponse, ErrorResponse, GenerateResponse, RefineResponse, SuggestedAction, SuggestResponse, TestPageResponse, TriageAction};
+use crate::active;
+use crate::budget;
+use crate::children::{ChildRegistry, RegisteredChild};
+use crate::metrics;
+use crate::normalize::PriorityConvention;
+use crate::parse::{self, Fields};
+use crate::partial_json::FieldScanner;
+use crate::redact::redact;
+use crate::schemas;
+use crate::templates;
+use crate::{ClassifyResponse, ErrorResponse, ExplainResponse, GenerateResponse, RefineResponse, RewriteSummaryResponse, SuggestResponse, SummarizeCommentsResponse, TestPageResponse};
+
+/// Settings for the Claude CLI integration
+#[derive(Debug, Clone)]
+pub struct CliConfig {
+    /// The `claude` executable - a name on `PATH` or a path (`CLAUDE_BIN`)
+    pub program: String,
+    /// Level at which CLI stderr is logged after a successful run (`None` = off)
+    pub stderr_log: Option<Level>,
+    /// Fall back to built-in schemas when a request omits `schema`
+    pub allow_default_schemas: bool,
+    /// Schemas used instead of the request's, by endpoint (`SCHEMA_OVERRIDES`)
+    pub schema_overrides: Arc<BTreeMap<String, String>>,
+    /// Largest schema a request may send (`MAX_SCHEMA_BYTES`)
+    pub max_schema_bytes: usize,
+    /// Templates for requests without a prompt, by endpoint (`PROMPT_TEMPLATE_DIR`)
+    pub prompt_templates: Arc<templates::Templates>,
+    /// Extra arguments appended to every CLI invocation (e.g. permission flags)
+    pub extra_args: Vec<String>,
+    /// Command the CLI is run under (e.g. `nice -n 10`); `claude` and its args follow it
+    pub wrapper: Vec<String>,
+    /// Running CLI processes, killed on shutdown
+    pub children: Arc<ChildRegistry>,
+    /// Re-runs after a run with no structured output
+    pub max_retries: u32,
+    /// Upper bound for a request's `maxRetries`
+    pub max_retries_cap: u32,
+    /// Time limit for one CLI run (`None` = unlimited)
+    pub timeout: Option<Duration>,
+    /// Upper bound for a request's `timeoutMs` (`None` = unbounded)
+    pub timeout_cap: Option<Duration>,
+    /// Cost budget of this request in USD, checked before and after each run
+    pub max_cost_usd: Option<f32>,
+    /// Clean model output strings while parsing (`CLEAN_MODEL_STRINGS`), see
+    /// [`parse::clean`]
+    pub clean_strings: bool,
+}
+
+impl Default for CliConfig {
+    fn default() -> Self {
+        CliConfig {
+            program: "claude".to_string(),
+            stderr_log: None,
+            allow_default_schemas: false,
+            schema_overrides: Arc::default(),
+            max_schema_bytes: 64 * 1024,
+            prompt_templates: Arc::default(),
+            extra_args: Vec::new(),
+            wrapper: Vec::new(),
+            children: Arc::default(),
+            max_retries: 1,
+            max_retries_cap: 3,
+            timeout: Some(Duration::from_secs(300)),
+            timeout_cap: Some(Duration::from_secs(600)),
+            max_cost_usd: None,
+            clean_strings: true,
+        }
+    }
+}
+
+impl CliConfig {
+    /// This config with a request's `maxRetries`/`timeoutMs` applied. Values above the
+    /// server caps are clamped to the cap rather than rejected.
+    pub fn with_overrides(mut self, max_retries: Option<u32>, timeout_ms: Option<u64>) -> Self {
+        if let Some(retries) = max_retries {
+            self.max_retries = retries.min(self.max_retries_cap);
+        }
+        if let Some(ms) = timeout_ms {
+            self.timeout = Some(self.capped_timeout(ms));
+        }
+        self
+    }
+
+    /// A request's `timeoutMs`, clamped to the server cap
+    pub fn capped_timeout(&self, timeout_ms: u64) -> Duration {
+        let timeout = Duration::from_millis(timeout_ms);
+        self.timeout_cap.map_or(timeout, |cap| timeout.min(cap))
+    }
+}
+
+/// Whether `program` resolves to a file - a path as given, or a bare name on `PATH`
+pub fn executable_exists(program: &str) -> bool {
+    if program.contains(std::path::MAIN_SEPARATOR) || program.contains('/') {
+        return std::path::Path::new(program).is_file();
+    }
+    std::env::var_os("PATH")
+        .map(|paths| std::env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+        .unwrap_or(false)
+}
+
+/// Time allowed for `claude --version`; some wrappers hang on it or don't implement it
+const VERSION_PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Outcome of running `claude --version` for the health and status pages
+#[derive(Debug)]
+pub enum VersionProbe {
+    /// Exited successfully, printing this version
+    Version(String),
+    /// Exited with an error, printing this to stderr
+    Failed(String),
+    /// Started but didn't exit within [`VERSION_PROBE_TIMEOUT`]
+    TimedOut,
+    /// Couldn't be started
+    NotFound(String),
+}
+
+impl VersionProbe {
+    /// Whether the CLI counts as available. A probe that timed out found the binary, so
+    /// it is assumed to work even though its version is unknown.
+    pub fn available(&self) -> bool {
+        matches!(self, VersionProbe::Version(_) | VersionProbe::TimedOut)
+    }
+
+    /// Short status for `/health`
+    pub fn status(&self) -> &'static str {
+        match self {
+            VersionProbe::Version(_) => "ok",
+            VersionProbe::Failed(_) => "error",
+            VersionProbe::TimedOut => "version-unknown",
+            VersionProbe::NotFound(_) => "not-found",
+        }
+    }
+}
+
+/// Run `program --version`, giving up after [`VERSION_PROBE_TIMEOUT`]
+pub async fn probe_version(program: &str) -> VersionProbe {
+    let output = Command::new(program)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    match tokio::time::timeout(VERSION_PROBE_TIMEOUT, output).await {
+        Ok(Ok(output)) if output.status.success() => {
+            VersionProbe::Version(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        }
+        Ok(Ok(output)) => {
+            VersionProbe::Failed(String::from_utf8_lossy(&output.stderr).trim().to_string())
+        }
+        Ok(Err(e)) => VersionProbe::NotFound(e.to_string()),
+        Err(_) => {
+            warn!(
+                "{} --version didn't exit within {}s, assuming it is available",
+                program,
+                VERSION_PROBE_TIMEOUT.as_secs()
+            );
+            VersionProbe::TimedOut
+        }
+    }
+}
+
+/// Default for `CLAUDE_CLI_EXTRA_ARGS`: deny anything that would otherwise wait for an
+/// interactive permission prompt, so a non-interactive run can never hang
+pub const DEFAULT_EXTRA_ARGS: &str = "--permission-mode dontAsk";
+
+/// Characters rejected in configured arguments. Arguments are passed straight to argv
+/// (no shell), so these would never be interpreted - but their presence almost always
+/// means the value was written for a shell and won't do what the operator expects.
+const SHELL_METACHARACTERS: &[char] = &[';', '|', '&', '$', '`', '<', '>', '\n', '\r', '\0'];
+
+/// Split a configured argument string into argv, honoring single and double quotes
+/// (e.g. `--allowed-tools "Bash(git log:*)"`). No other shell syntax is supported.
+pub fn parse_args(input: &str) -> Result<Vec<String>, String> {
+    if let Some(c) = input.chars().find(|c| SHELL_METACHARACTERS.contains(c)) {
+        return Err(format!("shell metacharacter {:?} is not allowed", c));
+    }
+
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_arg = false;
+    let mut quote: Option<char> = None;
+
+    for c in input.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => current.push(c),
+            None if c == '\'' || c == '"' => {
+                quote = Some(c);
+                in_arg = true;
+            }
+            None if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            None => {
+                current.push(c);
+                in_arg = true;
+            }
+        }
+    }
+
+    if let Some(q) = quote {
+        return Err(format!("unterminated {} quote", q));
+    }
+    if in_arg {
+        args.push(current);
+    }
+    Ok(args)
+}
 
 /// Claude CLI output structure
 #[derive(Debug, Deserialize)]
 struct ClaudeCliOutput {
     #[serde(rename = "type")]
     output_type: Option<String>,
-    #[allow(dead_code)]
+    /// How a `result` line's run ended: `success` or an `error_*` kind
     subtype: Option<String>,
-    result: Option<ClaudeResult>,
+    /// What the run cost, as reported by the CLI
+    total_cost_usd: Option<f64>,
+    /// How long the run took, as reported by the CLI
+    duration_ms: Option<u64>,
+    /// Model turns the run took
+    num_turns: Option<u64>,
+    /// Current CLIs put the structured output on the result line itself...
+    structured_output: Option<serde_json::Value>,
+    /// ...older ones nested it in a `result` object (newer ones use `result` for text)
+    result: Option<serde_json::Value>,
 }
 
-#[derive(Debug, Deserialize)]
-struct ClaudeResult {
-    #[serde(rename = "type")]
-    #[allow(dead_code)]
-    result_type: Option<String>,
-    structured_output: Option<serde_json::Value>,
+/// Cost and duration of the CLI runs behind one response, as the CLI reported them;
+/// sent as `meta` by [`crate::middleware::cli_meta`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunMeta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost_usd: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_turns: Option<u64>,
 }
 
-/// Run the claude CLI with the given prompt and schema
+impl RunMeta {
+    /// Totals of two sets of runs, e.g. a run and its retry
+    pub fn add(self, other: RunMeta) -> RunMeta {
+        fn sum<T: std::ops::Add<Output = T>>(a: Option<T>, b: Option<T>) -> Option<T> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(a + b),
+                (a, b) => a.or(b),
+            }
+        }
+        RunMeta {
+            cost_usd: sum(self.cost_usd, other.cost_usd),
+            duration_ms: sum(self.duration_ms, other.duration_ms),
+            num_turns: sum(self.num_turns, other.num_turns),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == RunMeta::default()
+    }
+}
+
+impl ClaudeCliOutput {
+    fn meta(&self) -> RunMeta {
+        RunMeta {
+            cost_usd: self.total_cost_usd,
+            duration_ms: self.duration_ms,
+            num_turns: self.num_turns,
+        }
+    }
+
+    /// The error a `result` line reports through its `subtype`, if any
+    fn error(&self) -> Option<ErrorResponse> {
+        if self.output_type.as_deref() != Some("result") {
+            return None;
+        }
+        result_error(self.subtype.as_deref()?, self.result.as_ref())
+    }
+
+    /// The structured output of a `result` line, unless absent or `null`
+    fn into_structured_output(self) -> Option<serde_json::Value> {
+        if self.output_type.as_deref() != Some("result") {
+            return None;
+        }
+        let nested = self
+            .result
+            .and_then(|mut result| result.get_mut("structured_output").map(|s| s.take()));
+        [self.structured_output, nested]
+            .into_iter()
+            .flatten()
+            .find(|output| !output.is_null())
+    }
+}
+
+/// Error code for a successful CLI run whose output had no structured result
+const STRUCTURED_OUTPUT_MISSING: &str = "STRUCTURED_OUTPUT_MISSING";
+
+/// Error code for a CLI run that exceeded its time limit
+const CLI_TIMEOUT: &str = "CLI_TIMEOUT";
+
+/// Error codes for the `error_*` result subtypes: the CLI ran out of turns, failed while
+/// running, or ended with an error kind this backend doesn't know
+const CLI_MAX_TURNS: &str = "CLI_MAX_TURNS";
+const CLI_EXECUTION_ERROR: &str = "CLI_EXECUTION_ERROR";
+const CLI_RESULT_ERROR: &str = "CLI_RESULT_ERROR";
+
+/// The error for a result line with `subtype`; `None` unless it is an `error_*` kind.
+/// A text `result` (the CLI's error message, when it gives one) becomes the details.
+fn result_error(subtype: &str, result: Option<&serde_json::Value>) -> Option<ErrorResponse> {
+    let (error, code) = match subtype {
+        "error_max_turns" => ("Claude CLI reached its turn limit", CLI_MAX_TURNS),
+        "error_during_execution" => ("Claude CLI failed during execution", CLI_EXECUTION_ERROR),
+        kind if kind.starts_with("error") => {
+            ("Claude CLI run ended with an error", CLI_RESULT_ERROR)
+        }
+        _ => return None,
+    };
+    let message = result.and_then(|r| r.as_str()).filter(|r| !r.trim().is_empty());
+    warn!("Claude CLI result {}: {}", subtype, message.unwrap_or("(no message)"));
+    Some(ErrorResponse {
+        error: error.to_string(),
+        details: Some(match message {
+            Some(message) => format!("{}: {}", subtype, message),
+            None => subtype.to_string(),
+        }),
+        code: Some(code),
+        status: StatusCode::BAD_GATEWAY,
+    })
+}
+
+/// Run the claude CLI with the given prompt and schema.
+///
+/// The CLI occasionally succeeds without producing `structured_output`; re-asking
+/// usually fixes that, so that one failure is retried up to `max_retries` times (once
+/// by default). Repeated misses most likely mean a broken schema, and retrying further
+/// would only add cost. The budget covers the runs together: no retry starts once they
+/// have spent it.
 async fn run_claude_cli(
+    cli: &CliConfig,
+    prompt: &str,
+    schema: &str,
+    model: &str,
+) -> Result<serde_json::Value, ErrorResponse> {
+    budget::check_estimate(cli.max_cost_usd, model, prompt, schema)?;
+    let mut spent = 0.0;
+    let mut result = invoke_with_timeout(cli, prompt, schema, model, &mut spent).await;
+    for attempt in 1..=cli.max_retries {
+        match result {
+            Err(ref e) if e.code == Some(STRUCTURED_OUTPUT_MISSING) => {}
+            _ => break,
+        }
+        budget::check_spent(cli.max_cost_usd, "Claude CLI", spent)?;
+        warn!(
+            "Claude CLI returned no structured output, retrying ({}/{})",
+            attempt, cli.max_retries
+        );
+        metrics::inc("claude_cli_structured_output_retries_total");
+        result = invoke_with_timeout(cli, prompt, schema, model, &mut spent).await;
+        match result {
+            Ok(_) => {
+                info!("Structured output retry succeeded");
+                metrics::inc("claude_cli_structured_output_retry_successes_total");
+            }
+            Err(_) => warn!("Structured output retry failed"),
+        }
+    }
+    result
+}
+
+/// One CLI run under `cli.timeout`. On timeout the run is dropped, which kills the child.
+async fn invoke_with_timeout(
+    cli: &CliConfig,
     prompt: &str,
     schema: &str,
     model: &str,
+    spent: &mut f64,
 ) -> Result<serde_json::Value, ErrorResponse> {
+    with_cli_timeout(cli.timeout, invoke_claude_cli(cli, prompt, schema, model, spent)).await
+}
+
+/// Bound a CLI run by `timeout` (504 `CLI_TIMEOUT`)
+async fn with_cli_timeout<T>(
+    timeout: Option<Duration>,
+    run: impl std::future::Future<Output = Result<T, ErrorResponse>>,
+) -> Result<T, ErrorResponse> {
+    let Some(timeout) = timeout else {
+        return run.await;
+    };
+    tokio::time::timeout(timeout, run)
+        .await
+        .unwrap_or_else(|_| {
+            warn!("Claude CLI timed out after {}ms", timeout.as_millis());
+            Err(ErrorResponse {
+                error: "Claude CLI timed out".to_string(),
+                details: Some(format!("No result after {}ms", timeout.as_millis())),
+                code: Some(CLI_TIMEOUT),
+                status: StatusCode::GATEWAY_TIMEOUT,
+            })
+        })
+}
+
+/// Largest schema that fits in a single CLI argument: Linux caps one argument at
+/// 128 KiB (`MAX_ARG_STRLEN`), including its terminating NUL. `MAX_SCHEMA_BYTES` is
+/// held to this.
+pub const MAX_SCHEMA_ARG_BYTES: usize = 128 * 1024 - 1;
+
+/// A spawned CLI process with the prompt written to its stdin
+struct SpawnedCli {
+    child: RegisteredChild,
+    stdout: Option<ChildStdout>,
+    stderr: Option<ChildStderr>,
+}
+
+/// Spawn the claude CLI (under the configured wrapper, if any) with `output_args`
+/// selecting its output format, and write `prompt` to its stdin
+async fn spawn_cli(
+    cli: &CliConfig,
+    prompt: &str,
+    schema: &str,
+    model: &str,
+    output_args: &[&str],
+) -> Result<SpawnedCli, ErrorResponse> {
     info!("Running Claude CLI with model: {}", model);
     debug!("Prompt length: {} chars", prompt.len());
+    active::note_prompt_sent(prompt);
 
-    // Build the command
-    let mut cmd = Command::new("claude");
+    // Build the command, under the configured wrapper if any
+    let mut cmd = match cli.wrapper.split_first() {
+        Some((program, wrapper_args)) => {
+            let mut cmd = Command::new(program);
+            cmd.args(wrapper_args).arg(&cli.program);
+            cmd
+        }
+        None => Command::new(&cli.program),
+    };
     cmd.arg("-p")
         .arg("--output-format")
-        .arg("json")
+        .args(output_args)
         .arg("--model")
         .arg(model)
         .arg("--json-schema")
         .arg(schema)
+        .args(&cli.extra_args)
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
-        .stderr(Stdio::piped());
+        .stderr(Stdio::piped())
+        // Kill the CLI if the request is dropped (e.g. it lost a provider race)
+        .kill_on_drop(true);
 
     // Spawn the process
     let mut child = cmd.spawn().map_err(|e| {
@@ -60,68 +477,152 @@ async fn run_claude_cli(
         ErrorResponse {
             error: "Failed to spawn claude CLI".to_string(),
             details: Some(format!(
-                "Ensure 'claude' is installed and in PATH. Error: {}",
-                e
+                "Ensure '{}' is installed and in PATH. Error: {}",
+                cli.program, e
             )),
+            ..Default::default()
         }
     })?;
 
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+    let stdin_pipe = child.stdin.take();
+    let child = cli.children.register(child);
+
     // Write prompt to stdin
-    if let Some(mut stdin) = child.stdin.take() {
+    if let Some(mut stdin) = stdin_pipe {
         use tokio::io::AsyncWriteExt;
         stdin.write_all(prompt.as_bytes()).await.map_err(|e| {
             error!("Failed to write to claude stdin: {}", e);
             ErrorResponse {
                 error: "Failed to write to claude CLI".to_string(),
                 details: Some(e.to_string()),
+                ..Default::default()
             }
         })?;
     }
 
-    // Wait for the process to complete
-    let output = child.wait_with_output().await.map_err(|e| {
-        error!("Failed to get claude CLI output: {}", e);
-        ErrorResponse {
-            error: "Failed to get claude CLI output".to_string(),
-            details: Some(e.to_string()),
-        }
+    Ok(SpawnedCli {
+        child,
+        stdout,
+        stderr,
+    })
+}
+
+fn output_failed(e: std::io::Error) -> ErrorResponse {
+    error!("Failed to get claude CLI output: {}", e);
+    ErrorResponse {
+        error: "Failed to get claude CLI output".to_string(),
+        details: Some(e.to_string()),
+        ..Default::default()
+    }
+}
+
+/// Collect the exit status of a CLI run whose output has been read. A failed run is an
+/// error carrying its stderr; a successful run's stderr is logged per `stderr_log`.
+async fn finish_cli(
+    cli: &CliConfig,
+    child: RegisteredChild,
+    stderr: &[u8],
+) -> Result<(), ErrorResponse> {
+    let mut child = child.take().ok_or_else(|| ErrorResponse {
+        error: "Claude CLI process was terminated".to_string(),
+        details: None,
+        ..Default::default()
     })?;
+    let status = child.wait().await.map_err(output_failed)?;
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
+    if !status.success() {
+        let stderr = String::from_utf8_lossy(stderr);
         error!("Claude CLI failed: {}", stderr);
         return Err(ErrorResponse {
             error: "Claude CLI execution failed".to_string(),
             details: Some(stderr.to_string()),
+            ..Default::default()
         });
     }
 
-    // Parse the JSON output
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    debug!("Claude CLI output: {}", stdout);
-
-    // Claude CLI outputs multiple JSON objects, we need the last result one
-    // Look for the structured_output in the response
-    for line in stdout.lines() {
-        if line.trim().is_empty() {
-            continue;
+    // Surface diagnostics (deprecation warnings, near-misses) from successful runs too
+    let stderr = String::from_utf8_lossy(stderr);
+    if !stderr.trim().is_empty() {
+        let stderr = redact(stderr.trim());
+        match cli.stderr_log {
+            Some(Level::WARN) => warn!("Claude CLI stderr: {}", stderr),
+            Some(Level::INFO) => info!("Claude CLI stderr: {}", stderr),
+            Some(_) => debug!("Claude CLI stderr: {}", stderr),
+            None => {}
         }
+    }
+    Ok(())
+}
 
-        if let Ok(parsed) = serde_json::from_str::<ClaudeCliOutput>(line) {
-            if parsed.output_type.as_deref() == Some("result") {
-                if let Some(result) = parsed.result {
-                    if let Some(structured) = result.structured_output {
-                        info!("Successfully extracted structured output from Claude CLI");
-                        return Ok(structured);
-                    }
-                }
-            }
+/// Spawn the claude CLI once and extract its structured output. The run's reported cost
+/// is added to `spent`, which a result is checked against.
+async fn invoke_claude_cli(
+    cli: &CliConfig,
+    prompt: &str,
+    schema: &str,
+    model: &str,
+    spent: &mut f64,
+) -> Result<serde_json::Value, ErrorResponse> {
+    let SpawnedCli {
+        child,
+        mut stdout,
+        mut stderr,
+    } = spawn_cli(cli, prompt, schema, model, &["json"]).await?;
+
+    // Read output until the process closes its pipes, then collect its exit status
+    let (stdout, stderr) = tokio::try_join!(read_pipe(&mut stdout), read_pipe(&mut stderr))
+        .map_err(output_failed)?;
+    let finished = finish_cli(cli, child, &stderr).await;
+
+    // Parse the JSON output from the raw bytes: a lossy conversion would quietly turn
+    // invalid UTF-8 into replacement characters and hand the parser altered JSON
+    let stdout = stdout.as_slice();
+    debug!("Claude CLI output: {}", String::from_utf8_lossy(stdout));
+    let outputs = || {
+        stdout
+            .split(|b| *b == b'\n')
+            .rev()
+            .filter(|line| !line.trim_ascii().is_empty())
+            .filter_map(|line| serde_json::from_slice::<ClaudeCliOutput>(line).ok())
+    };
+
+    // The final result line says how the run ended. An error subtype explains a failed
+    // exit better than stderr does, so it is checked first.
+    let last_result = outputs().find(|output| output.output_type.as_deref() == Some("result"));
+    // A failed run is reported too: it cost the same
+    let run_cost = last_result.as_ref().and_then(|result| result.meta().cost_usd);
+    if let Some(result) = &last_result {
+        active::add_cli_meta(result.meta());
+    }
+    *spent += run_cost.unwrap_or(0.0);
+    if let Some(error) = last_result.as_ref().and_then(ClaudeCliOutput::error) {
+        return Err(error);
+    }
+    finished?;
+
+    // Claude CLI outputs multiple JSON objects, and some versions (or multi-turn runs)
+    // more than one result: the final result is the last one with structured output
+    let last_structured = outputs()
+        .find_map(|output| {
+            let meta = output.meta();
+            output.into_structured_output().map(|structured| (structured, meta))
+        });
+    if let Some((structured, meta)) = last_structured {
+        info!("Successfully extracted structured output from Claude CLI");
+        if run_cost.is_none() {
+            *spent += meta.cost_usd.unwrap_or(0.0);
         }
+        budget::check_spent(cli.max_cost_usd, "Claude CLI", *spent)?;
+        return Ok(structured);
     }
 
     // If we couldn't find structured output, try parsing the whole output
-    // In case the format changed or it's a simple JSON response
-    if let Ok(json) = serde_json::from_str::<serde_json::Value>(&stdout) {
+    // In case the format changed or it's a simple JSON response, possibly fenced
+    let unfenced = std::str::from_utf8(stdout)
+        .map_or(stdout, |text| strip_code_fences(text).as_bytes());
+    if let Ok(json) = serde_json::from_slice::<serde_json::Value>(unfenced) {
         if let Some(obj) = json.as_object() {
             if obj.contains_key("structured_output") {
                 if let Some(structured) = obj.get("structured_output") {
@@ -135,287 +636,586 @@ async fn run_claude_cli(
         }
     }
 
+    let mut details = format!("Output: {}", String::from_utf8_lossy(stdout));
+    let replaced = invalid_utf8_bytes(stdout);
+    if replaced > 0 {
+        warn!("Claude CLI produced non-UTF8 output ({} bytes)", replaced);
+        details = format!(
+            "CLI produced non-UTF8 output, {} bytes replaced. {}",
+            replaced, details
+        );
+    }
     Err(ErrorResponse {
         error: "Failed to parse Claude CLI output".to_string(),
-        details: Some(format!("Output: {}", stdout)),
+        details: Some(details),
+        code: Some(STRUCTURED_OUTPUT_MISSING),
+        ..Default::default()
+    })
+}
+
+/// `text` without surrounding whitespace and markdown code fences (```` ``` ```` or
+/// ```` ```json ````), as some models wrap JSON in them. A fence on one side only is
+/// removed as well.
+fn strip_code_fences(text: &str) -> &str {
+    let mut text = text.trim();
+    if let Some(rest) = text.strip_prefix("```") {
+        text = rest.trim_start_matches(|c: char| c.is_ascii_alphanumeric());
+    }
+    if let Some(rest) = text.strip_suffix("```") {
+        text = rest;
+    }
+    text.trim()
+}
+
+/// Number of bytes in `bytes` that aren't part of valid UTF-8 sequences, i.e. that a
+/// lossy conversion replaces
+fn invalid_utf8_bytes(mut bytes: &[u8]) -> usize {
+    let mut invalid = 0;
+    while let Err(e) = std::str::from_utf8(bytes) {
+        // `None` means the input ends mid-sequence: the rest is all invalid
+        let len = e.error_len().unwrap_or(bytes.len() - e.valid_up_to());
+        invalid += len;
+        bytes = &bytes[e.valid_up_to() + len..];
+    }
+    invalid
+}
+
+async fn read_pipe<R: tokio::io::AsyncRead + Unpin>(
+    pipe: &mut Option<R>,
+) -> std::io::Result<Vec<u8>> {
+    use tokio::io::AsyncReadExt;
+    let mut buf = Vec::new();
+    if let Some(pipe) = pipe {
+        pipe.read_to_end(&mut buf).await?;
+    }
+    Ok(buf)
+}
+
+/// Output arguments for a run that streams partial messages as JSON lines
+const STREAM_OUTPUT_ARGS: &[&str] = &["stream-json", "--verbose", "--include-partial-messages"];
+
+/// Spawn the claude CLI once with streaming output, sending each top-level field of the
+/// structured output on `fields` as soon as the CLI has written it. Resolves to the
+/// complete structured output from the final `result` line. A CLI that doesn't stream
+/// partial messages sends no fields; only the final result is used then.
+async fn invoke_claude_cli_streaming(
+    cli: &CliConfig,
+    prompt: &str,
+    schema: &str,
+    model: &str,
+    fields: &mpsc::Sender<(String, serde_json::Value)>,
+) -> Result<serde_json::Value, ErrorResponse> {
+    let SpawnedCli {
+        child,
+        stdout,
+        mut stderr,
+    } = spawn_cli(cli, prompt, schema, model, STREAM_OUTPUT_ARGS).await?;
+
+    let read_events = async {
+        let mut scanner = FieldScanner::default();
+        let mut structured = None;
+        let mut meta = RunMeta::default();
+        let mut failed = None;
+        let Some(stdout) = stdout else {
+            return Ok((structured, meta, failed));
+        };
+        let mut lines = JsonLines::new(stdout);
+        while let Some(event) = lines.next().await? {
+            if event.get("type").and_then(|t| t.as_str()) == Some("result") {
+                meta = result_meta(&event);
+            }
+            match stream_event(&event) {
+                StreamEvent::BlockStart => scanner = FieldScanner::default(),
+                StreamEvent::JsonDelta(fragment) => {
+                    for field in scanner.push(fragment) {
+                        // The receiver going away only means nobody is watching fields
+                        let _ = fields.send(field).await;
+                    }
+                }
+                StreamEvent::Result(output) => structured = Some(output.clone()),
+                StreamEvent::Failed(error) => failed = Some(error),
+                StreamEvent::Other => {}
+            }
+        }
+        Ok((structured, meta, failed))
+    };
+    let ((structured, meta, failed), stderr) =
+        tokio::try_join!(read_events, read_pipe(&mut stderr)).map_err(output_failed)?;
+    // A failed run is reported too: it cost the same
+    active::add_cli_meta(meta);
+    let finished = finish_cli(cli, child, &stderr).await;
+    if let Some(error) = failed {
+        return Err(error);
+    }
+    finished?;
+
+    if let Some(cost) = meta.cost_usd {
+        budget::check_spent(cli.max_cost_usd, "Claude CLI", cost)?;
+    }
+    structured.ok_or_else(|| ErrorResponse {
+        error: "Failed to parse Claude CLI output".to_string(),
+        details: Some("The streamed output had no structured result".to_string()),
+        code: Some(STRUCTURED_OUTPUT_MISSING),
+        ..Default::default()
     })
 }
 
+/// The newline-delimited JSON values of a `stream-json` output. A read returns whatever
+/// the pipe holds, which can end mid-line (or mid-character), so bytes are buffered
+/// until a newline, or the end of the output, completes a line; only whole lines are
+/// parsed. Lines that aren't JSON are skipped.
+struct JsonLines<R> {
+    lines: tokio::io::Split<BufReader<R>>,
+}
+
+impl<R: tokio::io::AsyncRead + Unpin> JsonLines<R> {
+    fn new(reader: R) -> Self {
+        JsonLines {
+            lines: BufReader::new(reader).split(b'\n'),
+        }
+    }
+
+    async fn next(&mut self) -> std::io::Result<Option<serde_json::Value>> {
+        while let Some(line) = self.lines.next_segment().await? {
+            if line.trim_ascii().is_empty() {
+                continue;
+            }
+            match serde_json::from_slice(&line) {
+                Ok(value) => return Ok(Some(value)),
+                Err(e) => debug!("Skipping stream-json line that isn't JSON: {}", e),
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// The parts of a `stream-json` line [`invoke_claude_cli_streaming`] acts on
+enum StreamEvent<'a> {
+    /// A new content block starts; partial JSON from an earlier block is abandoned
+    BlockStart,
+    /// A fragment of the structured output being written
+    JsonDelta(&'a str),
+    /// The final result's structured output
+    Result(&'a serde_json::Value),
+    /// A result with an `error_*` subtype
+    Failed(ErrorResponse),
+    Other,
+}
+
+/// The cost, duration and turns a `stream-json` `result` line reports
+fn result_meta(line: &serde_json::Value) -> RunMeta {
+    RunMeta {
+        cost_usd: line.get("total_cost_usd").and_then(|c| c.as_f64()),
+        duration_ms: line.get("duration_ms").and_then(|d| d.as_u64()),
+        num_turns: line.get("num_turns").and_then(|n| n.as_u64()),
+    }
+}
+
+fn stream_event(line: &serde_json::Value) -> StreamEvent<'_> {
+    match line.get("type").and_then(|t| t.as_str()) {
+        Some("stream_event") => {
+            let event = &line["event"];
+            match event.get("type").and_then(|t| t.as_str()) {
+                Some("content_block_start") => StreamEvent::BlockStart,
+                Some("content_block_delta") if event["delta"]["type"] == "input_json_delta" => {
+                    event["delta"]["partial_json"]
+                        .as_str()
+                        .map_or(StreamEvent::Other, StreamEvent::JsonDelta)
+                }
+                _ => StreamEvent::Other,
+            }
+        }
+        Some("result") => {
+            let subtype = line.get("subtype").and_then(|s| s.as_str()).unwrap_or("");
+            if let Some(error) = result_error(subtype, line.get("result")) {
+                return StreamEvent::Failed(error);
+            }
+            line.get("structured_output")
+                .or_else(|| line.get("result").and_then(|r| r.get("structured_output")))
+                .filter(|output| !output.is_null())
+                .map_or(StreamEvent::Other, StreamEvent::Result)
+        }
+        _ => StreamEvent::Other,
+    }
+}
+
+/// Error code for a request whose prompt is empty or whitespace
+const EMPTY_PROMPT: &str = "EMPTY_PROMPT";
+
+/// Error code for a request whose schema is empty or whitespace
+const EMPTY_SCHEMA: &str = "EMPTY_SCHEMA";
+
+/// Error code for a request whose schema is over `MAX_SCHEMA_BYTES`
+const SCHEMA_TOO_LARGE: &str = "SCHEMA_TOO_LARGE";
+
+/// Require the frontend to provide prompt and schema (centralized prompts), the schema
+/// no larger than `max_schema_bytes`. A missing prompt is rendered from the
+/// `PROMPT_TEMPLATE_DIR` template for `endpoint` with `bug`, if there is one. A
+/// `SCHEMA_OVERRIDES` schema for `endpoint` replaces the request's. When
+/// `allow_default_schemas` is on, a missing schema falls back to the built-in one for
+/// `endpoint`.
+pub fn prompt_and_schema<'a>(
+    cli: &CliConfig,
+    endpoint: &str,
+    bug: &serde_json::Value,
+    frontend_prompt: Option<&'a str>,
+    frontend_schema: Option<&'a str>,
+) -> Result<(Cow<'a, str>, Cow<'a, str>), ErrorResponse> {
+    let prompt = match (frontend_prompt, cli.prompt_templates.render(endpoint, bug)) {
+        (Some(prompt), _) => Cow::Borrowed(prompt),
+        (None, Some(Ok(rendered))) => {
+            debug!("Using the PROMPT_TEMPLATE_DIR template for {}", endpoint);
+            Cow::Owned(rendered)
+        }
+        (None, Some(Err(e))) => {
+            error!("Failed to render the {} template: {}", endpoint, e);
+            return Err(ErrorResponse {
+                error: "Failed to render the prompt template".to_string(),
+                details: Some(e),
+                ..Default::default()
+            });
+        }
+        (None, None) => {
+            return Err(ErrorResponse {
+                error: "Missing prompt from frontend".to_string(),
+                details: Some("Prompts are centralized in frontend/src/prompts.js".to_string()),
+                ..Default::default()
+            })
+        }
+    };
+    // Usually a prompt built from a missing template - don't pay for a CLI run
+    if prompt.trim().is_empty() {
+        return Err(ErrorResponse {
+            error: "Empty prompt".to_string(),
+            details: Some("The prompt is empty or whitespace only".to_string()),
+            code: Some(EMPTY_PROMPT),
+            status: StatusCode::BAD_REQUEST,
+        });
+    }
+
+    if let Some(schema) = cli.schema_overrides.get(endpoint) {
+        debug!("Using the SCHEMA_OVERRIDES schema for {}", endpoint);
+        return Ok((prompt, Cow::Owned(schema.clone())));
+    }
+
+    let schema = match frontend_schema {
+        Some(schema) if schema.trim().is_empty() => {
+            return Err(ErrorResponse {
+                error: "Empty schema".to_string(),
+                details: Some("The schema is empty or whitespace only".to_string()),
+                code: Some(EMPTY_SCHEMA),
+                status: StatusCode::BAD_REQUEST,
+            });
+        }
+        // Passed as a single CLI argument, so a huge one could exceed ARG_MAX
+        Some(schema) if schema.len() > cli.max_schema_bytes => {
+            return Err(ErrorResponse {
+                error: "Schema too large".to_string(),
+                details: Some(format!(
+                    "The schema is {} bytes, the limit is {} (MAX_SCHEMA_BYTES)",
+                    schema.len(),
+                    cli.max_schema_bytes
+                )),
+                code: Some(SCHEMA_TOO_LARGE),
+                status: StatusCode::BAD_REQUEST,
+            });
+        }
+        Some(schema) => Cow::Borrowed(schema),
+        None => {
+            let default = cli
+                .allow_default_schemas
+                .then(|| schemas::default_schema(endpoint))
+                .flatten()
+                .ok_or_else(|| ErrorResponse {
+                    error: "Missing schema from frontend".to_string(),
+                    details: Some(
+                        "Schemas are centralized in frontend/src/prompts.js".to_string(),
+                    ),
+                    ..Default::default()
+                })?;
+            debug!("Using built-in default schema for {}", endpoint);
+            Cow::Owned(default.to_string())
+        }
+    };
+
+    Ok((prompt, schema))
+}
+
 /// Classify a bug using Claude CLI.
 /// Prompts and schemas are now centralized in the frontend and must be provided.
 pub async fn classify_bug(
-    _bug: &serde_json::Value,
+    cli: &CliConfig,
+    bug: &serde_json::Value,
     model: &str,
     frontend_prompt: Option<&str>,
     frontend_schema: Option<&str>,
+    convention: &PriorityConvention,
 ) -> Result<Json<ClassifyResponse>, ErrorResponse> {
-    // Require frontend to provide prompt and schema (centralized prompts)
-    let prompt = frontend_prompt.ok_or_else(|| ErrorResponse {
-        error: "Missing prompt from frontend".to_string(),
-        details: Some("Prompts are centralized in frontend/src/prompts.js".to_string()),
-    })?;
-    let schema = frontend_schema.ok_or_else(|| ErrorResponse {
-        error: "Missing schema from frontend".to_string(),
-        details: Some("Schemas are centralized in frontend/src/prompts.js".to_string()),
-    })?;
-    let result = run_claude_cli(&prompt, schema, model).await?;
-
-    // Parse suggested_actions array
-    let suggested_actions = result
-        .get("suggested_actions")
-        .and_then(|v| v.as_array())
-        .map(|arr| {
-            arr.iter()
-                .filter_map(|item| {
-                    let action = item.get("action").and_then(|a| a.as_str())?;
-                    let reason = item.get("reason").and_then(|r| r.as_str()).unwrap_or("");
-                    Some(TriageAction {
-                        action: action.to_string(),
-                        reason: reason.to_string(),
-                    })
-                })
-                .collect()
-        })
-        .unwrap_or_default();
-
-    // Parse the result into our response type
-    let response = ClassifyResponse {
-        ai_detected_str: result
-            .get("ai_detected_str")
-            .and_then(|v| v.as_bool())
-            .unwrap_or(false),
-        ai_detected_test_attached: result
-            .get("ai_detected_test_attached")
-            .and_then(|v| v.as_bool())
-            .unwrap_or(false),
-        crashstack_present: result
-            .get("crashstack_present")
-            .and_then(|v| v.as_bool())
-            .unwrap_or(false),
-        fuzzing_testcase: result
-            .get("fuzzing_testcase")
-            .and_then(|v| v.as_bool())
-            .unwrap_or(false),
-        summary: result
-            .get("summary")
-            .and_then(|v| v.as_str())
-            .unwrap_or("")
-            .to_string(),
-        suggested_severity: result
-            .get("suggested_severity")
-            .and_then(|v| v.as_str())
-            .map(|s| s.to_string()),
-        suggested_priority: result
-            .get("suggested_priority")
-            .and_then(|v| v.as_str())
-            .map(|s| s.to_string()),
-        suggested_actions,
-        triage_reasoning: result
-            .get("triage_reasoning")
-            .and_then(|v| v.as_str())
-            .map(|s| s.to_string()),
-        suggested_canned_id: result
-            .get("suggested_canned_id")
-            .and_then(|v| v.as_str())
-            .filter(|s| !s.is_empty())
-            .map(|s| s.to_string()),
-        draft_response: result
-            .get("draft_response")
-            .and_then(|v| v.as_str())
-            .filter(|s| !s.is_empty())
-            .map(|s| s.to_string()),
-        notes: None,
-    };
+    let (prompt, schema) =
+        prompt_and_schema(cli, "classify", bug, frontend_prompt, frontend_schema)?;
+    let result = run_claude_cli(cli, &prompt, &schema, model).await?;
 
-    Ok(Json(response))
+    Ok(Json(parse::parse_classify(&Fields::new(&result).cleaning(cli.clean_strings), convention)))
+}
+
+/// Classify a bug using Claude CLI with streaming output, sending each top-level field
+/// on `fields` as the CLI produces it. Runs once: a retry would repeat fields that were
+/// already sent.
+pub async fn classify_bug_streaming(
+    cli: &CliConfig,
+    bug: &serde_json::Value,
+    model: &str,
+    frontend_prompt: Option<&str>,
+    frontend_schema: Option<&str>,
+    fields: mpsc::Sender<(String, serde_json::Value)>,
+    convention: &PriorityConvention,
+) -> Result<Json<ClassifyResponse>, ErrorResponse> {
+    let (prompt, schema) =
+        prompt_and_schema(cli, "classify", bug, frontend_prompt, frontend_schema)?;
+    budget::check_estimate(cli.max_cost_usd, model, &prompt, &schema)?;
+    let result = with_cli_timeout(
+        cli.timeout,
+        invoke_claude_cli_streaming(cli, &prompt, &schema, model, &fields),
+    )
+    .await?;
+
+    Ok(Json(parse::parse_classify(&Fields::new(&result).cleaning(cli.clean_strings), convention)))
+}
+
+/// Prompt and schema of the startup self-test: the smallest run that needs a working,
+/// authenticated CLI
+const SELF_TEST_PROMPT: &str = "Reply with ok set to true.";
+const SELF_TEST_SCHEMA: &str =
+    r#"{"type":"object","properties":{"ok":{"type":"boolean"}},"required":["ok"]}"#;
+
+/// One trivial structured-output run, checking the CLI is installed and authenticated
+pub async fn self_test(cli: &CliConfig, model: &str) -> Result<(), ErrorResponse> {
+    let cli = CliConfig {
+        max_retries: 0,
+        ..cli.clone()
+    };
+    run_claude_cli(&cli, SELF_TEST_PROMPT, SELF_TEST_SCHEMA, model)
+        .await
+        .map(|_| ())
 }
 
 /// Suggest a response from canned responses using Claude CLI.
 /// Prompts and schemas are now centralized in the frontend and must be provided.
 pub async fn suggest_response(
-    _bug: &serde_json::Value,
+    cli: &CliConfig,
+    bug: &serde_json::Value,
     _canned_responses: &[serde_json::Value],
     model: &str,
     frontend_prompt: Option<&str>,
     frontend_schema: Option<&str>,
 ) -> Result<Json<SuggestResponse>, ErrorResponse> {
-    // Require frontend to provide prompt and schema (centralized prompts)
-    let prompt = frontend_prompt.ok_or_else(|| ErrorResponse {
-        error: "Missing prompt from frontend".to_string(),
-        details: Some("Prompts are centralized in frontend/src/prompts.js".to_string()),
-    })?;
-    let schema = frontend_schema.ok_or_else(|| ErrorResponse {
-        error: "Missing schema from frontend".to_string(),
-        details: Some("Schemas are centralized in frontend/src/prompts.js".to_string()),
-    })?;
-    let result = run_claude_cli(&prompt, schema, model).await?;
-
-    let response = SuggestResponse {
-        suggested_response_id: result
-            .get("suggested_response_id")
-            .and_then(|v| v.as_str())
-            .unwrap_or("")
-            .to_string(),
-        draft_response: result
-            .get("draft_response")
-            .and_then(|v| v.as_str())
-            .unwrap_or("")
-            .to_string(),
-        reasoning: result
-            .get("reasoning")
-            .and_then(|v| v.as_str())
-            .map(|s| s.to_string()),
-    };
+    let (prompt, schema) =
+        prompt_and_schema(cli, "suggest-response", bug, frontend_prompt, frontend_schema)?;
+    let result = run_claude_cli(cli, &prompt, &schema, model).await?;
 
-    Ok(Json(response))
+    Ok(Json(parse::parse_suggest(&Fields::new(&result).cleaning(cli.clean_strings))))
 }
 
 /// Generate a triage response or action suggestions using Claude CLI.
 /// Prompts and schemas are now centralized in the frontend and must be provided.
 pub async fn generate_response(
-    _bug: &serde_json::Value,
+    cli: &CliConfig,
+    bug: &serde_json::Value,
     _options: &serde_json::Value,
     model: &str,
     frontend_prompt: Option<&str>,
     frontend_schema: Option<&str>,
 ) -> Result<Json<GenerateResponse>, ErrorResponse> {
-    // Require frontend to provide prompt and schema (centralized prompts)
-    let prompt = frontend_prompt.ok_or_else(|| ErrorResponse {
-        error: "Missing prompt from frontend".to_string(),
-        details: Some("Prompts are centralized in frontend/src/prompts.js".to_string()),
-    })?;
-    let schema = frontend_schema.ok_or_else(|| ErrorResponse {
-        error: "Missing schema from frontend".to_string(),
-        details: Some("Schemas are centralized in frontend/src/prompts.js".to_string()),
-    })?;
-    let result = run_claude_cli(&prompt, schema, model).await?;
-
-    // Parse suggested_actions array
-    let suggested_actions = result
-        .get("suggested_actions")
-        .and_then(|v| v.as_array())
-        .map(|arr| {
-            arr.iter()
-                .filter_map(|item| {
-                    let action = item.get("action").and_then(|a| a.as_str())?;
-                    Some(SuggestedAction {
-                        action: action.to_string(),
-                        reason: item.get("reason").and_then(|r| r.as_str()).map(|s| s.to_string()),
-                    })
-                })
-                .collect()
-        })
-        .unwrap_or_default();
-
-    // Parse used_canned_ids array
-    let used_canned_ids = result
-        .get("used_canned_ids")
-        .and_then(|v| v.as_array())
-        .map(|arr| {
-            arr.iter()
-                .filter_map(|item| item.as_str().map(|s| s.to_string()))
-                .collect()
-        })
-        .unwrap_or_default();
-
-    let response = GenerateResponse {
-        response_text: result
-            .get("response_text")
-            .and_then(|v| v.as_str())
-            .unwrap_or("")
-            .to_string(),
-        suggested_actions,
-        used_canned_ids,
-        reasoning: result
-            .get("reasoning")
-            .and_then(|v| v.as_str())
-            .unwrap_or("")
-            .to_string(),
-    };
+    let (prompt, schema) =
+        prompt_and_schema(cli, "generate", bug, frontend_prompt, frontend_schema)?;
+    let result = run_claude_cli(cli, &prompt, &schema, model).await?;
 
-    Ok(Json(response))
+    Ok(Json(parse::parse_generate(&Fields::new(&result).cleaning(cli.clean_strings))))
 }
 
 /// Refine a response based on user instructions via Claude CLI.
-/// Prompts and schemas are now centralized in the frontend and must be provided.
+/// Prompts and schemas are now centralized in the frontend and must be provided; the
+/// instruction, current response and context reach the model through the prompt.
 pub async fn refine_response(
-    _bug: &serde_json::Value,
-    current_response: &str,
-    _user_instruction: &str,
-    _context: &serde_json::Value,
+    cli: &CliConfig,
+    bug: &serde_json::Value,
     model: &str,
     frontend_prompt: Option<&str>,
     frontend_schema: Option<&str>,
 ) -> Result<Json<RefineResponse>, ErrorResponse> {
-    // Require frontend to provide prompt and schema (centralized prompts)
-    let prompt = frontend_prompt.ok_or_else(|| ErrorResponse {
-        error: "Missing prompt from frontend".to_string(),
-        details: Some("Prompts are centralized in frontend/src/prompts.js".to_string()),
-    })?;
-    let schema = frontend_schema.ok_or_else(|| ErrorResponse {
-        error: "Missing schema from frontend".to_string(),
-        details: Some("Schemas are centralized in frontend/src/prompts.js".to_string()),
-    })?;
-    let result = run_claude_cli(&prompt, schema, model).await?;
-
-    // Parse changes_made array
-    let changes_made = result
-        .get("changes_made")
-        .and_then(|v| v.as_array())
-        .map(|arr| {
-            arr.iter()
-                .filter_map(|item| item.as_str().map(|s| s.to_string()))
-                .collect()
-        })
-        .unwrap_or_default();
-
-    let response = RefineResponse {
-        refined_response: result
-            .get("refined_response")
-            .and_then(|v| v.as_str())
-            .unwrap_or(current_response)
-            .to_string(),
-        changes_made,
-    };
+    let (prompt, schema) =
+        prompt_and_schema(cli, "refine", bug, frontend_prompt, frontend_schema)?;
+    let result = run_claude_cli(cli, &prompt, &schema, model).await?;
 
-    Ok(Json(response))
+    parse::parse_refine(&Fields::new(&result).cleaning(cli.clean_strings)).map(Json)
 }
 
 /// Generate a test page from a bug report using Claude CLI.
 /// Prompts and schemas are now centralized in the frontend and must be provided.
 pub async fn generate_testpage(
-    _bug: &serde_json::Value,
+    cli: &CliConfig,
+    bug: &serde_json::Value,
     model: &str,
     frontend_prompt: Option<&str>,
     frontend_schema: Option<&str>,
 ) -> Result<Json<TestPageResponse>, ErrorResponse> {
-    // Require frontend to provide prompt and schema (centralized prompts)
-    let prompt = frontend_prompt.ok_or_else(|| ErrorResponse {
-        error: "Missing prompt from frontend".to_string(),
-        details: Some("Prompts are centralized in frontend/src/prompts.js".to_string()),
-    })?;
-    let schema = frontend_schema.ok_or_else(|| ErrorResponse {
-        error: "Missing schema from frontend".to_string(),
-        details: Some("Schemas are centralized in frontend/src/prompts.js".to_string()),
-    })?;
-    let result = run_claude_cli(&prompt, schema, model).await?;
-
-    let response = TestPageResponse {
-        can_generate: result
-            .get("can_generate")
-            .and_then(|v| v.as_bool())
-            .unwrap_or(false),
-        html_content: result
-            .get("html_content")
-            .and_then(|v| v.as_str())
-            .unwrap_or("")
-            .to_string(),
-        reason: result
-            .get("reason")
-            .and_then(|v| v.as_str())
-            .unwrap_or("")
-            .to_string(),
-    };
+    let (prompt, schema) =
+        prompt_and_schema(cli, "testpage", bug, frontend_prompt, frontend_schema)?;
+    let result = run_claude_cli(cli, &prompt, &schema, model).await?;
 
-    Ok(Json(response))
+    Ok(Json(parse::parse_testpage(&Fields::new(&result).cleaning(cli.clean_strings))))
+}
+
+/// Explain a prior classification in depth using Claude CLI.
+/// Prompts and schemas are now centralized in the frontend and must be provided.
+pub async fn explain_classification(
+    cli: &CliConfig,
+    bug: &serde_json::Value,
+    _classification: &ClassifyResponse,
+    model: &str,
+    frontend_prompt: Option<&str>,
+    frontend_schema: Option<&str>,
+) -> Result<Json<ExplainResponse>, ErrorResponse> {
+    let (prompt, schema) =
+        prompt_and_schema(cli, "explain", bug, frontend_prompt, frontend_schema)?;
+    let result = run_claude_cli(cli, &prompt, &schema, model).await?;
+
+    Ok(Json(parse::parse_explain(&Fields::new(&result).cleaning(cli.clean_strings))))
+}
+
+/// Summarize a bug's comment thread using Claude Code CLI
+pub async fn summarize_comments(
+    cli: &CliConfig,
+    bug: &serde_json::Value,
+    model: &str,
+    frontend_prompt: Option<&str>,
+    frontend_schema: Option<&str>,
+) -> Result<Json<SummarizeCommentsResponse>, ErrorResponse> {
+    let (prompt, schema) =
+        prompt_and_schema(cli, "summarize-comments", bug, frontend_prompt, frontend_schema)?;
+    let result = run_claude_cli(cli, &prompt, &schema, model).await?;
+
+    Ok(Json(parse::parse_summarize_comments(&Fields::new(&result).cleaning(cli.clean_strings))))
+}
+
+/// Suggest a replacement for the bug's summary using Claude Code CLI
+pub async fn rewrite_summary(
+    cli: &CliConfig,
+    bug: &serde_json::Value,
+    model: &str,
+    frontend_prompt: Option<&str>,
+    frontend_schema: Option<&str>,
+) -> Result<Json<RewriteSummaryResponse>, ErrorResponse> {
+    let (prompt, schema) =
+        prompt_and_schema(cli, "rewrite-summary", bug, frontend_prompt, frontend_schema)?;
+    let result = run_claude_cli(cli, &prompt, &schema, model).await?;
+
+    Ok(Json(parse::parse_rewrite_summary(&Fields::new(&result).cleaning(cli.clean_strings))?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    /// A pipe that hands out its chunks one read at a time
+    struct Chunks(VecDeque<&'static [u8]>);
+
+    impl tokio::io::AsyncRead for Chunks {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            if let Some(chunk) = self.0.pop_front() {
+                buf.put_slice(chunk);
+            }
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn json_lines_are_reassembled_across_reads() {
+        let bytes: &'static [u8] = concat!(
+            "{\"type\":\"system\"}\n",
+            "{\"text\":\"caf\u{e9} \u{1F600}\"}\n",
+            "not json\n\n",
+            "{\"n\":1}\n{\"n\":2}",
+        )
+        .as_bytes();
+        // Split mid-object, mid-character, right after a newline, and with several lines
+        // (and a final line without its newline) in one read
+        let cuts = [3, 18, 31, 35, 40];
+        let mut chunks = VecDeque::new();
+        let mut start = 0;
+        for cut in cuts.into_iter().chain([bytes.len()]) {
+            chunks.push_back(&bytes[start..cut]);
+            start = cut;
+        }
+
+        let mut lines = JsonLines::new(Chunks(chunks));
+        let mut values = Vec::new();
+        while let Some(value) = lines.next().await.unwrap() {
+            values.push(value);
+        }
+        assert_eq!(
+            values,
+            vec![
+                serde_json::json!({ "type": "system" }),
+                serde_json::json!({ "text": "caf\u{e9} \u{1F600}" }),
+                serde_json::json!({ "n": 1 }),
+                serde_json::json!({ "n": 2 }),
+            ]
+        );
+    }
+
+    #[test]
+    fn schema_override_replaces_the_request_schema() {
+        let cli = CliConfig {
+            schema_overrides: Arc::new(BTreeMap::from([(
+                "classify".to_string(),
+                r#"{"type":"object"}"#.to_string(),
+            )])),
+            ..Default::default()
+        };
+        let bug = serde_json::Value::Null;
+        let (_, schema) = prompt_and_schema(&cli, "classify", &bug, Some("p"), Some("{}")).unwrap();
+        assert_eq!(schema, r#"{"type":"object"}"#);
+        let (_, schema) = prompt_and_schema(&cli, "generate", &bug, Some("p"), Some("{}")).unwrap();
+        assert_eq!(schema, "{}");
+    }
+
+    #[test]
+    fn template_is_used_when_the_request_has_no_prompt() {
+        let mut templates = templates::Templates::default();
+        templates
+            .add("classify", templates::Syntax::Handlebars, "Classify: {{bug.summary}}")
+            .unwrap();
+        templates.add("explain", templates::Syntax::Tera, "{{ bug.missing }}").unwrap();
+        let cli = CliConfig {
+            prompt_templates: Arc::new(templates),
+            ..Default::default()
+        };
+        let bug = serde_json::json!({ "id": 1, "summary": "Crash" });
+        let (prompt, _) = prompt_and_schema(&cli, "classify", &bug, None, Some("{}")).unwrap();
+        assert_eq!(prompt, "Classify: Crash");
+        let (prompt, _) = prompt_and_schema(&cli, "classify", &bug, Some("p"), Some("{}")).unwrap();
+        assert_eq!(prompt, "p");
+        assert!(prompt_and_schema(&cli, "generate", &bug, None, Some("{}")).is_err());
+        let e = prompt_and_schema(&cli, "explain", &bug, None, Some("{}")).unwrap_err();
+        assert_eq!(e.error, "Failed to render the prompt template");
+    }
+
+    #[test]
+    fn code_fences_are_stripped() {
+        assert_eq!(strip_code_fences("```json\n{\"a\": 1}\n```\n"), "{\"a\": 1}");
+        assert_eq!(strip_code_fences("```\n{\"a\": 1}\n```"), "{\"a\": 1}");
+        assert_eq!(strip_code_fences("  {\"a\": 1}  "), "{\"a\": 1}");
+        // Only an opening or only a closing fence
+        assert_eq!(strip_code_fences("```json\n{\"a\": 1}"), "{\"a\": 1}");
+        assert_eq!(strip_code_fences("{\"a\": 1}\n```"), "{\"a\": 1}");
+        assert_eq!(strip_code_fences("```json{\"a\": 1}```"), "{\"a\": 1}");
+    }
 }
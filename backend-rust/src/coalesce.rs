@@ -0,0 +1,128 @@
+//! Latest-wins coalescing of in-flight requests
+//!
+//! Refine is often triggered several times in quick succession while the user is still
+//! editing the instruction, and only the last result is wanted. Requests that carry the
+//! same key run through [`Coalescer::run_latest`]: starting a new one cancels the one
+//! already in flight for that key. Cancelling drops its future, which drops the CLI
+//! child guard and kills the process.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use axum::http::StatusCode;
+use tokio::sync::oneshot;
+use tracing::info;
+
+use crate::ErrorResponse;
+
+#[derive(Debug, Default)]
+pub struct Coalescer {
+    /// Key -> (generation, cancel sender) of the request currently in flight
+    inflight: Mutex<HashMap<String, (u64, oneshot::Sender<()>)>>,
+    next_generation: AtomicU64,
+}
+
+/// Removes its entry on drop, unless a newer request has replaced it
+struct InflightGuard<'a> {
+    coalescer: &'a Coalescer,
+    key: &'a str,
+    generation: u64,
+}
+
+impl Drop for InflightGuard<'_> {
+    fn drop(&mut self) {
+        let mut inflight = self.coalescer.inflight.lock().unwrap();
+        if inflight.get(self.key).is_some_and(|(gen, _)| *gen == self.generation) {
+            inflight.remove(self.key);
+        }
+    }
+}
+
+impl Coalescer {
+    /// Run `fut`, cancelling any earlier request for `key` that is still running. If a
+    /// later request for `key` arrives first, this one fails with 409 `SUPERSEDED`.
+    pub async fn run_latest<T>(
+        self: &Arc<Self>,
+        key: &str,
+        fut: impl Future<Output = Result<T, ErrorResponse>>,
+    ) -> Result<T, ErrorResponse> {
+        let generation = self.next_generation.fetch_add(1, Ordering::Relaxed);
+        let (cancel_tx, cancel_rx) = oneshot::channel();
+        let previous = self
+            .inflight
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), (generation, cancel_tx));
+        if let Some((_, previous)) = previous {
+            info!("Cancelling superseded request for session {}", key);
+            let _ = previous.send(());
+        }
+        let _guard = InflightGuard {
+            coalescer: self,
+            key,
+            generation,
+        };
+
+        tokio::select! {
+            result = fut => result,
+            _ = cancel_rx => Err(ErrorResponse {
+                error: "Superseded by a newer request for the same session".to_string(),
+                details: Some(format!("Session: {}", key)),
+                code: Some("SUPERSEDED"),
+                status: StatusCode::CONFLICT,
+            }),
+        }
+    }
+
+    /// Number of keys with a request in flight
+    pub fn len(&self) -> usize {
+        self.inflight.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn newer_request_cancels_older() {
+        let coalescer = Arc::new(Coalescer::default());
+        let slow = {
+            let coalescer = Arc::clone(&coalescer);
+            tokio::spawn(async move {
+                coalescer
+                    .run_latest("s1", async {
+                        tokio::time::sleep(Duration::from_secs(30)).await;
+                        Ok::<_, ErrorResponse>("stale")
+                    })
+                    .await
+            })
+        };
+        // Let the first request register before the second arrives
+        while coalescer.len() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let latest = coalescer.run_latest("s1", async { Ok("latest") }).await;
+        assert_eq!(latest.unwrap(), "latest");
+
+        let stale = slow.await.unwrap().unwrap_err();
+        assert_eq!(stale.code, Some("SUPERSEDED"));
+        assert_eq!(stale.status, StatusCode::CONFLICT);
+        assert_eq!(coalescer.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn different_keys_do_not_interfere() {
+        let coalescer = Arc::new(Coalescer::default());
+        let (a, b) = tokio::join!(
+            coalescer.run_latest("a", async { Ok::<_, ErrorResponse>(1) }),
+            coalescer.run_latest("b", async { Ok::<_, ErrorResponse>(2) }),
+        );
+        assert_eq!((a.unwrap(), b.unwrap()), (1, 2));
+        assert_eq!(coalescer.len(), 0);
+    }
+}
@@ -0,0 +1,138 @@
+//! Free disk space guard for persistence
+//!
+//! The transcript is the backend's only disk writer. When the disk it is on fills up,
+//! requests must keep working, so instead of letting writes fail one by one a
+//! [`DiskGuard`] checks free space periodically (`DISK_CHECK_INTERVAL_SECS`) and pauses
+//! disk writes while it is below `MIN_FREE_DISK_BYTES`. The paused state is reported as
+//! `diskDegraded` in `/health`, and entering or leaving it is logged.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tracing::{error, info, warn};
+
+pub struct DiskGuard {
+    /// A path on the watched filesystem
+    path: PathBuf,
+    min_free_bytes: u64,
+    degraded: AtomicBool,
+}
+
+impl DiskGuard {
+    pub fn new(path: PathBuf, min_free_bytes: u64) -> Self {
+        DiskGuard {
+            path,
+            min_free_bytes,
+            degraded: AtomicBool::new(false),
+        }
+    }
+
+    /// Whether disk writes are paused for lack of space
+    pub fn is_degraded(&self) -> bool {
+        self.degraded.load(Ordering::Acquire)
+    }
+
+    /// Re-check free space and update the degraded state. A failed check leaves the
+    /// state as it was.
+    pub fn check(&self) {
+        match free_bytes(&self.path) {
+            Ok(free) => self.update(free),
+            Err(e) => warn!("Can't check free disk space at {}: {}", self.path.display(), e),
+        }
+    }
+
+    fn update(&self, free: u64) {
+        let low = free < self.min_free_bytes;
+        if self.degraded.swap(low, Ordering::AcqRel) == low {
+            return;
+        }
+        if low {
+            error!(
+                "Free disk space at {} is {} bytes, below MIN_FREE_DISK_BYTES ({}); \
+                 pausing transcript writes",
+                self.path.display(),
+                free,
+                self.min_free_bytes
+            );
+        } else {
+            info!(
+                "Free disk space at {} is back to {} bytes; resuming transcript writes",
+                self.path.display(),
+                free
+            );
+        }
+    }
+
+    /// Pause writes right away, e.g. after a write failed because the disk is full. The
+    /// next check resumes them once there is space again.
+    pub fn trip(&self) {
+        if !self.degraded.swap(true, Ordering::AcqRel) {
+            error!("Disk at {} is full; pausing transcript writes", self.path.display());
+        }
+    }
+
+    /// Check now, then every `interval` for as long as the server runs
+    pub fn spawn_monitor(self: &Arc<Self>, interval: Duration) {
+        self.check();
+        let guard = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            ticks.tick().await;
+            loop {
+                ticks.tick().await;
+                guard.check();
+            }
+        });
+    }
+}
+
+/// Bytes available to unprivileged writers on the filesystem holding `path`
+#[cfg(unix)]
+// The statvfs field types differ between platforms
+#[allow(clippy::unnecessary_cast)]
+pub fn free_bytes(path: &Path) -> io::Result<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).map_err(io::Error::other)?;
+    // SAFETY: statvfs is plain data, and `path` is a valid NUL-terminated string
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+pub fn free_bytes(_path: &Path) -> io::Result<u64> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "free space check needs unix"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn free_space_is_reported() {
+        assert!(free_bytes(&std::env::temp_dir()).unwrap() > 0);
+        assert!(free_bytes(Path::new("/nonexistent/dir")).is_err());
+    }
+
+    #[test]
+    fn low_space_degrades_until_it_recovers() {
+        let guard = DiskGuard::new(std::env::temp_dir(), 1000);
+        guard.update(5000);
+        assert!(!guard.is_degraded());
+        guard.update(999);
+        assert!(guard.is_degraded());
+        guard.update(1000);
+        assert!(!guard.is_degraded());
+
+        guard.trip();
+        assert!(guard.is_degraded());
+        guard.update(5000);
+        assert!(!guard.is_degraded());
+    }
+}
@@ -0,0 +1,220 @@
+//! Ensemble classification
+//!
+//! With `ensemble: N` a classify request runs the same prompt N times and merges the
+//! results: each boolean field and the severity/priority get the value most runs agree
+//! on, and the response reports, per field, the share of runs that agreed. The other
+//! fields (summary, actions, drafts) come from the run agreeing with the consensus on
+//! the most fields. A borderline bug thus gets a steadier answer, at N times the cost.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::ClassifyResponse;
+
+/// Most runs one ensemble request may ask for; larger values are capped
+pub const MAX_RUNS: usize = 5;
+
+/// How an ensemble result was reached, sent as `ensemble` on the classification
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ensemble {
+    /// Runs whose results were merged
+    pub runs: usize,
+    /// Runs that failed and were left out of the vote
+    pub failed: usize,
+    /// Share of the merged runs agreeing with the consensus, per voted field
+    pub agreement: BTreeMap<String, f32>,
+}
+
+/// The most common of `values`, ties going to the one seen first, and its share of them
+fn majority<T: PartialEq + Clone>(values: &[T]) -> (T, f32) {
+    let mut best: Option<(&T, usize)> = None;
+    for value in values {
+        let count = values.iter().filter(|v| *v == value).count();
+        if best.is_none_or(|(_, most)| count > most) {
+            best = Some((value, count));
+        }
+    }
+    let (value, count) = best.expect("an ensemble has at least one result");
+    (value.clone(), count as f32 / values.len() as f32)
+}
+
+/// The voted fields of a classification, in the order of [`VOTED_FIELDS`]
+#[derive(Clone, PartialEq)]
+struct Votes {
+    flags: [bool; 4],
+    severity: Option<String>,
+    priority: Option<String>,
+}
+
+const VOTED_FIELDS: [&str; 6] = [
+    "ai_detected_str",
+    "ai_detected_test_attached",
+    "crashstack_present",
+    "fuzzing_testcase",
+    "suggested_severity",
+    "suggested_priority",
+];
+
+impl Votes {
+    fn of(response: &ClassifyResponse) -> Self {
+        Votes {
+            flags: [
+                response.ai_detected_str,
+                response.ai_detected_test_attached,
+                response.crashstack_present,
+                response.fuzzing_testcase,
+            ],
+            severity: response.suggested_severity.clone(),
+            priority: response.suggested_priority.clone(),
+        }
+    }
+
+    /// Fields on which `self` and `other` agree
+    fn agreeing(&self, other: &Votes) -> usize {
+        let flags = self.flags.iter().zip(&other.flags).filter(|(a, b)| a == b).count();
+        let severity = self.severity == other.severity;
+        let priority = self.priority == other.priority;
+        flags + usize::from(severity) + usize::from(priority)
+    }
+}
+
+/// Merge the results of an ensemble's successful runs (at least one) by majority vote;
+/// `failed` runs are only counted
+pub fn merge(mut results: Vec<ClassifyResponse>, failed: usize) -> ClassifyResponse {
+    let votes: Vec<Votes> = results.iter().map(Votes::of).collect();
+    let mut shares = Vec::new();
+    let mut flags = [false; 4];
+    for (i, flag) in flags.iter_mut().enumerate() {
+        let (value, share) = majority(&votes.iter().map(|v| v.flags[i]).collect::<Vec<_>>());
+        *flag = value;
+        shares.push(share);
+    }
+    let severities: Vec<_> = votes.iter().map(|v| v.severity.clone()).collect();
+    let (severity, share) = majority(&severities);
+    shares.push(share);
+    let priorities: Vec<_> = votes.iter().map(|v| v.priority.clone()).collect();
+    let (priority, share) = majority(&priorities);
+    shares.push(share);
+    let consensus = Votes {
+        flags,
+        severity,
+        priority,
+    };
+
+    // The first run closest to the consensus provides the fields that aren't voted on
+    let mut closest = 0;
+    for (i, vote) in votes.iter().enumerate() {
+        if vote.agreeing(&consensus) > votes[closest].agreeing(&consensus) {
+            closest = i;
+        }
+    }
+    let mut parse_warnings: Vec<String> = Vec::new();
+    for warning in results.iter().flat_map(|r| &r.parse_warnings) {
+        if !parse_warnings.contains(warning) {
+            parse_warnings.push(warning.clone());
+        }
+    }
+    let runs = results.len();
+    let mut merged = results.swap_remove(closest);
+
+    let Votes {
+        flags: [ai_detected_str, ai_detected_test_attached, crashstack_present, fuzzing_testcase],
+        severity,
+        priority,
+    } = consensus;
+    merged.ai_detected_str = ai_detected_str;
+    merged.ai_detected_test_attached = ai_detected_test_attached;
+    merged.crashstack_present = crashstack_present;
+    merged.fuzzing_testcase = fuzzing_testcase;
+    merged.suggested_severity = severity;
+    merged.suggested_priority = priority;
+    merged.parse_warnings = parse_warnings;
+    merged.ensemble = Some(Ensemble {
+        runs,
+        failed,
+        agreement: VOTED_FIELDS
+            .iter()
+            .map(|field| field.to_string())
+            .zip(shares)
+            .collect(),
+    });
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::{parse_classify, Fields};
+    use serde_json::json;
+
+    fn result(value: serde_json::Value) -> ClassifyResponse {
+        parse_classify(&Fields::new(&value), &Default::default())
+    }
+
+    fn vote(summary: &str, has_str: bool, severity: &str, priority: &str) -> ClassifyResponse {
+        result(json!({
+            "summary": summary,
+            "ai_detected_str": has_str,
+            "suggested_severity": severity,
+            "suggested_priority": priority,
+        }))
+    }
+
+    #[test]
+    fn majority_wins_each_field() {
+        let merged = merge(
+            vec![
+                vote("a", true, "S2", "P1"),
+                vote("b", true, "S3", "P2"),
+                vote("c", false, "S3", "P2"),
+            ],
+            1,
+        );
+        assert!(merged.ai_detected_str);
+        assert_eq!(merged.suggested_severity.as_deref(), Some("S3"));
+        assert_eq!(merged.suggested_priority.as_deref(), Some("P2"));
+        // Run "b" matches the consensus on every field
+        assert_eq!(merged.summary, "b");
+
+        let ensemble = merged.ensemble.unwrap();
+        assert_eq!((ensemble.runs, ensemble.failed), (3, 1));
+        let third = 1.0 / 3.0;
+        assert!((ensemble.agreement["ai_detected_str"] - 2.0 * third).abs() < 1e-6);
+        assert!((ensemble.agreement["suggested_severity"] - 2.0 * third).abs() < 1e-6);
+        assert_eq!(ensemble.agreement["crashstack_present"], 1.0);
+    }
+
+    #[test]
+    fn ties_go_to_the_earlier_run() {
+        let merged = merge(
+            vec![
+                result(json!({
+                    "summary": "a",
+                    "suggested_severity": "S2",
+                    "fuzzing_testcase": true,
+                })),
+                vote("b", false, "S4", "P3"),
+            ],
+            0,
+        );
+        assert_eq!(merged.suggested_severity.as_deref(), Some("S2"));
+        assert!(merged.fuzzing_testcase);
+        assert_eq!(merged.summary, "a");
+        assert_eq!(merged.ensemble.unwrap().agreement["fuzzing_testcase"], 0.5);
+    }
+
+    #[test]
+    fn missing_values_vote_too() {
+        let merged = merge(
+            vec![
+                result(json!({ "suggested_priority": "P1" })),
+                result(json!({})),
+                result(json!({ "suggested_priority": "bogus" })),
+            ],
+            0,
+        );
+        assert_eq!(merged.suggested_priority, None);
+        assert_eq!(merged.parse_warnings.len(), 1);
+    }
+}
@@ -0,0 +1,124 @@
+//! Provider availability for `/health`
+//!
+//! Working out which providers are available means running `claude --version` and
+//! checking the API keys, and whether the frontend can be served means opening its
+//! `index.html`, so `/health` keeps the answers for `HEALTH_CACHE_TTL_SECS`
+//! (default 10s). The frontend can then poll it without starting a subprocess each
+//! time; `?refresh=true` recomputes it regardless. Concurrent requests that find the
+//! cache stale share one recomputation.
+
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use tokio::sync::Mutex;
+
+/// What `/health` reports about the providers and the frontend
+#[derive(Debug, Clone, PartialEq)]
+pub struct Availability {
+    /// Providers that can serve requests, the recommended one first
+    pub providers: Vec<&'static str>,
+    /// `ok`, `error`, `version-unknown` or `not-found` from `claude --version`
+    pub claude_cli: &'static str,
+    /// Whether `frontend_index` is a file that could be opened
+    pub frontend_servable: bool,
+    /// The frontend's `index.html`, resolved to an absolute path when it exists
+    pub frontend_index: PathBuf,
+}
+
+/// Check that `FRONTEND_DIR` has a readable `index.html`. Without one the backend still
+/// answers the API, but `/` is a 404 - usually a wrong `FRONTEND_DIR` or working
+/// directory.
+pub async fn frontend_index(frontend_dir: &Path) -> (bool, PathBuf) {
+    let index = frontend_dir.join("index.html");
+    let index = tokio::fs::canonicalize(&index).await.unwrap_or(index);
+    let servable = match tokio::fs::File::open(&index).await {
+        Ok(file) => file.metadata().await.is_ok_and(|metadata| metadata.is_file()),
+        Err(_) => false,
+    };
+    (servable, index)
+}
+
+#[derive(Debug)]
+pub struct AvailabilityCache {
+    ttl: Duration,
+    last: Mutex<Option<(Instant, Availability)>>,
+}
+
+impl AvailabilityCache {
+    /// A cache keeping results for `ttl`; zero recomputes on every call
+    pub fn new(ttl: Duration) -> Self {
+        AvailabilityCache {
+            ttl,
+            last: Mutex::default(),
+        }
+    }
+
+    /// The cached availability and its age, or a fresh one from `compute` (age zero) when
+    /// there is none, it has expired, or `refresh` is set
+    pub async fn get<F, Fut>(&self, refresh: bool, compute: F) -> (Availability, Duration)
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Availability>,
+    {
+        let mut last = self.last.lock().await;
+        if let Some((checked, availability)) = last.as_ref() {
+            if !refresh && checked.elapsed() < self.ttl {
+                return (availability.clone(), checked.elapsed());
+            }
+        }
+        let availability = compute().await;
+        *last = Some((Instant::now(), availability.clone()));
+        (availability, Duration::ZERO)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn availability_is_recomputed_after_the_ttl_or_on_refresh() {
+        let cache = AvailabilityCache::new(Duration::from_millis(50));
+        let computed = AtomicUsize::new(0);
+        let compute = || async {
+            computed.fetch_add(1, Ordering::SeqCst);
+            Availability {
+                providers: vec!["claude"],
+                claude_cli: "ok",
+                frontend_servable: false,
+                frontend_index: PathBuf::new(),
+            }
+        };
+
+        let (availability, age) = cache.get(false, compute).await;
+        assert_eq!(availability.providers, vec!["claude"]);
+        assert_eq!(age, Duration::ZERO);
+        cache.get(false, compute).await;
+        assert_eq!(computed.load(Ordering::SeqCst), 1);
+
+        cache.get(true, compute).await;
+        assert_eq!(computed.load(Ordering::SeqCst), 2);
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        cache.get(false, compute).await;
+        assert_eq!(computed.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn frontend_needs_an_index_file() {
+        let dir = std::env::temp_dir().join(format!("health-frontend-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("index.html")).unwrap();
+        // A directory named index.html can't be served
+        assert!(!frontend_index(&dir).await.0);
+        std::fs::remove_dir(dir.join("index.html")).unwrap();
+        assert_eq!(frontend_index(&dir).await, (false, dir.join("index.html")));
+
+        std::fs::write(dir.join("index.html"), "<!doctype html>").unwrap();
+        let (servable, index) = frontend_index(&dir).await;
+        assert!(servable);
+        assert!(index.is_absolute() && index.ends_with("index.html"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,228 @@
+//! Classification history in SQLite
+//!
+//! With `RESULT_DB_PATH` set, every successful classification of a bug with an id is
+//! stored with its provider, model and result, and `GET /api/history/{bugId}` lists a
+//! bug's past classifications, newest first, to compare re-triage over time.
+//!
+//! As with the transcript, inserts go through a bounded channel to one writer (here a
+//! thread, since SQLite calls block), so requests never wait on the database; records
+//! that don't fit in the queue are dropped and counted in
+//! `history_records_dropped_total`. Lookups open their own read connection.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rusqlite::{params, Connection, OpenFlags};
+use serde::Serialize;
+use tokio::sync::{mpsc, oneshot};
+use tracing::warn;
+
+use crate::metrics;
+
+/// Records that can wait for the writer before new ones are dropped
+const QUEUE_CAPACITY: usize = 1024;
+
+/// Most records inserted per transaction
+const MAX_BATCH: usize = 64;
+
+/// Most classifications returned for one bug
+pub const MAX_ENTRIES: usize = 100;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS classifications (
+        id INTEGER PRIMARY KEY,
+        bug_id INTEGER NOT NULL,
+        provider TEXT NOT NULL,
+        model TEXT NOT NULL,
+        result TEXT NOT NULL,
+        created_ms INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS classifications_by_bug ON classifications (bug_id, id);
+";
+
+/// One classification to store
+#[derive(Debug)]
+pub struct Record {
+    pub bug_id: u64,
+    pub provider: String,
+    pub model: String,
+    pub result: serde_json::Value,
+}
+
+/// One stored classification, as listed by `/api/history/{bugId}`
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Entry {
+    pub provider: String,
+    pub model: String,
+    pub result: serde_json::Value,
+    /// Unix time in seconds
+    pub timestamp: u64,
+    /// The same, as an HTTP date
+    pub time: String,
+}
+
+enum Message {
+    Record(Record, SystemTime),
+    /// Answered once everything queued before it is committed
+    Flush(oneshot::Sender<()>),
+}
+
+pub struct History {
+    path: PathBuf,
+    queue: mpsc::Sender<Message>,
+}
+
+impl History {
+    /// Open (creating if needed) the database at `path` and start its writer
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let connection = (|| {
+            let connection = Connection::open(path)?;
+            connection.pragma_update(None, "journal_mode", "WAL")?;
+            connection.busy_timeout(Duration::from_secs(5))?;
+            connection.execute_batch(SCHEMA)?;
+            Ok::<_, rusqlite::Error>(connection)
+        })()
+        .map_err(|e| e.to_string())?;
+        let (queue, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        std::thread::Builder::new()
+            .name("history-writer".to_string())
+            .spawn(move || write_records(connection, receiver))
+            .map_err(|e| e.to_string())?;
+        Ok(History {
+            path: path.to_path_buf(),
+            queue,
+        })
+    }
+
+    /// Queue `record` to be stored. Never blocks: a record that doesn't fit in the
+    /// queue is dropped and counted.
+    pub fn record(&self, record: Record) {
+        if self
+            .queue
+            .try_send(Message::Record(record, SystemTime::now()))
+            .is_err()
+        {
+            metrics::inc("history_records_dropped_total");
+            warn!("History queue full or writer stopped, classification not stored");
+        }
+    }
+
+    /// Wait until every record queued so far has been stored
+    pub async fn flush(&self) {
+        let (done, stored) = oneshot::channel();
+        if self.queue.send(Message::Flush(done)).await.is_ok() {
+            let _ = stored.await;
+        }
+    }
+
+    /// The latest [`MAX_ENTRIES`] classifications of `bug_id`, newest first
+    pub async fn for_bug(&self, bug_id: u64) -> Result<Vec<Entry>, String> {
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || {
+            let connection = Connection::open_with_flags(
+                path,
+                OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            )?;
+            connection.busy_timeout(Duration::from_secs(5))?;
+            let mut query = connection.prepare(
+                "SELECT provider, model, result, created_ms FROM classifications
+                 WHERE bug_id = ?1 ORDER BY id DESC LIMIT ?2",
+            )?;
+            let entries = query.query_map(params![bug_id as i64, MAX_ENTRIES as i64], |row| {
+                let result: String = row.get(2)?;
+                let created = UNIX_EPOCH + Duration::from_millis(row.get::<_, i64>(3)? as u64);
+                Ok(Entry {
+                    provider: row.get(0)?,
+                    model: row.get(1)?,
+                    result: serde_json::from_str(&result).unwrap_or(result.into()),
+                    timestamp: created.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+                    time: httpdate::fmt_http_date(created),
+                })
+            })?;
+            entries.collect::<rusqlite::Result<Vec<Entry>>>()
+        })
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+    }
+}
+
+/// Writer thread: inserts queued records in batches until every [`History`] is dropped.
+/// Failures are logged, never surfaced to the request.
+fn write_records(mut connection: Connection, mut receiver: mpsc::Receiver<Message>) {
+    let mut batch = Vec::with_capacity(MAX_BATCH);
+    while receiver.blocking_recv_many(&mut batch, MAX_BATCH) > 0 {
+        let mut flushed = Vec::new();
+        let inserted = (|| {
+            let transaction = connection.transaction()?;
+            for message in batch.drain(..) {
+                match message {
+                    Message::Record(record, created) => {
+                        let created_ms =
+                            created.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+                        transaction.execute(
+                            "INSERT INTO classifications
+                             (bug_id, provider, model, result, created_ms)
+                             VALUES (?1, ?2, ?3, ?4, ?5)",
+                            params![
+                                record.bug_id as i64,
+                                record.provider,
+                                record.model,
+                                record.result.to_string(),
+                                created_ms as i64,
+                            ],
+                        )?;
+                    }
+                    Message::Flush(done) => flushed.push(done),
+                }
+            }
+            transaction.commit()
+        })();
+        if let Err(e) = inserted {
+            warn!("Failed to store classification history: {}", e);
+        }
+        for done in flushed {
+            let _ = done.send(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(bug_id: u64, model: &str) -> Record {
+        Record {
+            bug_id,
+            provider: "claude".to_string(),
+            model: model.to_string(),
+            result: serde_json::json!({ "summary": model }),
+        }
+    }
+
+    #[tokio::test]
+    async fn classifications_are_listed_per_bug_newest_first() {
+        let path = std::env::temp_dir().join(format!("history-{}.db", uuid::Uuid::new_v4()));
+        let history = History::open(&path).unwrap();
+        history.record(record(1, "sonnet"));
+        history.record(record(2, "haiku"));
+        history.record(record(1, "opus"));
+        history.flush().await;
+
+        let entries = history.for_bug(1).await.unwrap();
+        let models: Vec<&str> = entries.iter().map(|e| e.model.as_str()).collect();
+        assert_eq!(models, ["opus", "sonnet"]);
+        assert_eq!(entries[0].result["summary"], "opus");
+        assert!(entries[0].timestamp > 0);
+        assert!(history.for_bug(3).await.unwrap().is_empty());
+
+        // Stored across reopening
+        drop(history);
+        let history = History::open(&path).unwrap();
+        assert_eq!(history.for_bug(2).await.unwrap().len(), 1);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+}
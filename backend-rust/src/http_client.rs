@@ -0,0 +1,219 @@
+//! Shared outbound HTTP client
+//!
+//! One `reqwest::Client` is built at startup and shared through `AppState` by every
+//! upstream call (Bugzilla today, HTTP providers later). reqwest has no timeout by
+//! default, so a hung upstream would hold a task forever; everything here is bounded.
+//!
+//! Responses are requested gzip or brotli compressed (`Accept-Encoding`) and decompressed
+//! transparently. Bodies are read through [`read_json`], which caps the *decompressed*
+//! size, so a small compressed body can't expand into an unbounded allocation.
+
+use std::time::Duration;
+
+use axum::http::StatusCode;
+use futures_util::StreamExt;
+use serde::de::DeserializeOwned;
+use tracing::warn;
+
+use crate::ErrorResponse;
+
+/// Error code for an upstream request that hit the client timeout
+pub const UPSTREAM_TIMEOUT: &str = "UPSTREAM_TIMEOUT";
+
+/// Error code for an upstream response body larger than the cap
+pub const UPSTREAM_TOO_LARGE: &str = "UPSTREAM_TOO_LARGE";
+
+/// Largest upstream response body read into memory, after decompression
+pub const MAX_RESPONSE_BYTES: usize = 32 * 1024 * 1024;
+
+/// Outbound client settings
+#[derive(Debug, Clone)]
+pub struct HttpClientConfig {
+    /// Total time for a request, from connect until the body is read
+    pub timeout: Duration,
+    pub connect_timeout: Duration,
+    /// Idle connections kept open per upstream host
+    pub pool_max_idle_per_host: usize,
+}
+
+impl Default for HttpClientConfig {
+    fn default() -> Self {
+        HttpClientConfig {
+            timeout: Duration::from_secs(30),
+            connect_timeout: Duration::from_secs(10),
+            pool_max_idle_per_host: 8,
+        }
+    }
+}
+
+pub fn build(config: &HttpClientConfig) -> reqwest::Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .timeout(config.timeout)
+        .connect_timeout(config.connect_timeout)
+        .pool_max_idle_per_host(config.pool_max_idle_per_host)
+        .gzip(true)
+        .brotli(true)
+        .build()
+}
+
+/// Read `response`'s decompressed body, failing with 502 `UPSTREAM_TOO_LARGE` as soon as
+/// it exceeds `max_bytes`. `Content-Length` isn't trusted: it is the compressed size, if
+/// present at all.
+pub async fn read_body(
+    upstream: &str,
+    response: reqwest::Response,
+    max_bytes: usize,
+) -> Result<Vec<u8>, ErrorResponse> {
+    let mut body = Vec::new();
+    let mut chunks = response.bytes_stream();
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk.map_err(|e| request_failed(upstream, e))?;
+        if body.len() + chunk.len() > max_bytes {
+            warn!("{} response exceeded {} bytes, aborting", upstream, max_bytes);
+            return Err(ErrorResponse {
+                error: format!("{} response too large", upstream),
+                details: Some(format!("More than {} bytes after decompression", max_bytes)),
+                code: Some(UPSTREAM_TOO_LARGE),
+                status: StatusCode::BAD_GATEWAY,
+            });
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+/// [`read_body`] up to [`MAX_RESPONSE_BYTES`], parsed as JSON
+pub async fn read_json<T: DeserializeOwned>(
+    upstream: &str,
+    response: reqwest::Response,
+) -> Result<T, ErrorResponse> {
+    let body = read_body(upstream, response, MAX_RESPONSE_BYTES).await?;
+    serde_json::from_slice(&body).map_err(|e| {
+        warn!("{} returned invalid JSON: {}", upstream, e);
+        ErrorResponse {
+            error: format!("{} returned invalid JSON", upstream),
+            details: Some(e.to_string()),
+            status: StatusCode::BAD_GATEWAY,
+            ..Default::default()
+        }
+    })
+}
+
+/// Map a failed upstream request to an error: 504 `UPSTREAM_TIMEOUT` for timeouts,
+/// 502 otherwise. `upstream` names the service in the message, e.g. "Bugzilla".
+pub fn request_failed(upstream: &str, e: reqwest::Error) -> ErrorResponse {
+    warn!("{} request failed: {}", upstream, e);
+    if e.is_timeout() {
+        return ErrorResponse {
+            error: format!("{} request timed out", upstream),
+            details: Some(e.to_string()),
+            code: Some(UPSTREAM_TIMEOUT),
+            status: StatusCode::GATEWAY_TIMEOUT,
+        };
+    }
+    ErrorResponse {
+        error: format!("{} request failed", upstream),
+        details: Some(e.to_string()),
+        status: StatusCode::BAD_GATEWAY,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn slow_upstream_maps_to_gateway_timeout() {
+        // Accepts connections but never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                held.push(socket);
+            }
+        });
+
+        let client = build(&HttpClientConfig {
+            timeout: Duration::from_millis(200),
+            ..Default::default()
+        })
+        .unwrap();
+        let err = client
+            .get(format!("http://{}/", addr))
+            .send()
+            .await
+            .unwrap_err();
+
+        let response = request_failed("Test", err);
+        assert_eq!(response.status, StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(response.code, Some(UPSTREAM_TIMEOUT));
+    }
+
+    /// Serve one gzip-encoded response with `body` as its decompressed content
+    async fn gzip_upstream(body: Vec<u8>) -> std::net::SocketAddr {
+        use std::io::Write;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        encoder.write_all(&body).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; 4096];
+            let n = socket.read(&mut request).await.unwrap();
+            let request = String::from_utf8_lossy(&request[..n]).to_ascii_lowercase();
+            assert!(request.contains("accept-encoding: gzip"), "{}", request);
+            let head = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\n\
+                 content-encoding: gzip\r\ncontent-length: {}\r\n\r\n",
+                compressed.len()
+            );
+            socket.write_all(head.as_bytes()).await.unwrap();
+            socket.write_all(&compressed).await.unwrap();
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn gzip_responses_are_decompressed() {
+        let addr = gzip_upstream(br#"{"bugs":[{"id":1}]}"#.to_vec()).await;
+        let client = build(&HttpClientConfig::default()).unwrap();
+        let response = client.get(format!("http://{}/", addr)).send().await.unwrap();
+        let body: serde_json::Value = read_json("Test", response).await.unwrap();
+        assert_eq!(body, serde_json::json!({ "bugs": [{ "id": 1 }] }));
+    }
+
+    #[tokio::test]
+    async fn size_cap_applies_to_decompressed_body() {
+        // 1 MiB of zeros compresses to about 1 KiB
+        let addr = gzip_upstream(vec![b'0'; 1024 * 1024]).await;
+        let client = build(&HttpClientConfig::default()).unwrap();
+        let response = client.get(format!("http://{}/", addr)).send().await.unwrap();
+        let err = read_body("Test", response, 64 * 1024).await.unwrap_err();
+        assert_eq!(err.code, Some(UPSTREAM_TOO_LARGE));
+        assert_eq!(err.status, StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn connection_refused_maps_to_bad_gateway() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let client = build(&HttpClientConfig::default()).unwrap();
+        let err = client
+            .get(format!("http://{}/", addr))
+            .send()
+            .await
+            .unwrap_err();
+
+        let response = request_failed("Test", err);
+        assert_eq!(response.status, StatusCode::BAD_GATEWAY);
+        assert_eq!(response.code, None);
+    }
+}
@@ -4,20 +4,72 @@
 //! Prioritizes Claude Code CLI integration for Mozilla developers.
 
 use axum::{
-    extract::State,
-    http::{header, HeaderValue, Method, StatusCode},
-    response::{IntoResponse, Json},
-    routing::{get, post},
+    extract::{DefaultBodyLimit, Path, Query, State},
+    http::{header, HeaderName, HeaderValue, Method, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Json,
+    },
+    handler::Handler,
+    routing::{get, on, post, MethodFilter, MethodRouter},
     Router,
 };
+use futures_util::future::select_ok;
+use futures_util::StreamExt;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::conn::auto::Builder,
+    service::TowerToHyperService,
+};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 use tower_http::cors::{Any, CorsLayer};
+use tower_http::decompression::RequestDecompressionLayer;
 use tower_http::services::ServeDir;
 use tower_http::set_header::SetResponseHeaderLayer;
-use tracing::info;
-
+use tracing::{info, warn, Instrument};
+
+mod actions;
+mod active;
+mod anthropic;
+mod budget;
+mod bug_actions;
+mod bugzilla;
+mod capabilities;
+mod children;
 mod claude_cli;
+mod coalesce;
+mod disk;
+mod ensemble;
+mod health;
+mod history;
+mod http_client;
+mod metrics;
+mod middleware;
+mod mock;
+mod normalize;
+mod parse;
+mod partial_json;
+mod postprocess;
+mod prompt_limit;
+mod prompt_versions;
+mod providers;
+mod recent_errors;
+mod redact;
+mod render;
+mod required_fields;
+mod response_cache;
+mod schemas;
+mod templates;
+#[cfg(test)]
+mod tests;
+mod tokens;
+mod transcript;
 
 /// Application state shared across handlers
 #[derive(Clone)]
@@ -32,15 +84,217 @@ pub struct AppState {
     pub openai_api_key: Option<String>,
     /// Claude model to use
     pub claude_model: String,
+    /// Anthropic HTTP API settings (for api mode)
+    pub anthropic: anthropic::AnthropicConfig,
+    /// Claude CLI spawn settings
+    pub cli: claude_cli::CliConfig,
+    /// Per-provider time limits from `PROVIDER_TIMEOUTS` (`None` = unlimited); providers
+    /// not listed use the CLI default `cli.timeout`
+    pub provider_timeouts: HashMap<String, Option<Duration>>,
+    /// Most providers raced concurrently for `provider: "fastest"`
+    pub race_max_providers: usize,
+    /// Most candidates of one `/api/ai/classify-compare` run at once
+    pub compare_max_concurrency: usize,
+    /// Bugzilla proxy settings
+    pub bugzilla: bugzilla::BugzillaConfig,
+    /// Shared HTTP client for upstream requests
+    pub http_client: reqwest::Client,
+    /// Longest URI (path + query) accepted on the Bugzilla proxy routes
+    pub max_uri_bytes: usize,
+    /// Most recent error responses, shown on `/status`
+    pub recent_errors: Arc<recent_errors::RecentErrors>,
+    /// In-flight refines keyed by `refineSessionId`
+    pub refine_sessions: Arc<coalesce::Coalescer>,
+    /// In-flight `/api/*` requests, listed at `/admin/requests`
+    pub active_requests: Arc<active::ActiveRequests>,
+    /// Bearer token for `/admin/*`; the admin routes are disabled when unset
+    pub admin_token: Option<String>,
+    /// `POSTPROCESS_CMD` hook applied to AI results
+    pub postprocess: postprocess::PostprocessConfig,
+    /// JSONL transcript of AI requests, when `TRANSCRIPT_PATH` is set
+    pub transcript: Option<Arc<transcript::Transcript>>,
+    /// Free space check for the transcript's disk; `None` without a transcript or when
+    /// `MIN_FREE_DISK_BYTES` is 0
+    pub disk: Option<Arc<disk::DiskGuard>>,
+    /// Fields each AI endpoint's results must have (`REQUIRED_FIELDS_*`)
+    pub required_fields: Arc<BTreeMap<String, Vec<String>>>,
+    /// Stored classifications, when `RESULT_DB_PATH` is set
+    pub history: Option<Arc<history::History>>,
+    /// Cached classify responses, when `RESPONSE_CACHE_TTL_SECS` is set
+    pub response_cache: Option<Arc<response_cache::ResponseCache>>,
+    /// Permits for `MAX_CONCURRENT_REQUESTS`; unlimited when `None`
+    pub request_limit: Option<Arc<tokio::sync::Semaphore>>,
+    /// Permits for `MAX_CONCURRENT_HTTP_CALLS`, held while calling an HTTP provider API;
+    /// unlimited when `None`
+    pub http_call_limit: Option<Arc<tokio::sync::Semaphore>>,
+    /// Size of `http_call_limit`, for reporting its utilization
+    pub max_concurrent_http_calls: usize,
+    /// Largest request body accepted, counted after `Content-Encoding` decompression
+    pub max_body_bytes: usize,
+    /// Longest `prompt` an AI request may send (`MAX_PROMPT_BYTES`); 0 for no limit
+    pub max_prompt_bytes: usize,
+    /// Most attachments of a bug used for a test page (`TESTPAGE_MAX_ATTACHMENTS`)
+    pub testpage_max_attachments: usize,
+    /// Attachment data longer than this is left out of test pages
+    /// (`TESTPAGE_MAX_ATTACHMENT_BYTES`)
+    pub testpage_max_attachment_bytes: usize,
+    /// Server cap on a request's cost budget in USD (`MAX_COST_USD`)
+    pub max_cost_usd: Option<f32>,
+    /// Static headers added to every response (`RESPONSE_HEADERS`, `RESPONSE_HEADERS_FILE`)
+    pub response_headers: Vec<(HeaderName, HeaderValue)>,
+    /// `Content-Security-Policy` for HTML responses; `None` when disabled
+    pub content_security_policy: Option<HeaderValue>,
+    /// Last `promptVersion` per AI endpoint, for `/api/ai/prompt-version`
+    pub prompt_versions: Arc<prompt_versions::PromptVersions>,
+    /// Prefix every route is served under, e.g. `/triage`; empty for the root
+    pub base_path: String,
+    /// Fraction of requests whose full prompt and response are logged
+    pub log_sample_rate: f64,
+    /// Suggested actions stripped from classify and generate results (lowercase)
+    pub action_denylist: Vec<String>,
+    /// Expected priorities per severity; mismatched classifications get a parse warning
+    pub priority_convention: normalize::PriorityConvention,
+    /// Set once the startup CLI self-test passes (immediately when it is disabled);
+    /// `/health` answers 503 until then
+    pub ready: Arc<AtomicBool>,
+    /// Provider availability last reported by `/health` (`HEALTH_CACHE_TTL_SECS`)
+    pub provider_availability: Arc<health::AvailabilityCache>,
+    /// When the server started, for `/health`'s `uptimeSecs` and `startedAt`
+    pub started_at: SystemTime,
+    /// `FRONTEND_DIR`, checked by `/health` for an `index.html`
+    pub frontend_dir: PathBuf,
+}
+
+impl AppState {
+    /// `"cli"` when `provider` runs through the claude CLI, `"api"` for HTTP APIs
+    pub fn served_via(&self, provider: &str) -> &'static str {
+        if provider == "claude" && self.claude_mode == "cli" {
+            "cli"
+        } else {
+            "api"
+        }
+    }
+
+    /// Strip `ACTION_DENYLIST` actions from `actions`, noting how many in `warnings`
+    pub fn remove_denied_actions<T>(
+        &self,
+        actions: &mut Vec<T>,
+        action: impl Fn(&T) -> &str,
+        warnings: &mut Vec<String>,
+    ) {
+        let removed = actions::remove_denied(actions, &self.action_denylist, action);
+        if removed > 0 {
+            warn!("Removed {} suggested action(s) matching ACTION_DENYLIST", removed);
+            warnings.push(format!("{} suggested action(s) removed by ACTION_DENYLIST", removed));
+        }
+    }
+
+    /// The model `provider` uses when a request doesn't name one: `CLAUDE_MODEL` for
+    /// Claude, the frontend's defaults for the others
+    pub fn default_model(&self, provider: &str) -> String {
+        match provider {
+            "gemini" => DEFAULT_GEMINI_MODEL.to_string(),
+            "openai" => DEFAULT_OPENAI_MODEL.to_string(),
+            _ => self.claude_model.clone(),
+        }
+    }
+
+    /// The model a request for `provider` asked for, or the provider's default; an empty
+    /// `model` counts as none, as in [`AppState::model_or_default`]
+    pub fn model_for(&self, provider: &str, requested: Option<String>) -> String {
+        match requested {
+            Some(model) if !model.trim().is_empty() => model,
+            _ => self.default_model(provider),
+        }
+    }
+
+    /// The model a request asked for, or `CLAUDE_MODEL`. An empty `model` (e.g. from an
+    /// unset dropdown) counts as none rather than being passed on as `--model ""`.
+    pub fn model_or_default(&self, requested: Option<String>) -> String {
+        match requested {
+            Some(model) if model.trim().is_empty() => {
+                warn!("Empty model received, using the default {}", self.claude_model);
+                self.claude_model.clone()
+            }
+            Some(model) => model,
+            None => self.claude_model.clone(),
+        }
+    }
+
+    /// Wait for a `MAX_CONCURRENT_HTTP_CALLS` permit; hold it for the duration of the call
+    pub async fn http_call_permit(&self) -> Option<tokio::sync::OwnedSemaphorePermit> {
+        let limit = self.http_call_limit.as_ref()?;
+        Arc::clone(limit).acquire_owned().await.ok()
+    }
+
+    /// Time limit for one request to `provider`
+    pub fn provider_timeout(&self, provider: &str) -> Option<Duration> {
+        self.provider_timeouts
+            .get(provider)
+            .copied()
+            .unwrap_or(self.cli.timeout)
+    }
+
+    /// Time limit for one request to an HTTP provider: the request's `timeoutMs`, clamped
+    /// to `CLI_TIMEOUT_CAP_SECS`, else the provider's
+    pub fn request_timeout(&self, provider: &str, settings: &RequestSettings) -> Option<Duration> {
+        match settings.timeout_ms {
+            Some(ms) => Some(self.cli.capped_timeout(ms)),
+            None => self.provider_timeout(provider),
+        }
+    }
+
+    /// CLI settings for one request: the Claude provider timeout, then the request's
+    /// `maxRetries`/`timeoutMs` overrides and its `maxCostUsd` capped by `MAX_COST_USD`
+    pub fn cli_for_request(&self, settings: &RequestSettings) -> claude_cli::CliConfig {
+        claude_cli::CliConfig {
+            timeout: self.provider_timeout("claude"),
+            max_cost_usd: budget::effective(settings.max_cost_usd, self.max_cost_usd),
+            ..self.cli.clone()
+        }
+        .with_overrides(settings.max_retries, settings.timeout_ms)
+    }
+}
+
+/// Per-request settings accepted by every AI request body, flattened into each
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestSettings {
+    /// Sampling seed, logged per request; no current provider takes one
+    pub seed: Option<u64>,
+    /// Structured-output retries per model run (clamped to the server cap)
+    pub max_retries: Option<u32>,
+    /// CLI time limit per model run in ms (clamped to the server cap)
+    pub timeout_ms: Option<u64>,
+    /// Cost limit per request in USD (capped by `MAX_COST_USD`); an ensemble shares it
+    /// between its runs
+    pub max_cost_usd: Option<f32>,
+    /// `PROMPT_VERSION` of the frontend's `prompts.js`, for drift detection
+    pub prompt_version: Option<String>,
+    /// `false` leaves the reasoning field out of classify, suggest-response and generate
+    /// results and asks the model to skip it; the other endpoints have none
+    pub include_reasoning: Option<bool>,
 }
 
 /// Classification request from frontend
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ClassifyRequest {
+    #[serde(deserialize_with = "providers::deserialize")]
     pub provider: String,
     pub model: Option<String>,
+    #[serde(flatten)]
+    pub settings: RequestSettings,
     pub bug: serde_json::Value,
+    /// Canned responses offered to the model; when present, `suggested_canned_id` is
+    /// checked against their ids
+    pub canned_responses: Option<Vec<serde_json::Value>>,
+    /// Also return the draft rendered from markdown to sanitized HTML
+    #[serde(default)]
+    pub render_html: bool,
+    /// Classify this many times and merge the results by vote (capped at
+    /// `ensemble::MAX_RUNS`)
+    pub ensemble: Option<usize>,
     /// Optional pre-built prompt from frontend (for centralized prompts)
     pub prompt: Option<String>,
     /// Optional JSON schema for structured output
@@ -50,12 +304,15 @@ pub struct ClassifyRequest {
 /// Triage action recommendation
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct TriageAction {
+    /// Stable id derived from `action` + `reason`
+    #[serde(default)]
+    pub id: String,
     pub action: String,
     pub reason: String,
 }
 
 /// Classification response to frontend
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ClassifyResponse {
     pub ai_detected_str: bool,
     pub ai_detected_test_attached: bool,
@@ -76,16 +333,37 @@ pub struct ClassifyResponse {
     pub draft_response: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub notes: Option<serde_json::Value>,
+    /// Model's confidence in each classified field, from 0 to 1, when it reported any
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub confidence: Option<HashMap<String, f32>>,
+    /// Provider that produced this result (resolved when `provider` is `"fastest"`)
+    #[serde(rename = "usedProvider", skip_serializing_if = "Option::is_none", default)]
+    pub used_provider: Option<String>,
+    /// `draft_response` as sanitized HTML, when the request set `renderHtml`
+    #[serde(rename = "draftResponseHtml", skip_serializing_if = "Option::is_none", default)]
+    pub draft_response_html: Option<String>,
+    /// Problems found while parsing the model output, e.g. an unrecognized severity
+    #[serde(rename = "parseWarnings", skip_serializing_if = "Vec::is_empty", default)]
+    pub parse_warnings: Vec<String>,
+    /// Runs and per-field agreement, when the request set `ensemble`
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub ensemble: Option<ensemble::Ensemble>,
 }
 
 /// Suggest response request
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SuggestRequest {
+    #[serde(deserialize_with = "providers::deserialize")]
     pub provider: String,
     pub model: Option<String>,
+    #[serde(flatten)]
+    pub settings: RequestSettings,
     pub bug: serde_json::Value,
     pub canned_responses: Vec<serde_json::Value>,
+    /// Also return the draft rendered from markdown to sanitized HTML
+    #[serde(default)]
+    pub render_html: bool,
     /// Optional pre-built prompt from frontend (for centralized prompts)
     pub prompt: Option<String>,
     /// Optional JSON schema for structured output
@@ -95,31 +373,62 @@ pub struct SuggestRequest {
 /// Suggest response result
 #[derive(Debug, Serialize)]
 pub struct SuggestResponse {
-    pub suggested_response_id: String,
+    /// `None` when the model returned no id, or set `no_match`
+    pub suggested_response_id: Option<String>,
+    /// The model found no fitting canned response; `draft_response` is freeform
+    #[serde(rename = "noMatch")]
+    pub no_match: bool,
     pub draft_response: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub reasoning: Option<String>,
+    /// `draft_response` as sanitized HTML, when the request set `renderHtml`
+    #[serde(rename = "draftResponseHtml", skip_serializing_if = "Option::is_none")]
+    pub draft_response_html: Option<String>,
 }
 
 /// Generate response request (for triage actions/comment generation)
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GenerateRequest {
+    #[serde(deserialize_with = "providers::deserialize")]
     pub provider: String,
     pub model: Option<String>,
+    #[serde(flatten)]
+    pub settings: RequestSettings,
     pub bug: serde_json::Value,
     /// Generation options (mode, cannedResponses, etc.)
-    #[serde(default)]
+    #[serde(default, deserialize_with = "deserialize_options")]
     pub options: serde_json::Value,
+    /// Also return the draft rendered from markdown to sanitized HTML
+    #[serde(default)]
+    pub render_html: bool,
     /// Optional pre-built prompt from frontend (for centralized prompts)
     pub prompt: Option<String>,
     /// Optional JSON schema for structured output
     pub schema: Option<String>,
 }
 
+/// `deserialize_with` for `GenerateRequest.options`: an object sent JSON-encoded as a
+/// string (a frontend serializing it twice) is decoded. Anything else is kept as sent.
+fn deserialize_options<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<serde_json::Value, D::Error> {
+    let options = serde_json::Value::deserialize(deserializer)?;
+    if let Some(encoded) = options.as_str() {
+        if let Ok(decoded @ serde_json::Value::Object(_)) = serde_json::from_str(encoded) {
+            warn!("Generate options were sent as a JSON-encoded string; decoding them");
+            return Ok(decoded);
+        }
+    }
+    Ok(options)
+}
+
 /// Suggested action from generate response
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SuggestedAction {
+    /// Stable id derived from `action` + `reason`
+    #[serde(default)]
+    pub id: String,
     pub action: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub reason: Option<String>,
@@ -132,15 +441,26 @@ pub struct GenerateResponse {
     pub suggested_actions: Vec<SuggestedAction>,
     #[serde(skip_serializing_if = "Vec::is_empty", default)]
     pub used_canned_ids: Vec<String>,
-    pub reasoning: String,
+    /// Left out when the request set `includeReasoning: false`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<String>,
+    /// `response_text` as sanitized HTML, when the request set `renderHtml`
+    #[serde(rename = "responseTextHtml", skip_serializing_if = "Option::is_none")]
+    pub response_text_html: Option<String>,
+    /// Problems found while processing the result, e.g. actions removed by the denylist
+    #[serde(rename = "parseWarnings", skip_serializing_if = "Vec::is_empty", default)]
+    pub parse_warnings: Vec<String>,
 }
 
 /// Refine response request
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct RefineRequest {
+    #[serde(deserialize_with = "providers::deserialize")]
     pub provider: String,
     pub model: Option<String>,
+    #[serde(flatten)]
+    pub settings: RequestSettings,
     pub bug: serde_json::Value,
     pub current_response: String,
     pub user_instruction: String,
@@ -151,6 +471,8 @@ pub struct RefineRequest {
     pub prompt: Option<String>,
     /// Optional JSON schema for structured output
     pub schema: Option<String>,
+    /// Opt-in coalescing: a newer refine with the same id cancels this one
+    pub refine_session_id: Option<String>,
 }
 
 /// Refine response result
@@ -158,14 +480,20 @@ pub struct RefineRequest {
 pub struct RefineResponse {
     pub refined_response: String,
     pub changes_made: Vec<String>,
+    /// Problems found while parsing the model output (dropped or truncated items)
+    #[serde(rename = "parseWarnings", skip_serializing_if = "Vec::is_empty")]
+    pub parse_warnings: Vec<String>,
 }
 
 /// Test page generation request
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TestPageRequest {
+    #[serde(deserialize_with = "providers::deserialize")]
     pub provider: String,
     pub model: Option<String>,
+    #[serde(flatten)]
+    pub settings: RequestSettings,
     pub bug: serde_json::Value,
     /// Optional pre-built prompt from frontend (for centralized prompts)
     pub prompt: Option<String>,
@@ -179,18 +507,305 @@ pub struct TestPageResponse {
     pub can_generate: bool,
     pub html_content: String,
     pub reason: String,
+    /// What was cut from the bug's attachments before generating, if anything
+    #[serde(rename = "attachmentsTruncated", skip_serializing_if = "Option::is_none")]
+    pub attachments_truncated: Option<String>,
 }
 
-/// Error response
+/// Explain request - asks for a deeper justification of a prior classification
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExplainRequest {
+    #[serde(deserialize_with = "providers::deserialize")]
+    pub provider: String,
+    pub model: Option<String>,
+    #[serde(flatten)]
+    pub settings: RequestSettings,
+    pub bug: serde_json::Value,
+    /// Classification previously returned by `/api/ai/classify`
+    pub classification: ClassifyResponse,
+    /// Optional pre-built prompt from frontend (for centralized prompts)
+    pub prompt: Option<String>,
+    /// Optional JSON schema for structured output
+    pub schema: Option<String>,
+}
+
+/// Explain result
+#[derive(Debug, Serialize)]
+pub struct ExplainResponse {
+    pub overview: String,
+    /// Prose explanation per classification field (e.g. `suggested_severity`)
+    pub field_explanations: BTreeMap<String, String>,
+}
+
+/// Summarize-comments request - a TL;DR of the bug's discussion
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SummarizeCommentsRequest {
+    #[serde(deserialize_with = "providers::deserialize")]
+    pub provider: String,
+    pub model: Option<String>,
+    #[serde(flatten)]
+    pub settings: RequestSettings,
+    /// Bug including its `comments`
+    pub bug: serde_json::Value,
+    /// Index of the first comment to summarize (default: 0)
+    #[serde(default)]
+    pub comments_offset: usize,
+    /// Comments to summarize, clamped to `BUGZILLA_COMMENTS_MAX_PAGE` (default: the maximum)
+    pub comments_limit: Option<usize>,
+    /// Optional pre-built prompt from frontend (for centralized prompts)
+    pub prompt: Option<String>,
+    /// Optional JSON schema for structured output
+    pub schema: Option<String>,
+}
+
+/// Summarize-comments result
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SummarizeCommentsResponse {
+    pub summary: String,
+    pub key_points: Vec<String>,
+    pub open_questions: Vec<String>,
+    /// Offset of the comments left unsummarized, `null` when the page reached the end
+    pub next_comments_offset: Option<usize>,
+}
+
+/// Rewrite-summary request - a cleaner replacement for the bug's own title
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RewriteSummaryRequest {
+    #[serde(deserialize_with = "providers::deserialize")]
+    pub provider: String,
+    pub model: Option<String>,
+    #[serde(flatten)]
+    pub settings: RequestSettings,
+    pub bug: serde_json::Value,
+    /// Optional pre-built prompt from frontend (for centralized prompts)
+    pub prompt: Option<String>,
+    /// Optional JSON schema for structured output
+    pub schema: Option<String>,
+}
+
+/// Rewrite-summary result
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RewriteSummaryResponse {
+    /// One line, non-empty and within Bugzilla's summary length limit
+    pub suggested_summary: String,
+    pub reason: String,
+}
+
+/// One provider/model pair to run in a comparison
+#[derive(Debug, Deserialize)]
+pub struct CompareCandidate {
+    #[serde(deserialize_with = "providers::deserialize")]
+    pub provider: String,
+    pub model: Option<String>,
+}
+
+/// Classify-compare request - the same bug and prompt through several models
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClassifyCompareRequest {
+    pub bug: serde_json::Value,
+    pub candidates: Vec<CompareCandidate>,
+    #[serde(flatten)]
+    pub settings: RequestSettings,
+    /// Optional pre-built prompt from frontend (for centralized prompts)
+    pub prompt: Option<String>,
+    /// Optional JSON schema for structured output
+    pub schema: Option<String>,
+}
+
+/// Outcome for one comparison candidate - `result` on success, `error` otherwise
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompareResult {
+    pub provider: String,
+    pub model: String,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<ClassifyResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorResponse>,
+}
+
+/// Classify-compare result, in candidate order
+#[derive(Debug, Serialize)]
+pub struct ClassifyCompareResponse {
+    pub results: Vec<CompareResult>,
+}
+
+/// Classify-by-id request - the server fetches the bug from Bugzilla itself
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClassifyByIdRequest {
+    pub id: u64,
+    #[serde(deserialize_with = "providers::deserialize")]
+    pub provider: String,
+    pub model: Option<String>,
+    #[serde(flatten)]
+    pub settings: RequestSettings,
+    /// Bugzilla API key for this fetch (e.g. for private bugs); defaults to the server's
+    pub api_key: Option<String>,
+    /// Canned responses offered to the model, as for `/api/ai/classify`
+    pub canned_responses: Option<Vec<serde_json::Value>>,
+    /// Also return the draft rendered from markdown to sanitized HTML
+    #[serde(default)]
+    pub render_html: bool,
+    /// Classify this many times and merge the results by vote, as for `/api/ai/classify`
+    pub ensemble: Option<usize>,
+    /// Pre-built prompt; `{{bug}}` is replaced with the fetched bug's JSON, which is
+    /// appended instead when the placeholder is absent
+    pub prompt: Option<String>,
+    /// Optional JSON schema for structured output
+    pub schema: Option<String>,
+}
+
+impl ClassifyByIdRequest {
+    /// The classify request for `bug`, the bug this request fetched
+    fn into_classify(self, bug: &serde_json::Value) -> ClassifyRequest {
+        ClassifyRequest {
+            provider: self.provider,
+            model: self.model,
+            settings: self.settings,
+            canned_responses: self.canned_responses,
+            render_html: self.render_html,
+            ensemble: self.ensemble,
+            prompt: self.prompt.map(|p| insert_bug_into_prompt(&p, bug)),
+            schema: self.schema,
+            bug: bug.clone(),
+        }
+    }
+}
+
+/// Classify-by-id result - the classification plus the bug that was fetched
+#[derive(Debug, Serialize)]
+pub struct ClassifyByIdResponse {
+    #[serde(flatten)]
+    pub classification: ClassifyResponse,
+    pub bug: serde_json::Value,
+}
+
+/// Dry-run request - any AI request body; only these fields are read
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DryRunRequest {
+    #[serde(deserialize_with = "providers::deserialize")]
+    pub provider: String,
+    pub model: Option<String>,
+    #[serde(default)]
+    pub prompt: String,
+    pub schema: Option<String>,
+    /// Prompts of a planned batch, estimated instead of `prompt`/`schema`
+    #[serde(default)]
+    pub items: Vec<DryRunItem>,
+}
+
+/// One prompt of a planned batch
+#[derive(Debug, Deserialize)]
+pub struct DryRunItem {
+    #[serde(default)]
+    pub prompt: String,
+    pub schema: Option<String>,
+}
+
+/// Estimate for one prompt of a batch dry run
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DryRunItemEstimate {
+    pub prompt_bytes: usize,
+    pub estimated_tokens: usize,
+    /// At list price, when the model's price is known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimated_cost_usd: Option<f64>,
+}
+
+/// Dry-run result - what would be sent, without calling the model
 #[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DryRunResponse {
+    pub provider: String,
+    pub model: String,
+    pub prompt_bytes: usize,
+    pub estimated_tokens: usize,
+    /// Which estimator produced `estimated_tokens`
+    pub token_estimator: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema_bytes: Option<usize>,
+    /// Estimated cost at list price (see `budget::estimate_usd`), when the model's price
+    /// is known; the total over `items` for a batch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimated_cost_usd: Option<f64>,
+    /// Per-item estimates of a batch dry run; the other fields are then totals
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub items: Option<Vec<DryRunItemEstimate>>,
+}
+
+/// Reparse request - a captured provider output to run through an endpoint's parser
+#[derive(Debug, Deserialize)]
+pub struct ReparseRequest {
+    /// Endpoint whose parser to run, e.g. `classify` or `suggest-response`
+    pub endpoint: String,
+    /// CLI or provider output as captured, or just its structured result
+    pub raw: serde_json::Value,
+}
+
+/// Self-test request - a prompt and schema to try against the mock provider
+#[derive(Debug, Deserialize)]
+pub struct SelfTestRequest {
+    /// Endpoint whose parser the mock output must satisfy, e.g. `classify`
+    pub endpoint: String,
+    pub prompt: Option<String>,
+    pub schema: Option<String>,
+}
+
+/// Error response
+#[derive(Debug, Clone, Serialize)]
 pub struct ErrorResponse {
     pub error: String,
     pub details: Option<String>,
+    /// Machine-readable error code, e.g. `STRUCTURED_OUTPUT_MISSING`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<&'static str>,
+    /// HTTP status to respond with
+    #[serde(skip)]
+    pub status: StatusCode,
+}
+
+impl Default for ErrorResponse {
+    fn default() -> Self {
+        ErrorResponse {
+            error: String::new(),
+            details: None,
+            code: None,
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl ErrorResponse {
+    /// Plain-text rendering for clients that ask for `text/plain`
+    pub fn to_plain_text(&self) -> String {
+        let mut text = format!("Error: {}\n", self.error);
+        if let Some(ref details) = self.details {
+            text.push_str(&format!("Details: {}\n", details));
+        }
+        if let Some(code) = self.code {
+            text.push_str(&format!("Code: {}\n", code));
+        }
+        text
+    }
 }
 
 impl IntoResponse for ErrorResponse {
     fn into_response(self) -> axum::response::Response {
-        (StatusCode::INTERNAL_SERVER_ERROR, Json(self)).into_response()
+        // Keep a copy on the response so middleware can record it
+        let error = self.clone();
+        let mut response = (self.status, Json(self)).into_response();
+        response.extensions_mut().insert(error);
+        response
     }
 }
 
@@ -215,360 +830,2489 @@ async fn main() {
     let claude_model =
         std::env::var("CLAUDE_MODEL").unwrap_or_else(|_| "claude-sonnet-4-5-20250929".to_string());
 
-    info!("Claude backend mode: {}", claude_mode);
-    if claude_mode == "cli" {
-        info!("Using Claude Code CLI - ensure 'claude' is installed and authenticated");
+    let bugzilla_url = std::env::var("BUGZILLA_URL")
+        .unwrap_or_else(|_| "https://bugzilla.mozilla.org".to_string());
+    let bugzilla_api_key = std::env::var("BUGZILLA_API_KEY").ok();
+    let max_attachment_bytes = env_number::<u64>("MAX_ATTACHMENT_BYTES").unwrap_or(5 * 1024 * 1024);
+    let bugzilla_search_max_limit = env_number::<u32>("BUGZILLA_SEARCH_MAX_LIMIT")
+        .filter(|limit| *limit > 0)
+        .unwrap_or(100);
+    let bugzilla_comments_max_page = env_number::<usize>("BUGZILLA_COMMENTS_MAX_PAGE")
+        .filter(|limit| *limit > 0)
+        .unwrap_or(50);
+
+    // Triage actions apply-actions may carry out (default: the built-in vocabulary)
+    let actions_file = std::env::var("BUGZILLA_ACTIONS_FILE").ok().filter(|p| !p.is_empty());
+    let action_vocabulary = match actions_file {
+        Some(path) => match bug_actions::ActionVocabulary::load(path.as_ref()) {
+            Ok(vocabulary) => {
+                info!("Loaded {} triage actions from {}", vocabulary.len(), path);
+                vocabulary
+            }
+            Err(e) => {
+                tracing::error!("Invalid BUGZILLA_ACTIONS_FILE {}: {}", path, e);
+                std::process::exit(1);
+            }
+        },
+        None => bug_actions::ActionVocabulary::default(),
+    };
+
+    // The claude executable, e.g. a stub for tests or a non-PATH install
+    let claude_bin = std::env::var("CLAUDE_BIN").unwrap_or_else(|_| "claude".to_string());
+
+    // Extra CLI arguments, parsed into argv (no shell involved)
+    let cli_extra_args = std::env::var("CLAUDE_CLI_EXTRA_ARGS")
+        .unwrap_or_else(|_| claude_cli::DEFAULT_EXTRA_ARGS.to_string());
+    let cli_extra_args = match claude_cli::parse_args(&cli_extra_args) {
+        Ok(args) => args,
+        Err(e) => {
+            tracing::error!("Invalid CLAUDE_CLI_EXTRA_ARGS: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if !cli_extra_args.is_empty() {
+        info!("Extra Claude CLI arguments: {:?}", cli_extra_args);
     }
 
-    let state = Arc::new(AppState {
-        claude_mode,
-        anthropic_api_key,
-        gemini_api_key,
-        openai_api_key,
-        claude_model,
-    });
+    // Command that AI results are piped through before responding, parsed into argv
+    let postprocess_cmd = match claude_cli::parse_args(
+        &std::env::var("POSTPROCESS_CMD").unwrap_or_default(),
+    ) {
+        Ok(args) => args,
+        Err(e) => {
+            tracing::error!("Invalid POSTPROCESS_CMD: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if !postprocess_cmd.is_empty() {
+        info!("Post-processing AI results with: {:?}", postprocess_cmd);
+    }
 
-    // Configure CORS
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods([Method::GET, Method::POST, Method::OPTIONS])
-        .allow_headers([header::CONTENT_TYPE, header::AUTHORIZATION]);
+    let max_uri_bytes = env_number::<usize>("MAX_URI_BYTES").unwrap_or(4096);
+
+    // Shared outbound HTTP client - bounded so a hung upstream can't hold a task forever
+    let http_defaults = http_client::HttpClientConfig::default();
+    let http_client_config = http_client::HttpClientConfig {
+        timeout: env_number::<u64>("HTTP_CLIENT_TIMEOUT_SECS")
+            .map(Duration::from_secs)
+            .unwrap_or(http_defaults.timeout),
+        connect_timeout: env_number::<u64>("HTTP_CLIENT_CONNECT_TIMEOUT_SECS")
+            .map(Duration::from_secs)
+            .unwrap_or(http_defaults.connect_timeout),
+        pool_max_idle_per_host: env_number::<usize>("HTTP_CLIENT_POOL_MAX_IDLE")
+            .unwrap_or(http_defaults.pool_max_idle_per_host),
+    };
+    let http_client = match http_client::build(&http_client_config) {
+        Ok(client) => client,
+        Err(e) => {
+            tracing::error!("Failed to build HTTP client: {}", e);
+            std::process::exit(1);
+        }
+    };
 
-    // Determine frontend directory path
-    // Try relative path from backend-rust directory, or use FRONTEND_DIR env var
-    let frontend_dir = std::env::var("FRONTEND_DIR")
-        .unwrap_or_else(|_| "../frontend".to_string());
+    // CLI retry/time limits; requests may override them up to the caps
+    let cli_max_retries = env_number::<u32>("CLI_MAX_RETRIES").unwrap_or(1);
+    let cli_max_retries_cap = env_number::<u32>("CLI_MAX_RETRIES_CAP").unwrap_or(3);
+    // 0 disables the limit
+    let cli_timeout_secs = env_number::<u64>("CLI_TIMEOUT_SECS").unwrap_or(300);
+    let cli_timeout_cap_secs = env_number::<u64>("CLI_TIMEOUT_CAP_SECS").unwrap_or(600);
+
+    // Per-provider time limits, e.g. "claude:120,openai:60,gemini:45"; unlisted providers
+    // use CLI_TIMEOUT_SECS
+    let provider_timeouts =
+        match parse_provider_timeouts(&std::env::var("PROVIDER_TIMEOUTS").unwrap_or_default()) {
+            Ok(timeouts) => timeouts,
+            Err(e) => {
+                tracing::error!("Invalid PROVIDER_TIMEOUTS: {}", e);
+                std::process::exit(1);
+            }
+        };
+    if !provider_timeouts.is_empty() {
+        info!("Provider timeouts: {:?}", provider_timeouts);
+    }
 
-    info!("Serving frontend from: {}", frontend_dir);
+    // Command to run the CLI under (e.g. "nice -n 10", "timeout 300"), same syntax as
+    // CLAUDE_CLI_EXTRA_ARGS
+    let cli_wrapper = match claude_cli::parse_args(
+        &std::env::var("CLI_WRAPPER").unwrap_or_default(),
+    ) {
+        Ok(args) => args,
+        Err(e) => {
+            tracing::error!("Invalid CLI_WRAPPER: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Some(program) = cli_wrapper.first() {
+        info!("Running Claude CLI under wrapper: {:?}", cli_wrapper);
+        if !claude_cli::executable_exists(program) {
+            tracing::warn!("CLI_WRAPPER executable '{}' not found", program);
+        }
+    }
 
-    // Static file service with no-cache headers to ensure fresh files during development
-    let static_service = ServeDir::new(&frontend_dir).precompressed_gzip();
-    let static_with_cache_control = tower::ServiceBuilder::new()
-        .layer(SetResponseHeaderLayer::overriding(
-            header::CACHE_CONTROL,
-            HeaderValue::from_static("no-cache, no-store, must-revalidate"),
-        ))
-        .service(static_service);
+    let race_max_providers = env_number::<usize>("RACE_MAX_PROVIDERS").unwrap_or(3);
+    // Racing no providers would fail every `provider: "fastest"` request
+    if race_max_providers == 0 {
+        tracing::error!("Invalid RACE_MAX_PROVIDERS: must be at least 1");
+        std::process::exit(1);
+    }
 
-    // Build router - API routes first, then fallback to static files
-    let app = Router::new()
-        .route("/health", get(health_check))
-        .route("/status", get(status_page))
-        .route("/api/ai/classify", post(classify_bug))
-        .route("/api/ai/suggest-response", post(suggest_response))
-        .route("/api/ai/generate", post(generate_response))
-        .route("/api/ai/refine", post(refine_response))
-        .route("/api/ai/testpage", post(generate_testpage))
-        .fallback_service(static_with_cache_control)
-        .layer(cors)
-        .with_state(state);
+    let compare_max_concurrency = env_number::<usize>("COMPARE_MAX_CONCURRENCY")
+        .filter(|n| *n > 0)
+        .unwrap_or(3);
 
-    // Start server
-    let port = std::env::var("PORT").unwrap_or_else(|_| "3000".to_string());
-    let addr = format!("0.0.0.0:{}", port);
-    let url = format!("http://localhost:{}", port);
-    info!("Starting server on {}", url);
+    let recent_errors_capacity = env_number::<usize>("RECENT_ERRORS_CAPACITY").unwrap_or(50);
 
-    // Check if we should auto-open browser (default: yes)
-    let no_open = std::env::var("NO_OPEN").is_ok();
+    let admin_token = std::env::var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty());
 
-    if !no_open {
-        let open_url = url.clone();
-        // Spawn a task to open browser after a short delay
-        tokio::spawn(async move {
-            // Small delay to ensure server is ready
-            tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
-            info!("Opening browser at {}", open_url);
-            if let Err(e) = open::that(&open_url) {
-                tracing::warn!("Failed to open browser: {}. Open {} manually.", e, open_url);
-            }
-        });
+    // Fraction of AI requests whose redacted prompt and response are logged in full
+    let log_sample_rate = env_number::<f64>("LOG_SAMPLE_RATE")
+        .unwrap_or(0.0)
+        .clamp(0.0, 1.0);
+
+    // Suggested actions never returned to the UI, e.g. "close,close-as-wontfix"
+    let action_denylist: Vec<String> = std::env::var("ACTION_DENYLIST")
+        .unwrap_or_default()
+        .split(',')
+        .map(|action| action.trim().to_ascii_lowercase())
+        .filter(|action| !action.is_empty())
+        .collect();
+    if !action_denylist.is_empty() {
+        info!("Denied suggested actions: {:?}", action_denylist);
     }
 
-    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
-}
+    // Expected priorities per severity, e.g. "S1:P1-P2,S2:P1-P3"; empty disables the check
+    let priority_convention = match std::env::var("SEVERITY_PRIORITY_CONVENTION") {
+        Ok(input) => match normalize::PriorityConvention::parse(&input) {
+            Ok(convention) => convention,
+            Err(e) => {
+                tracing::error!("Invalid SEVERITY_PRIORITY_CONVENTION: {}", e);
+                std::process::exit(1);
+            }
+        },
+        Err(_) => normalize::PriorityConvention::default(),
+    };
+    if priority_convention.is_empty() {
+        info!("Severity/priority cross-check disabled");
+    }
 
-/// Health check endpoint - also reports available AI providers for frontend auto-configuration
-async fn health_check() -> impl IntoResponse {
-    // Check which AI providers are available
-    let mut available_providers: Vec<&str> = Vec::new();
+    // Path prefix for deployments behind a path-routing reverse proxy
+    let base_path = std::env::var("BASE_PATH").unwrap_or_default();
+    let base_path = match normalize_base_path(&base_path) {
+        Ok(base_path) => base_path,
+        Err(e) => {
+            tracing::error!("Invalid BASE_PATH: {}", e);
+            std::process::exit(1);
+        }
+    };
 
-    // Check Claude Code CLI
-    let claude_available = tokio::process::Command::new("claude")
-        .arg("--version")
-        .output()
-        .await
-        .map(|o| o.status.success())
-        .unwrap_or(false);
+    // Requests in progress at once across all routes, beyond which new ones get 503
+    let max_concurrent_requests = env_number::<usize>("MAX_CONCURRENT_REQUESTS").unwrap_or(0);
+
+    // HTTP provider API calls in flight at once; more wait for a free slot
+    let max_concurrent_http_calls = env_number::<usize>("MAX_CONCURRENT_HTTP_CALLS").unwrap_or(6);
+
+    // Largest request body, after decompressing gzip request bodies; axum's default
+    let max_body_bytes = env_number::<usize>("MAX_BODY_BYTES")
+        .filter(|n| *n > 0)
+        .unwrap_or(2 * 1024 * 1024);
+
+    // Largest schema a request may send; it ends up as one CLI argument, so it can't
+    // be larger than the OS allows for one
+    let mut max_schema_bytes = env_number::<usize>("MAX_SCHEMA_BYTES")
+        .filter(|n| *n > 0)
+        .unwrap_or(64 * 1024);
+    if max_schema_bytes > claude_cli::MAX_SCHEMA_ARG_BYTES {
+        warn!(
+            "MAX_SCHEMA_BYTES={} is more than one CLI argument can hold, using {}",
+            max_schema_bytes,
+            claude_cli::MAX_SCHEMA_ARG_BYTES
+        );
+        max_schema_bytes = claude_cli::MAX_SCHEMA_ARG_BYTES;
+    }
 
-    if claude_available {
-        available_providers.push("claude");
+    // Longest prompt an AI request may send; longer ones get 413, or are cut down with
+    // `autoTruncatePrompt` (default: 0 = no limit)
+    let max_prompt_bytes = env_number::<usize>("MAX_PROMPT_BYTES").unwrap_or(0);
+
+    // Attachments of a bug used for a test page: how many, and how much data each;
+    // 0 = no limit
+    let testpage_max_attachments = env_number::<usize>("TESTPAGE_MAX_ATTACHMENTS").unwrap_or(10);
+    let testpage_max_attachment_bytes = env_number::<usize>("TESTPAGE_MAX_ATTACHMENT_BYTES")
+        .unwrap_or(256 * 1024);
+
+    // Cost budget in USD for every AI request, also the cap on a request's maxCostUsd
+    let max_cost_usd = env_number::<f32>("MAX_COST_USD")
+        .filter(|usd| *usd >= 0.0);
+    if let Some(usd) = max_cost_usd {
+        info!("AI requests are limited to ${} each", usd);
     }
 
-    // Future: Check Gemini Code CLI
-    // let gemini_available = tokio::process::Command::new("gemini")
-    //     .arg("--version")
-    //     .output()
-    //     .await
-    //     .map(|o| o.status.success())
-    //     .unwrap_or(false);
-    // if gemini_available {
-    //     available_providers.push("gemini");
-    // }
+    // Extra headers on every response, e.g. "X-Instance-Id: web-1"
+    let response_headers_file = match std::env::var("RESPONSE_HEADERS_FILE")
+        .ok()
+        .filter(|p| !p.is_empty())
+    {
+        Some(path) => match std::fs::read_to_string(&path) {
+            Ok(text) => Some(text),
+            Err(e) => {
+                tracing::error!("Failed to read RESPONSE_HEADERS_FILE {}: {}", path, e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+    let response_headers = match parse_response_headers(
+        response_headers_file.as_deref(),
+        &std::env::var("RESPONSE_HEADERS").unwrap_or_default(),
+    ) {
+        Ok(headers) => headers,
+        Err(e) => {
+            tracing::error!("Invalid response headers: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if !response_headers.is_empty() {
+        info!("Extra response headers: {:?}", response_headers);
+    }
+
+    // Content-Security-Policy of the served HTML (default: none). Off by default because
+    // generated test pages open as blob: URLs, which inherit the frontend's policy, and
+    // a self-only policy blocks their scripts.
+    let content_security_policy = std::env::var("CONTENT_SECURITY_POLICY").unwrap_or_default();
+    let content_security_policy = match content_security_policy.trim() {
+        "" => None,
+        policy => match HeaderValue::try_from(policy) {
+            Ok(policy) => Some(policy),
+            Err(_) => {
+                tracing::error!("Invalid CONTENT_SECURITY_POLICY: {:?}", policy);
+                std::process::exit(1);
+            }
+        },
+    };
+
+    // Frontend prompt versions the parsers support, e.g. "1,2.1"; "1" accepts 1.x.y
+    let compatible_prompt_versions: Vec<String> = std::env::var("COMPATIBLE_PROMPT_VERSIONS")
+        .ok()
+        .map(|v| {
+            v.split(',')
+                .map(|version| version.trim().to_string())
+                .filter(|version| !version.is_empty())
+                .collect()
+        })
+        .unwrap_or_else(|| {
+            prompt_versions::DEFAULT_COMPATIBLE
+                .iter()
+                .map(|v| v.to_string())
+                .collect()
+        });
+
+    // JSONL transcript of AI requests; each prompt/result is cut to the field limit
+    let transcript_max_field_bytes = env_number::<usize>("TRANSCRIPT_MAX_FIELD_BYTES")
+        .unwrap_or(64 * 1024);
+    // Transcript writes pause while its disk has less free space than this
+    let min_free_disk_bytes = env_number::<u64>("MIN_FREE_DISK_BYTES").unwrap_or(100 * 1024 * 1024);
+    let disk_check_interval = env_number::<u64>("DISK_CHECK_INTERVAL_SECS")
+        .filter(|secs| *secs > 0)
+        .map_or(Duration::from_secs(30), Duration::from_secs);
+    let transcript_path = std::env::var("TRANSCRIPT_PATH").ok().filter(|p| !p.is_empty());
+    let disk = transcript_path
+        .as_ref()
+        .filter(|_| min_free_disk_bytes > 0)
+        .map(|path| {
+            // The file may not exist yet; its directory is on the same filesystem
+            let dir = std::path::Path::new(path)
+                .parent()
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .unwrap_or(std::path::Path::new("."));
+            let disk = Arc::new(disk::DiskGuard::new(dir.to_path_buf(), min_free_disk_bytes));
+            disk.spawn_monitor(disk_check_interval);
+            disk
+        });
+    let transcript = match transcript_path {
+        Some(path) => {
+            let opened = transcript::Transcript::open(
+                path.as_ref(),
+                transcript_max_field_bytes,
+                disk.clone(),
+            )
+            .await;
+            match opened {
+                Ok(transcript) => {
+                    info!("Writing AI transcript to {}", path);
+                    Some(Arc::new(transcript))
+                }
+                Err(e) => {
+                    tracing::error!("Cannot open TRANSCRIPT_PATH {}: {}", path, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        None => None,
+    };
+
+    // Fields that must be present in results, per endpoint
+    let required_fields = match required_fields::from_vars(std::env::vars()) {
+        Ok(required) => required,
+        Err(e) => {
+            tracing::error!("Invalid REQUIRED_FIELDS_*: {}", e);
+            std::process::exit(1);
+        }
+    };
+    for (endpoint, fields) in &required_fields {
+        info!("Required fields for {}: {}", endpoint, fields.join(", "));
+    }
+
+    // SQLite history of classifications, listed by /api/history/{bugId}
+    let history = match std::env::var("RESULT_DB_PATH").ok().filter(|p| !p.is_empty()) {
+        Some(path) => match history::History::open(path.as_ref()) {
+            Ok(history) => {
+                info!("Storing classification history in {}", path);
+                Some(Arc::new(history))
+            }
+            Err(e) => {
+                tracing::error!("Cannot open RESULT_DB_PATH {}: {}", path, e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    // Seconds a classify response is reused for an identical request (default: 0 = off)
+    let response_cache = env_number::<u64>("RESPONSE_CACHE_TTL_SECS")
+        .filter(|secs| *secs > 0)
+        .map(|secs| {
+            info!("Caching classify responses for {}s", secs);
+            Arc::new(response_cache::ResponseCache::new(Duration::from_secs(secs)))
+        });
+
+    // Seconds /health reuses its provider availability check (default: 10, 0 = never)
+    let health_cache_ttl_secs = env_number::<u64>("HEALTH_CACHE_TTL_SECS").unwrap_or(10);
+
+    // Log CLI stderr on successful runs too: "warn", "info", "debug", or "off" (default)
+    let cli_stderr_log = match std::env::var("CLI_STDERR_LOG").as_deref() {
+        Ok("warn") => Some(tracing::Level::WARN),
+        Ok("info") => Some(tracing::Level::INFO),
+        Ok("debug") => Some(tracing::Level::DEBUG),
+        Ok("off") | Err(_) => None,
+        Ok(other) => {
+            tracing::warn!("Unknown CLI_STDERR_LOG value '{}', using 'off'", other);
+            None
+        }
+    };
+
+    // Retry with forced tool use for models without structured outputs (default: on)
+    let anthropic_tool_fallback = std::env::var("ANTHROPIC_TOOL_FALLBACK")
+        .map(|v| !(v == "0" || v.eq_ignore_ascii_case("false")))
+        .unwrap_or(true);
+
+    // Strip control and zero-width characters and surrounding whitespace from strings
+    // in model output (default: on)
+    let clean_strings = std::env::var("CLEAN_MODEL_STRINGS")
+        .map(|v| !(v == "0" || v.eq_ignore_ascii_case("false")))
+        .unwrap_or(true);
+
+    // Use built-in schemas when a request omits `schema` (default: off)
+    let allow_default_schemas = std::env::var("ALLOW_DEFAULT_SCHEMAS")
+        .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+        .unwrap_or(false);
+    // Hold readiness until one CLI run succeeds, e.g. while the CLI isn't authenticated
+    // yet (default: off)
+    let cli_self_test = std::env::var("CLI_SELF_TEST")
+        .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+        .unwrap_or(false)
+        && claude_mode == "cli";
+
+    if allow_default_schemas {
+        info!("Built-in default schemas enabled for requests without a schema");
+    }
+
+    // Schema files replacing the request's schema for their endpoint
+    let schema_overrides =
+        match schemas::load_overrides(&std::env::var("SCHEMA_OVERRIDES").unwrap_or_default()) {
+            Ok(overrides) => overrides,
+            Err(e) => {
+                tracing::error!("Invalid SCHEMA_OVERRIDES: {}", e);
+                std::process::exit(1);
+            }
+        };
+    for endpoint in schema_overrides.keys() {
+        info!("Schema override active for {}: request schemas are ignored", endpoint);
+    }
+
+    // Server-side prompts for requests that don't send one
+    let prompt_templates = match std::env::var("PROMPT_TEMPLATE_DIR") {
+        Ok(dir) if !dir.is_empty() => match templates::load(dir.as_ref()) {
+            Ok(templates) => templates,
+            Err(e) => {
+                tracing::error!("Invalid PROMPT_TEMPLATE_DIR: {}", e);
+                std::process::exit(1);
+            }
+        },
+        _ => templates::Templates::default(),
+    };
+    for endpoint in prompt_templates.endpoints() {
+        info!("Prompt template active for {}: used when a request has no prompt", endpoint);
+    }
+
+    info!("Claude backend mode: {}", claude_mode);
+    if claude_mode == "cli" {
+        info!("Using Claude Code CLI - ensure 'claude' is installed and authenticated");
+    }
+
+    // Determine frontend directory path
+    // Try relative path from backend-rust directory, or use FRONTEND_DIR env var
+    let frontend_dir = std::env::var("FRONTEND_DIR")
+        .unwrap_or_else(|_| "../frontend".to_string());
+
+    info!("Serving frontend from: {}", frontend_dir);
+
+    let cli_children = Arc::new(children::ChildRegistry::default());
+    let cli_timeout = (cli_timeout_secs > 0).then(|| Duration::from_secs(cli_timeout_secs));
+    let state = Arc::new(AppState {
+        claude_mode,
+        anthropic_api_key,
+        gemini_api_key,
+        openai_api_key,
+        claude_model,
+        anthropic: anthropic::AnthropicConfig {
+            tool_fallback: anthropic_tool_fallback,
+            ..Default::default()
+        },
+        cli: claude_cli::CliConfig {
+            program: claude_bin,
+            stderr_log: cli_stderr_log,
+            allow_default_schemas,
+            schema_overrides: Arc::new(schema_overrides),
+            max_schema_bytes,
+            prompt_templates: Arc::new(prompt_templates),
+            extra_args: cli_extra_args,
+            wrapper: cli_wrapper,
+            children: Arc::clone(&cli_children),
+            max_retries: cli_max_retries,
+            max_retries_cap: cli_max_retries_cap,
+            timeout: cli_timeout,
+            timeout_cap: (cli_timeout_cap_secs > 0)
+                .then(|| Duration::from_secs(cli_timeout_cap_secs)),
+            max_cost_usd,
+            clean_strings,
+        },
+        provider_timeouts,
+        race_max_providers,
+        compare_max_concurrency,
+        bugzilla: bugzilla::BugzillaConfig {
+            base_url: bugzilla_url,
+            api_key: bugzilla_api_key,
+            max_attachment_bytes,
+            search_max_limit: bugzilla_search_max_limit,
+            comments_max_page: bugzilla_comments_max_page,
+            actions: Arc::new(action_vocabulary),
+        },
+        http_client,
+        max_uri_bytes,
+        recent_errors: Arc::new(recent_errors::RecentErrors::new(recent_errors_capacity)),
+        refine_sessions: Arc::default(),
+        active_requests: Arc::default(),
+        admin_token,
+        postprocess: postprocess::PostprocessConfig {
+            command: postprocess_cmd,
+            timeout: cli_timeout,
+            children: cli_children,
+        },
+        transcript,
+        required_fields: Arc::new(required_fields),
+        history,
+        response_cache,
+        disk,
+        request_limit: (max_concurrent_requests > 0)
+            .then(|| Arc::new(tokio::sync::Semaphore::new(max_concurrent_requests))),
+        http_call_limit: (max_concurrent_http_calls > 0)
+            .then(|| Arc::new(tokio::sync::Semaphore::new(max_concurrent_http_calls))),
+        max_concurrent_http_calls,
+        max_body_bytes,
+        max_prompt_bytes,
+        testpage_max_attachments,
+        testpage_max_attachment_bytes,
+        max_cost_usd,
+        response_headers,
+        content_security_policy,
+        prompt_versions: Arc::new(prompt_versions::PromptVersions::new(compatible_prompt_versions)),
+        base_path: base_path.clone(),
+        log_sample_rate,
+        action_denylist,
+        priority_convention,
+        ready: Arc::new(AtomicBool::new(!cli_self_test)),
+        provider_availability: Arc::new(health::AvailabilityCache::new(Duration::from_secs(
+            health_cache_ttl_secs,
+        ))),
+        started_at: SystemTime::now(),
+        frontend_dir: PathBuf::from(&frontend_dir),
+    });
+    if cli_self_test {
+        tokio::spawn(self_test_until_ready(state.clone()));
+    }
+
+    // Kept to kill running CLI processes on shutdown
+    let shutdown_state = state.clone();
+
+    let app = build_router(state, &frontend_dir);
+
+    // Start server
+    let port = match parse_port(std::env::var("PORT").ok().as_deref()) {
+        Ok(port) => port,
+        Err(e) => {
+            tracing::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let addr = format!("0.0.0.0:{}", port);
+    let url = format!("http://localhost:{}{}/", port, base_path);
+    info!("Starting server on {}", url);
+
+    // Check if we should auto-open browser (default: yes, unless NO_OPEN is set or
+    // there is evidently no browser to open)
+    let no_open = std::env::var("NO_OPEN").is_ok();
+    let headless =
+        headless_reason(|name| std::env::var_os(name).map(|v| v.to_string_lossy().into_owned()));
+    if let (false, Some(reason)) = (no_open, headless) {
+        info!("Not opening a browser ({}). Open {} manually.", reason, url);
+    }
+
+    if !no_open && headless.is_none() {
+        let open_url = url.clone();
+        // Spawn a task to open browser after a short delay
+        tokio::spawn(async move {
+            // Small delay to ensure server is ready
+            tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+            info!("Opening browser at {}", open_url);
+            if let Err(e) = open::that(&open_url) {
+                tracing::warn!("Failed to open browser: {}. Open {} manually.", e, open_url);
+            }
+        });
+    }
+
+    // Idle keep-alive connections (e.g. from abandoned browser tabs) are closed after this
+    // many seconds; 0 disables the timeout
+    let http_idle_timeout = env_number::<u64>("HTTP_IDLE_TIMEOUT_SECS").unwrap_or(60);
+    let idle_timeout = (http_idle_timeout > 0).then(|| Duration::from_secs(http_idle_timeout));
+    match idle_timeout {
+        Some(timeout) => info!("HTTP idle connection timeout: {}s", timeout.as_secs()),
+        None => info!("HTTP idle connection timeout: disabled"),
+    }
+
+    let listener = match tokio::net::TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            tracing::error!("Failed to listen on {}: {}", addr, e);
+            std::process::exit(1);
+        }
+    };
+    tokio::select! {
+        _ = serve(listener, app, idle_timeout) => {}
+        _ = shutdown_signal() => {
+            let killed = shutdown_state.cli.children.kill_all();
+            info!("Shutting down; killed {} running Claude CLI process(es)", killed);
+            if let Some(ref transcript) = shutdown_state.transcript {
+                transcript.flush().await;
+            }
+            if let Some(ref history) = shutdown_state.history {
+                history.flush().await;
+            }
+        }
+    }
+}
+
+/// Resolves on Ctrl-C, or SIGTERM on Unix
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::warn!("Failed to listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::warn!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+/// Parse `provider:seconds` pairs separated by commas; 0 seconds means no limit.
+/// Providers are resolved like request `provider`s; one the backend doesn't know is an
+/// error, so a typo can't leave a provider on the default silently.
+fn parse_provider_timeouts(input: &str) -> Result<HashMap<String, Option<Duration>>, String> {
+    input
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (provider, secs) = entry
+                .split_once(':')
+                .ok_or_else(|| format!("expected provider:seconds, got '{}'", entry))?;
+            let provider = providers::canonical(provider);
+            if !matches!(provider.as_str(), "claude" | "gemini" | "openai") {
+                return Err(format!("unknown provider in '{}'", entry));
+            }
+            let secs = secs
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| format!("invalid seconds in '{}'", entry))?;
+            let timeout = (secs > 0).then(|| Duration::from_secs(secs));
+            Ok((provider, timeout))
+        })
+        .collect()
+}
+
+/// Static response headers: `file`, a JSON object of name to value
+/// (`RESPONSE_HEADERS_FILE`), then `list`, `Name: value` pairs separated by commas
+/// (`RESPONSE_HEADERS`). A header in both takes the value from `list`.
+fn parse_response_headers(
+    file: Option<&str>,
+    list: &str,
+) -> Result<Vec<(HeaderName, HeaderValue)>, String> {
+    let mut entries = Vec::new();
+    if let Some(file) = file {
+        let object: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(file).map_err(|e| e.to_string())?;
+        for (name, value) in object {
+            let value = value
+                .as_str()
+                .ok_or_else(|| format!("value of '{}' must be a string", name))?
+                .to_string();
+            entries.push((name, value));
+        }
+    }
+    for entry in list.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        let (name, value) = entry
+            .split_once(':')
+            .ok_or_else(|| format!("expected Name: value, got '{}'", entry))?;
+        entries.push((name.trim().to_string(), value.trim().to_string()));
+    }
+
+    let mut headers: Vec<(HeaderName, HeaderValue)> = Vec::new();
+    for (name, value) in entries {
+        let name = HeaderName::try_from(name.as_str())
+            .map_err(|_| format!("invalid header name '{}'", name))?;
+        let value = HeaderValue::try_from(value.as_str())
+            .map_err(|_| format!("invalid value for header '{}'", name))?;
+        headers.retain(|(existing, _)| *existing != name);
+        headers.push((name, value));
+    }
+    Ok(headers)
+}
+
+/// Why the machine looks headless, if it does: an SSH session, a CI run, `BROWSER=none`,
+/// or (on Linux) no X11/Wayland display. `var` looks up an environment variable.
+fn headless_reason(var: impl Fn(&str) -> Option<String>) -> Option<&'static str> {
+    let is_set = |name: &str| var(name).is_some();
+    if is_set("SSH_CONNECTION") {
+        Some("SSH session")
+    } else if is_set("CI") {
+        Some("CI environment")
+    } else if var("BROWSER").is_some_and(|browser| browser == "none") {
+        Some("BROWSER=none")
+    } else if cfg!(target_os = "linux") && !is_set("DISPLAY") && !is_set("WAYLAND_DISPLAY") {
+        Some("no display")
+    } else {
+        None
+    }
+}
+
+fn is_html(response: &axum::response::Response) -> bool {
+    response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/html"))
+}
+
+/// The numeric env var `name` from its `value`: `None` when unset or empty, an error
+/// when it isn't a number of type `T`
+fn parse_env_number<T>(name: &str, value: Option<&str>) -> Result<Option<T>, String>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    let Some(value) = value.map(str::trim).filter(|v| !v.is_empty()) else {
+        return Ok(None);
+    };
+    value
+        .parse()
+        .map(Some)
+        .map_err(|e| format!("{} must be a number, got '{}': {}", name, value, e))
+}
+
+/// The numeric env var `name`, `None` when unset or empty. A value that isn't a number
+/// stops the server rather than silently falling back to the default.
+fn env_number<T>(name: &str) -> Option<T>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    match parse_env_number(name, std::env::var(name).ok().as_deref()) {
+        Ok(number) => number,
+        Err(e) => {
+            tracing::error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `PORT` as a port number, 3000 when unset or empty
+fn parse_port(raw: Option<&str>) -> Result<u16, String> {
+    let raw = raw.map(str::trim).unwrap_or("");
+    if raw.is_empty() {
+        return Ok(3000);
+    }
+    raw.parse::<u16>()
+        .ok()
+        .filter(|port| *port > 0)
+        .ok_or_else(|| format!("PORT must be 1-65535, got '{}'", raw))
+}
+
+/// `BASE_PATH` as a router prefix: leading slash, no trailing slash, empty for the root
+fn normalize_base_path(raw: &str) -> Result<String, String> {
+    let trimmed = raw.trim().trim_end_matches('/');
+    if trimmed.is_empty() {
+        return Ok(String::new());
+    }
+    if !trimmed.starts_with('/') {
+        return Err(format!("'{}' must start with '/'", raw));
+    }
+    if trimmed.contains(['{', '}', '*', '?', '#']) {
+        return Err(format!("'{}' contains characters not allowed in a path prefix", raw));
+    }
+    Ok(trimmed.to_string())
+}
+
+/// One entry of the route table: mounted by `build_router`, listed by `GET /api` and
+/// the status page
+struct ApiRoute {
+    method: Method,
+    path: &'static str,
+    description: &'static str,
+    router: MethodRouter<Arc<AppState>>,
+}
+
+fn api_route<H, T>(
+    method: Method,
+    path: &'static str,
+    description: &'static str,
+    handler: H,
+) -> ApiRoute
+where
+    H: Handler<T, Arc<AppState>>,
+    T: 'static,
+{
+    let filter = MethodFilter::try_from(method.clone()).expect("route method");
+    ApiRoute {
+        method,
+        path,
+        description,
+        router: on(filter, handler),
+    }
+}
+
+/// Every public route. The admin routes are left out: without a token they don't exist.
+fn api_routes() -> Vec<ApiRoute> {
+    use Method as M;
+    vec![
+        api_route(M::GET, "/api", "This list of endpoints", api_index),
+        api_route(M::GET, "/healthz", "Liveness check", liveness_check),
+        api_route(M::GET, "/health", "Readiness check with provider probes", health_check),
+        api_route(M::GET, "/status", "Human-readable status page", status_page),
+        api_route(M::GET, "/status.json", "The status page as JSON", status_json),
+        api_route(M::GET, "/status/errors.json", "Recent errors", recent_errors_json),
+        api_route(M::GET, "/metrics", "Prometheus metrics", metrics_endpoint),
+        api_route(M::POST, "/api/ai/classify", "Classify a bug", classify_bug),
+        api_route(
+            M::POST,
+            "/api/ai/suggest-response",
+            "Suggest a canned response",
+            suggest_response,
+        ),
+        api_route(M::POST, "/api/ai/generate", "Generate a triage response", generate_response),
+        api_route(M::POST, "/api/ai/refine", "Refine a response", refine_response),
+        api_route(M::POST, "/api/ai/testpage", "Generate a test page", generate_testpage),
+        api_route(
+            M::POST,
+            "/api/ai/explain",
+            "Explain a classification",
+            explain_classification,
+        ),
+        api_route(
+            M::POST,
+            "/api/ai/summarize-comments",
+            "Summarize the comment thread",
+            summarize_comments,
+        ),
+        api_route(
+            M::POST,
+            "/api/ai/rewrite-summary",
+            "Suggest a cleaner bug summary",
+            rewrite_summary,
+        ),
+        api_route(
+            M::GET,
+            "/api/ai/expected-schemas",
+            "Output fields the parsers read",
+            expected_schemas,
+        ),
+        api_route(M::POST, "/api/ai/dry-run", "Prompt size and token estimate", dry_run),
+        api_route(
+            M::POST,
+            "/api/ai/reparse",
+            "Run a captured output through a parser",
+            reparse,
+        ),
+        api_route(
+            M::POST,
+            "/api/ai/selftest",
+            "Check a prompt and schema against the mock provider",
+            selftest,
+        ),
+        api_route(
+            M::GET,
+            "/api/ai/capabilities",
+            "Endpoints each provider supports",
+            provider_capabilities,
+        ),
+        api_route(
+            M::GET,
+            "/api/ai/prompt-version",
+            "Last prompt version seen per endpoint",
+            prompt_version,
+        ),
+        api_route(
+            M::POST,
+            "/api/ai/classify-by-id",
+            "Fetch a bug by id and classify it",
+            classify_by_id,
+        ),
+        api_route(
+            M::POST,
+            "/api/ai/classify-compare",
+            "Classify with several candidates",
+            classify_compare,
+        ),
+        api_route(
+            M::POST,
+            "/api/ai/classify-stream",
+            "Classify over SSE, field by field",
+            classify_stream,
+        ),
+        api_route(
+            M::POST,
+            "/api/ai/stream-classify-by-id",
+            "Fetch a bug by id and classify it over SSE",
+            stream_classify_by_id,
+        ),
+        api_route(
+            M::GET,
+            "/api/history/{bug_id}",
+            "Past classifications of a bug",
+            classification_history,
+        ),
+        api_route(M::GET, "/api/bugzilla/search", "Bug search", bugzilla::search_bugs),
+        api_route(
+            M::GET,
+            "/api/bugzilla/bug/{id}",
+            "Bug with attachments and comments",
+            bugzilla::get_bug,
+        ),
+        api_route(
+            M::POST,
+            "/api/bugzilla/bug/{id}/apply-actions",
+            "Apply triage actions to a bug",
+            bugzilla::apply_actions,
+        ),
+        api_route(
+            M::GET,
+            "/api/bugzilla/bug/{id}/comments",
+            "Page of a bug's comments",
+            bugzilla::get_comments,
+        ),
+        api_route(
+            M::GET,
+            "/api/bugzilla/bug/{id}/attachments",
+            "Attachment metadata",
+            bugzilla::get_attachments,
+        ),
+        api_route(
+            M::GET,
+            "/api/bugzilla/attachment/{id}",
+            "Attachment content",
+            bugzilla::get_attachment_content,
+        ),
+    ]
+}
+
+/// All routes and middleware, nested under `state.base_path` when set. Static files
+/// from `frontend_dir` are the fallback.
+fn build_router(state: Arc<AppState>, frontend_dir: &str) -> Router {
+    let base_path = state.base_path.clone();
+
+    // Configure CORS
+    let cors = CorsLayer::new()
+        .allow_origin(Any)
+        .allow_methods([Method::GET, Method::POST, Method::OPTIONS])
+        .allow_headers([
+            header::CONTENT_TYPE,
+            header::CONTENT_ENCODING,
+            header::AUTHORIZATION,
+            HeaderName::from_static("x-provider"),
+            HeaderName::from_static("x-model"),
+        ]);
+
+    // Static file service with no-cache headers to ensure fresh files during development
+    let static_service = ServeDir::new(frontend_dir).precompressed_gzip();
+    let static_with_cache_control = tower::ServiceBuilder::new()
+        .layer(SetResponseHeaderLayer::overriding(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static("no-cache, no-store, must-revalidate"),
+        ))
+        .service(static_service);
+
+    // Operator endpoints, behind the admin token
+    let admin = Router::new()
+        .route("/admin/requests", get(admin_requests))
+        .route("/admin/requests/{id}/cancel", post(admin_cancel_request))
+        .route_layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            middleware::require_admin,
+        ));
+
+    // Build router - API routes first, then fallback to static files
+    let app = api_routes()
+        .into_iter()
+        .fold(Router::new(), |app, route| app.route(route.path, route.router))
+        .merge(admin)
+        .fallback_service(static_with_cache_control)
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            middleware::postprocess_responses,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            middleware::require_fields,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            middleware::limit_prompt,
+        ))
+        .layer(axum::middleware::from_fn(middleware::provenance))
+        .layer(axum::middleware::from_fn(middleware::prompt_on_error))
+        .layer(axum::middleware::from_fn(middleware::cli_meta))
+        .layer(axum::middleware::from_fn(middleware::echo_bug))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            middleware::cache_responses,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            middleware::record_exchanges,
+        ))
+        .layer(axum::middleware::from_fn(middleware::provider_headers))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            middleware::force_model,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            middleware::limit_proxy_uri,
+        ))
+        // Body extractors stop reading past the limit, and they see the decompressed
+        // body, so a small gzip body can't expand without bound
+        .layer(DefaultBodyLimit::max(state.max_body_bytes))
+        .layer(RequestDecompressionLayer::new())
+        .layer(axum::middleware::from_fn(middleware::body_sizes))
+        .layer(axum::middleware::from_fn(middleware::negotiate_errors))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            middleware::limit_concurrency,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            middleware::track_requests,
+        ))
+        .layer(cors)
+        .layer(axum::middleware::from_fn(middleware::preflight_no_content))
+        .layer(SetResponseHeaderLayer::overriding(
+            header::X_CONTENT_TYPE_OPTIONS,
+            HeaderValue::from_static("nosniff"),
+        ));
+    let app = state
+        .response_headers
+        .iter()
+        .fold(app, |app, (name, value)| {
+            app.layer(SetResponseHeaderLayer::overriding(name.clone(), value.clone()))
+        });
+    let app = match state.content_security_policy.clone() {
+        Some(policy) => app.layer(SetResponseHeaderLayer::if_not_present(
+            header::CONTENT_SECURITY_POLICY,
+            move |response: &axum::response::Response| {
+                is_html(response).then(|| policy.clone())
+            },
+        )),
+        None => app,
+    }
+    .with_state(state);
+
+    if base_path.is_empty() {
+        app
+    } else {
+        Router::new().nest(&base_path, app)
+    }
+}
+
+/// Accept connections and serve the router on each of them.
+///
+/// `axum::serve` doesn't expose hyper's connection settings, so connections are driven
+/// through hyper-util directly. For HTTP/1 the idle timeout is enforced with hyper's header
+/// read timeout, which only runs while waiting for the next request head, so a long-lived
+/// response (such as an SSE stream) is never cut off. HTTP/2 connections use keep-alive
+/// pings to detect dead peers instead. Failing `accept()` calls are retried with a
+/// backoff of up to a second.
+async fn serve(listener: tokio::net::TcpListener, app: Router, idle_timeout: Option<Duration>) {
+    const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+    let mut accept_backoff = Duration::from_millis(10);
+    loop {
+        let (stream, remote_addr) = match listener.accept().await {
+            Ok(conn) => {
+                accept_backoff = Duration::from_millis(10);
+                conn
+            }
+            Err(e) => {
+                // Usually out of file descriptors, which retrying at once won't fix: back
+                // off rather than spin
+                tracing::warn!(
+                    "Failed to accept connection: {}; retrying in {:?}",
+                    e,
+                    accept_backoff
+                );
+                tokio::time::sleep(accept_backoff).await;
+                accept_backoff = (accept_backoff * 2).min(MAX_ACCEPT_BACKOFF);
+                continue;
+            }
+        };
+
+        let service = TowerToHyperService::new(app.clone());
+        tokio::spawn(async move {
+            let mut builder = Builder::new(TokioExecutor::new());
+            builder
+                .http1()
+                .timer(TokioTimer::new())
+                .header_read_timeout(idle_timeout);
+            if let Some(timeout) = idle_timeout {
+                builder
+                    .http2()
+                    .timer(TokioTimer::new())
+                    .keep_alive_interval(timeout)
+                    .keep_alive_timeout(Duration::from_secs(20));
+            }
+
+            if let Err(e) = builder
+                .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                .await
+            {
+                tracing::debug!("Connection from {} closed: {}", remote_addr, e);
+            }
+        });
+    }
+}
+
+/// Liveness probe - returns 200 as long as the server is serving requests.
+/// Deliberately does no provider checks, so a temporarily unauthenticated CLI
+/// never causes an orchestrator to restart the process.
+async fn liveness_check() -> impl IntoResponse {
+    Json(serde_json::json!({ "status": "ok" }))
+}
+
+/// Query parameters for `/health`
+#[derive(Debug, Default, Deserialize)]
+struct HealthQuery {
+    /// Recompute provider availability instead of using the cached result
+    #[serde(default)]
+    refresh: bool,
+}
+
+/// Health check endpoint (readiness) - also reports available AI providers for frontend auto-configuration
+async fn health_check(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<HealthQuery>,
+) -> impl IntoResponse {
+    let uptime_secs = state.started_at.elapsed().unwrap_or_default().as_secs();
+    let started_at = httpdate::fmt_http_date(state.started_at);
+    if !state.ready.load(Ordering::Acquire) {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({
+                "status": "starting",
+                "version": env!("CARGO_PKG_VERSION"),
+                "uptimeSecs": uptime_secs,
+                "startedAt": started_at,
+                "details": "Waiting for the Claude CLI self-test to pass"
+            })),
+        );
+    }
+
+    let (availability, age) = state
+        .provider_availability
+        .get(query.refresh, || provider_availability(&state))
+        .await;
 
     // Determine recommended provider (first available, or none)
-    let recommended_provider = available_providers.first().copied();
+    let recommended_provider = availability.providers.first().copied();
+
+    (
+        StatusCode::OK,
+        Json(serde_json::json!({
+            "status": "ok",
+            "version": env!("CARGO_PKG_VERSION"),
+            "uptimeSecs": uptime_secs,
+            "startedAt": started_at,
+            "availableProviders": availability.providers,
+            "recommendedProvider": recommended_provider,
+            "claudeCli": availability.claude_cli,
+            "frontendServable": availability.frontend_servable,
+            "frontendIndex": availability.frontend_index,
+            "providersCheckedSecsAgo": age.as_secs(),
+            "cliChildren": state.cli.children.len(),
+            "diskDegraded": state.disk.as_ref().is_some_and(|disk| disk.is_degraded()),
+            "httpCalls": state.http_call_limit.as_ref().map(|limit| serde_json::json!({
+                "inFlight": state.max_concurrent_http_calls - limit.available_permits(),
+                "limit": state.max_concurrent_http_calls,
+            })),
+        })),
+    )
+}
 
-    Json(serde_json::json!({
-        "status": "ok",
-        "version": env!("CARGO_PKG_VERSION"),
-        "availableProviders": available_providers,
-        "recommendedProvider": recommended_provider
-    }))
+/// Check which AI providers are available: Claude when its CLI answers or, outside CLI
+/// mode, an Anthropic API key is set, the others when their API key is set. Also checks
+/// the frontend.
+async fn provider_availability(state: &AppState) -> health::Availability {
+    let mut providers: Vec<&'static str> = Vec::new();
+
+    // Check Claude Code CLI
+    let claude_probe = claude_cli::probe_version(&state.cli.program).await;
+
+    // In CLI mode requests never use the API key
+    let claude_api = state.claude_mode != "cli" && state.anthropic_api_key.is_some();
+    if claude_probe.available() || claude_api {
+        providers.push("claude");
+    }
+    if state.gemini_api_key.is_some() {
+        providers.push("gemini");
+    }
+    if state.openai_api_key.is_some() {
+        providers.push("openai");
+    }
+    // Providers whose classify is still a stub can't serve anything
+    let claude_cli = state.claude_mode == "cli";
+    providers.retain(|provider| capabilities::support("classify", provider, claude_cli).is_some());
+
+    let (frontend_servable, frontend_index) = health::frontend_index(&state.frontend_dir).await;
+
+    health::Availability {
+        providers,
+        claude_cli: claude_probe.status(),
+        frontend_servable,
+        frontend_index,
+    }
+}
+
+/// Delay between failed startup self-tests
+const SELF_TEST_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Run the CLI self-test until it passes, then mark the server ready
+async fn self_test_until_ready(state: Arc<AppState>) {
+    loop {
+        info!("Running Claude CLI self-test");
+        match claude_cli::self_test(&state.cli, &state.claude_model).await {
+            Ok(()) => {
+                info!("Claude CLI self-test passed; ready for traffic");
+                state.ready.store(true, Ordering::Release);
+                return;
+            }
+            Err(e) => {
+                warn!(
+                    "Claude CLI self-test failed, retrying in {}s: {} ({})",
+                    SELF_TEST_RETRY_INTERVAL.as_secs(),
+                    e.error,
+                    e.details.as_deref().unwrap_or("no details")
+                );
+                tokio::time::sleep(SELF_TEST_RETRY_INTERVAL).await;
+            }
+        }
+    }
+}
+
+/// What `/status` and `/status.json` report, gathered in one place so the two agree
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct StatusReport {
+    version: &'static str,
+    /// `false` while waiting for the CLI self-test
+    ready: bool,
+    /// `CLAUDE_BACKEND_MODE`
+    mode: String,
+    mode_description: &'static str,
+    claude_cli: CliStatus,
+    endpoints: Vec<EndpointInfo>,
+    recent_errors: Vec<recent_errors::RecordedError>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CliStatus {
+    available: bool,
+    /// `ok`, `error`, `version-unknown` or `not-found`, as in `/health`
+    status: &'static str,
+    /// The version, or what went wrong checking it
+    version: String,
+}
+
+#[derive(Debug, Serialize)]
+struct EndpointInfo {
+    method: String,
+    path: &'static str,
+    description: &'static str,
+}
+
+async fn status_report(state: &AppState) -> StatusReport {
+    let claude_probe = claude_cli::probe_version(&state.cli.program).await;
+    let claude_cli = CliStatus {
+        available: claude_probe.available(),
+        status: claude_probe.status(),
+        version: match claude_probe {
+            claude_cli::VersionProbe::Version(version) => version,
+            claude_cli::VersionProbe::Failed(stderr) => format!("Error: {}", stderr),
+            claude_cli::VersionProbe::TimedOut => {
+                "Found, but --version timed out (version unknown)".to_string()
+            }
+            claude_cli::VersionProbe::NotFound(e) => format!("Not found: {}", e),
+        },
+    };
+
+    StatusReport {
+        version: env!("CARGO_PKG_VERSION"),
+        ready: state.ready.load(Ordering::Acquire),
+        mode: state.claude_mode.clone(),
+        mode_description: if state.claude_mode == "cli" {
+            "Claude Code CLI (recommended)"
+        } else {
+            "HTTP API"
+        },
+        claude_cli,
+        endpoints: api_routes()
+            .iter()
+            .map(|route| EndpointInfo {
+                method: route.method.to_string(),
+                path: route.path,
+                description: route.description,
+            })
+            .collect(),
+        recent_errors: state.recent_errors.snapshot(),
+    }
+}
+
+/// `/status` as JSON, for scripts
+async fn status_json(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(status_report(&state).await)
 }
 
 /// Status page - shows backend configuration and checks
 async fn status_page(State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    // Check if Claude CLI is available
-    let claude_check = tokio::process::Command::new("claude")
-        .arg("--version")
-        .output()
+    let report = status_report(&state).await;
+    let claude_status = if report.claude_cli.available { "✅" } else { "❌" };
+    let server_status = if report.ready {
+        r#"<span class="value ok">✅ Running</span>"#
+    } else {
+        r#"<span class="value">⏳ Starting (waiting for the CLI self-test)</span>"#
+    };
+
+    let recent_errors = &report.recent_errors;
+    let recent_errors_html = if recent_errors.is_empty() {
+        r#"<div class="status-row"><span class="label">None</span></div>"#.to_string()
+    } else {
+        recent_errors
+            .iter()
+            .map(|e| {
+                format!(
+                    r#"<div class="status-row"><span class="label">{time}<br><code>{status} {endpoint}</code><br><small>{request_id}</small></span><span class="value error">{error}{details}</span></div>"#,
+                    time = html_escape(&e.time),
+                    status = e.status,
+                    endpoint = html_escape(&e.endpoint),
+                    request_id = html_escape(&e.request_id),
+                    error = html_escape(&e.error),
+                    details = e
+                        .details
+                        .as_deref()
+                        .map(|d| format!("<br><small>{}</small>", html_escape(d)))
+                        .unwrap_or_default(),
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n        ")
+    };
+
+    let endpoints_html = report
+        .endpoints
+        .iter()
+        .map(|route| {
+            format!(
+                r#"<div class="status-row"><span class="label">{description}</span><span class="value"><code>{method} {path}</code></span></div>"#,
+                description = html_escape(route.description),
+                method = route.method,
+                path = html_escape(route.path),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n        ");
+
+    let html = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+    <title>Triage Wizard - Backend Status</title>
+    <style>
+        body {{ font-family: system-ui, sans-serif; max-width: 700px; margin: 50px auto; padding: 20px; }}
+        h1 {{ color: #333; }}
+        .status-card {{ background: #f8f9fa; border-radius: 8px; padding: 20px; margin: 20px 0; }}
+        .status-row {{ display: flex; justify-content: space-between; padding: 10px 0; border-bottom: 1px solid #eee; }}
+        .status-row:last-child {{ border-bottom: none; }}
+        .label {{ font-weight: 500; color: #555; }}
+        .value {{ font-family: monospace; }}
+        .ok {{ color: #28a745; }}
+        .error {{ color: #dc3545; }}
+        code {{ background: #e9ecef; padding: 2px 6px; border-radius: 3px; font-size: 0.9em; }}
+        a {{ color: #007bff; }}
+        .nav {{ margin-bottom: 20px; }}
+    </style>
+</head>
+<body>
+    <div class="nav">
+        <a href="./">← Back to App</a>
+    </div>
+    <h1>Backend Status</h1>
+
+    <div class="status-card">
+        <h3>Server</h3>
+        <div class="status-row">
+            <span class="label">Status</span>
+            {server_status}
+        </div>
+        <div class="status-row">
+            <span class="label">Version</span>
+            <span class="value">{version}</span>
+        </div>
+        <div class="status-row">
+            <span class="label">Mode</span>
+            <span class="value">{mode_info}</span>
+        </div>
+    </div>
+
+    <div class="status-card">
+        <h3>Claude Code CLI</h3>
+        <div class="status-row">
+            <span class="label">Available</span>
+            <span class="value">{claude_status} {claude_available_text}</span>
+        </div>
+        <div class="status-row">
+            <span class="label">Version</span>
+            <span class="value">{claude_version}</span>
+        </div>
+    </div>
+
+    <div class="status-card">
+        <h3>API Endpoints</h3>
+        {endpoints_html}
+    </div>
+
+    <div class="status-card">
+        <h3>Recent Errors</h3>
+        {recent_errors_html}
+        <p><small>Also available as <a href="status/errors.json">JSON</a>.</small></p>
+    </div>
+
+    <p><small>Refresh this page to re-check status. All of it is also available as
+    <a href="status.json">JSON</a>.</small></p>
+</body>
+</html>"#,
+        version = report.version,
+        server_status = server_status,
+        mode_info = report.mode_description,
+        claude_status = claude_status,
+        claude_available_text = if report.claude_cli.available { "Yes" } else { "No" },
+        claude_version = html_escape(&report.claude_cli.version),
+        recent_errors_html = recent_errors_html,
+        endpoints_html = endpoints_html,
+    );
+
+    axum::response::Html(html)
+}
+
+/// Machine-readable index of the public routes: method, path (under `BASE_PATH`) and a
+/// one-line description
+async fn api_index(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let endpoints: Vec<serde_json::Value> = api_routes()
+        .iter()
+        .map(|route| {
+            serde_json::json!({
+                "method": route.method.as_str(),
+                "path": format!("{}{}", state.base_path, route.path),
+                "description": route.description,
+            })
+        })
+        .collect();
+    Json(serde_json::json!({ "endpoints": endpoints }))
+}
+
+/// Counters in the Prometheus text format
+async fn metrics_endpoint() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics::render(),
+    )
+}
+
+/// Recent errors as JSON, newest first
+async fn recent_errors_json(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(state.recent_errors.snapshot())
+}
+
+/// In-flight API requests, longest-running first
+async fn admin_requests(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(serde_json::json!({ "requests": state.active_requests.snapshot() }))
+}
+
+/// Cancel an in-flight API request; it is answered with 503 `CANCELLED`
+async fn admin_cancel_request(
+    State(state): State<Arc<AppState>>,
+    Path(request_id): Path<String>,
+) -> Result<StatusCode, ErrorResponse> {
+    if state.active_requests.cancel(&request_id) {
+        info!("Cancelled request {} via admin endpoint", request_id);
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ErrorResponse {
+            error: format!("No in-flight request {}", request_id),
+            details: None,
+            status: StatusCode::NOT_FOUND,
+            ..Default::default()
+        })
+    }
+}
+
+/// Escape text for inclusion in HTML
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Fields the backend parsers read from each endpoint's structured output, so the
+/// frontend (or CI) can check `prompts.js` schemas against them
+async fn expected_schemas() -> impl IntoResponse {
+    Json(parse::expected_fields())
+}
+
+/// Run a captured CLI or provider output through an endpoint's parser without calling
+/// the model, to see exactly how the backend reads it
+async fn reparse(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<ReparseRequest>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let structured = parse::structured_result(&request.raw);
+    let result = parse::reparse(
+        &request.endpoint,
+        &structured,
+        &state.priority_convention,
+        state.cli.clean_strings,
+    )
+    .ok_or_else(|| unknown_endpoint(&request.endpoint))??;
+    Ok(Json(serde_json::json!({
+        "endpoint": request.endpoint,
+        "structuredOutput": structured,
+        "result": result,
+    })))
+}
+
+/// 400 `UNKNOWN_ENDPOINT` for an endpoint name that has no parser
+fn unknown_endpoint(endpoint: &str) -> ErrorResponse {
+    ErrorResponse {
+        error: format!("Unknown endpoint {:?}", endpoint),
+        details: Some(format!(
+            "Known endpoints: {}",
+            parse::expected_fields().into_keys().collect::<Vec<_>>().join(", ")
+        )),
+        code: Some("UNKNOWN_ENDPOINT"),
+        status: StatusCode::BAD_REQUEST,
+    }
+}
+
+/// Check a prompt and schema against the backend without a model: the mock provider
+/// answers from the schema, and the answer must satisfy the schema and parse into the
+/// endpoint's response without errors or warnings. Always 200 once the request is
+/// valid; `pass` says whether it would work.
+async fn selftest(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<SelfTestRequest>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    if !parse::expected_fields().contains_key(request.endpoint.as_str()) {
+        return Err(unknown_endpoint(&request.endpoint));
+    }
+    let (_, schema) = claude_cli::prompt_and_schema(
+        &state.cli,
+        &request.endpoint,
+        &serde_json::Value::Null,
+        request.prompt.as_deref(),
+        request.schema.as_deref(),
+    )?;
+    let schema: serde_json::Value = serde_json::from_str(&schema).map_err(|e| ErrorResponse {
+        error: "Invalid schema".to_string(),
+        details: Some(e.to_string()),
+        code: Some("INVALID_SCHEMA"),
+        status: StatusCode::BAD_REQUEST,
+    })?;
+
+    let output = mock::output(&schema);
+    let mut problems = mock::check(&schema, &output);
+    let parsed = parse::reparse(
+        &request.endpoint,
+        &output,
+        &state.priority_convention,
+        state.cli.clean_strings,
+    )
+    .ok_or_else(|| unknown_endpoint(&request.endpoint))?;
+    let result = match parsed {
+        Ok(result) => {
+            let warnings = result.get("parseWarnings").and_then(|w| w.as_array());
+            problems.extend(warnings.into_iter().flatten().map(|w| match w.as_str() {
+                Some(warning) => format!("Parse warning: {}", warning),
+                None => format!("Parse warning: {}", w),
+            }));
+            Some(result)
+        }
+        Err(e) => {
+            problems.push(format!("{}: {}", e.error, e.details.unwrap_or_default()));
+            None
+        }
+    };
+    Ok(Json(serde_json::json!({
+        "endpoint": request.endpoint,
+        "pass": problems.is_empty(),
+        "problems": problems,
+        "output": output,
+        "result": result,
+    })))
+}
+
+/// Last `promptVersion` seen per endpoint, and whether the parsers support it
+async fn prompt_version(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let versions = &state.prompt_versions;
+    let endpoints = versions.seen();
+    Json(serde_json::json!({
+        "compatibleVersions": versions.compatible_versions(),
+        "compatible": endpoints.values().all(|seen| seen.compatible),
+        "endpoints": endpoints,
+    }))
+}
+
+/// Per provider: whether it is configured, how it is served, and the endpoints that
+/// support it, `native` or `proxied` (see `src/capabilities.rs`)
+async fn provider_capabilities(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let configured = configured_providers(&state);
+    let claude_cli = state.claude_mode == "cli";
+    let providers: serde_json::Map<String, serde_json::Value> = capabilities::PROVIDERS
+        .iter()
+        .map(|&provider| {
+            let endpoints: BTreeMap<&str, capabilities::Support> = capabilities::ENDPOINTS
+                .iter()
+                .filter_map(|&endpoint| {
+                    capabilities::support(endpoint, provider, claude_cli).map(|s| (endpoint, s))
+                })
+                .collect();
+            let racing = provider == "fastest";
+            let info = serde_json::json!({
+                "configured": if racing {
+                    !configured.is_empty()
+                } else {
+                    configured.contains(&provider)
+                },
+                "servedVia": (!racing).then(|| state.served_via(provider)),
+                "endpoints": endpoints,
+            });
+            (provider.to_string(), info)
+        })
+        .collect();
+    Json(serde_json::json!({ "providers": providers }))
+}
+
+/// Stored classifications of a bug, newest first (`RESULT_DB_PATH`)
+async fn classification_history(
+    State(state): State<Arc<AppState>>,
+    Path(bug_id): Path<u64>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let history = state.history.as_ref().ok_or_else(|| ErrorResponse {
+        error: "Classification history is disabled".to_string(),
+        details: Some("Set RESULT_DB_PATH to store classifications".to_string()),
+        status: StatusCode::NOT_FOUND,
+        ..Default::default()
+    })?;
+    let classifications = history.for_bug(bug_id).await.map_err(|e| {
+        tracing::error!("Failed to read classification history: {}", e);
+        ErrorResponse {
+            error: "Failed to read classification history".to_string(),
+            details: Some(e),
+            ..Default::default()
+        }
+    })?;
+    Ok(Json(serde_json::json!({
+        "bugId": bug_id,
+        "classifications": classifications,
+    })))
+}
+
+/// Dry run - report prompt size, estimated token count and cost without calling the
+/// model. With `items`, each prompt of a planned batch is estimated and the totals
+/// reported, to preview the spend of a large triage run.
+async fn dry_run(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<DryRunRequest>,
+) -> Json<DryRunResponse> {
+    let model = state.model_or_default(request.model);
+    let estimator = tokens::estimator_for(&model);
+    let estimate = |prompt: &str, schema: Option<&str>| DryRunItemEstimate {
+        prompt_bytes: prompt.len(),
+        estimated_tokens: estimator.estimate(prompt),
+        estimated_cost_usd: budget::estimate_usd(&model, prompt, schema.unwrap_or("")),
+    };
+
+    if request.items.is_empty() {
+        let single = estimate(&request.prompt, request.schema.as_deref());
+        return Json(DryRunResponse {
+            provider: request.provider,
+            prompt_bytes: single.prompt_bytes,
+            estimated_tokens: single.estimated_tokens,
+            token_estimator: estimator.name(),
+            schema_bytes: request.schema.as_ref().map(|s| s.len()),
+            estimated_cost_usd: single.estimated_cost_usd,
+            items: None,
+            model,
+        });
+    }
+
+    let items: Vec<DryRunItemEstimate> = request
+        .items
+        .iter()
+        .map(|item| estimate(&item.prompt, item.schema.as_deref()))
+        .collect();
+    let schema_bytes = request
+        .items
+        .iter()
+        .filter_map(|item| item.schema.as_ref().map(|s| s.len()))
+        .reduce(|total, bytes| total + bytes);
+    Json(DryRunResponse {
+        provider: request.provider,
+        prompt_bytes: items.iter().map(|item| item.prompt_bytes).sum(),
+        estimated_tokens: items.iter().map(|item| item.estimated_tokens).sum(),
+        token_estimator: estimator.name(),
+        schema_bytes,
+        estimated_cost_usd: items.iter().map(|item| item.estimated_cost_usd).sum(),
+        items: Some(items),
+        model,
+    })
+}
+
+/// Classify a bug using AI
+async fn classify_bug(
+    State(state): State<Arc<AppState>>,
+    Json(mut request): Json<ClassifyRequest>,
+) -> Result<Json<ClassifyResponse>, ErrorResponse> {
+    info!("Classify request for provider: {}", request.provider);
+    log_seed(request.settings.seed);
+    state.prompt_versions.record("classify", request.settings.prompt_version.as_deref());
+    if request.settings.include_reasoning == Some(false) {
+        skip_reasoning(&mut request.prompt, "triage_reasoning");
+    }
+
+    let model = state.model_or_default(request.model.clone());
+
+    let provider = request.provider.clone();
+    active::annotate(&provider, &model, active::bug_id(&request.bug));
+    metrics::track("classify", &provider, async move {
+        let runs = request.ensemble.unwrap_or(1).clamp(1, ensemble::MAX_RUNS);
+        let Json(mut response) = if runs > 1 {
+            classify_ensemble(&state, &request, &model, runs).await?
+        } else {
+            classify_once(&state, &request, &model).await?
+        };
+
+        if let Some(ref used_provider) = response.used_provider {
+            active::set_served_via(state.served_via(used_provider));
+        }
+        finish_classification(&state, &request, &mut response)?;
+        record_classification(&state, &request, &model, &response);
+        Ok(Json(response))
+    })
+    .await
+}
+
+/// One classification with the request's provider, or a race for `"fastest"`
+async fn classify_once(
+    state: &AppState,
+    request: &ClassifyRequest,
+    model: &str,
+) -> Result<Json<ClassifyResponse>, ErrorResponse> {
+    if request.provider == "fastest" {
+        return classify_fastest(state, request).await;
+    }
+    let Json(mut response) =
+        classify_with_provider(state, &request.provider, request, model).await?;
+    response.used_provider = Some(request.provider.clone());
+    Ok(Json(response))
+}
+
+/// `ensemble: N` - classify `runs` times, at most `COMPARE_MAX_CONCURRENCY` at once, and
+/// merge the results by majority vote. The cost budget covers the whole ensemble, so
+/// each run gets an equal share, and a run over its share fails the request. Runs that
+/// fail otherwise are left out; the request only fails when all of them do.
+async fn classify_ensemble(
+    state: &AppState,
+    request: &ClassifyRequest,
+    model: &str,
+    runs: usize,
+) -> Result<Json<ClassifyResponse>, ErrorResponse> {
+    info!("Classifying with an ensemble of {} runs", runs);
+    let budget = budget::effective(request.settings.max_cost_usd, state.max_cost_usd);
+    let mut run = request.clone();
+    run.settings.max_cost_usd = budget.map(|usd| usd / runs as f32);
+    let outcomes: Vec<_> = futures_util::stream::iter(0..runs)
+        .map(|_| classify_once(state, &run, model))
+        .buffered(state.compare_max_concurrency)
+        .collect()
         .await;
 
-    let (claude_available, claude_version) = match claude_check {
-        Ok(output) if output.status.success() => {
-            let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
-            (true, version)
+    let mut results = Vec::new();
+    let mut last_error = None;
+    for outcome in outcomes {
+        match outcome {
+            Ok(Json(response)) => results.push(response),
+            Err(e) if e.code == Some(budget::BUDGET_EXCEEDED) => return Err(e),
+            Err(e) => {
+                tracing::warn!("Ensemble run failed: {}", e.error);
+                last_error = Some(e);
+            }
         }
-        Ok(output) => {
-            let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
-            (false, format!("Error: {}", stderr))
+    }
+    if results.is_empty() {
+        return Err(last_error.unwrap_or_default());
+    }
+    let failed = runs - results.len();
+    Ok(Json(ensemble::merge(results, failed)))
+}
+
+/// Store a classification in the history, if it is enabled and the bug has an id
+fn record_classification(
+    state: &AppState,
+    request: &ClassifyRequest,
+    model: &str,
+    response: &ClassifyResponse,
+) {
+    let (Some(history), Some(bug_id)) = (&state.history, active::bug_id(&request.bug)) else {
+        return;
+    };
+    let provider = response
+        .used_provider
+        .clone()
+        .unwrap_or_else(|| request.provider.clone());
+    // The winner of a race ran with its own default model
+    let model = match request.provider.as_str() {
+        "fastest" => state.model_for(&provider, request.model.clone()),
+        _ => model.to_string(),
+    };
+    history.record(history::Record {
+        bug_id,
+        provider,
+        model,
+        result: serde_json::to_value(response).unwrap_or_default(),
+    });
+}
+
+/// Request-dependent checks and extras applied to every classification result, then the
+/// `REQUIRED_FIELDS_classify` check, so incomplete results are never recorded
+fn finish_classification(
+    state: &AppState,
+    request: &ClassifyRequest,
+    response: &mut ClassifyResponse,
+) -> Result<(), ErrorResponse> {
+    if let Some(ref canned_responses) = request.canned_responses {
+        parse::validate_canned_id(response, canned_responses);
+    }
+    if request.render_html {
+        response.draft_response_html =
+            response.draft_response.as_deref().map(render::markdown_to_html);
+    }
+    if request.settings.include_reasoning == Some(false) {
+        response.triage_reasoning = None;
+    }
+    match state.required_fields.get("classify") {
+        Some(fields) => {
+            let result = serde_json::to_value(&*response).unwrap_or_default();
+            required_fields::check(fields, &result)
+        }
+        None => Ok(()),
+    }
+}
+
+/// For `includeReasoning: false`: ask the model to leave the reasoning `field` empty,
+/// saving the tokens and time spent writing it. The field stays in the schema, so
+/// schemas that require it still validate. A missing prompt (from a template) is left
+/// alone.
+fn skip_reasoning(prompt: &mut Option<String>, field: &str) {
+    if let Some(prompt) = prompt {
+        prompt.push_str(&format!(
+            "\n\nDon't explain your answer: set `{}` to an empty string.",
+            field
+        ));
+    }
+}
+
+/// Aborts the spawned task when dropped, e.g. when an SSE client disconnects
+struct AbortOnDrop(tokio::task::JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Classify a bug like `/api/ai/classify`, but over SSE: each top-level field of the
+/// result is sent as a `field` event (`{ name, value }`) as soon as the Claude CLI has
+/// written it, then the complete `ClassifyResponse` as a `result` event, or an `error`
+/// event. Field values are raw model output, except that `ACTION_DENYLIST` actions are
+/// already removed from `suggested_actions`; the `result` event is authoritative.
+/// Other providers, and CLI versions that don't stream partial output, only send the
+/// final event.
+async fn classify_stream(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<ClassifyRequest>,
+) -> impl IntoResponse {
+    info!("Streaming classify request for provider: {}", request.provider);
+    state.prompt_versions.record("classify-stream", request.settings.prompt_version.as_deref());
+    let model = state.model_or_default(request.model.clone());
+    active::annotate(&request.provider, &model, active::bug_id(&request.bug));
+    if streams_fields(&state, &request.provider) {
+        active::set_served_via("cli");
+    }
+
+    event_stream(|events| async move {
+        let result = classify_streaming(state, request, model, "classify-stream", &events).await;
+        send_outcome(&events, result).await;
+    })
+}
+
+/// Like `/api/ai/classify-stream`, for a bug fetched from Bugzilla by id as in
+/// `/api/ai/classify-by-id`: a `fetched` event with the bug comes first, and the
+/// `result` event is the classification plus the bug. A failed fetch is an `error`
+/// event.
+async fn stream_classify_by_id(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<ClassifyByIdRequest>,
+) -> impl IntoResponse {
+    info!(
+        "Streaming classify-by-id request for bug {} with provider: {}",
+        request.id, request.provider
+    );
+    let prompt_version = request.settings.prompt_version.as_deref();
+    state.prompt_versions.record("stream-classify-by-id", prompt_version);
+    let model = state.model_or_default(request.model.clone());
+    active::annotate(&request.provider, &model, Some(request.id));
+    if streams_fields(&state, &request.provider) {
+        active::set_served_via("cli");
+    }
+
+    event_stream(|events| async move {
+        let bug = match bugzilla::fetch_bug(&state, request.id, request.api_key.as_deref()).await
+        {
+            Ok(bug) => bug,
+            Err(e) => return send_outcome::<()>(&events, Err(e)).await,
+        };
+        send_event(&events, "fetched", &bug).await;
+        let classify = request.into_classify(&bug);
+        let endpoint = "stream-classify-by-id";
+        let result = classify_streaming(state, classify, model, endpoint, &events)
+            .await
+            .map(|Json(classification)| Json(ClassifyByIdResponse { classification, bug }));
+        send_outcome(&events, result).await;
+    })
+}
+
+/// Whether classifications by `provider` can stream their fields (the Claude CLI)
+fn streams_fields(state: &AppState, provider: &str) -> bool {
+    provider == "claude" && state.claude_mode == "cli"
+}
+
+/// Classify for an SSE stream, counted under `endpoint`: with the Claude CLI, each
+/// top-level field goes to `events` as a `field` event as soon as it is written; other
+/// providers are classified like `/api/ai/classify`.
+async fn classify_streaming(
+    state: Arc<AppState>,
+    mut request: ClassifyRequest,
+    model: String,
+    endpoint: &'static str,
+    events: &tokio::sync::mpsc::Sender<Event>,
+) -> Result<Json<ClassifyResponse>, ErrorResponse> {
+    if !streams_fields(&state, &request.provider) {
+        return classify_bug(State(state), Json(request)).await;
+    }
+    log_seed(request.settings.seed);
+    // classify_bug does this for the other providers
+    if request.settings.include_reasoning == Some(false) {
+        skip_reasoning(&mut request.prompt, "triage_reasoning");
+    }
+
+    let (fields, mut field_receiver) = tokio::sync::mpsc::channel(32);
+    let provider = request.provider.clone();
+    let classify = metrics::track(endpoint, &provider, async {
+        let cli = state.cli_for_request(&request.settings);
+        let Json(mut response) = claude_cli::classify_bug_streaming(
+            &cli,
+            &request.bug,
+            &model,
+            request.prompt.as_deref(),
+            request.schema.as_deref(),
+            fields,
+            &state.priority_convention,
+        )
+        .await?;
+        response.used_provider = Some(request.provider.clone());
+        state.remove_denied_actions(
+            &mut response.suggested_actions,
+            |a| &a.action,
+            &mut response.parse_warnings,
+        );
+        finish_classification(&state, &request, &mut response)?;
+        record_classification(&state, &request, &model, &response);
+        Ok(Json(response))
+    });
+    let forward = async {
+        while let Some((name, mut value)) = field_receiver.recv().await {
+            // Denied actions must not reach the client before the result strips them
+            if name == "suggested_actions" {
+                if let Some(actions) = value.as_array_mut() {
+                    actions::remove_denied(actions, &state.action_denylist, |action| {
+                        action.get("action").and_then(|a| a.as_str()).unwrap_or_default()
+                    });
+                }
+            }
+            let field = serde_json::json!({ "name": name, "value": value });
+            send_event(events, "field", &field).await;
         }
-        Err(e) => (false, format!("Not found: {}", e)),
     };
+    tokio::join!(classify, forward).0
+}
 
-    let claude_status = if claude_available { "✅" } else { "❌" };
-    let mode_info = if state.claude_mode == "cli" {
-        "Claude Code CLI (recommended)"
-    } else {
-        "HTTP API"
+/// An SSE response with the events `produce` sends. It runs on its own task, owned by
+/// the stream, so a client that disconnects stops it (and any CLI run). The task takes
+/// the request over from the handler: until it ends the request stays in
+/// `/admin/requests`, where cancelling it ends the stream with an `error` event, and it
+/// keeps its `MAX_CONCURRENT_REQUESTS` permit.
+fn event_stream<F, Fut>(produce: F) -> impl IntoResponse
+where
+    F: FnOnce(tokio::sync::mpsc::Sender<Event>) -> Fut,
+    Fut: std::future::Future<Output = ()> + Send + 'static,
+{
+    let (events, receiver) = tokio::sync::mpsc::channel::<Event>(32);
+    let request = active::detach();
+    let on_cancel = events.clone();
+    let produced = produce(events);
+    let task = async move {
+        let Some(request) = request else {
+            return produced.await;
+        };
+        if request.run(produced).await.is_none() {
+            send_event(&on_cancel, "error", &middleware::cancelled()).await;
+        }
     };
+    let task = AbortOnDrop(tokio::spawn(task.instrument(tracing::Span::current())));
 
-    let html = format!(
-        r#"<!DOCTYPE html>
-<html>
-<head>
-    <title>Triage Wizard - Backend Status</title>
-    <style>
-        body {{ font-family: system-ui, sans-serif; max-width: 700px; margin: 50px auto; padding: 20px; }}
-        h1 {{ color: #333; }}
-        .status-card {{ background: #f8f9fa; border-radius: 8px; padding: 20px; margin: 20px 0; }}
-        .status-row {{ display: flex; justify-content: space-between; padding: 10px 0; border-bottom: 1px solid #eee; }}
-        .status-row:last-child {{ border-bottom: none; }}
-        .label {{ font-weight: 500; color: #555; }}
-        .value {{ font-family: monospace; }}
-        .ok {{ color: #28a745; }}
-        .error {{ color: #dc3545; }}
-        code {{ background: #e9ecef; padding: 2px 6px; border-radius: 3px; font-size: 0.9em; }}
-        a {{ color: #007bff; }}
-        .nav {{ margin-bottom: 20px; }}
-    </style>
-</head>
-<body>
-    <div class="nav">
-        <a href="/">← Back to App</a>
-    </div>
-    <h1>Backend Status</h1>
-
-    <div class="status-card">
-        <h3>Server</h3>
-        <div class="status-row">
-            <span class="label">Status</span>
-            <span class="value ok">✅ Running</span>
-        </div>
-        <div class="status-row">
-            <span class="label">Version</span>
-            <span class="value">{version}</span>
-        </div>
-        <div class="status-row">
-            <span class="label">Mode</span>
-            <span class="value">{mode_info}</span>
-        </div>
-    </div>
+    let stream = futures_util::stream::unfold((receiver, task), |(mut receiver, task)| async {
+        let event = receiver.recv().await?;
+        Some((Ok::<_, Infallible>(event), (receiver, task)))
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
 
-    <div class="status-card">
-        <h3>Claude Code CLI</h3>
-        <div class="status-row">
-            <span class="label">Available</span>
-            <span class="value">{claude_status} {claude_available_text}</span>
-        </div>
-        <div class="status-row">
-            <span class="label">Version</span>
-            <span class="value">{claude_version}</span>
-        </div>
-    </div>
+/// Send a `name` event with `data` as JSON; a client that has gone away is ignored
+async fn send_event(
+    events: &tokio::sync::mpsc::Sender<Event>,
+    name: &str,
+    data: &impl Serialize,
+) {
+    if let Ok(event) = Event::default().event(name).json_data(data) {
+        let _ = events.send(event).await;
+    }
+}
 
-    <div class="status-card">
-        <h3>API Endpoints</h3>
-        <div class="status-row">
-            <span class="label">Health</span>
-            <span class="value"><code>GET /health</code></span>
-        </div>
-        <div class="status-row">
-            <span class="label">Classify</span>
-            <span class="value"><code>POST /api/ai/classify</code></span>
-        </div>
-        <div class="status-row">
-            <span class="label">Suggest</span>
-            <span class="value"><code>POST /api/ai/suggest-response</code></span>
-        </div>
-        <div class="status-row">
-            <span class="label">Generate</span>
-            <span class="value"><code>POST /api/ai/generate</code></span>
-        </div>
-        <div class="status-row">
-            <span class="label">Refine</span>
-            <span class="value"><code>POST /api/ai/refine</code></span>
-        </div>
-    </div>
+/// Send the final `result` or `error` event, with `meta` like the `cli_meta` middleware
+/// adds to other responses
+async fn send_outcome<T: Serialize>(
+    events: &tokio::sync::mpsc::Sender<Event>,
+    outcome: Result<Json<T>, ErrorResponse>,
+) {
+    let (name, data) = match outcome {
+        Ok(Json(result)) => ("result", serde_json::to_value(result)),
+        Err(e) => ("error", serde_json::to_value(e)),
+    };
+    let Ok(mut data) = data else {
+        return;
+    };
+    if let (Some(fields), Some(meta)) = (data.as_object_mut(), active::cli_meta()) {
+        fields.insert("meta".to_string(), serde_json::json!(meta));
+    }
+    send_event(events, name, &data).await;
+}
 
-    <p><small>Refresh this page to re-check status.</small></p>
-</body>
-</html>"#,
-        version = env!("CARGO_PKG_VERSION"),
-        mode_info = mode_info,
-        claude_status = claude_status,
-        claude_available_text = if claude_available { "Yes" } else { "No" },
-        claude_version = claude_version,
+/// Fetch a bug from Bugzilla by id, then classify it like `/api/ai/classify`
+async fn classify_by_id(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<ClassifyByIdRequest>,
+) -> Result<Json<ClassifyByIdResponse>, ErrorResponse> {
+    info!(
+        "Classify-by-id request for bug {} with provider: {}",
+        request.id, request.provider
     );
 
-    axum::response::Html(html)
+    let bug = bugzilla::fetch_bug(&state, request.id, request.api_key.as_deref()).await?;
+    let classify = request.into_classify(&bug);
+    let Json(classification) = classify_bug(State(state), Json(classify)).await?;
+
+    Ok(Json(ClassifyByIdResponse {
+        classification,
+        bug,
+    }))
 }
 
-/// Classify a bug using AI
-async fn classify_bug(
-    State(state): State<Arc<AppState>>,
-    Json(request): Json<ClassifyRequest>,
-) -> Result<Json<ClassifyResponse>, ErrorResponse> {
-    info!("Classify request for provider: {}", request.provider);
+/// Log a request's seed so its result can be traced back to it. No provider is sent
+/// it yet: the Claude CLI and Gemini have no seed parameter, and the OpenAI proxy that
+/// would take one is a stub.
+fn log_seed(seed: Option<u64>) {
+    if let Some(seed) = seed {
+        info!("Request seed: {}", seed);
+    }
+}
 
-    let model = request
-        .model
-        .unwrap_or_else(|| state.claude_model.clone());
+/// Substitute `{{bug}}` in a prompt with the bug's JSON, or append the JSON when
+/// the prompt has no placeholder
+fn insert_bug_into_prompt(prompt: &str, bug: &serde_json::Value) -> String {
+    let bug_json = serde_json::to_string_pretty(bug).unwrap_or_default();
+    if prompt.contains("{{bug}}") {
+        prompt.replace("{{bug}}", &bug_json)
+    } else {
+        format!("{}\n\nBug data (JSON):\n{}", prompt, bug_json)
+    }
+}
 
-    // Route to appropriate provider
-    match request.provider.as_str() {
+/// Route a classification to a single provider
+async fn classify_with_provider(
+    state: &AppState,
+    provider: &str,
+    request: &ClassifyRequest,
+    model: &str,
+) -> Result<Json<ClassifyResponse>, ErrorResponse> {
+    let Json(mut response) = match provider {
         "claude" => {
             if state.claude_mode == "cli" {
                 claude_cli::classify_bug(
+                    &state.cli_for_request(&request.settings),
                     &request.bug,
-                    &model,
+                    model,
                     request.prompt.as_deref(),
                     request.schema.as_deref(),
+                    &state.priority_convention,
                 ).await
             } else {
                 // HTTP API mode - requires API key
-                let api_key = state.anthropic_api_key.as_ref().ok_or_else(|| ErrorResponse {
-                    error: "ANTHROPIC_API_KEY not configured".to_string(),
-                    details: None,
-                })?;
-                claude_api_classify(&request.bug, &model, api_key).await
+                let api_key = state
+                    .anthropic_api_key
+                    .as_ref()
+                    .ok_or_else(|| missing_key(state, "claude", "ANTHROPIC_API_KEY"))?;
+                claude_api_classify(state, request, model, api_key).await
             }
         }
         "gemini" => {
-            let api_key = state.gemini_api_key.as_ref().ok_or_else(|| ErrorResponse {
-                error: "GEMINI_API_KEY not configured".to_string(),
-                details: None,
-            })?;
-            gemini_classify(&request.bug, &model, api_key).await
+            let api_key = state
+                .gemini_api_key
+                .as_ref()
+                .ok_or_else(|| missing_key(state, "gemini", "GEMINI_API_KEY"))?;
+            let _permit = state.http_call_permit().await;
+            with_timeout(
+                "Gemini",
+                state.request_timeout("gemini", &request.settings),
+                gemini_classify(&request.bug, model, api_key),
+            )
+            .await
         }
         "openai" => {
-            let api_key = state.openai_api_key.as_ref().ok_or_else(|| ErrorResponse {
-                error: "OPENAI_API_KEY not configured".to_string(),
-                details: None,
-            })?;
-            openai_classify(&request.bug, &model, api_key).await
+            let api_key = state
+                .openai_api_key
+                .as_ref()
+                .ok_or_else(|| missing_key(state, "openai", "OPENAI_API_KEY"))?;
+            let _permit = state.http_call_permit().await;
+            with_timeout(
+                "OpenAI",
+                state.request_timeout("openai", &request.settings),
+                openai_classify(&request.bug, model, request.settings.seed, api_key),
+            )
+            .await
         }
         _ => Err(ErrorResponse {
-            error: format!("Unknown provider: {}", request.provider),
+            error: format!("Unknown provider: {}", provider),
             details: None,
+            status: StatusCode::BAD_REQUEST,
+            ..Default::default()
         }),
+    }?;
+    state.remove_denied_actions(
+        &mut response.suggested_actions,
+        |a| &a.action,
+        &mut response.parse_warnings,
+    );
+    Ok(Json(response))
+}
+
+/// Bound an HTTP provider request by its configured timeout (504 `UPSTREAM_TIMEOUT`)
+async fn with_timeout<T>(
+    upstream: &str,
+    timeout: Option<Duration>,
+    request: impl std::future::Future<Output = Result<T, ErrorResponse>>,
+) -> Result<T, ErrorResponse> {
+    let Some(timeout) = timeout else {
+        return request.await;
+    };
+    tokio::time::timeout(timeout, request)
+        .await
+        .unwrap_or_else(|_| {
+            Err(ErrorResponse {
+                error: format!("{} request timed out", upstream),
+                details: Some(format!("No response after {}s", timeout.as_secs())),
+                code: Some(http_client::UPSTREAM_TIMEOUT),
+                status: StatusCode::GATEWAY_TIMEOUT,
+            })
+        })
+}
+
+/// Providers that are configured to serve requests: Claude in CLI mode or with an API
+/// key, and the other providers when their API key is set
+fn configured_providers(state: &AppState) -> Vec<&'static str> {
+    let mut providers = Vec::new();
+    if state.claude_mode == "cli" || state.anthropic_api_key.is_some() {
+        providers.push("claude");
+    }
+    if state.gemini_api_key.is_some() {
+        providers.push("gemini");
+    }
+    if state.openai_api_key.is_some() {
+        providers.push("openai");
+    }
+    providers
+}
+
+/// Error for a request to `provider` whose API key (`key_var`) isn't set. When other
+/// providers are configured, `details` lists them so the client can offer to switch.
+fn missing_key(state: &AppState, provider: &str, key_var: &str) -> ErrorResponse {
+    let others: Vec<&str> = configured_providers(state)
+        .into_iter()
+        .filter(|other| *other != provider)
+        .collect();
+    ErrorResponse {
+        error: format!("{} not configured", key_var),
+        details: (!others.is_empty())
+            .then(|| format!("Available providers: {}", others.join(", "))),
+        ..Default::default()
+    }
+}
+
+/// `provider: "fastest"` - send the classification to the configured providers that can
+/// classify concurrently and return the first successful result. The losing requests are
+/// dropped, which kills their CLI children (`kill_on_drop`). Each provider runs with its
+/// own default model unless the request names one.
+async fn classify_fastest(
+    state: &AppState,
+    request: &ClassifyRequest,
+) -> Result<Json<ClassifyResponse>, ErrorResponse> {
+    let claude_cli = state.claude_mode == "cli";
+    let providers: Vec<&str> = configured_providers(state)
+        .into_iter()
+        .filter(|provider| capabilities::support("classify", provider, claude_cli).is_some())
+        .take(state.race_max_providers)
+        .collect();
+    if providers.is_empty() {
+        return Err(ErrorResponse {
+            error: "No providers configured to race".to_string(),
+            details: Some("Configure claude CLI mode or an API key".to_string()),
+            ..Default::default()
+        });
     }
+    info!("Racing classification across providers: {:?}", providers);
+
+    let races = providers.into_iter().map(|provider| {
+        Box::pin(async move {
+            // A model is specific to one provider, so only a named one is shared
+            let model = state.model_for(provider, request.model.clone());
+            let Json(mut response) =
+                classify_with_provider(state, provider, request, &model).await?;
+            response.used_provider = Some(provider.to_string());
+            Ok::<_, ErrorResponse>(Json(response))
+        })
+    });
+
+    // On failure of every provider, select_ok yields the last error
+    let (winner, _losers) = select_ok(races).await?;
+    info!(
+        "Fastest provider: {}",
+        winner.used_provider.as_deref().unwrap_or("unknown")
+    );
+    Ok(winner)
+}
+
+/// Model for Gemini requests that don't name one; matches the frontend's default
+const DEFAULT_GEMINI_MODEL: &str = "gemini-2.5-flash";
+
+/// Model for OpenAI requests that don't name one; matches the frontend's default
+const DEFAULT_OPENAI_MODEL: &str = "gpt-4o";
+
+/// Most candidates accepted by one `/api/ai/classify-compare` request
+const MAX_COMPARE_CANDIDATES: usize = 10;
+
+/// Classify one bug with several provider/model candidates for side-by-side comparison.
+/// Candidates run concurrently (up to `COMPARE_MAX_CONCURRENCY`); each reports its own
+/// result or error and its latency. Candidates without a model use their provider's
+/// default. Each candidate running at once counts against `MAX_CONCURRENT_REQUESTS`:
+/// the request's own slot covers one, and the others are taken up front (503
+/// `OVERLOADED` when they aren't free).
+async fn classify_compare(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<ClassifyCompareRequest>,
+) -> Result<Json<ClassifyCompareResponse>, ErrorResponse> {
+    info!("Classify-compare request with {} candidates", request.candidates.len());
+    log_seed(request.settings.seed);
+
+    if request.candidates.is_empty() || request.candidates.len() > MAX_COMPARE_CANDIDATES {
+        return Err(ErrorResponse {
+            error: "Invalid number of candidates".to_string(),
+            details: Some(format!("Expected 1 to {} candidates", MAX_COMPARE_CANDIDATES)),
+            status: StatusCode::BAD_REQUEST,
+            ..Default::default()
+        });
+    }
+
+    let ClassifyCompareRequest {
+        bug,
+        candidates,
+        settings,
+        prompt,
+        schema,
+    } = request;
+    state.prompt_versions.record("classify-compare", settings.prompt_version.as_deref());
+    let extra_slots = candidates.len().min(state.compare_max_concurrency) - 1;
+    let _permits = match state.request_limit {
+        Some(ref limit) if extra_slots > 0 => Some(
+            Arc::clone(limit)
+                .try_acquire_many_owned(extra_slots as u32)
+                .map_err(|_| middleware::overloaded())?,
+        ),
+        _ => None,
+    };
+    let classify = ClassifyRequest {
+        provider: String::new(),
+        model: None,
+        settings,
+        bug,
+        canned_responses: None,
+        render_html: false,
+        ensemble: None,
+        prompt,
+        schema,
+    };
+
+    let state = &state;
+    let classify = &classify;
+    let results = futures_util::stream::iter(candidates)
+        .map(|candidate| async move {
+            let model = state.model_for(&candidate.provider, candidate.model);
+            let start = std::time::Instant::now();
+            let outcome = metrics::track(
+                "classify-compare",
+                &candidate.provider,
+                classify_with_provider(state, &candidate.provider, classify, &model),
+            )
+            .await;
+            let latency_ms = start.elapsed().as_millis() as u64;
+            let (result, error) = match outcome {
+                Ok(Json(response)) => (Some(response), None),
+                Err(e) => (None, Some(e)),
+            };
+            CompareResult {
+                provider: candidate.provider,
+                model,
+                latency_ms,
+                result,
+                error,
+            }
+        })
+        .buffered(state.compare_max_concurrency)
+        .collect()
+        .await;
+
+    Ok(Json(ClassifyCompareResponse { results }))
 }
 
 /// Suggest a response from canned responses using AI
 async fn suggest_response(
     State(state): State<Arc<AppState>>,
-    Json(request): Json<SuggestRequest>,
+    Json(mut request): Json<SuggestRequest>,
 ) -> Result<Json<SuggestResponse>, ErrorResponse> {
     info!("Suggest request for provider: {}", request.provider);
+    log_seed(request.settings.seed);
+    state.prompt_versions.record("suggest-response", request.settings.prompt_version.as_deref());
+    let include_reasoning = request.settings.include_reasoning != Some(false);
+    if !include_reasoning {
+        skip_reasoning(&mut request.prompt, "reasoning");
+    }
 
-    let model = request
-        .model
-        .unwrap_or_else(|| state.claude_model.clone());
-
-    match request.provider.as_str() {
-        "claude" => {
-            if state.claude_mode == "cli" {
-                claude_cli::suggest_response(
-                    &request.bug,
-                    &request.canned_responses,
-                    &model,
-                    request.prompt.as_deref(),
-                    request.schema.as_deref(),
-                ).await
-            } else {
-                let api_key = state.anthropic_api_key.as_ref().ok_or_else(|| ErrorResponse {
-                    error: "ANTHROPIC_API_KEY not configured".to_string(),
-                    details: None,
-                })?;
-                claude_api_suggest(&request.bug, &request.canned_responses, &model, api_key).await
+    let model = state.model_or_default(request.model);
+
+    let provider = request.provider.clone();
+    active::annotate(&provider, &model, active::bug_id(&request.bug));
+    active::set_served_via(state.served_via(&provider));
+    let render_html = request.render_html;
+    metrics::track("suggest-response", &provider, async move {
+        match request.provider.as_str() {
+            "claude" => {
+                if state.claude_mode == "cli" {
+                    claude_cli::suggest_response(
+                        &state.cli_for_request(&request.settings),
+                        &request.bug,
+                        &request.canned_responses,
+                        &model,
+                        request.prompt.as_deref(),
+                        request.schema.as_deref(),
+                    ).await
+                } else {
+                    let api_key = state.anthropic_api_key.as_ref().ok_or_else(|| ErrorResponse {
+                        error: "ANTHROPIC_API_KEY not configured".to_string(),
+                        details: None,
+                        ..Default::default()
+                    })?;
+                    claude_api_suggest(&request.bug, &request.canned_responses, &model, api_key).await
+                }
             }
+            _ => Err(ErrorResponse {
+                error: "Only Claude provider supported for suggest".to_string(),
+                details: None,
+                status: StatusCode::BAD_REQUEST,
+                ..Default::default()
+            }),
         }
-        _ => Err(ErrorResponse {
-            error: "Only Claude provider supported for suggest".to_string(),
-            details: None,
-        }),
-    }
+    })
+    .await
+    .map(|Json(mut response)| {
+        if render_html {
+            response.draft_response_html =
+                Some(render::markdown_to_html(&response.draft_response));
+        }
+        if !include_reasoning {
+            response.reasoning = None;
+        }
+        Json(response)
+    })
 }
 
 /// Generate response endpoint - creates triage comment or action suggestions
 async fn generate_response(
     State(state): State<Arc<AppState>>,
-    Json(request): Json<GenerateRequest>,
+    Json(mut request): Json<GenerateRequest>,
 ) -> Result<Json<GenerateResponse>, ErrorResponse> {
     info!("Generate request for provider: {}", request.provider);
+    log_seed(request.settings.seed);
+    state.prompt_versions.record("generate", request.settings.prompt_version.as_deref());
+    let include_reasoning = request.settings.include_reasoning != Some(false);
+    if !include_reasoning {
+        skip_reasoning(&mut request.prompt, "reasoning");
+    }
 
-    let model = request
-        .model
-        .unwrap_or_else(|| state.claude_model.clone());
-
-    match request.provider.as_str() {
-        "claude" => {
-            if state.claude_mode == "cli" {
-                claude_cli::generate_response(
-                    &request.bug,
-                    &request.options,
-                    &model,
-                    request.prompt.as_deref(),
-                    request.schema.as_deref(),
-                )
-                .await
-            } else if let Some(ref api_key) = state.anthropic_api_key {
-                claude_api_generate(&request.bug, &request.options, &model, api_key).await
-            } else {
-                Err(ErrorResponse {
-                    error: "Anthropic API key not configured".to_string(),
-                    details: Some("Set ANTHROPIC_API_KEY or use CLI mode".to_string()),
-                })
+    let model = state.model_or_default(request.model);
+
+    let provider = request.provider.clone();
+    active::annotate(&provider, &model, active::bug_id(&request.bug));
+    active::set_served_via(state.served_via(&provider));
+    let render_html = request.render_html;
+    metrics::track("generate", &provider, async move {
+        let Json(mut response) = match request.provider.as_str() {
+            "claude" => {
+                if state.claude_mode == "cli" {
+                    claude_cli::generate_response(
+                        &state.cli_for_request(&request.settings),
+                        &request.bug,
+                        &request.options,
+                        &model,
+                        request.prompt.as_deref(),
+                        request.schema.as_deref(),
+                    )
+                    .await
+                } else if let Some(ref api_key) = state.anthropic_api_key {
+                    claude_api_generate(&request.bug, &request.options, &model, api_key).await
+                } else {
+                    Err(ErrorResponse {
+                        error: "Anthropic API key not configured".to_string(),
+                        details: Some("Set ANTHROPIC_API_KEY or use CLI mode".to_string()),
+                        ..Default::default()
+                    })
+                }
             }
+            _ => Err(ErrorResponse {
+                error: "Only Claude provider supported for generate".to_string(),
+                details: None,
+                status: StatusCode::BAD_REQUEST,
+                ..Default::default()
+            }),
+        }?;
+        state.remove_denied_actions(
+            &mut response.suggested_actions,
+            |a| &a.action,
+            &mut response.parse_warnings,
+        );
+        Ok(Json(response))
+    })
+    .await
+    .map(|Json(mut response)| {
+        if render_html {
+            response.response_text_html =
+                Some(render::markdown_to_html(&response.response_text));
         }
-        _ => Err(ErrorResponse {
-            error: "Only Claude provider supported for generate".to_string(),
-            details: None,
-        }),
-    }
+        if !include_reasoning {
+            response.reasoning = None;
+        }
+        Json(response)
+    })
 }
 
 /// Refine response handler
@@ -577,97 +3321,342 @@ async fn refine_response(
     Json(request): Json<RefineRequest>,
 ) -> Result<Json<RefineResponse>, ErrorResponse> {
     info!("Refine request for provider: {}", request.provider);
-
-    let model = request
-        .model
-        .unwrap_or_else(|| state.claude_model.clone());
-
-    match request.provider.as_str() {
-        "claude" => {
-            if state.claude_mode == "cli" {
-                claude_cli::refine_response(
-                    &request.bug,
-                    &request.current_response,
-                    &request.user_instruction,
-                    &request.context,
-                    &model,
-                    request.prompt.as_deref(),
-                    request.schema.as_deref(),
-                )
-                .await
-            } else if let Some(ref api_key) = state.anthropic_api_key {
-                claude_api_refine(
-                    &request.bug,
-                    &request.current_response,
-                    &request.user_instruction,
-                    &request.context,
-                    &model,
-                    api_key,
-                )
-                .await
-            } else {
-                Err(ErrorResponse {
-                    error: "Anthropic API key not configured".to_string(),
-                    details: Some("Set ANTHROPIC_API_KEY or use CLI mode".to_string()),
-                })
+    log_seed(request.settings.seed);
+    state.prompt_versions.record("refine", request.settings.prompt_version.as_deref());
+
+    let model = state.model_or_default(request.model);
+
+    let provider = request.provider.clone();
+    active::annotate(&provider, &model, active::bug_id(&request.bug));
+    active::set_served_via(state.served_via(&provider));
+    let session_id = request.refine_session_id.clone().filter(|id| !id.is_empty());
+    let sessions = Arc::clone(&state.refine_sessions);
+    let refine = metrics::track("refine", &provider, async move {
+        match request.provider.as_str() {
+            "claude" => {
+                if state.claude_mode == "cli" {
+                    claude_cli::refine_response(
+                        &state.cli_for_request(&request.settings),
+                        &request.bug,
+                        &model,
+                        request.prompt.as_deref(),
+                        request.schema.as_deref(),
+                    )
+                    .await
+                } else if let Some(ref api_key) = state.anthropic_api_key {
+                    claude_api_refine(
+                        &request.bug,
+                        &request.current_response,
+                        &request.user_instruction,
+                        &request.context,
+                        &model,
+                        api_key,
+                    )
+                    .await
+                } else {
+                    Err(ErrorResponse {
+                        error: "Anthropic API key not configured".to_string(),
+                        details: Some("Set ANTHROPIC_API_KEY or use CLI mode".to_string()),
+                        ..Default::default()
+                    })
+                }
             }
+            _ => Err(ErrorResponse {
+                error: "Only Claude provider supported for refine".to_string(),
+                details: None,
+                status: StatusCode::BAD_REQUEST,
+                ..Default::default()
+            }),
         }
-        _ => Err(ErrorResponse {
-            error: "Only Claude provider supported for refine".to_string(),
-            details: None,
-        }),
+    });
+
+    match session_id {
+        Some(id) => sessions.run_latest(&id, refine).await,
+        None => refine.await,
     }
 }
 
 /// Generate test page handler
 async fn generate_testpage(
     State(state): State<Arc<AppState>>,
-    Json(request): Json<TestPageRequest>,
+    Json(mut request): Json<TestPageRequest>,
 ) -> Result<Json<TestPageResponse>, ErrorResponse> {
     info!("Test page generation request for provider: {}", request.provider);
+    log_seed(request.settings.seed);
+    state.prompt_versions.record("testpage", request.settings.prompt_version.as_deref());
+
+    let model = state.model_or_default(request.model);
+
+    let provider = request.provider.clone();
+    let bug_id = active::bug_id(&request.bug);
+    active::annotate(&provider, &model, bug_id);
+    active::set_served_via(state.served_via(&provider));
+    let truncated = limit_attachments(
+        &mut request.bug,
+        state.testpage_max_attachments,
+        state.testpage_max_attachment_bytes,
+    );
+    if let Some(ref truncated) = truncated {
+        info!("Test page for bug {:?}: {}", bug_id, truncated);
+    }
+    metrics::track("testpage", &provider, async move {
+        let result = match request.provider.as_str() {
+            "claude" => {
+                if state.claude_mode == "cli" {
+                    claude_cli::generate_testpage(
+                        &state.cli_for_request(&request.settings),
+                        &request.bug,
+                        &model,
+                        request.prompt.as_deref(),
+                        request.schema.as_deref(),
+                    )
+                    .await
+                } else if let Some(ref api_key) = state.anthropic_api_key {
+                    claude_api_testpage(&request.bug, &model, api_key).await
+                } else {
+                    Err(ErrorResponse {
+                        error: "Anthropic API key not configured".to_string(),
+                        details: Some("Set ANTHROPIC_API_KEY or use CLI mode".to_string()),
+                        ..Default::default()
+                    })
+                }
+            }
+            _ => Err(ErrorResponse {
+                error: "Only Claude provider supported for test page generation".to_string(),
+                details: None,
+                status: StatusCode::BAD_REQUEST,
+                ..Default::default()
+            }),
+        };
+        result.map(|Json(response)| {
+            Json(TestPageResponse {
+                attachments_truncated: truncated,
+                ..response
+            })
+        })
+    })
+    .await
+}
 
-    let model = request
-        .model
-        .unwrap_or_else(|| state.claude_model.clone());
+/// Cap the attachments of a bug used for a test page: keep the first `max_count`, in
+/// Bugzilla's (creation) order, and drop the `data` of those over `max_bytes`, keeping
+/// their metadata. 0 means no limit. Returns what was cut, if anything.
+fn limit_attachments(
+    bug: &mut serde_json::Value,
+    max_count: usize,
+    max_bytes: usize,
+) -> Option<String> {
+    let attachments = bug.get_mut("attachments")?.as_array_mut()?;
+    let total = attachments.len();
+    if max_count > 0 {
+        attachments.truncate(max_count);
+    }
+    let mut stripped = 0;
+    for attachment in attachments.iter_mut().filter_map(|a| a.as_object_mut()) {
+        let oversized = attachment
+            .get("data")
+            .and_then(|data| data.as_str())
+            .is_some_and(|data| max_bytes > 0 && data.len() > max_bytes);
+        if oversized {
+            attachment.remove("data");
+            stripped += 1;
+        }
+    }
 
-    match request.provider.as_str() {
-        "claude" => {
-            if state.claude_mode == "cli" {
-                claude_cli::generate_testpage(
-                    &request.bug,
-                    &model,
-                    request.prompt.as_deref(),
-                    request.schema.as_deref(),
-                )
-                .await
-            } else if let Some(ref api_key) = state.anthropic_api_key {
-                claude_api_testpage(&request.bug, &model, api_key).await
-            } else {
-                Err(ErrorResponse {
-                    error: "Anthropic API key not configured".to_string(),
-                    details: Some("Set ANTHROPIC_API_KEY or use CLI mode".to_string()),
-                })
+    let mut cut = Vec::new();
+    if attachments.len() < total {
+        cut.push(format!("kept the first {} of {} attachments", attachments.len(), total));
+    }
+    if stripped > 0 {
+        cut.push(format!("left out the data of {} over {} bytes", stripped, max_bytes));
+    }
+    (!cut.is_empty()).then(|| cut.join("; "))
+}
+
+/// Explain handler - deeper justification of a prior classification
+async fn explain_classification(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<ExplainRequest>,
+) -> Result<Json<ExplainResponse>, ErrorResponse> {
+    info!("Explain request for provider: {}", request.provider);
+    log_seed(request.settings.seed);
+    state.prompt_versions.record("explain", request.settings.prompt_version.as_deref());
+
+    let model = state.model_or_default(request.model);
+
+    let provider = request.provider.clone();
+    active::annotate(&provider, &model, active::bug_id(&request.bug));
+    active::set_served_via(state.served_via(&provider));
+    metrics::track("explain", &provider, async move {
+        match request.provider.as_str() {
+            "claude" => {
+                if state.claude_mode == "cli" {
+                    claude_cli::explain_classification(
+                        &state.cli_for_request(&request.settings),
+                        &request.bug,
+                        &request.classification,
+                        &model,
+                        request.prompt.as_deref(),
+                        request.schema.as_deref(),
+                    )
+                    .await
+                } else if let Some(ref api_key) = state.anthropic_api_key {
+                    claude_api_explain(&request.bug, &request.classification, &model, api_key).await
+                } else {
+                    Err(ErrorResponse {
+                        error: "Anthropic API key not configured".to_string(),
+                        details: Some("Set ANTHROPIC_API_KEY or use CLI mode".to_string()),
+                        ..Default::default()
+                    })
+                }
             }
+            _ => Err(ErrorResponse {
+                error: "Only Claude provider supported for explain".to_string(),
+                details: None,
+                status: StatusCode::BAD_REQUEST,
+                ..Default::default()
+            }),
         }
-        _ => Err(ErrorResponse {
-            error: "Only Claude provider supported for test page generation".to_string(),
-            details: None,
-        }),
-    }
+    })
+    .await
+}
+
+/// Summarize-comments handler - TL;DR of a bug's comment thread
+async fn summarize_comments(
+    State(state): State<Arc<AppState>>,
+    Json(mut request): Json<SummarizeCommentsRequest>,
+) -> Result<Json<SummarizeCommentsResponse>, ErrorResponse> {
+    info!("Summarize comments request for provider: {}", request.provider);
+    log_seed(request.settings.seed);
+    state.prompt_versions.record("summarize-comments", request.settings.prompt_version.as_deref());
+
+    let model = state.model_or_default(request.model);
+    // Long threads are summarized a page at a time, like the comments proxy pages them
+    let query = bugzilla::CommentsQuery {
+        offset: request.comments_offset,
+        limit: request.comments_limit,
+    };
+    let next_comments_offset =
+        bugzilla::page_bug_comments(&mut request.bug, &query, state.bugzilla.comments_max_page);
+
+    let provider = request.provider.clone();
+    active::annotate(&provider, &model, active::bug_id(&request.bug));
+    active::set_served_via(state.served_via(&provider));
+    metrics::track("summarize-comments", &provider, async move {
+        match request.provider.as_str() {
+            "claude" => {
+                if state.claude_mode == "cli" {
+                    claude_cli::summarize_comments(
+                        &state.cli_for_request(&request.settings),
+                        &request.bug,
+                        &model,
+                        request.prompt.as_deref(),
+                        request.schema.as_deref(),
+                    )
+                    .await
+                } else if let Some(ref api_key) = state.anthropic_api_key {
+                    claude_api_summarize_comments(&request.bug, &model, api_key).await
+                } else {
+                    Err(ErrorResponse {
+                        error: "Anthropic API key not configured".to_string(),
+                        details: Some("Set ANTHROPIC_API_KEY or use CLI mode".to_string()),
+                        ..Default::default()
+                    })
+                }
+            }
+            _ => Err(ErrorResponse {
+                error: "Only Claude provider supported for summarize-comments".to_string(),
+                details: None,
+                status: StatusCode::BAD_REQUEST,
+                ..Default::default()
+            }),
+        }
+        .map(|Json(response)| {
+            Json(SummarizeCommentsResponse {
+                next_comments_offset,
+                ..response
+            })
+        })
+    })
+    .await
+}
+
+/// Rewrite-summary handler - a proposed replacement for the bug's summary line
+async fn rewrite_summary(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<RewriteSummaryRequest>,
+) -> Result<Json<RewriteSummaryResponse>, ErrorResponse> {
+    info!("Rewrite summary request for provider: {}", request.provider);
+    log_seed(request.settings.seed);
+    state.prompt_versions.record("rewrite-summary", request.settings.prompt_version.as_deref());
+
+    let model = state.model_or_default(request.model);
+
+    let provider = request.provider.clone();
+    active::annotate(&provider, &model, active::bug_id(&request.bug));
+    active::set_served_via(state.served_via(&provider));
+    metrics::track("rewrite-summary", &provider, async move {
+        match request.provider.as_str() {
+            "claude" => {
+                if state.claude_mode == "cli" {
+                    claude_cli::rewrite_summary(
+                        &state.cli_for_request(&request.settings),
+                        &request.bug,
+                        &model,
+                        request.prompt.as_deref(),
+                        request.schema.as_deref(),
+                    )
+                    .await
+                } else {
+                    Err(ErrorResponse {
+                        error: "Claude HTTP API mode not yet implemented - use CLI mode"
+                            .to_string(),
+                        details: Some("Set CLAUDE_BACKEND_MODE=cli".to_string()),
+                        ..Default::default()
+                    })
+                }
+            }
+            _ => Err(ErrorResponse {
+                error: "Only Claude provider supported for rewrite-summary".to_string(),
+                details: None,
+                status: StatusCode::BAD_REQUEST,
+                ..Default::default()
+            }),
+        }
+    })
+    .await
 }
 
 // Placeholder implementations for HTTP API calls
 // These can be expanded later if needed
 
 async fn claude_api_classify(
-    _bug: &serde_json::Value,
-    _model: &str,
-    _api_key: &str,
+    state: &AppState,
+    request: &ClassifyRequest,
+    model: &str,
+    api_key: &str,
 ) -> Result<Json<ClassifyResponse>, ErrorResponse> {
-    Err(ErrorResponse {
-        error: "Claude HTTP API mode not yet implemented - use CLI mode".to_string(),
-        details: Some("Set CLAUDE_BACKEND_MODE=cli".to_string()),
-    })
+    let (prompt, schema) = claude_cli::prompt_and_schema(
+        &state.cli,
+        "classify",
+        &request.bug,
+        request.prompt.as_deref(),
+        request.schema.as_deref(),
+    )?;
+    let _permit = state.http_call_permit().await;
+    let result = anthropic::structured_request(
+        &state.anthropic,
+        &state.http_client,
+        api_key,
+        model,
+        &prompt,
+        &schema,
+        state.request_timeout("claude", &request.settings),
+        budget::effective(request.settings.max_cost_usd, state.max_cost_usd),
+    )
+    .await?;
+    let fields = parse::Fields::new(&result).cleaning(state.cli.clean_strings);
+    Ok(Json(parse::parse_classify(&fields, &state.priority_convention)))
 }
 
 async fn claude_api_suggest(
@@ -679,6 +3668,7 @@ async fn claude_api_suggest(
     Err(ErrorResponse {
         error: "Claude HTTP API mode not yet implemented - use CLI mode".to_string(),
         details: Some("Set CLAUDE_BACKEND_MODE=cli".to_string()),
+        ..Default::default()
     })
 }
 
@@ -691,6 +3681,7 @@ async fn claude_api_generate(
     Err(ErrorResponse {
         error: "Claude HTTP API mode not yet implemented - use CLI mode".to_string(),
         details: Some("Set CLAUDE_BACKEND_MODE=cli".to_string()),
+        ..Default::default()
     })
 }
 
@@ -705,6 +3696,7 @@ async fn claude_api_refine(
     Err(ErrorResponse {
         error: "Claude HTTP API mode not yet implemented - use CLI mode".to_string(),
         details: Some("Set CLAUDE_BACKEND_MODE=cli".to_string()),
+        ..Default::default()
     })
 }
 
@@ -716,6 +3708,32 @@ async fn claude_api_testpage(
     Err(ErrorResponse {
         error: "Claude HTTP API mode not yet implemented - use CLI mode".to_string(),
         details: Some("Set CLAUDE_BACKEND_MODE=cli".to_string()),
+        ..Default::default()
+    })
+}
+
+async fn claude_api_summarize_comments(
+    _bug: &serde_json::Value,
+    _model: &str,
+    _api_key: &str,
+) -> Result<Json<SummarizeCommentsResponse>, ErrorResponse> {
+    Err(ErrorResponse {
+        error: "Claude HTTP API mode not yet implemented - use CLI mode".to_string(),
+        details: Some("Set CLAUDE_BACKEND_MODE=cli".to_string()),
+        ..Default::default()
+    })
+}
+
+async fn claude_api_explain(
+    _bug: &serde_json::Value,
+    _classification: &ClassifyResponse,
+    _model: &str,
+    _api_key: &str,
+) -> Result<Json<ExplainResponse>, ErrorResponse> {
+    Err(ErrorResponse {
+        error: "Claude HTTP API mode not yet implemented - use CLI mode".to_string(),
+        details: Some("Set CLAUDE_BACKEND_MODE=cli".to_string()),
+        ..Default::default()
     })
 }
 
@@ -727,16 +3745,20 @@ async fn gemini_classify(
     Err(ErrorResponse {
         error: "Gemini backend proxy not yet implemented - use browser mode".to_string(),
         details: None,
+        ..Default::default()
     })
 }
 
+/// `seed` is for the OpenAI `seed` parameter, once this proxy is implemented
 async fn openai_classify(
     _bug: &serde_json::Value,
     _model: &str,
+    _seed: Option<u64>,
     _api_key: &str,
 ) -> Result<Json<ClassifyResponse>, ErrorResponse> {
     Err(ErrorResponse {
         error: "OpenAI backend proxy not yet implemented".to_string(),
         details: None,
+        ..Default::default()
     })
 }
@@ -0,0 +1,263 @@
+//! Process-wide metrics
+//!
+//! A small counter and histogram registry rendered in the Prometheus text format at
+//! `/metrics`. It is a global so code paths without access to `AppState` (like the CLI
+//! runner) can count events.
+//!
+//! Label values are `&'static str` on purpose: they must come from fixed sets (provider
+//! names via [`provider_label`], endpoint names, error codes), never from request or
+//! error text, so series cardinality stays bounded.
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::ErrorResponse;
+
+type Labels = Vec<(&'static str, &'static str)>;
+
+/// Upper bounds (seconds) of the latency histogram buckets - AI requests run from a
+/// second or two up to several minutes
+const BUCKETS: &[f64] = &[0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0];
+
+/// Upper bounds (bytes) of the body size histogram buckets, 1 KiB to 16 MiB
+pub const SIZE_BUCKETS: &[f64] = &[
+    1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0, 4194304.0, 16777216.0,
+];
+
+/// Providers that get their own label value; anything else is `"other"`
+const PROVIDERS: &[&str] = &["claude", "gemini", "openai", "fastest"];
+
+/// `/api/ai/*` endpoints that get their own label value in [`endpoint_label`]
+const AI_ENDPOINTS: &[&str] = &[
+    "classify",
+    "suggest-response",
+    "generate",
+    "refine",
+    "testpage",
+    "explain",
+    "summarize-comments",
+    "rewrite-summary",
+    "expected-schemas",
+    "dry-run",
+    "reparse",
+    "selftest",
+    "prompt-version",
+    "capabilities",
+    "classify-by-id",
+    "classify-compare",
+    "classify-stream",
+    "stream-classify-by-id",
+];
+
+#[derive(Default)]
+struct Histogram {
+    /// Upper bounds of the buckets
+    bounds: &'static [f64],
+    /// Cumulative count per entry of `bounds`
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+static COUNTERS: Mutex<BTreeMap<(&'static str, Labels), u64>> = Mutex::new(BTreeMap::new());
+static HISTOGRAMS: Mutex<BTreeMap<(&'static str, Labels), Histogram>> =
+    Mutex::new(BTreeMap::new());
+
+/// Increment a counter
+pub fn inc(name: &'static str) {
+    inc_with(name, &[]);
+}
+
+/// Increment a labeled counter
+pub fn inc_with(name: &'static str, labels: &[(&'static str, &'static str)]) {
+    *COUNTERS
+        .lock()
+        .unwrap()
+        .entry((name, labels.to_vec()))
+        .or_insert(0) += 1;
+}
+
+/// Record an observation in a labeled latency histogram
+pub fn observe(name: &'static str, labels: &[(&'static str, &'static str)], seconds: f64) {
+    observe_in(name, labels, BUCKETS, seconds);
+}
+
+/// Record an observation in a labeled histogram with buckets `bounds`. A histogram
+/// keeps the bounds it was first observed with.
+pub fn observe_in(
+    name: &'static str,
+    labels: &[(&'static str, &'static str)],
+    bounds: &'static [f64],
+    value: f64,
+) {
+    let mut histograms = HISTOGRAMS.lock().unwrap();
+    let histogram = histograms.entry((name, labels.to_vec())).or_default();
+    if histogram.buckets.is_empty() {
+        histogram.bounds = bounds;
+        histogram.buckets = vec![0; bounds.len()];
+    }
+    for (bucket, bound) in histogram.buckets.iter_mut().zip(histogram.bounds) {
+        if value <= *bound {
+            *bucket += 1;
+        }
+    }
+    histogram.sum += value;
+    histogram.count += 1;
+}
+
+/// Bounded label value for a requested provider name
+pub fn provider_label(provider: &str) -> &'static str {
+    PROVIDERS
+        .iter()
+        .find(|p| **p == provider)
+        .copied()
+        .unwrap_or("other")
+}
+
+/// Bounded label value for a request path: the endpoint name for `/api/ai/*` routes,
+/// `"bugzilla"` for the Bugzilla proxy, `"other"` for anything else
+pub fn endpoint_label(path: &str) -> &'static str {
+    if let Some(name) = path.strip_prefix("/api/ai/") {
+        AI_ENDPOINTS.iter().find(|e| **e == name).copied().unwrap_or("other")
+    } else if path.starts_with("/api/bugzilla/") {
+        "bugzilla"
+    } else {
+        "other"
+    }
+}
+
+/// Run an AI request, counting it in `ai_requests_total`, its failures in
+/// `ai_request_errors_total` (by error code), and its latency in
+/// `ai_request_duration_seconds`, all labeled by provider and endpoint
+pub async fn track<T>(
+    endpoint: &'static str,
+    provider: &str,
+    request: impl Future<Output = Result<T, ErrorResponse>>,
+) -> Result<T, ErrorResponse> {
+    let labels = [("provider", provider_label(provider)), ("endpoint", endpoint)];
+    let start = Instant::now();
+    let result = request.await;
+
+    inc_with("ai_requests_total", &labels);
+    observe("ai_request_duration_seconds", &labels, start.elapsed().as_secs_f64());
+    if let Err(ref e) = result {
+        let [provider, endpoint] = labels;
+        inc_with(
+            "ai_request_errors_total",
+            &[provider, endpoint, ("error_kind", e.code.unwrap_or("OTHER"))],
+        );
+    }
+    result
+}
+
+fn render_labels(labels: &[(&str, &str)], extra: Option<(&str, &str)>) -> String {
+    let pairs: Vec<String> = labels
+        .iter()
+        .chain(extra.as_ref())
+        .map(|(key, value)| format!("{}=\"{}\"", key, value))
+        .collect();
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+/// All metrics in the Prometheus text exposition format
+pub fn render() -> String {
+    let mut out = String::new();
+
+    let counters = COUNTERS.lock().unwrap();
+    let mut last_name = None;
+    for ((name, labels), value) in counters.iter() {
+        if last_name != Some(*name) {
+            out.push_str(&format!("# TYPE {} counter\n", name));
+            last_name = Some(*name);
+        }
+        out.push_str(&format!("{}{} {}\n", name, render_labels(labels, None), value));
+    }
+
+    let histograms = HISTOGRAMS.lock().unwrap();
+    let mut last_name = None;
+    for ((name, labels), histogram) in histograms.iter() {
+        if last_name != Some(*name) {
+            out.push_str(&format!("# TYPE {} histogram\n", name));
+            last_name = Some(*name);
+        }
+        for (count, bound) in histogram.buckets.iter().zip(histogram.bounds) {
+            let le = bound.to_string();
+            out.push_str(&format!(
+                "{}_bucket{} {}\n",
+                name,
+                render_labels(labels, Some(("le", &le))),
+                count
+            ));
+        }
+        out.push_str(&format!(
+            "{}_bucket{} {}\n",
+            name,
+            render_labels(labels, Some(("le", "+Inf"))),
+            histogram.count
+        ));
+        out.push_str(&format!("{}_sum{} {}\n", name, render_labels(labels, None), histogram.sum));
+        out.push_str(&format!(
+            "{}_count{} {}\n",
+            name,
+            render_labels(labels, None),
+            histogram.count
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_labeled_series() {
+        let labels = [("provider", provider_label("claude")), ("endpoint", "test")];
+        inc_with("test_labeled_total", &labels);
+        inc_with("test_labeled_total", &labels);
+        observe("test_duration_seconds", &labels, 3.0);
+
+        let out = render();
+        assert!(out.contains("test_labeled_total{provider=\"claude\",endpoint=\"test\"} 2\n"));
+        assert!(out.contains(
+            "test_duration_seconds_bucket{provider=\"claude\",endpoint=\"test\",le=\"2.5\"} 0\n"
+        ));
+        assert!(out.contains(
+            "test_duration_seconds_bucket{provider=\"claude\",endpoint=\"test\",le=\"5\"} 1\n"
+        ));
+        assert!(out.contains("test_duration_seconds_count{provider=\"claude\",endpoint=\"test\"} 1\n"));
+    }
+
+    #[test]
+    fn size_histogram_uses_its_own_buckets() {
+        let labels = [("endpoint", "test"), ("direction", "request")];
+        observe_in("test_body_size_bytes", &labels, SIZE_BUCKETS, 5000.0);
+
+        let out = render();
+        assert!(out.contains(
+            "test_body_size_bytes_bucket{endpoint=\"test\",direction=\"request\",le=\"4096\"} 0\n"
+        ));
+        assert!(out.contains(
+            "test_body_size_bytes_bucket{endpoint=\"test\",direction=\"request\",le=\"16384\"} 1\n"
+        ));
+    }
+
+    #[test]
+    fn endpoint_labels_are_bounded() {
+        assert_eq!(endpoint_label("/api/ai/classify"), "classify");
+        assert_eq!(endpoint_label("/api/ai/whatever-you-like"), "other");
+        assert_eq!(endpoint_label("/api/bugzilla/bug/123"), "bugzilla");
+    }
+
+    #[test]
+    fn unknown_providers_share_one_label() {
+        assert_eq!(provider_label("gemini"), "gemini");
+        assert_eq!(provider_label("my-custom-llm"), "other");
+    }
+}
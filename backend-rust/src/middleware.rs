@@ -0,0 +1,908 @@
+//! Request middleware
+
+use std::sync::Arc;
+
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    extract::{FromRequest, Request, State},
+    http::{header, request, HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tracing::{debug, info, info_span, warn, Instrument};
+
+use crate::redact::redact;
+use crate::{
+    active, metrics, prompt_limit, required_fields, response_cache, transcript, AppState,
+    ErrorResponse,
+};
+
+/// Id assigned by [`track_requests`], available to inner layers as a request extension
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+/// Request extension set by [`track_requests`] on requests picked by `LOG_SAMPLE_RATE`
+#[derive(Debug, Clone, Copy)]
+pub struct LogSampled;
+
+/// Assign a request id (echoed as `X-Request-Id`), register `/api/*` requests as
+/// in flight for `/admin/requests`, and record error responses in the recent-errors
+/// buffer
+pub async fn track_requests(
+    State(state): State<Arc<AppState>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let request_id = uuid::Uuid::new_v4().to_string();
+    let endpoint = request.uri().path().to_string();
+    request.extensions_mut().insert(RequestId(request_id.clone()));
+    // Decided once here so every layer agrees on whether this request is sampled
+    let sampled = state.log_sample_rate > 0.0 && fastrand::f64() < state.log_sample_rate;
+    if sampled {
+        request.extensions_mut().insert(LogSampled);
+    }
+    let span = info_span!("request", id = %request_id, sampled);
+
+    let mut response = if endpoint.starts_with("/api/") {
+        match state
+            .active_requests
+            .run(&request_id, &endpoint, next.run(request))
+            .instrument(span)
+            .await
+        {
+            Some(response) => response,
+            None => cancelled().into_response(),
+        }
+    } else {
+        next.run(request).instrument(span).await
+    };
+
+    if let Some(error) = response.extensions().get::<ErrorResponse>() {
+        state.recent_errors.record(&request_id, &endpoint, error);
+    }
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert("x-request-id", value);
+    }
+    response
+}
+
+/// Largest response body captured for the transcript or a sampled log; requests are
+/// limited to `MAX_BODY_BYTES` like everywhere else
+const EXCHANGE_BODY_LIMIT: usize = 16 * 1024 * 1024;
+
+/// Longest prompt or response written by a sampled log line
+const SAMPLED_LOG_MAX_BYTES: usize = 64 * 1024;
+
+/// Log each `/api/ai/*` POST once it completes: metadata (provider, model, bug, status,
+/// duration) always, plus the redacted prompt and response for requests picked by
+/// `LOG_SAMPLE_RATE`. Also appends it to the transcript when `TRANSCRIPT_PATH` is set.
+/// Bodies are only buffered when sampled or transcribed, and event streams never are.
+pub async fn record_exchanges(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let endpoint = request.uri().path().to_string();
+    if request.method() != Method::POST || !endpoint.starts_with("/api/ai/") {
+        return next.run(request).await;
+    }
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .map(|id| id.0.clone())
+        .unwrap_or_default();
+    let sampled = request.extensions().get::<LogSampled>().is_some();
+    let start = std::time::Instant::now();
+
+    if state.transcript.is_none() && !sampled {
+        let response = next.run(request).await;
+        log_exchange_metadata(&endpoint, response.status(), start.elapsed().as_millis());
+        return response;
+    }
+
+    let (parts, bytes) = match read_request_body(request).await {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    let fields: serde_json::Value = serde_json::from_slice(&bytes).unwrap_or_default();
+    let field = |name: &str| fields.get(name).and_then(|v| v.as_str()).map(str::to_string);
+
+    let response = next.run(Request::from_parts(parts, Body::from(bytes.clone()))).await;
+    let duration_ms = start.elapsed().as_millis();
+    log_exchange_metadata(&endpoint, response.status(), duration_ms);
+    // Buffering an event stream would hold back every event until the last one
+    let is_event_stream = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|v| v.as_bytes().starts_with(b"text/event-stream"));
+    if is_event_stream {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = match axum::body::to_bytes(body, EXCHANGE_BODY_LIMIT).await {
+        Ok(body) => body,
+        Err(e) => {
+            warn!("Failed to read {} response for logging: {}", endpoint, e);
+            return unreadable_response("logging", e);
+        }
+    };
+
+    if sampled {
+        let prompt = field("prompt").unwrap_or_default();
+        info!(
+            prompt = %transcript::truncate_field(&redact(&prompt), SAMPLED_LOG_MAX_BYTES),
+            response = %transcript::truncate_field(
+                &redact(&String::from_utf8_lossy(&body)),
+                SAMPLED_LOG_MAX_BYTES
+            ),
+            "Sampled AI exchange"
+        );
+    }
+    if let Some(ref transcript) = state.transcript {
+        transcript
+            .write(transcript::Record {
+                request_id,
+                endpoint,
+                provider: field("provider"),
+                model: field("model"),
+                bug_id: fields.get("bug").and_then(active::bug_id),
+                status: parts.status.as_u16(),
+                duration_ms: duration_ms as u64,
+                prompt: field("prompt"),
+                result: serde_json::from_slice(&body)
+                    .unwrap_or_else(|_| String::from_utf8_lossy(&body).into()),
+            });
+    }
+    Response::from_parts(parts, Body::from(body))
+}
+
+/// The always-logged summary line of an AI request, from what its handler annotated
+fn log_exchange_metadata(endpoint: &str, status: StatusCode, duration_ms: u128) {
+    let (provider, model, bug_id) = active::current_details().unwrap_or_default();
+    info!(
+        endpoint,
+        provider = provider.as_deref().unwrap_or("-"),
+        model = model.as_deref().unwrap_or("-"),
+        bug_id = bug_id.map_or("-".to_string(), |id| id.to_string()),
+        status = status.as_u16(),
+        duration_ms = duration_ms as u64,
+        "AI request completed"
+    );
+}
+
+/// 500 for a response whose body couldn't be read for `purpose`. Its headers, such as
+/// `Content-Length`, described the lost body, so none of them are kept.
+fn unreadable_response(purpose: &str, e: axum::Error) -> Response {
+    ErrorResponse {
+        error: format!("Failed to read response for {}", purpose),
+        details: Some(e.to_string()),
+        ..Default::default()
+    }
+    .into_response()
+}
+
+/// Largest response body [`add_response_field`] will add a field to
+const ADDED_FIELD_BODY_LIMIT: usize = 16 * 1024 * 1024;
+
+/// Add `name` to a successful JSON object response. Other responses, including event
+/// streams, are passed through.
+async fn add_response_field(response: Response, name: &str, value: serde_json::Value) -> Response {
+    if !response.status().is_success() {
+        return response;
+    }
+    add_json_field(response, name, value).await
+}
+
+/// Add `name` to a JSON object response, error responses included. Other responses,
+/// including event streams, are passed through.
+async fn add_json_field(response: Response, name: &str, value: serde_json::Value) -> Response {
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|v| v.as_bytes().starts_with(b"application/json"));
+    if !is_json {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match axum::body::to_bytes(body, ADDED_FIELD_BODY_LIMIT).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            warn!("Failed to read response to add {}: {}", name, e);
+            return unreadable_response(&format!("adding {}", name), e);
+        }
+    };
+    let body = match serde_json::from_slice::<serde_json::Value>(&bytes) {
+        Ok(serde_json::Value::Object(mut fields)) => {
+            fields.insert(name.to_string(), value);
+            parts.headers.remove(header::CONTENT_LENGTH);
+            Body::from(serde_json::Value::Object(fields).to_string())
+        }
+        _ => Body::from(bytes),
+    };
+    Response::from_parts(parts, body)
+}
+
+/// With `?provenance=true`, add `servedVia` (`"cli"` or `"api"`, as recorded by the
+/// handler) to successful JSON object responses. Without it responses are untouched.
+pub async fn provenance(request: Request, next: Next) -> Response {
+    let requested = request
+        .uri()
+        .query()
+        .is_some_and(|query| query.split('&').any(|pair| pair == "provenance=true"));
+    let response = next.run(request).await;
+    match active::served_via().filter(|_| requested) {
+        Some(served_via) => add_response_field(response, "servedVia", served_via.into()).await,
+        None => response,
+    }
+}
+
+/// With `?includePromptOnError=true`, append the prompt that was actually sent to the
+/// provider, redacted, to the `details` of an error response. Errors raised before any
+/// prompt was sent are untouched.
+pub async fn prompt_on_error(request: Request, next: Next) -> Response {
+    let requested = request.uri().query().is_some_and(|query| {
+        query.split('&').any(|pair| pair == "includePromptOnError=true")
+    });
+    if !requested {
+        return next.run(request).await;
+    }
+    active::capture_prompt();
+    let response = next.run(request).await;
+    let (Some(error), Some(prompt)) =
+        (response.extensions().get::<ErrorResponse>(), active::prompt_sent())
+    else {
+        return response;
+    };
+    let mut error = error.clone();
+    let prompt = format!("Prompt sent:\n{}", redact(&prompt));
+    error.details = Some(match error.details.take() {
+        Some(details) => format!("{}\n\n{}", details, prompt),
+        None => prompt,
+    });
+    // Replace the body only, keeping headers set by inner layers
+    let (mut parts, _) = response.into_parts();
+    parts.headers.remove(header::CONTENT_LENGTH);
+    let body = Body::from(serde_json::to_string(&error).unwrap_or_default());
+    parts.extensions.insert(error);
+    Response::from_parts(parts, body)
+}
+
+/// Add `meta` (`costUsd`, `durationMs`, `numTurns`, as reported by the claude CLI and
+/// totalled over the request's runs) to JSON object responses, errors included: a failed
+/// run costs too. Responses not produced by the CLI, or whose CLI reported none of them,
+/// are untouched. Event streams send it with their final event instead.
+pub async fn cli_meta(request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    match active::cli_meta().and_then(|meta| serde_json::to_value(meta).ok()) {
+        Some(meta) => add_json_field(response, "meta", meta).await,
+        None => response,
+    }
+}
+
+/// The field `echoBug` in an AI request body asks to have added to the response:
+/// `true` echoes the request's `bug` as `bug`, `"id"` only its id as `bugId`.
+/// `classify-by-id`, which has no `bug`, echoes its `id` as `bugId` either way.
+fn echoed_bug(fields: &serde_json::Value) -> Option<(&'static str, serde_json::Value)> {
+    let bug = fields.get("bug").filter(|bug| !bug.is_null());
+    let id = || {
+        bug.and_then(active::bug_id)
+            .map(serde_json::Value::from)
+            .or_else(|| fields.get("id").cloned())
+            .map(|id| ("bugId", id))
+    };
+    match fields.get("echoBug")? {
+        serde_json::Value::Bool(true) => bug.map(|bug| ("bug", bug.clone())).or_else(id),
+        serde_json::Value::String(only) if only == "id" => id(),
+        _ => None,
+    }
+}
+
+/// With `echoBug` set in an `/api/ai/*` request body, add the request's bug (or its
+/// id) to a successful JSON object response, so clients firing many requests can match
+/// results to bugs. Event streams and responses without the flag are untouched, and
+/// bodies that don't mention `echoBug` aren't parsed.
+pub async fn echo_bug(request: Request, next: Next) -> Response {
+    if request.method() != Method::POST || !request.uri().path().starts_with("/api/ai/") {
+        return next.run(request).await;
+    }
+
+    let (parts, bytes) = match read_request_body(request).await {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    let mentions_flag = bytes.windows(b"\"echoBug\"".len()).any(|w| w == b"\"echoBug\"");
+    let echo = mentions_flag
+        .then(|| serde_json::from_slice::<serde_json::Value>(&bytes).ok())
+        .flatten()
+        .as_ref()
+        .and_then(echoed_bug);
+    let response = next.run(Request::from_parts(parts, Body::from(bytes))).await;
+    match echo {
+        Some((field, value)) => add_response_field(response, field, value).await,
+        None => response,
+    }
+}
+
+/// Enforce `MAX_PROMPT_BYTES` on the `prompt` of `/api/ai/*` requests: 413
+/// `PROMPT_TOO_LARGE`, or with `autoTruncatePrompt: true` in the body, the prompt with
+/// its middle cut out and `promptTruncated: true` added to a successful response.
+pub async fn limit_prompt(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let max_bytes = state.max_prompt_bytes;
+    if max_bytes == 0
+        || request.method() != Method::POST
+        || !request.uri().path().starts_with("/api/ai/")
+    {
+        return next.run(request).await;
+    }
+
+    let (parts, bytes) = match read_request_body(request).await {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    let mut fields = match serde_json::from_slice::<serde_json::Value>(&bytes) {
+        Ok(fields @ serde_json::Value::Object(_)) => fields,
+        // Left for the handler to reject
+        _ => return next.run(Request::from_parts(parts, Body::from(bytes))).await,
+    };
+    let Some(prompt) = fields["prompt"].as_str().filter(|p| p.len() > max_bytes) else {
+        return next.run(Request::from_parts(parts, Body::from(bytes))).await;
+    };
+    if fields["autoTruncatePrompt"] != serde_json::Value::Bool(true) {
+        return ErrorResponse {
+            error: "Prompt too large".to_string(),
+            details: Some(format!(
+                "The prompt is {} bytes, the limit is {} (MAX_PROMPT_BYTES); \
+                 set autoTruncatePrompt to cut it down instead",
+                prompt.len(),
+                max_bytes
+            )),
+            code: Some(prompt_limit::PROMPT_TOO_LARGE),
+            status: StatusCode::PAYLOAD_TOO_LARGE,
+        }
+        .into_response();
+    }
+
+    let (truncated, removed) = prompt_limit::truncate_middle(prompt, max_bytes);
+    info!(
+        "Truncated the prompt to fit MAX_PROMPT_BYTES: removed {} of {} bytes",
+        removed,
+        prompt.len()
+    );
+    fields["prompt"] = truncated.into();
+    let mut parts = parts;
+    parts.headers.remove(header::CONTENT_LENGTH);
+    let request = Request::from_parts(parts, Body::from(fields.to_string()));
+    add_response_field(next.run(request).await, "promptTruncated", true.into()).await
+}
+
+/// Largest response body [`cache_responses`] reads
+const CACHE_BODY_LIMIT: usize = 16 * 1024 * 1024;
+
+/// Answer a repeated `/api/ai/classify` request from `RESPONSE_CACHE_TTL_SECS`' cache
+/// (marked `cached: true`, without `meta`), and cache successful JSON responses. Both
+/// carry an `ETag`; when the request's `If-None-Match` matches it, the answer is 304
+/// without a body.
+pub async fn cache_responses(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(cache) = state.response_cache.clone() else {
+        return next.run(request).await;
+    };
+    if request.method() != Method::POST || request.uri().path() != "/api/ai/classify" {
+        return next.run(request).await;
+    }
+
+    let if_none_match = request
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string());
+    let (parts, bytes) = match read_request_body(request).await {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    let not_modified = |etag: &str| {
+        if_none_match
+            .as_deref()
+            .is_some_and(|tags| response_cache::none_match(tags, etag))
+    };
+
+    if let Some(cached) = cache.get(&bytes) {
+        debug!("Answering classify from the response cache");
+        metrics::inc("response_cache_hits_total");
+        let etag = HeaderValue::try_from(cached.etag.as_str()).ok();
+        let response = if not_modified(&cached.etag) {
+            StatusCode::NOT_MODIFIED.into_response()
+        } else {
+            ([(header::CONTENT_TYPE, "application/json")], cached.body).into_response()
+        };
+        return with_etag(response, etag);
+    }
+
+    metrics::inc("response_cache_misses_total");
+    let response = next.run(Request::from_parts(parts, Body::from(bytes.clone()))).await;
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|v| v.as_bytes().starts_with(b"application/json"));
+    if !response.status().is_success() || !is_json {
+        return response;
+    }
+    let (parts, body) = response.into_parts();
+    let body = match axum::body::to_bytes(body, CACHE_BODY_LIMIT).await {
+        Ok(body) => body,
+        Err(e) => {
+            warn!("Failed to read response to cache it: {}", e);
+            return unreadable_response("caching", e);
+        }
+    };
+    let cached = cache.insert(bytes, &body);
+    let etag = HeaderValue::try_from(cached.etag.as_str()).ok();
+    if not_modified(&cached.etag) {
+        return with_etag(StatusCode::NOT_MODIFIED.into_response(), etag);
+    }
+    with_etag(Response::from_parts(parts, Body::from(body)), etag)
+}
+
+fn with_etag(mut response: Response, etag: Option<HeaderValue>) -> Response {
+    if let Some(etag) = etag {
+        response.headers_mut().insert(header::ETAG, etag);
+    }
+    response
+}
+
+/// Largest response body handed to `POSTPROCESS_CMD`; bigger ones are sent unmodified
+pub const POSTPROCESS_BODY_LIMIT: usize = 8 * 1024 * 1024;
+
+/// Pipe successful JSON results of the AI endpoints through `POSTPROCESS_CMD`, when
+/// configured. Failures of the command fall back to the unmodified body. `dry-run`,
+/// `reparse` and `selftest` don't produce model results and are left alone, as are
+/// bodies that aren't known to fit in [`POSTPROCESS_BODY_LIMIT`].
+pub async fn postprocess_responses(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    let applies = state.postprocess.enabled()
+        && request.method() == Method::POST
+        && path.starts_with("/api/ai/")
+        && path != "/api/ai/dry-run"
+        && path != "/api/ai/reparse"
+        && path != "/api/ai/selftest";
+    let response = next.run(request).await;
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|v| v.as_bytes().starts_with(b"application/json"));
+    // Reading a body that turns out too big would lose it, so only known sizes are read
+    let size =
+        content_length(response.headers()).or_else(|| response.body().size_hint().upper());
+    let fits = size.is_some_and(|size| size <= POSTPROCESS_BODY_LIMIT as u64);
+    if !applies || !response.status().is_success() || !is_json || !fits {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match axum::body::to_bytes(body, POSTPROCESS_BODY_LIMIT).await {
+        Ok(bytes) => bytes,
+        Err(e) => return unreadable_response("post-processing", e),
+    };
+    let body = match state.postprocess.apply(&bytes).await {
+        Some(output) => Body::from(output),
+        None => Body::from(bytes),
+    };
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, body)
+}
+
+/// Largest response body [`require_fields`] reads
+const REQUIRED_FIELDS_BODY_LIMIT: usize = 16 * 1024 * 1024;
+
+/// Answer a successful AI result lacking one of its endpoint's `REQUIRED_FIELDS_*` with
+/// 422 `MISSING_REQUIRED_FIELD`. `classify-by-id` is checked against the `classify` list.
+/// Runs after `POSTPROCESS_CMD`; classifications were already checked by the handler.
+pub async fn require_fields(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let endpoint = request
+        .uri()
+        .path()
+        .strip_prefix("/api/ai/")
+        .filter(|_| request.method() == Method::POST)
+        .map(|endpoint| match endpoint {
+            "classify-by-id" => "classify",
+            endpoint => endpoint,
+        });
+    let Some(fields) = endpoint.and_then(|endpoint| state.required_fields.get(endpoint)) else {
+        return next.run(request).await;
+    };
+    let by_id = request.uri().path() == "/api/ai/classify-by-id";
+    let response = next.run(request).await;
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|v| v.as_bytes().starts_with(b"application/json"));
+    if !response.status().is_success() || !is_json {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let bytes = match axum::body::to_bytes(body, REQUIRED_FIELDS_BODY_LIMIT).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            warn!("Failed to read response to check required fields: {}", e);
+            return unreadable_response("checking required fields", e);
+        }
+    };
+    let result = serde_json::from_slice::<serde_json::Value>(&bytes).unwrap_or_default();
+    let result = if by_id { &result["classification"] } else { &result };
+    match required_fields::check(fields, result) {
+        Ok(()) => Response::from_parts(parts, Body::from(bytes)),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// Log (at debug) and record in `http_body_size_bytes` the request and response body
+/// sizes of `/api/*` requests. Sizes come from `Content-Length`, or for responses the
+/// exact size of an already-serialized body; bodies are never buffered to measure them.
+pub async fn body_sizes(request: Request, next: Next) -> Response {
+    let path = request.uri().path().to_string();
+    if !path.starts_with("/api/") {
+        return next.run(request).await;
+    }
+    let request_size = content_length(request.headers());
+    let response = next.run(request).await;
+    let response_size =
+        content_length(response.headers()).or_else(|| response.body().size_hint().exact());
+
+    debug!(
+        "{} body sizes: request {}, response {}",
+        path,
+        request_size.map_or("unknown".to_string(), |n| format!("{} bytes", n)),
+        response_size.map_or("unknown".to_string(), |n| format!("{} bytes", n)),
+    );
+    let endpoint = metrics::endpoint_label(&path);
+    for (direction, size) in [("request", request_size), ("response", response_size)] {
+        if let Some(size) = size {
+            metrics::observe_in(
+                "http_body_size_bytes",
+                &[("endpoint", endpoint), ("direction", direction)],
+                metrics::SIZE_BUCKETS,
+                size as f64,
+            );
+        }
+    }
+    response
+}
+
+fn content_length(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+}
+
+/// Guard for `/admin/*`: requires `Authorization: Bearer <ADMIN_TOKEN>`. Without a
+/// configured token the admin routes answer 404, as if they didn't exist.
+pub async fn require_admin(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(ref token) = state.admin_token else {
+        return ErrorResponse {
+            error: "Admin endpoints are disabled".to_string(),
+            details: Some("Set ADMIN_TOKEN to enable them".to_string()),
+            status: StatusCode::NOT_FOUND,
+            ..Default::default()
+        }
+        .into_response();
+    };
+    if !has_admin_token(request.headers(), token) {
+        return ErrorResponse {
+            error: "Invalid or missing admin token".to_string(),
+            details: None,
+            status: StatusCode::UNAUTHORIZED,
+            ..Default::default()
+        }
+        .into_response();
+    }
+    next.run(request).await
+}
+
+/// Whether `headers` carry `Authorization: Bearer <token>`
+fn has_admin_token(headers: &HeaderMap, token: &str) -> bool {
+    let presented = header_str(headers, "authorization");
+    let presented = presented.as_deref().and_then(|value| value.strip_prefix("Bearer "));
+    presented.is_some_and(|presented| constant_time_eq(presented, token))
+}
+
+/// String comparison whose running time doesn't depend on where the inputs differ
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes().zip(b.bytes()).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Answer `OPTIONS` requests with 204 No Content. The CORS layer already handles every
+/// `OPTIONS` itself (so none reach a JSON handler or the static-file fallback), but
+/// replies 200; this sits outside it and fixes up the status.
+pub async fn preflight_no_content(request: Request, next: Next) -> Response {
+    let is_options = request.method() == Method::OPTIONS;
+    let mut response = next.run(request).await;
+    if is_options && response.status() == StatusCode::OK {
+        *response.status_mut() = StatusCode::NO_CONTENT;
+    }
+    response
+}
+
+/// Paths exempt from [`limit_concurrency`], so probes still answer under load
+const UNLIMITED_PATHS: &[&str] = &["/health", "/healthz"];
+
+/// Answer 503 `OVERLOADED` once `MAX_CONCURRENT_REQUESTS` requests are already in
+/// progress, instead of queueing more (and buffering their bodies)
+pub async fn limit_concurrency(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(ref limit) = state.request_limit else {
+        return next.run(request).await;
+    };
+    if UNLIMITED_PATHS.contains(&request.uri().path()) {
+        return next.run(request).await;
+    }
+    let Ok(permit) = Arc::clone(limit).try_acquire_owned() else {
+        warn!(
+            "Concurrent request limit reached, rejecting {} {}",
+            request.method(),
+            request.uri().path()
+        );
+        return overloaded().into_response();
+    };
+    // Held by the request rather than by this call, so an event stream that is still
+    // running after its response has been returned keeps it too
+    let _permit = active::hold(permit).err();
+    next.run(request).await
+}
+
+/// 503 `CANCELLED` for a request cancelled through `/admin/requests`
+pub fn cancelled() -> ErrorResponse {
+    ErrorResponse {
+        error: "Request cancelled by operator".to_string(),
+        details: None,
+        code: Some("CANCELLED"),
+        status: StatusCode::SERVICE_UNAVAILABLE,
+    }
+}
+
+/// 503 `OVERLOADED` for work over `MAX_CONCURRENT_REQUESTS`, counted in
+/// `http_requests_overloaded_total`
+pub fn overloaded() -> ErrorResponse {
+    metrics::inc("http_requests_overloaded_total");
+    ErrorResponse {
+        error: "Server is busy".to_string(),
+        details: Some("Too many concurrent requests, retry shortly".to_string()),
+        code: Some("OVERLOADED"),
+        status: StatusCode::SERVICE_UNAVAILABLE,
+    }
+}
+
+/// Render error responses as plain text for clients whose `Accept` header prefers
+/// `text/plain` (e.g. curl users); JSON stays the default
+pub async fn negotiate_errors(request: Request, next: Next) -> Response {
+    let plain_text = prefers_plain_text(request.headers());
+    let response = next.run(request).await;
+    if !plain_text {
+        return response;
+    }
+    let Some(text) = response
+        .extensions()
+        .get::<ErrorResponse>()
+        .map(|error| error.to_plain_text())
+    else {
+        return response;
+    };
+
+    let (mut parts, _) = response.into_parts();
+    parts.headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(text))
+}
+
+/// Whether `text/plain` comes before `application/json` (or any JSON fallback like
+/// `*/*`) in the `Accept` header. Quality values are not weighed.
+fn prefers_plain_text(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|item| item.split(';').next().unwrap_or("").trim())
+        .find(|media_type| {
+            matches!(*media_type, "text/plain" | "application/json" | "*/*")
+        })
+        == Some("text/plain")
+}
+
+/// Reject Bugzilla proxy requests whose URI (path + query) is longer than
+/// `max_uri_bytes` with 414, before anything is forwarded upstream
+pub async fn limit_proxy_uri(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let uri = request.uri();
+    if uri.path().starts_with("/api/bugzilla/") {
+        let length = uri.path_and_query().map_or(0, |pq| pq.as_str().len());
+        if length > state.max_uri_bytes {
+            return ErrorResponse {
+                error: "Request URI too long".to_string(),
+                details: Some(format!(
+                    "{} bytes exceeds the {} byte limit",
+                    length, state.max_uri_bytes
+                )),
+                status: StatusCode::URI_TOO_LONG,
+                ..Default::default()
+            }
+            .into_response();
+        }
+    }
+    next.run(request).await
+}
+
+/// Fill in `provider`/`model` on `/api/ai/*` request bodies from the `X-Provider` and
+/// `X-Model` headers, for clients that can't shape the body (e.g. OpenAI-SDK-style
+/// proxies). Headers are a fallback only: a non-empty body value always wins.
+pub async fn provider_headers(request: Request, next: Next) -> Response {
+    if !request.uri().path().starts_with("/api/ai/") {
+        return next.run(request).await;
+    }
+    let provider = header_str(request.headers(), "x-provider");
+    let model = header_str(request.headers(), "x-model");
+    if provider.is_none() && model.is_none() {
+        return next.run(request).await;
+    }
+
+    let request = rewrite_json_body(request, |obj| {
+        for (field, value) in [("provider", provider), ("model", model)] {
+            let missing = match obj.get(field) {
+                None | Some(serde_json::Value::Null) => true,
+                Some(v) => v.as_str() == Some(""),
+            };
+            if let (true, Some(value)) = (missing, value) {
+                obj.insert(field.to_string(), value.into());
+            }
+        }
+    })
+    .await;
+    match request {
+        Ok(request) => next.run(request).await,
+        Err(response) => response,
+    }
+}
+
+/// Operator escape hatch for trying a model on a single request: with the admin token
+/// in `Authorization`, `X-Force-Model` replaces the `model` of an `/api/ai/*` request
+/// body, whatever the body says. Every use is logged with the request id. Without a
+/// valid token (or with `ADMIN_TOKEN` unset) the header is ignored.
+pub async fn force_model(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !request.uri().path().starts_with("/api/ai/") {
+        return next.run(request).await;
+    }
+    let Some(model) = header_str(request.headers(), "x-force-model") else {
+        return next.run(request).await;
+    };
+    let authorized = state
+        .admin_token
+        .as_deref()
+        .is_some_and(|token| has_admin_token(request.headers(), token));
+    if !authorized {
+        debug!("Ignoring X-Force-Model without a valid admin token");
+        return next.run(request).await;
+    }
+
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .map(|id| id.0.clone())
+        .unwrap_or_default();
+    warn!(
+        request_id = %request_id,
+        "X-Force-Model: {} runs with model {}",
+        request.uri().path(),
+        model
+    );
+    let request = rewrite_json_body(request, |obj| {
+        obj.insert("model".to_string(), model.into());
+    })
+    .await;
+    match request {
+        Ok(request) => next.run(request).await,
+        Err(response) => response,
+    }
+}
+
+/// Buffer a request body the way the handlers' extractors do: limited by
+/// `DefaultBodyLimit`, i.e. `state.max_body_bytes` (`MAX_BODY_BYTES`), with its 413 for a
+/// body over the limit and 400 for one that couldn't be read. Every middleware reading
+/// request bodies goes through this, so none has a limit of its own.
+async fn read_request_body(request: Request) -> Result<(request::Parts, Bytes), Response> {
+    let (parts, body) = request.into_parts();
+    let bytes = Bytes::from_request(Request::from_parts(parts.clone(), body), &())
+        .await
+        .map_err(IntoResponse::into_response)?;
+    Ok((parts, bytes))
+}
+
+/// Apply `rewrite` to a JSON object request body. Bodies that aren't JSON objects are
+/// passed through for the handler to reject.
+async fn rewrite_json_body(
+    request: Request,
+    rewrite: impl FnOnce(&mut serde_json::Map<String, serde_json::Value>),
+) -> Result<Request, Response> {
+    let (parts, bytes) = read_request_body(request).await?;
+
+    let body = match serde_json::from_slice::<serde_json::Value>(&bytes) {
+        Ok(serde_json::Value::Object(mut obj)) => {
+            rewrite(&mut obj);
+            Body::from(serde_json::Value::Object(obj).to_string())
+        }
+        _ => Body::from(bytes),
+    };
+
+    let mut request = Request::from_parts(parts, body);
+    request.headers_mut().remove(header::CONTENT_LENGTH);
+    Ok(request)
+}
+
+fn header_str(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn unreadable_bodies_become_errors() {
+        let failing = futures_util::stream::once(async {
+            Err::<axum::body::Bytes, _>(std::io::Error::other("connection reset"))
+        });
+        let response = Response::builder()
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::CONTENT_LENGTH, "100")
+            .body(Body::from_stream(failing))
+            .unwrap();
+
+        let response = add_response_field(response, "meta", serde_json::json!({})).await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let stale = HeaderValue::from_static("100");
+        assert_ne!(response.headers().get(header::CONTENT_LENGTH), Some(&stale));
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["error"], "Failed to read response for adding meta");
+    }
+}
@@ -0,0 +1,159 @@
+//! Mock provider
+//!
+//! Produces the output a model could return for a JSON schema without calling one, for
+//! `/api/ai/selftest`: every property is filled in, with the first `enum` value, `true`,
+//! the `minimum` (or 1), `"example"`, or a single array item. [`check`] is the matching
+//! minimal schema validator (types, `required`, `enum`, nested properties and items),
+//! enough for the schemas in prompts.js.
+
+use serde_json::{json, Value};
+
+/// Sample output for `schema`
+pub fn output(schema: &Value) -> Value {
+    if let Some(value) = schema.get("const") {
+        return value.clone();
+    }
+    if let Some(first) = schema.get("enum").and_then(|e| e.as_array()).and_then(|e| e.first()) {
+        return first.clone();
+    }
+    for combinator in ["anyOf", "oneOf", "allOf"] {
+        let first = schema.get(combinator).and_then(|s| s.as_array()).and_then(|s| s.first());
+        if let Some(first) = first {
+            return output(first);
+        }
+    }
+    match schema_type(schema) {
+        Some("object") => Value::Object(
+            properties(schema)
+                .map(|(name, property)| (name.clone(), output(property)))
+                .collect(),
+        ),
+        Some("array") => match schema.get("items") {
+            Some(items) if schema.get("maxItems").and_then(|m| m.as_u64()) != Some(0) => {
+                json!([output(items)])
+            }
+            _ => json!([]),
+        },
+        Some("string") => json!("example"),
+        Some("boolean") => json!(true),
+        Some("integer") => json!(schema.get("minimum").and_then(|m| m.as_i64()).unwrap_or(1)),
+        Some("number") => json!(schema.get("minimum").and_then(|m| m.as_f64()).unwrap_or(1.0)),
+        _ => Value::Null,
+    }
+}
+
+/// Where `value` doesn't satisfy `schema`, one message per problem, each starting with
+/// the path of the offending value
+pub fn check(schema: &Value, value: &Value) -> Vec<String> {
+    let mut problems = Vec::new();
+    check_at("$", schema, value, &mut problems);
+    problems
+}
+
+fn check_at(path: &str, schema: &Value, value: &Value, problems: &mut Vec<String>) {
+    if let Some(allowed) = schema.get("enum").and_then(|e| e.as_array()) {
+        if !allowed.contains(value) {
+            problems.push(format!("{}: {} is not one of the enum values", path, value));
+        }
+    }
+    let Some(expected) = schema_type(schema) else {
+        return;
+    };
+    let matches = match expected {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "boolean" => value.is_boolean(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "null" => value.is_null(),
+        _ => true,
+    };
+    if !matches {
+        problems.push(format!("{}: expected {}, got {}", path, expected, value));
+        return;
+    }
+
+    if let Some(object) = value.as_object() {
+        let required = schema.get("required").and_then(|r| r.as_array()).into_iter().flatten();
+        for name in required.filter_map(|name| name.as_str()) {
+            if !object.contains_key(name) {
+                problems.push(format!("{}: missing required field {}", path, name));
+            }
+        }
+        for (name, field) in object {
+            let field_path = format!("{}.{}", path, name);
+            match schema.get("properties").and_then(|p| p.get(name)) {
+                Some(property) => check_at(&field_path, property, field, problems),
+                None => match schema.get("additionalProperties") {
+                    Some(Value::Bool(false)) => {
+                        problems.push(format!("{}: not allowed by the schema", field_path))
+                    }
+                    Some(additional) => check_at(&field_path, additional, field, problems),
+                    None => {}
+                },
+            }
+        }
+    }
+    if let (Some(items), Some(array)) = (schema.get("items"), value.as_array()) {
+        for (i, item) in array.iter().enumerate() {
+            check_at(&format!("{}[{}]", path, i), items, item, problems);
+        }
+    }
+}
+
+/// The schema's `type`: the first non-`null` one of a list, `object` when only
+/// `properties` are given
+fn schema_type(schema: &Value) -> Option<&str> {
+    match schema.get("type") {
+        Some(Value::String(t)) => Some(t),
+        Some(Value::Array(types)) => types
+            .iter()
+            .filter_map(|t| t.as_str())
+            .find(|t| *t != "null")
+            .or(Some("null")),
+        _ => schema.get("properties").map(|_| "object"),
+    }
+}
+
+fn properties(schema: &Value) -> impl Iterator<Item = (&String, &Value)> {
+    schema.get("properties").and_then(|p| p.as_object()).into_iter().flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schemas::default_schema;
+
+    #[test]
+    fn output_satisfies_the_default_schemas() {
+        for endpoint in crate::parse::expected_fields().into_keys() {
+            let schema = default_schema(endpoint).unwrap();
+            let output = output(&schema);
+            assert_eq!(check(&schema, &output), Vec::<String>::new(), "{}", endpoint);
+        }
+    }
+
+    #[test]
+    fn check_reports_each_problem() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "severity": { "type": "string", "enum": ["S1", "S2"] },
+                "actions": { "type": "array", "items": { "type": "string" } }
+            },
+            "required": ["severity", "summary"],
+            "additionalProperties": false
+        });
+        let value = json!({ "severity": "S9", "actions": ["ok", 1], "extra": true });
+        assert_eq!(
+            check(&schema, &value),
+            vec![
+                "$: missing required field summary",
+                "$.actions[1]: expected string, got 1",
+                "$.extra: not allowed by the schema",
+                "$.severity: \"S9\" is not one of the enum values",
+            ]
+        );
+    }
+}
@@ -0,0 +1,243 @@
+//! Severity/priority normalization
+//!
+//! Models don't always stick to the schema's enums and return things like `"sev2"`,
+//! `"severity-high"` or `"High"`. The frontend expects canonical Bugzilla values, so
+//! `parse_classify` maps known variants here. Anything not in these tables is rejected
+//! rather than guessed.
+//!
+//! [`PriorityConvention`] then cross-checks the two: which priorities are expected for
+//! each severity. A mismatch only adds a parse warning for the triager; values are kept.
+
+use std::collections::BTreeMap;
+
+/// Severity aliases, after [`key`] folding and with any `severity`/`sev` prefix removed
+const SEVERITY_ALIASES: &[(&str, &str)] = &[
+    ("s1", "S1"),
+    ("1", "S1"),
+    ("blocker", "S1"),
+    ("critical", "S1"),
+    ("s2", "S2"),
+    ("2", "S2"),
+    ("major", "S2"),
+    ("high", "S2"),
+    ("s3", "S3"),
+    ("3", "S3"),
+    ("normal", "S3"),
+    ("medium", "S3"),
+    ("moderate", "S3"),
+    ("s4", "S4"),
+    ("4", "S4"),
+    ("minor", "S4"),
+    ("trivial", "S4"),
+    ("low", "S4"),
+    ("na", "N/A"),
+    ("", "--"),
+];
+
+/// Priority aliases, after [`key`] folding and with any `priority`/`pri` prefix removed
+const PRIORITY_ALIASES: &[(&str, &str)] = &[
+    ("p1", "P1"),
+    ("1", "P1"),
+    ("highest", "P1"),
+    ("urgent", "P1"),
+    ("p2", "P2"),
+    ("2", "P2"),
+    ("high", "P2"),
+    ("p3", "P3"),
+    ("3", "P3"),
+    ("medium", "P3"),
+    ("normal", "P3"),
+    ("p4", "P4"),
+    ("4", "P4"),
+    ("low", "P4"),
+    ("p5", "P5"),
+    ("5", "P5"),
+    ("lowest", "P5"),
+    ("", "--"),
+];
+
+/// Lowercase and drop separators, so `"Severity - High"` becomes `"severityhigh"`.
+/// `--` folds to the empty string and maps to the "unset" value.
+fn key(value: &str) -> String {
+    value
+        .chars()
+        .filter(|c| !matches!(c, ' ' | '-' | '_' | ':' | '/' | '.'))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn lookup(
+    value: &str,
+    prefixes: &[&str],
+    aliases: &'static [(&'static str, &'static str)],
+) -> Option<&'static str> {
+    let key = key(value);
+    let key = prefixes
+        .iter()
+        .find_map(|prefix| key.strip_prefix(prefix))
+        .unwrap_or(&key);
+    aliases
+        .iter()
+        .find(|(alias, _)| *alias == key)
+        .map(|(_, canonical)| *canonical)
+}
+
+/// Canonical severity (`S1`-`S4`, `N/A`, `--`), or `None` when `value` isn't recognized
+pub fn severity(value: &str) -> Option<&'static str> {
+    // Longest prefix first
+    lookup(value, &["severity", "sev"], SEVERITY_ALIASES)
+}
+
+/// Canonical priority (`P1`-`P5`, `--`), or `None` when `value` isn't recognized
+pub fn priority(value: &str) -> Option<&'static str> {
+    lookup(value, &["priority", "pri"], PRIORITY_ALIASES)
+}
+
+/// Expected priorities per severity, e.g. S1 bugs are P1 or P2. Severities without an
+/// entry (and `N/A`/`--` on either side) aren't checked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PriorityConvention {
+    expected: BTreeMap<&'static str, Vec<&'static str>>,
+}
+
+impl Default for PriorityConvention {
+    fn default() -> Self {
+        // "S1:P1-P2,S2:P1-P3,S3:P2-P5,S4:P3-P5"
+        let expected = [
+            ("S1", vec!["P1", "P2"]),
+            ("S2", vec!["P1", "P2", "P3"]),
+            ("S3", vec!["P2", "P3", "P4", "P5"]),
+            ("S4", vec!["P3", "P4", "P5"]),
+        ];
+        PriorityConvention { expected: expected.into_iter().collect() }
+    }
+}
+
+impl PriorityConvention {
+    /// Parse `severity:priorities` entries separated by commas, where priorities is one
+    /// priority or a range (`S1:P1-P2,S4:P3-P5`). Values go through [`severity`] and
+    /// [`priority`], so aliases work too. An empty string disables the check.
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let mut expected = BTreeMap::new();
+        for entry in input.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (sev, priorities) = entry
+                .split_once(':')
+                .ok_or_else(|| format!("expected severity:priorities, got '{}'", entry))?;
+            let sev = severity(sev)
+                .filter(|s| s.starts_with('S'))
+                .ok_or_else(|| format!("unknown severity in '{}'", entry))?;
+            let (low, high) = priorities.split_once('-').unwrap_or((priorities, priorities));
+            let bound = |value: &str| {
+                priority(value)
+                    .filter(|p| p.starts_with('P'))
+                    .ok_or_else(|| format!("unknown priority in '{}'", entry))
+            };
+            let (low, high) = (bound(low)?, bound(high)?);
+            let range: Vec<&'static str> = ["P1", "P2", "P3", "P4", "P5"]
+                .into_iter()
+                .filter(|p| (low..=high).contains(p))
+                .collect();
+            if range.is_empty() {
+                return Err(format!("empty priority range in '{}'", entry));
+            }
+            expected.insert(sev, range);
+        }
+        Ok(PriorityConvention { expected })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.expected.is_empty()
+    }
+
+    /// A warning when canonical `severity` and `priority` don't fit the convention
+    pub fn check(&self, severity: &str, priority: &str) -> Option<String> {
+        let expected = self.expected.get(severity)?;
+        if !priority.starts_with('P') || expected.contains(&priority) {
+            return None;
+        }
+        Some(format!(
+            "suggested_priority {} is unusual for suggested_severity {} (expected {})",
+            priority,
+            severity,
+            expected.join("/")
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canonical_values_pass_through() {
+        for value in ["S1", "S2", "S3", "S4", "N/A", "--"] {
+            assert_eq!(severity(value), Some(value));
+        }
+        for value in ["P1", "P2", "P3", "P4", "P5", "--"] {
+            assert_eq!(priority(value), Some(value));
+        }
+    }
+
+    #[test]
+    fn severity_aliases() {
+        assert_eq!(severity("s2"), Some("S2"));
+        assert_eq!(severity("sev2"), Some("S2"));
+        assert_eq!(severity("Sev 3"), Some("S3"));
+        assert_eq!(severity("severity-high"), Some("S2"));
+        assert_eq!(severity("Severity: S1"), Some("S1"));
+        assert_eq!(severity("high"), Some("S2"));
+        assert_eq!(severity("Critical"), Some("S1"));
+        assert_eq!(severity("blocker"), Some("S1"));
+        assert_eq!(severity("normal"), Some("S3"));
+        assert_eq!(severity("minor"), Some("S4"));
+        assert_eq!(severity(" trivial "), Some("S4"));
+        assert_eq!(severity("n/a"), Some("N/A"));
+    }
+
+    #[test]
+    fn priority_aliases() {
+        assert_eq!(priority("p1"), Some("P1"));
+        assert_eq!(priority("priority-2"), Some("P2"));
+        assert_eq!(priority("Priority: P3"), Some("P3"));
+        assert_eq!(priority("high"), Some("P2"));
+        assert_eq!(priority("lowest"), Some("P5"));
+        assert_eq!(priority("5"), Some("P5"));
+    }
+
+    #[test]
+    fn unknown_values_are_rejected() {
+        assert_eq!(severity("S5"), None);
+        assert_eq!(severity("P1"), None);
+        assert_eq!(severity("very bad"), None);
+        assert_eq!(priority("P6"), None);
+        assert_eq!(priority("S1"), None);
+        assert_eq!(priority("asap-ish"), None);
+    }
+
+    #[test]
+    fn priority_convention_flags_mismatches() {
+        let convention = PriorityConvention::default();
+        assert_eq!(convention.check("S1", "P1"), None);
+        assert_eq!(convention.check("S3", "P5"), None);
+        assert_eq!(convention.check("N/A", "P5"), None);
+        assert_eq!(convention.check("S1", "--"), None);
+        assert_eq!(
+            convention.check("S1", "P5").unwrap(),
+            "suggested_priority P5 is unusual for suggested_severity S1 (expected P1/P2)"
+        );
+    }
+
+    #[test]
+    fn priority_convention_is_parsed() {
+        let convention = PriorityConvention::parse("S1:P1, sev2:P2-P3").unwrap();
+        assert!(convention.check("S1", "P2").is_some());
+        assert!(convention.check("S2", "P3").is_none());
+        assert!(convention.check("S2", "P1").is_some());
+        // Unlisted severities aren't checked
+        assert!(convention.check("S4", "P1").is_none());
+        assert!(PriorityConvention::parse("").unwrap().is_empty());
+        assert!(PriorityConvention::parse("S1").is_err());
+        assert!(PriorityConvention::parse("S1:P9").is_err());
+        assert!(PriorityConvention::parse("S1:P3-P1").is_err());
+    }
+}
@@ -0,0 +1,765 @@
+//! Structured output parsing
+//!
+//! Turns the structured JSON returned by a provider into the response types sent to the
+//! frontend. All field access goes through [`Fields`], which can also run in "probe" mode
+//! to record which fields each parser reads - that is what `/api/ai/expected-schemas`
+//! reports, so the list can never drift from the parsers themselves.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+
+use axum::http::StatusCode;
+use tracing::warn;
+
+use crate::{actions, normalize};
+use crate::{
+    ClassifyResponse, ErrorResponse, ExplainResponse, GenerateResponse, RefineResponse,
+    RewriteSummaryResponse, SuggestResponse, SuggestedAction, SummarizeCommentsResponse,
+    TestPageResponse, TriageAction,
+};
+
+static NULL: serde_json::Value = serde_json::Value::Null;
+
+/// Field accessor over a structured result object
+pub struct Fields<'a> {
+    value: &'a serde_json::Value,
+    path: String,
+    /// Set in probe mode: every field path read is appended here
+    probe: Option<&'a RefCell<Vec<String>>>,
+    /// Strings are passed through [`clean`] as they are read
+    clean: bool,
+}
+
+impl<'a> Fields<'a> {
+    pub fn new(value: &'a serde_json::Value) -> Self {
+        Fields {
+            value,
+            path: String::new(),
+            probe: None,
+            clean: false,
+        }
+    }
+
+    /// This reader, cleaning the strings it returns when `clean` is set
+    /// (`CLEAN_MODEL_STRINGS`)
+    pub fn cleaning(mut self, clean: bool) -> Self {
+        self.clean = clean;
+        self
+    }
+
+    /// A reader over no data that records the path of every field read into `log`
+    fn probe(log: &'a RefCell<Vec<String>>) -> Self {
+        Fields {
+            value: &NULL,
+            path: String::new(),
+            probe: Some(log),
+            clean: false,
+        }
+    }
+
+    fn record(&self, name: &str) {
+        if let Some(log) = self.probe {
+            let path = format!("{}{}", self.path, name);
+            let mut log = log.borrow_mut();
+            if !log.contains(&path) {
+                log.push(path);
+            }
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&'a serde_json::Value> {
+        self.record(name);
+        self.value.get(name)
+    }
+
+    /// Boolean field, `false` when absent
+    pub fn bool(&self, name: &str) -> bool {
+        self.get(name).and_then(|v| v.as_bool()).unwrap_or(false)
+    }
+
+    pub fn str(&self, name: &str) -> Option<&'a str> {
+        self.get(name).and_then(|v| v.as_str())
+    }
+
+    /// `text` as returned by the string accessors
+    fn read(&self, text: &str) -> String {
+        if self.clean {
+            clean(text)
+        } else {
+            text.to_string()
+        }
+    }
+
+    /// String field, empty when absent
+    pub fn string(&self, name: &str) -> String {
+        self.opt_string(name).unwrap_or_default()
+    }
+
+    pub fn opt_string(&self, name: &str) -> Option<String> {
+        self.str(name).map(|s| self.read(s))
+    }
+
+    /// String field, `None` when absent or empty
+    pub fn non_empty_string(&self, name: &str) -> Option<String> {
+        self.opt_string(name).filter(|s| !s.is_empty())
+    }
+
+    /// Object items of an array field. In probe mode yields a single probe item so the
+    /// nested fields are recorded as `name[].field`.
+    pub fn items(&self, name: &str) -> Vec<Fields<'a>> {
+        if let Some(log) = self.probe {
+            self.record(&format!("{}[]", name));
+            return vec![Fields {
+                value: &NULL,
+                path: format!("{}{}[].", self.path, name),
+                probe: Some(log),
+                clean: false,
+            }];
+        }
+        self.get(name)
+            .and_then(|v| v.as_array())
+            .map(|arr| {
+                arr.iter()
+                    .map(|item| Fields {
+                        value: item,
+                        path: String::new(),
+                        probe: None,
+                        clean: self.clean,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// String items of an array field; non-string items are skipped
+    pub fn strings(&self, name: &str) -> Vec<String> {
+        self.get(name)
+            .and_then(|v| v.as_array())
+            .map(|arr| {
+                arr.iter()
+                    .filter_map(|item| item.as_str().map(|s| self.read(s)))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Object field mapping names to non-empty strings
+    pub fn string_map(&self, name: &str) -> BTreeMap<String, String> {
+        self.get(name)
+            .and_then(|v| v.as_object())
+            .map(|obj| {
+                obj.iter()
+                    .filter_map(|(key, text)| {
+                        let text = self.read(text.as_str()?);
+                        (!text.is_empty()).then(|| (key.clone(), text))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// `text` without control characters other than newlines and tabs, without zero-width
+/// characters and byte order marks, and without surrounding whitespace: what models
+/// sometimes leave in strings and the UI would show oddly
+pub fn clean(text: &str) -> String {
+    let invisible = |c: char| {
+        (c.is_control() && c != '\n' && c != '\t')
+            || matches!(c, '\u{200B}'..='\u{200D}' | '\u{2060}' | '\u{FEFF}')
+    };
+    let kept: String = text.chars().filter(|c| !invisible(*c)).collect();
+    kept.trim().to_string()
+}
+
+/// Parse a classification; a severity/priority pair that doesn't fit `convention` is
+/// kept but reported in `parse_warnings`
+pub fn parse_classify(f: &Fields, convention: &normalize::PriorityConvention) -> ClassifyResponse {
+    let suggested_actions = f
+        .items("suggested_actions")
+        .iter()
+        .filter_map(|item| {
+            // Read both fields before bailing out so probes record them
+            let action = item.opt_string("action");
+            let reason = item.string("reason");
+            let action = action?;
+            Some(TriageAction {
+                id: actions::action_id(&action, &reason),
+                action,
+                reason,
+            })
+        })
+        .collect();
+
+    let mut parse_warnings = Vec::new();
+    let suggested_severity = normalized(
+        f.opt_string("suggested_severity").as_deref(),
+        "suggested_severity",
+        normalize::severity,
+        &mut parse_warnings,
+    );
+    let suggested_priority = normalized(
+        f.opt_string("suggested_priority").as_deref(),
+        "suggested_priority",
+        normalize::priority,
+        &mut parse_warnings,
+    );
+    if let (Some(severity), Some(priority)) = (&suggested_severity, &suggested_priority) {
+        if let Some(mismatch) = convention.check(severity, priority) {
+            warn!("Model returned an unusual severity/priority pair: {}", mismatch);
+            parse_warnings.push(mismatch);
+        }
+    }
+    let confidence = confidence(f.get("confidence"), &mut parse_warnings);
+
+    ClassifyResponse {
+        ai_detected_str: f.bool("ai_detected_str"),
+        ai_detected_test_attached: f.bool("ai_detected_test_attached"),
+        crashstack_present: f.bool("crashstack_present"),
+        fuzzing_testcase: f.bool("fuzzing_testcase"),
+        summary: f.string("summary"),
+        suggested_severity,
+        suggested_priority,
+        suggested_actions: actions::order_actions(suggested_actions, |a: &TriageAction| {
+            (&a.id, &a.action)
+        }),
+        triage_reasoning: f.opt_string("triage_reasoning"),
+        suggested_canned_id: f.non_empty_string("suggested_canned_id"),
+        draft_response: f.non_empty_string("draft_response"),
+        // Free-form, passed through in whatever shape the model returned
+        notes: f.get("notes").filter(|v| !v.is_null()).cloned(),
+        confidence,
+        used_provider: None,
+        draft_response_html: None,
+        parse_warnings,
+        ensemble: None,
+    }
+}
+
+/// Drop a `suggested_canned_id` that isn't the `id` of any of `canned_responses`, so
+/// the frontend never tries to apply a hallucinated canned response
+pub fn validate_canned_id(response: &mut ClassifyResponse, canned_responses: &[serde_json::Value]) {
+    let Some(id) = response.suggested_canned_id.take().filter(|id| !id.is_empty()) else {
+        return;
+    };
+    let known = canned_responses.iter().any(|canned| match canned.get("id") {
+        Some(serde_json::Value::String(known)) => *known == id,
+        Some(serde_json::Value::Number(known)) => known.to_string() == id,
+        _ => false,
+    });
+    if known {
+        response.suggested_canned_id = Some(id);
+    } else {
+        warn!("Model suggested unknown canned response id: {:?}", id);
+        response
+            .parse_warnings
+            .push(format!("Unknown suggested_canned_id {:?}, dropped", id));
+    }
+}
+
+/// Map `value` to its canonical form, recording a warning when it can't be mapped
+fn normalized(
+    value: Option<&str>,
+    field: &str,
+    normalize: fn(&str) -> Option<&'static str>,
+    warnings: &mut Vec<String>,
+) -> Option<String> {
+    let value = value?;
+    let canonical = normalize(value);
+    if canonical.is_none() {
+        warn!("Unrecognized {} value from model: {:?}", field, value);
+        warnings.push(format!("Unrecognized {} value {:?}, dropped", field, value));
+    }
+    canonical.map(|s| s.to_string())
+}
+
+/// Per-field confidence scores. Scores outside 0..=1 are clamped and non-numeric ones
+/// dropped, each with a warning; `None` when the model reported none.
+fn confidence(
+    value: Option<&serde_json::Value>,
+    warnings: &mut Vec<String>,
+) -> Option<HashMap<String, f32>> {
+    let scores = value?.as_object()?;
+    let mut confidence = HashMap::new();
+    for (field, score) in scores {
+        let Some(score) = score.as_f64() else {
+            warn!("Non-numeric confidence for {} from model: {}", field, score);
+            warnings.push(format!("Non-numeric confidence for {}, dropped", field));
+            continue;
+        };
+        if !(0.0..=1.0).contains(&score) {
+            warn!("Out-of-range confidence for {} from model: {}", field, score);
+            warnings.push(format!("Confidence {} for {} out of range, clamped", score, field));
+        }
+        confidence.insert(field.clone(), score.clamp(0.0, 1.0) as f32);
+    }
+    Some(confidence).filter(|c| !c.is_empty())
+}
+
+/// An explicit `no_match` wins over any id the model returned alongside it
+pub fn parse_suggest(f: &Fields) -> SuggestResponse {
+    let suggested_response_id = f.non_empty_string("suggested_response_id");
+    let no_match = f.bool("no_match");
+    SuggestResponse {
+        suggested_response_id: suggested_response_id.filter(|_| !no_match),
+        no_match,
+        draft_response: f.string("draft_response"),
+        reasoning: f.opt_string("reasoning"),
+        draft_response_html: None,
+    }
+}
+
+pub fn parse_generate(f: &Fields) -> GenerateResponse {
+    let suggested_actions = f
+        .items("suggested_actions")
+        .iter()
+        .filter_map(|item| {
+            // Read both fields before bailing out so probes record them
+            let action = item.opt_string("action");
+            let reason = item.opt_string("reason");
+            let action = action?;
+            Some(SuggestedAction {
+                id: actions::action_id(&action, reason.as_deref().unwrap_or("")),
+                action,
+                reason,
+            })
+        })
+        .collect();
+
+    GenerateResponse {
+        response_text: f.string("response_text"),
+        suggested_actions: actions::order_actions(suggested_actions, |a: &SuggestedAction| {
+            (&a.id, &a.action)
+        }),
+        used_canned_ids: f.strings("used_canned_ids"),
+        reasoning: Some(f.string("reasoning")),
+        response_text_html: None,
+        parse_warnings: Vec::new(),
+    }
+}
+
+/// Most `changes_made` entries kept from a refine result
+const MAX_CHANGES_MADE: usize = 50;
+
+/// Fails when the model left out `refined_response`: echoing the current response back
+/// would look like a successful refine that changed nothing
+pub fn parse_refine(f: &Fields) -> Result<RefineResponse, ErrorResponse> {
+    let refined_response = f.opt_string("refined_response");
+    let mut parse_warnings = Vec::new();
+    let mut changes_made = Vec::new();
+    let items = f.get("changes_made").and_then(|v| v.as_array());
+    for item in items.into_iter().flatten() {
+        match item {
+            serde_json::Value::String(s) => changes_made.push(s.clone()),
+            serde_json::Value::Number(_) | serde_json::Value::Bool(_) => {
+                changes_made.push(item.to_string())
+            }
+            _ => parse_warnings.push(format!("Dropped non-string changes_made item: {}", item)),
+        }
+    }
+    if changes_made.len() > MAX_CHANGES_MADE {
+        warn!(
+            "Model returned {} changes_made entries, keeping {}",
+            changes_made.len(),
+            MAX_CHANGES_MADE
+        );
+        parse_warnings.push(format!(
+            "changes_made truncated from {} to {} entries",
+            changes_made.len(),
+            MAX_CHANGES_MADE
+        ));
+        changes_made.truncate(MAX_CHANGES_MADE);
+    }
+
+    let refined_response = refined_response.ok_or_else(|| ErrorResponse {
+        error: "Model output is missing refined_response".to_string(),
+        details: None,
+        code: Some("REFINED_RESPONSE_MISSING"),
+        status: StatusCode::BAD_GATEWAY,
+    })?;
+    Ok(RefineResponse {
+        refined_response,
+        changes_made,
+        parse_warnings,
+    })
+}
+
+pub fn parse_testpage(f: &Fields) -> TestPageResponse {
+    TestPageResponse {
+        can_generate: f.bool("can_generate"),
+        html_content: f.string("html_content"),
+        reason: f.string("reason"),
+        attachments_truncated: None,
+    }
+}
+
+pub fn parse_explain(f: &Fields) -> ExplainResponse {
+    ExplainResponse {
+        overview: f.string("overview"),
+        field_explanations: f.string_map("field_explanations"),
+    }
+}
+
+pub fn parse_summarize_comments(f: &Fields) -> SummarizeCommentsResponse {
+    SummarizeCommentsResponse {
+        summary: f.string("summary"),
+        key_points: f.strings("key_points"),
+        open_questions: f.strings("open_questions"),
+        next_comments_offset: None,
+    }
+}
+
+/// Longest summary Bugzilla accepts, in characters
+pub const MAX_SUMMARY_CHARS: usize = 255;
+
+/// Fails (502) when the suggested summary is empty or too long for Bugzilla, since
+/// neither could replace the bug's summary. Line breaks and runs of whitespace collapse
+/// to single spaces: a summary is one line.
+pub fn parse_rewrite_summary(f: &Fields) -> Result<RewriteSummaryResponse, ErrorResponse> {
+    let suggested_summary = f
+        .string("suggested_summary")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    let reason = f.string("reason");
+
+    let invalid = |error: &str, code| ErrorResponse {
+        error: error.to_string(),
+        details: Some(format!("Suggested summary: {:?}", suggested_summary)),
+        code: Some(code),
+        status: StatusCode::BAD_GATEWAY,
+    };
+    if suggested_summary.is_empty() {
+        return Err(invalid("Model returned an empty summary", "SUGGESTED_SUMMARY_EMPTY"));
+    }
+    let chars = suggested_summary.chars().count();
+    if chars > MAX_SUMMARY_CHARS {
+        warn!("Suggested summary is {} characters, over {}", chars, MAX_SUMMARY_CHARS);
+        return Err(invalid(
+            &format!("Suggested summary exceeds {} characters", MAX_SUMMARY_CHARS),
+            "SUGGESTED_SUMMARY_TOO_LONG",
+        ));
+    }
+    Ok(RewriteSummaryResponse {
+        suggested_summary,
+        reason,
+    })
+}
+
+/// Fields each endpoint's parser reads, keyed by endpoint name (the last path segment
+/// of its route), in the order the parser reads them
+pub fn expected_fields() -> BTreeMap<&'static str, Vec<String>> {
+    fn probe(parse: impl Fn(&Fields)) -> Vec<String> {
+        let log = RefCell::new(Vec::new());
+        parse(&Fields::probe(&log));
+        log.into_inner()
+    }
+
+    BTreeMap::from([
+        ("classify", probe(|f| {
+            parse_classify(f, &normalize::PriorityConvention::default());
+        })),
+        ("suggest-response", probe(|f| {
+            parse_suggest(f);
+        })),
+        ("generate", probe(|f| {
+            parse_generate(f);
+        })),
+        ("refine", probe(|f| {
+            let _ = parse_refine(f);
+        })),
+        ("testpage", probe(|f| {
+            parse_testpage(f);
+        })),
+        ("explain", probe(|f| {
+            parse_explain(f);
+        })),
+        ("summarize-comments", probe(|f| {
+            parse_summarize_comments(f);
+        })),
+        ("rewrite-summary", probe(|f| {
+            let _ = parse_rewrite_summary(f);
+        })),
+    ])
+}
+
+/// The structured result inside a captured provider output: a CLI `result` line (or the
+/// array of lines `--output-format json --verbose` prints), an Anthropic Messages API
+/// response (forced tool call or JSON text block), or else the result object itself
+pub fn structured_result(raw: &serde_json::Value) -> serde_json::Value {
+    let raw = match raw.as_array() {
+        Some(lines) => lines
+            .iter()
+            .rev()
+            .find(|line| line.get("type").and_then(|t| t.as_str()) == Some("result"))
+            .unwrap_or(raw),
+        None => raw,
+    };
+    if raw.get("type").and_then(|t| t.as_str()) == Some("result") {
+        let nested = raw.get("result").and_then(|r| r.get("structured_output"));
+        if let Some(output) = [raw.get("structured_output"), nested]
+            .into_iter()
+            .flatten()
+            .find(|output| !output.is_null())
+        {
+            return output.clone();
+        }
+    }
+    let blocks = raw.get("content").and_then(|c| c.as_array()).into_iter().flatten();
+    for block in blocks {
+        let result = match block.get("type").and_then(|t| t.as_str()) {
+            Some("tool_use") => block.get("input").cloned(),
+            Some("text") => block
+                .get("text")
+                .and_then(|t| t.as_str())
+                .and_then(|text| serde_json::from_str(text).ok()),
+            _ => None,
+        };
+        if let Some(result) = result {
+            return result;
+        }
+    }
+    raw.clone()
+}
+
+/// Run `result` through the parser of `endpoint` (named as in [`expected_fields`]) and
+/// return what that endpoint would respond with; `None` for an unknown endpoint
+pub fn reparse(
+    endpoint: &str,
+    result: &serde_json::Value,
+    convention: &normalize::PriorityConvention,
+    clean: bool,
+) -> Option<Result<serde_json::Value, ErrorResponse>> {
+    fn json(response: impl serde::Serialize) -> serde_json::Value {
+        serde_json::to_value(response).unwrap_or_default()
+    }
+
+    let f = Fields::new(result).cleaning(clean);
+    let response = match endpoint {
+        "classify" => Ok(json(parse_classify(&f, convention))),
+        "suggest-response" => Ok(json(parse_suggest(&f))),
+        "generate" => Ok(json(parse_generate(&f))),
+        "refine" => parse_refine(&f).map(json),
+        "testpage" => Ok(json(parse_testpage(&f))),
+        "explain" => Ok(json(parse_explain(&f))),
+        "summarize-comments" => Ok(json(parse_summarize_comments(&f))),
+        "rewrite-summary" => parse_rewrite_summary(&f).map(json),
+        _ => return None,
+    };
+    Some(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn classify_surfaces_notes() {
+        let result = json!({
+            "summary": "Crash on load",
+            "notes": { "duplicates": [123, 456], "confidence": "low" }
+        });
+        let response = parse_classify(&Fields::new(&result), &Default::default());
+        assert_eq!(
+            response.notes,
+            Some(json!({ "duplicates": [123, 456], "confidence": "low" }))
+        );
+    }
+
+    #[test]
+    fn classify_confidence_is_optional() {
+        let result = json!({ "summary": "Crash on load" });
+        let response = parse_classify(&Fields::new(&result), &Default::default());
+        assert_eq!(response.confidence, None);
+        assert!(response.parse_warnings.is_empty());
+
+        let result = json!({ "confidence": { "suggested_severity": 0.8, "summary": 1 } });
+        let response = parse_classify(&Fields::new(&result), &Default::default());
+        let confidence = response.confidence.unwrap();
+        assert_eq!(confidence["suggested_severity"], 0.8);
+        assert_eq!(confidence["summary"], 1.0);
+    }
+
+    #[test]
+    fn classify_confidence_is_clamped() {
+        let result = json!({
+            "confidence": { "suggested_severity": 1.5, "suggested_priority": -0.2, "summary": "high" }
+        });
+        let response = parse_classify(&Fields::new(&result), &Default::default());
+        let confidence = response.confidence.unwrap();
+        assert_eq!(confidence["suggested_severity"], 1.0);
+        assert_eq!(confidence["suggested_priority"], 0.0);
+        assert!(!confidence.contains_key("summary"));
+        assert_eq!(response.parse_warnings.len(), 3);
+    }
+
+    #[test]
+    fn classify_flags_unusual_severity_priority_pairs() {
+        let result = json!({ "suggested_severity": "sev1", "suggested_priority": "lowest" });
+        let response = parse_classify(&Fields::new(&result), &Default::default());
+        // Values are kept, only flagged
+        assert_eq!(response.suggested_severity.as_deref(), Some("S1"));
+        assert_eq!(response.suggested_priority.as_deref(), Some("P5"));
+        assert_eq!(
+            response.parse_warnings,
+            vec!["suggested_priority P5 is unusual for suggested_severity S1 (expected P1/P2)"]
+        );
+
+        let result = json!({ "suggested_severity": "S2", "suggested_priority": "P2" });
+        let response = parse_classify(&Fields::new(&result), &Default::default());
+        assert!(response.parse_warnings.is_empty());
+
+        let disabled = normalize::PriorityConvention::parse("").unwrap();
+        let result = json!({ "suggested_severity": "S1", "suggested_priority": "P5" });
+        assert!(parse_classify(&Fields::new(&result), &disabled).parse_warnings.is_empty());
+    }
+
+    #[test]
+    fn rewritten_summary_is_one_line() {
+        let result = json!({
+            "suggested_summary": "  Crash in WebGL\n when loading   a page ",
+            "reason": "More specific"
+        });
+        let response = parse_rewrite_summary(&Fields::new(&result)).unwrap();
+        assert_eq!(response.suggested_summary, "Crash in WebGL when loading a page");
+        assert_eq!(response.reason, "More specific");
+    }
+
+    #[test]
+    fn rewritten_summary_is_validated() {
+        let err = parse_rewrite_summary(&Fields::new(&json!({ "suggested_summary": " \n" })))
+            .unwrap_err();
+        assert_eq!(err.code, Some("SUGGESTED_SUMMARY_EMPTY"));
+
+        let long = "é".repeat(MAX_SUMMARY_CHARS);
+        assert!(parse_rewrite_summary(&Fields::new(&json!({ "suggested_summary": long }))).is_ok());
+        let long = "é".repeat(MAX_SUMMARY_CHARS + 1);
+        let err = parse_rewrite_summary(&Fields::new(&json!({ "suggested_summary": long })))
+            .unwrap_err();
+        assert_eq!(err.code, Some("SUGGESTED_SUMMARY_TOO_LONG"));
+        assert_eq!(err.status, StatusCode::BAD_GATEWAY);
+    }
+
+    fn with_canned_id(id: &str) -> ClassifyResponse {
+        let mut response = parse_classify(&Fields::new(&json!({})), &Default::default());
+        response.suggested_canned_id = Some(id.to_string());
+        response
+    }
+
+    #[test]
+    fn known_canned_id_is_kept() {
+        let mut response = with_canned_id("need-str");
+        validate_canned_id(&mut response, &[json!({ "id": "need-str" })]);
+        assert_eq!(response.suggested_canned_id.as_deref(), Some("need-str"));
+        assert!(response.parse_warnings.is_empty());
+    }
+
+    #[test]
+    fn unknown_canned_id_is_dropped_with_warning() {
+        let mut response = with_canned_id("made-up");
+        validate_canned_id(&mut response, &[json!({ "id": "need-str" })]);
+        assert_eq!(response.suggested_canned_id, None);
+        assert_eq!(response.parse_warnings.len(), 1);
+    }
+
+    #[test]
+    fn empty_canned_id_is_dropped_silently() {
+        let mut response = with_canned_id("");
+        validate_canned_id(&mut response, &[json!({ "id": "need-str" })]);
+        assert_eq!(response.suggested_canned_id, None);
+        assert!(response.parse_warnings.is_empty());
+    }
+
+    #[test]
+    fn suggest_with_valid_id() {
+        let result = json!({ "suggested_response_id": "need-str", "draft_response": "Hi" });
+        let response = parse_suggest(&Fields::new(&result));
+        assert_eq!(response.suggested_response_id.as_deref(), Some("need-str"));
+        assert!(!response.no_match);
+    }
+
+    #[test]
+    fn suggest_with_explicit_no_match() {
+        let result = json!({
+            "suggested_response_id": "need-str",
+            "no_match": true,
+            "draft_response": "Thanks for the report..."
+        });
+        let response = parse_suggest(&Fields::new(&result));
+        assert_eq!(response.suggested_response_id, None);
+        assert!(response.no_match);
+        assert_eq!(response.draft_response, "Thanks for the report...");
+    }
+
+    #[test]
+    fn suggest_with_missing_id() {
+        for result in [json!({ "draft_response": "Hi" }), json!({ "suggested_response_id": "" })] {
+            let response = parse_suggest(&Fields::new(&result));
+            assert_eq!(response.suggested_response_id, None);
+            assert!(!response.no_match);
+        }
+    }
+
+    #[test]
+    fn refine_without_refined_response_fails() {
+        let result = json!({ "changes_made": ["Shorter"] });
+        let err = parse_refine(&Fields::new(&result)).unwrap_err();
+        assert_eq!(err.code, Some("REFINED_RESPONSE_MISSING"));
+        assert_eq!(err.status, StatusCode::BAD_GATEWAY);
+    }
+
+    #[test]
+    fn refine_caps_and_validates_changes_made() {
+        let mut changes: Vec<serde_json::Value> =
+            (0..MAX_CHANGES_MADE + 10).map(|i| json!(format!("Change {}", i))).collect();
+        changes.insert(0, json!({ "nested": true }));
+        changes.insert(1, json!(42));
+        let result = json!({ "refined_response": "Thanks!", "changes_made": changes });
+
+        let response = parse_refine(&Fields::new(&result)).unwrap();
+        assert_eq!(response.refined_response, "Thanks!");
+        assert_eq!(response.changes_made.len(), MAX_CHANGES_MADE);
+        assert_eq!(response.changes_made[0], "42");
+        assert_eq!(response.parse_warnings.len(), 2);
+    }
+
+    #[test]
+    fn classify_without_notes() {
+        let response = parse_classify(&Fields::new(&json!({ "notes": null })), &Default::default());
+        assert_eq!(response.notes, None);
+    }
+
+    #[test]
+    fn structured_result_is_found_in_provider_outputs() {
+        let result = json!({ "summary": "Crash on load" });
+        let cli_line = json!({ "type": "result", "structured_output": result });
+        let cli_lines = json!([{ "type": "system" }, cli_line, { "type": "other" }]);
+        let nested = json!({ "type": "result", "result": { "structured_output": result } });
+        let tool_use = json!({ "content": [{ "type": "tool_use", "input": result }] });
+        let text = json!({ "content": [{ "type": "text", "text": result.to_string() }] });
+        for raw in [&result, &cli_line, &cli_lines, &nested, &tool_use, &text] {
+            assert_eq!(structured_result(raw), result, "{}", raw);
+        }
+    }
+
+    #[test]
+    fn strings_are_cleaned_when_enabled() {
+        let result = json!({
+            "summary": "\u{200B}Crash on load \u{FEFF}\u{7}  \n",
+            "draft_response": "Thanks!\n\n\tSteps:\u{200D}\r\n1. Load",
+            "suggested_severity": " S2\u{200B}",
+            "suggested_canned_id": "\u{200B} ",
+            "suggested_actions": [{ "action": "Needinfo\u{0} ", "reason": "STR\t" }],
+        });
+        let response = parse_classify(&Fields::new(&result).cleaning(true), &Default::default());
+        assert_eq!(response.summary, "Crash on load");
+        assert_eq!(response.draft_response.as_deref(), Some("Thanks!\n\n\tSteps:\n1. Load"));
+        assert_eq!(response.suggested_severity.as_deref(), Some("S2"));
+        assert_eq!(response.suggested_canned_id, None);
+        assert_eq!(response.suggested_actions[0].action, "Needinfo");
+        assert_eq!(response.suggested_actions[0].reason, "STR");
+
+        let response = parse_classify(&Fields::new(&result), &Default::default());
+        assert_eq!(response.summary, "\u{200B}Crash on load \u{FEFF}\u{7}  \n");
+    }
+}
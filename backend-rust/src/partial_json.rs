@@ -0,0 +1,106 @@
+//! Incremental extraction of fields from a JSON object that is still being written
+//!
+//! With `--output-format stream-json`, the Claude CLI streams the structured output as
+//! fragments of one JSON object. [`FieldScanner`] is fed those fragments and returns each
+//! top-level field as soon as its value is complete, so `/api/ai/classify-stream` can
+//! forward the summary or severity before the rest of the object has arrived.
+
+use serde_json::Value;
+
+#[derive(Debug, Default)]
+pub struct FieldScanner {
+    buffer: String,
+    /// Byte offset in `buffer` just past the opening brace or the last complete field
+    pos: usize,
+    started: bool,
+}
+
+impl FieldScanner {
+    /// Append `fragment` and return the fields it completed, in order
+    pub fn push(&mut self, fragment: &str) -> Vec<(String, Value)> {
+        self.buffer.push_str(fragment);
+        let mut fields = Vec::new();
+        loop {
+            let rest = &self.buffer[self.pos..];
+            let mut next = rest.trim_start();
+            if !self.started {
+                let Some(body) = next.strip_prefix('{') else {
+                    break;
+                };
+                self.pos += rest.len() - body.len();
+                self.started = true;
+                continue;
+            }
+            if let Some(after_comma) = next.strip_prefix(',') {
+                next = after_comma.trim_start();
+            }
+            // Anything but a key is either the closing brace or not yet written
+            if !next.starts_with('"') {
+                break;
+            }
+            let Some((name, value, len)) = complete_field(next) else {
+                break;
+            };
+            fields.push((name, value));
+            self.pos += rest.len() - next.len() + len;
+        }
+        fields
+    }
+}
+
+/// The `"name": value` pair at the start of `text` and its length in bytes, if the
+/// value is complete
+fn complete_field(text: &str) -> Option<(String, Value, usize)> {
+    let mut keys = serde_json::Deserializer::from_str(text).into_iter::<String>();
+    let name = keys.next()?.ok()?;
+    let after_key = &text[keys.byte_offset()..];
+    let value_text = after_key.trim_start().strip_prefix(':')?;
+    let value_start = text.len() - value_text.len();
+
+    let mut values = serde_json::Deserializer::from_str(value_text).into_iter::<Value>();
+    let value = values.next()?.ok()?;
+    let end = value_start + values.byte_offset();
+    // `0.8` at the end of the buffer may still become `0.85`: a value only counts once
+    // the separator after it has arrived
+    if !text[end..].trim_start().starts_with([',', '}']) {
+        return None;
+    }
+    Some((name, value, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn fields_are_returned_once_complete() {
+        let mut scanner = FieldScanner::default();
+        assert!(scanner.push("{\"summ").is_empty());
+        assert!(scanner.push("ary\": \"Crash on").is_empty());
+        assert_eq!(
+            scanner.push(" load\", \"suggested_severity\""),
+            vec![("summary".to_string(), json!("Crash on load"))]
+        );
+        assert!(scanner.push(": \"S2\"").is_empty());
+        assert_eq!(
+            scanner.push(",\"confidence\": {\"summary\": 0.8}, \"ai_detected_str\": tr"),
+            vec![
+                ("suggested_severity".to_string(), json!("S2")),
+                ("confidence".to_string(), json!({ "summary": 0.8 })),
+            ]
+        );
+        assert_eq!(
+            scanner.push("ue}"),
+            vec![("ai_detected_str".to_string(), json!(true))]
+        );
+        assert!(scanner.push("").is_empty());
+    }
+
+    #[test]
+    fn numbers_wait_for_a_separator() {
+        let mut scanner = FieldScanner::default();
+        assert!(scanner.push("{\"score\": 0.8").is_empty());
+        assert_eq!(scanner.push("5 }"), vec![("score".to_string(), json!(0.85))]);
+    }
+}
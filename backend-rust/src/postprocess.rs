@@ -0,0 +1,123 @@
+//! Optional output post-processing hook
+//!
+//! When `POSTPROCESS_CMD` is set, each successful AI response is piped as JSON through
+//! that command's stdin, and the JSON it prints on stdout is sent instead (e.g. to run
+//! drafts through a policy filter). The command runs like the CLI does: tracked in the
+//! child registry and bounded by the CLI timeout. Any failure leaves the response as it
+//! was.
+
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
+use tracing::{debug, warn};
+
+use crate::children::ChildRegistry;
+
+/// Post-processing command settings
+#[derive(Debug, Clone, Default)]
+pub struct PostprocessConfig {
+    /// argv of the command; empty disables post-processing
+    pub command: Vec<String>,
+    pub timeout: Option<Duration>,
+    pub children: Arc<ChildRegistry>,
+}
+
+impl PostprocessConfig {
+    pub fn enabled(&self) -> bool {
+        !self.command.is_empty()
+    }
+
+    /// Pipe `json` through the command. Returns the command's output if it succeeded and
+    /// printed valid JSON, otherwise logs why and returns `None`.
+    pub async fn apply(&self, json: &[u8]) -> Option<Vec<u8>> {
+        let result = match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.run(json))
+                .await
+                .unwrap_or_else(|_| Err(format!("timed out after {}ms", timeout.as_millis()))),
+            None => self.run(json).await,
+        };
+        match result {
+            Ok(output) => Some(output),
+            Err(e) => {
+                warn!("POSTPROCESS_CMD failed, sending unmodified result: {}", e);
+                None
+            }
+        }
+    }
+
+    async fn run(&self, json: &[u8]) -> Result<Vec<u8>, String> {
+        let (program, args) = self.command.split_first().ok_or("no command")?;
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| format!("failed to spawn '{}': {}", program, e))?;
+
+        let mut stdin = child.stdin.take();
+        let mut stdout = child.stdout.take();
+        let registered = self.children.register(child);
+
+        // Write and read concurrently so a large result can't fill both pipes and stall
+        let write = async {
+            if let Some(mut stdin) = stdin.take() {
+                stdin.write_all(json).await?;
+            }
+            Ok::<_, std::io::Error>(())
+        };
+        let read = async {
+            let mut output = Vec::new();
+            if let Some(stdout) = stdout.as_mut() {
+                stdout.read_to_end(&mut output).await?;
+            }
+            Ok(output)
+        };
+        let ((), output) = tokio::try_join!(write, read).map_err(|e| e.to_string())?;
+
+        let mut child = registered.take().ok_or("process was terminated")?;
+        let status = child.wait().await.map_err(|e| e.to_string())?;
+        if !status.success() {
+            return Err(format!("exited with {}", status));
+        }
+        if let Err(e) = serde_json::from_slice::<serde_json::Value>(&output) {
+            return Err(format!("output is not JSON: {}", e));
+        }
+        debug!("POSTPROCESS_CMD rewrote result ({} bytes)", output.len());
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(command: &[&str]) -> PostprocessConfig {
+        PostprocessConfig {
+            command: command.iter().map(|s| s.to_string()).collect(),
+            timeout: Some(Duration::from_secs(5)),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn rewrites_result() {
+        let output = config(&["sed", "s/darn/[filtered]/"])
+            .apply(br#"{"draft":"darn it"}"#)
+            .await
+            .unwrap();
+        let output: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(output, serde_json::json!({ "draft": "[filtered] it" }));
+    }
+
+    #[tokio::test]
+    async fn failures_keep_the_original() {
+        assert_eq!(config(&["false"]).apply(b"{}").await, None);
+        assert_eq!(config(&["echo", "not json"]).apply(b"{}").await, None);
+        assert_eq!(config(&["/nonexistent/filter"]).apply(b"{}").await, None);
+    }
+}
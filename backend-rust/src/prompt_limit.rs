@@ -0,0 +1,73 @@
+//! Prompt size limit
+//!
+//! With `MAX_PROMPT_BYTES` set, an AI request whose `prompt` is longer is answered with
+//! 413 `PROMPT_TOO_LARGE`. A request setting `autoTruncatePrompt: true` instead has the
+//! middle of its prompt cut out: the instructions at the start and the end of the bug
+//! data are kept, behind a note saying how much was removed, and the response gets
+//! `promptTruncated: true`.
+
+/// Error code for a prompt over `MAX_PROMPT_BYTES`
+pub const PROMPT_TOO_LARGE: &str = "PROMPT_TOO_LARGE";
+
+/// Marks where the middle of a truncated prompt was cut
+const GAP: &str = "\n[...]\n";
+
+/// `prompt` cut down to at most `max_bytes` by removing its middle, prefixed with a
+/// `[content truncated]` note, and the number of bytes of `prompt` removed. Unchanged
+/// (and 0) when it already fits. A `max_bytes` too small for the note leaves only it.
+pub fn truncate_middle(prompt: &str, max_bytes: usize) -> (String, usize) {
+    if prompt.len() <= max_bytes {
+        return (prompt.to_string(), 0);
+    }
+    // The note's own length depends on the count it reports; size it for the worst case
+    let note = |removed: usize| format!("[content truncated: {} bytes removed]\n\n", removed);
+    let budget = max_bytes.saturating_sub(note(prompt.len()).len() + GAP.len());
+    let head = floor_char_boundary(prompt, budget - budget / 2);
+    let tail = ceil_char_boundary(prompt, prompt.len() - budget / 2);
+    let removed = tail - head;
+    let truncated = format!("{}{}{}{}", note(removed), &prompt[..head], GAP, &prompt[tail..]);
+    (truncated, removed)
+}
+
+fn floor_char_boundary(text: &str, mut index: usize) -> usize {
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+fn ceil_char_boundary(text: &str, mut index: usize) -> usize {
+    while !text.is_char_boundary(index) {
+        index += 1;
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prompts_that_fit_are_unchanged() {
+        assert_eq!(truncate_middle("Classify this bug", 17), ("Classify this bug".into(), 0));
+    }
+
+    #[test]
+    fn the_middle_is_removed() {
+        let prompt = format!("Instructions\n{}\nEnd of bug", "x".repeat(1000));
+        let (truncated, removed) = truncate_middle(&prompt, 200);
+        assert!(truncated.len() <= 200, "{}", truncated.len());
+        assert!(truncated.starts_with("[content truncated: "));
+        assert!(truncated.contains(&format!("{} bytes removed]\n\nInstructions\n", removed)));
+        assert!(truncated.ends_with("x\nEnd of bug"));
+        assert_eq!(truncated.matches('x').count(), 1000 - removed);
+    }
+
+    #[test]
+    fn cuts_respect_char_boundaries() {
+        let prompt = "é".repeat(200);
+        let (truncated, removed) = truncate_middle(&prompt, 100);
+        assert!(truncated.len() <= 100);
+        assert_eq!(removed % 2, 0);
+    }
+}
@@ -0,0 +1,137 @@
+//! Prompt version tracking
+//!
+//! The frontend sends `promptVersion` (the `PROMPT_VERSION` of `prompts.js`) with each
+//! AI request. The last version seen per endpoint is kept for
+//! `GET /api/ai/prompt-version`, together with whether the backend's parsers are known
+//! to work with it, so frontend/backend drift shows up before parsing starts to fail.
+//!
+//! A version is compatible when it equals an entry of the compatible list or extends it
+//! with more components: `1` accepts `1`, `1.2` and `1.2.3`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use serde::Serialize;
+use tracing::warn;
+
+/// Prompt versions this backend's parsers support (`COMPATIBLE_PROMPT_VERSIONS`)
+pub const DEFAULT_COMPATIBLE: &[&str] = &["1"];
+
+/// Last version seen on one endpoint
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SeenVersion {
+    pub version: String,
+    pub compatible: bool,
+    /// When it was last seen, as an HTTP date
+    pub last_seen: String,
+}
+
+pub struct PromptVersions {
+    compatible: Vec<String>,
+    seen: Mutex<BTreeMap<&'static str, SeenVersion>>,
+    /// Endpoints already warned about an incompatible version. Keyed by endpoint
+    /// alone, so clients sending made-up versions can't grow it.
+    warned: Mutex<BTreeSet<&'static str>>,
+}
+
+impl Default for PromptVersions {
+    fn default() -> Self {
+        Self::new(DEFAULT_COMPATIBLE.iter().map(|v| v.to_string()).collect())
+    }
+}
+
+impl PromptVersions {
+    pub fn new(compatible: Vec<String>) -> Self {
+        PromptVersions {
+            compatible,
+            seen: Mutex::default(),
+            warned: Mutex::default(),
+        }
+    }
+
+    pub fn compatible_versions(&self) -> &[String] {
+        &self.compatible
+    }
+
+    pub fn is_compatible(&self, version: &str) -> bool {
+        self.compatible.iter().any(|accepted| {
+            version == accepted
+                || version
+                    .strip_prefix(accepted.as_str())
+                    .is_some_and(|rest| rest.starts_with('.'))
+        })
+    }
+
+    /// Note the `promptVersion` of a request to `endpoint`; requests without one are
+    /// ignored. An incompatible version is warned about once per endpoint.
+    pub fn record(&self, endpoint: &'static str, version: Option<&str>) {
+        let Some(version) = version.map(str::trim).filter(|v| !v.is_empty()) else {
+            return;
+        };
+        let compatible = self.is_compatible(version);
+        if !compatible && self.warned.lock().unwrap().insert(endpoint) {
+            warn!(
+                "Unrecognized prompt version {:?} on {} (compatible: {})",
+                version,
+                endpoint,
+                self.compatible.join(", ")
+            );
+        }
+        self.seen.lock().unwrap().insert(
+            endpoint,
+            SeenVersion {
+                version: version.to_string(),
+                compatible,
+                last_seen: httpdate::fmt_http_date(SystemTime::now()),
+            },
+        );
+    }
+
+    /// Last version seen per endpoint
+    pub fn seen(&self) -> BTreeMap<&'static str, SeenVersion> {
+        self.seen.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_extending_a_compatible_one_match() {
+        let versions = PromptVersions::new(vec!["1".to_string(), "2.1".to_string()]);
+        assert!(versions.is_compatible("1"));
+        assert!(versions.is_compatible("1.4.2"));
+        assert!(versions.is_compatible("2.1.0"));
+        assert!(!versions.is_compatible("2.0.0"));
+        assert!(!versions.is_compatible("10.0.0"));
+        assert!(!versions.is_compatible("abc123"));
+    }
+
+    #[test]
+    fn last_version_per_endpoint_is_kept() {
+        let versions = PromptVersions::default();
+        versions.record("classify", Some("1.0.0"));
+        versions.record("classify", Some("3.0.0"));
+        versions.record("generate", None);
+        versions.record("explain", Some(" "));
+
+        let seen = versions.seen();
+        assert_eq!(seen.len(), 1);
+        assert_eq!(seen["classify"].version, "3.0.0");
+        assert!(!seen["classify"].compatible);
+    }
+
+    #[test]
+    fn incompatible_versions_are_warned_about_once_per_endpoint() {
+        let versions = PromptVersions::default();
+        for n in 0..1000 {
+            versions.record("classify", Some(&format!("9.{}", n)));
+        }
+        versions.record("generate", Some("9.0"));
+        versions.record("explain", Some("1.2"));
+        assert_eq!(*versions.warned.lock().unwrap(), BTreeSet::from(["classify", "generate"]));
+    }
+}
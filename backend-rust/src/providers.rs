@@ -0,0 +1,48 @@
+//! Provider name resolution
+//!
+//! Request bodies name a provider loosely: any case, surrounding whitespace, and a few
+//! common aliases are accepted. Names are resolved while deserializing, so handlers,
+//! metrics and `usedProvider` only ever see the canonical `claude`, `gemini` or
+//! `openai` (or `fastest`). Anything else is kept, lowercased, for the handler to
+//! reject as an unknown provider.
+
+use serde::{Deserialize, Deserializer};
+
+/// Alternative names and the provider they stand for
+const ALIASES: &[(&str, &str)] = &[
+    ("anthropic", "claude"),
+    ("google", "gemini"),
+    ("gpt", "openai"),
+    ("chatgpt", "openai"),
+];
+
+/// The canonical name for `name`
+pub fn canonical(name: &str) -> String {
+    let name = name.trim().to_ascii_lowercase();
+    ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == name)
+        .map_or(name, |(_, provider)| provider.to_string())
+}
+
+/// `deserialize_with` for request `provider` fields
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    String::deserialize(deserializer).map(|name| canonical(&name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_resolve_to_canonical_providers() {
+        assert_eq!(canonical("claude"), "claude");
+        assert_eq!(canonical(" Claude "), "claude");
+        assert_eq!(canonical("Anthropic"), "claude");
+        assert_eq!(canonical("GPT"), "openai");
+        assert_eq!(canonical("chatgpt"), "openai");
+        assert_eq!(canonical("google"), "gemini");
+        assert_eq!(canonical("FASTEST"), "fastest");
+        assert_eq!(canonical("Nope"), "nope");
+    }
+}
@@ -0,0 +1,72 @@
+//! Recent error ring buffer
+//!
+//! Keeps the last few error responses in memory so `/status` can show what has been
+//! failing without grepping logs. Secrets, email addresses and IP addresses are redacted
+//! before they are stored.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::redact::redact_pii;
+use crate::ErrorResponse;
+
+/// One recorded error
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordedError {
+    /// Unix timestamp (seconds)
+    pub timestamp: u64,
+    /// Same time as an HTTP date, for display
+    pub time: String,
+    pub request_id: String,
+    pub endpoint: String,
+    pub status: u16,
+    pub error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+}
+
+/// Fixed-capacity buffer of the most recent errors, oldest first
+pub struct RecentErrors {
+    capacity: usize,
+    errors: Mutex<VecDeque<RecordedError>>,
+}
+
+impl RecentErrors {
+    pub fn new(capacity: usize) -> Self {
+        RecentErrors {
+            capacity,
+            errors: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    pub fn record(&self, request_id: &str, endpoint: &str, error: &ErrorResponse) {
+        if self.capacity == 0 {
+            return;
+        }
+        let now = SystemTime::now();
+        let recorded = RecordedError {
+            timestamp: now.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            time: httpdate::fmt_http_date(now),
+            request_id: request_id.to_string(),
+            endpoint: endpoint.to_string(),
+            status: error.status.as_u16(),
+            error: redact_pii(&error.error),
+            details: error.details.as_deref().map(redact_pii),
+        };
+
+        let mut errors = self.errors.lock().unwrap();
+        if errors.len() == self.capacity {
+            errors.pop_front();
+        }
+        errors.push_back(recorded);
+    }
+
+    /// Recorded errors, newest first
+    pub fn snapshot(&self) -> Vec<RecordedError> {
+        self.errors.lock().unwrap().iter().rev().cloned().collect()
+    }
+}
@@ -0,0 +1,181 @@
+//! Secret and PII redaction
+//!
+//! Scrubs API keys and tokens from text before it is logged or kept in memory, and
+//! with [`redact_pii`] email and IP addresses too, for text anyone who can open
+//! `/status` can read.
+
+/// Prefixes that introduce a secret. The token following the prefix is masked.
+const SECRET_PREFIXES: &[&str] = &["sk-ant-", "sk-", "AIza", "Bearer ", "api_key="];
+
+/// Tokens shorter than this after the prefix are left alone (e.g. `sk-learn`).
+const MIN_SECRET_LEN: usize = 8;
+
+fn is_token_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')
+}
+
+/// Replace anything that looks like an API key or bearer token with `[REDACTED]`,
+/// keeping the prefix so the kind of secret is still recognizable.
+pub fn redact(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    let mut prev: Option<char> = None;
+
+    while let Some(c) = rest.chars().next() {
+        let at_boundary = prev.is_none_or(|p| !p.is_ascii_alphanumeric());
+        let prefix = at_boundary
+            .then(|| SECRET_PREFIXES.iter().find(|p| rest.starts_with(**p)))
+            .flatten();
+
+        if let Some(prefix) = prefix {
+            let after = &rest[prefix.len()..];
+            let token_len = after
+                .char_indices()
+                .find(|(_, c)| !is_token_char(*c))
+                .map(|(i, _)| i)
+                .unwrap_or(after.len());
+            if token_len >= MIN_SECRET_LEN {
+                out.push_str(prefix);
+                out.push_str("[REDACTED]");
+                rest = &after[token_len..];
+                prev = Some(']');
+                continue;
+            }
+        }
+
+        out.push(c);
+        rest = &rest[c.len_utf8()..];
+        prev = Some(c);
+    }
+
+    out
+}
+
+/// [`redact`], then replace email addresses with `[EMAIL]` and IPv4 addresses with
+/// `[IP]`.
+pub fn redact_pii(text: &str) -> String {
+    redact_ipv4(&redact_emails(&redact(text)))
+}
+
+fn is_local_part_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '%' | '+' | '-')
+}
+
+fn is_domain_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | '-')
+}
+
+/// A domain of at least two non-empty labels, ending in an alphabetic TLD
+fn is_domain(domain: &str) -> bool {
+    let labels: Vec<&str> = domain.split('.').collect();
+    labels.len() >= 2
+        && labels.iter().all(|label| !label.is_empty())
+        && labels
+            .last()
+            .is_some_and(|tld| tld.len() >= 2 && tld.chars().all(|c| c.is_ascii_alphabetic()))
+}
+
+fn redact_emails(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    // Everything before `copied` is already in `out`
+    let mut copied = 0;
+    for (at, _) in text.match_indices('@') {
+        if at < copied {
+            continue;
+        }
+        let start = text[copied..at]
+            .char_indices()
+            .rev()
+            .find(|(_, c)| !is_local_part_char(*c))
+            .map_or(copied, |(i, c)| copied + i + c.len_utf8());
+        let after = &text[at + 1..];
+        let domain_len = after.find(|c| !is_domain_char(c)).unwrap_or(after.len());
+        // A sentence may end right after the address
+        let domain = after[..domain_len].trim_end_matches(['.', '-']);
+        if start == at || !is_domain(domain) {
+            continue;
+        }
+        out.push_str(&text[copied..start]);
+        out.push_str("[EMAIL]");
+        copied = at + 1 + domain.len();
+    }
+    out.push_str(&text[copied..]);
+    out
+}
+
+fn redact_ipv4(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    let mut prev: Option<char> = None;
+
+    while let Some(c) = rest.chars().next() {
+        let at_boundary = prev.is_none_or(|p| !p.is_ascii_alphanumeric() && p != '.');
+        if c.is_ascii_digit() && at_boundary {
+            let run = rest
+                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                .unwrap_or(rest.len());
+            let candidate = rest[..run].trim_end_matches('.');
+            let word_follows = rest[run..].starts_with(|c: char| c.is_ascii_alphanumeric());
+            if !word_follows && candidate.parse::<std::net::Ipv4Addr>().is_ok() {
+                out.push_str("[IP]");
+                rest = &rest[candidate.len()..];
+                prev = Some(']');
+                continue;
+            }
+            // Skip the whole run, so no address is found inside a longer number
+            out.push_str(&rest[..run]);
+            prev = rest[..run].chars().last();
+            rest = &rest[run..];
+            continue;
+        }
+
+        out.push(c);
+        rest = &rest[c.len_utf8()..];
+        prev = Some(c);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_keep_their_prefix() {
+        assert_eq!(
+            redact("key sk-ant-api03-abcdefgh, Bearer abc.def.ghi"),
+            "key sk-ant-[REDACTED], Bearer [REDACTED]"
+        );
+        assert_eq!(redact("uses sk-learn"), "uses sk-learn");
+    }
+
+    #[test]
+    fn emails_are_redacted() {
+        assert_eq!(
+            redact_pii("Reporter jane.doe+bmo@mozilla.com. CC \"dev@example.co.uk\""),
+            "Reporter [EMAIL]. CC \"[EMAIL]\""
+        );
+        assert_eq!(redact_pii("Unknown user: jane@example.com"), "Unknown user: [EMAIL]");
+        // Not addresses
+        let not_addresses = "@nick, a@b, x@localhost, a @b.com, ü@x.org";
+        assert_eq!(redact_pii(not_addresses), not_addresses);
+        assert_eq!(redact_pii("über:jane@x.org"), "über:[EMAIL]");
+    }
+
+    #[test]
+    fn ipv4_addresses_are_redacted() {
+        assert_eq!(
+            redact_pii("connect to 10.0.0.12:8080 failed (from 192.168.1.1)."),
+            "connect to [IP]:8080 failed (from [IP])."
+        );
+        // Versions and longer numbers are left alone
+        let not_addresses = "Firefox 128.0.1, 1.2.3.4.5, 999.1.1.1, v1.2.3.4";
+        assert_eq!(redact_pii(not_addresses), not_addresses);
+    }
+
+    #[test]
+    fn pii_redaction_includes_secrets() {
+        assert_eq!(redact_pii("api_key=abcdefghij for a@b.io"), "api_key=[REDACTED] for [EMAIL]");
+    }
+}
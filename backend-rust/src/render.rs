@@ -0,0 +1,62 @@
+//! Markdown rendering for draft previews
+//!
+//! Drafts are model output, so the rendered HTML is sanitized with a deliberately small
+//! allowlist: basic formatting, lists, quotes, code and links. No images, tables, raw
+//! HTML, styles or event handlers survive, and links are limited to http(s)/mailto.
+
+use std::collections::HashSet;
+
+use pulldown_cmark::{html, Options, Parser};
+
+/// Tags kept in rendered drafts
+const ALLOWED_TAGS: &[&str] = &[
+    "p", "br", "hr", "strong", "em", "del", "code", "pre", "blockquote", "ul", "ol", "li",
+    "a", "h1", "h2", "h3", "h4", "h5", "h6",
+];
+
+/// Render markdown to sanitized HTML
+pub fn markdown_to_html(markdown: &str) -> String {
+    let mut unsafe_html = String::new();
+    html::push_html(
+        &mut unsafe_html,
+        Parser::new_ext(markdown, Options::ENABLE_STRIKETHROUGH),
+    );
+
+    ammonia::Builder::empty()
+        .tags(ALLOWED_TAGS.iter().copied().collect::<HashSet<_>>())
+        .add_tag_attributes("a", ["href"])
+        .url_schemes(HashSet::from(["http", "https", "mailto"]))
+        .link_rel(Some("noopener noreferrer nofollow"))
+        .clean(&unsafe_html)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_basic_markdown() {
+        assert_eq!(
+            markdown_to_html("Thanks for the **report**!\n\n- one\n- two"),
+            "<p>Thanks for the <strong>report</strong>!</p>\n<ul>\n<li>one</li>\n<li>two</li>\n</ul>\n"
+        );
+    }
+
+    #[test]
+    fn strips_scripts_and_event_handlers() {
+        let html = markdown_to_html("<script>alert(1)</script><img src=x onerror=alert(1)>hi");
+        assert!(!html.contains("script"));
+        assert!(!html.contains("onerror"));
+        assert!(!html.contains("<img"));
+    }
+
+    #[test]
+    fn strips_javascript_links() {
+        let html = markdown_to_html("[click](javascript:alert(1)) [ok](https://example.com)");
+        assert!(!html.contains("javascript"));
+        assert!(html.contains(
+            "<a href=\"https://example.com\" rel=\"noopener noreferrer nofollow\">ok</a>"
+        ));
+    }
+}
@@ -0,0 +1,132 @@
+//! Required response fields per endpoint
+//!
+//! `REQUIRED_FIELDS_<endpoint>=summary,suggested_severity` (dashes in the endpoint
+//! written as underscores: `REQUIRED_FIELDS_suggest_response`) lists fields a successful
+//! result of that AI endpoint must have. A result missing one - absent, `null`, or an
+//! empty string, array or object - is answered with 422 `MISSING_REQUIRED_FIELD` instead,
+//! so incomplete output is caught at the server rather than by the frontend. Endpoints
+//! without a list are unchecked.
+//!
+//! Classifications are checked by the handlers before they are stored in the history
+//! (streamed ones end with an `error` event); every endpoint's JSON response is checked
+//! again after `POSTPROCESS_CMD`.
+
+use std::collections::BTreeMap;
+
+use axum::http::StatusCode;
+use tracing::warn;
+
+use crate::{schemas, ErrorResponse};
+
+/// Error code for a result without one of its endpoint's required fields
+pub const MISSING_REQUIRED_FIELD: &str = "MISSING_REQUIRED_FIELD";
+
+const PREFIX: &str = "REQUIRED_FIELDS_";
+
+/// Required fields by endpoint from the `REQUIRED_FIELDS_*` entries of `vars`. Endpoints
+/// without a built-in schema are errors.
+pub fn from_vars(
+    vars: impl Iterator<Item = (String, String)>,
+) -> Result<BTreeMap<String, Vec<String>>, String> {
+    let mut required = BTreeMap::new();
+    for (name, value) in vars {
+        let Some(endpoint) = name.strip_prefix(PREFIX) else {
+            continue;
+        };
+        let endpoint = endpoint.to_ascii_lowercase().replace('_', "-");
+        if schemas::default_schema(&endpoint).is_none() {
+            return Err(format!("{}: unknown endpoint '{}'", name, endpoint));
+        }
+        let fields: Vec<String> = value
+            .split(',')
+            .map(str::trim)
+            .filter(|field| !field.is_empty())
+            .map(str::to_string)
+            .collect();
+        if !fields.is_empty() {
+            required.insert(endpoint, fields);
+        }
+    }
+    Ok(required)
+}
+
+/// 422 `MISSING_REQUIRED_FIELD` when `result` lacks one of `fields`
+pub fn check(fields: &[String], result: &serde_json::Value) -> Result<(), ErrorResponse> {
+    let missing = missing(fields, result);
+    if missing.is_empty() {
+        return Ok(());
+    }
+    warn!("Result is missing required fields: {}", missing.join(", "));
+    Err(ErrorResponse {
+        error: "Result is missing required fields".to_string(),
+        details: Some(format!("Missing required fields: {}", missing.join(", "))),
+        code: Some(MISSING_REQUIRED_FIELD),
+        status: StatusCode::UNPROCESSABLE_ENTITY,
+    })
+}
+
+/// Those of `fields` that `result` lacks or has empty
+pub fn missing<'a>(fields: &'a [String], result: &serde_json::Value) -> Vec<&'a str> {
+    fields
+        .iter()
+        .filter(|field| match result.get(field.as_str()) {
+            None | Some(serde_json::Value::Null) => true,
+            Some(serde_json::Value::String(s)) => s.trim().is_empty(),
+            Some(serde_json::Value::Array(items)) => items.is_empty(),
+            Some(serde_json::Value::Object(fields)) => fields.is_empty(),
+            Some(_) => false,
+        })
+        .map(String::as_str)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn vars(pairs: &[(&str, &str)]) -> impl Iterator<Item = (String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn lists_are_read_per_endpoint() {
+        let required = from_vars(vars(&[
+            ("REQUIRED_FIELDS_classify", "summary, suggested_severity"),
+            ("REQUIRED_FIELDS_suggest_response", "canned_id"),
+            ("REQUIRED_FIELDS_generate", " "),
+            ("PATH", "/usr/bin"),
+        ]))
+        .unwrap();
+        assert_eq!(required["classify"], ["summary", "suggested_severity"]);
+        assert_eq!(required["suggest-response"], ["canned_id"]);
+        assert_eq!(required.len(), 2);
+        assert!(from_vars(vars(&[("REQUIRED_FIELDS_nope", "x")])).is_err());
+    }
+
+    #[test]
+    fn empty_and_absent_fields_are_missing() {
+        let fields: Vec<String> = ["summary", "suggested_severity", "suggested_actions"]
+            .map(String::from)
+            .to_vec();
+        let complete = json!({
+            "summary": "Crash",
+            "suggested_severity": "S2",
+            "suggested_actions": [{ "action": "needinfo" }],
+            "fuzzing_testcase": false,
+        });
+        assert!(missing(&fields, &complete).is_empty());
+
+        let incomplete = json!({ "summary": " ", "suggested_actions": [] });
+        assert_eq!(
+            missing(&fields, &incomplete),
+            ["summary", "suggested_severity", "suggested_actions"]
+        );
+        // false is a value
+        assert!(missing(&["fuzzing_testcase".to_string()], &complete).is_empty());
+    }
+}
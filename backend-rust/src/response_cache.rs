@@ -0,0 +1,141 @@
+//! Classify response cache
+//!
+//! With `RESPONSE_CACHE_TTL_SECS` set, successful `/api/ai/classify` responses are kept
+//! in memory for that long, keyed by the request body, and an identical request is
+//! answered from the cache without running the model again. A cached answer has
+//! `cached: true` and no `meta`: the original run's cost and duration weren't spent on
+//! it. Responses carry a weak `ETag` (a hash of the cached body), since the first
+//! response and later cached ones differ in those fields; a client sending it back in
+//! `If-None-Match` gets 304. Without the cache a repeated request can produce a
+//! different result, so no `ETag` is sent then.
+
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use axum::body::Bytes;
+
+/// Most responses kept; the oldest is dropped to make room
+const MAX_ENTRIES: usize = 1024;
+
+/// A cached response body, as sent to repeated requests, and its `ETag`
+#[derive(Debug, Clone)]
+pub struct Cached {
+    pub body: Bytes,
+    pub etag: String,
+}
+
+#[derive(Debug)]
+pub struct ResponseCache {
+    ttl: Duration,
+    /// Keyed by the full request body, so different requests never share an entry
+    entries: Mutex<HashMap<Bytes, (Instant, Cached)>>,
+}
+
+impl ResponseCache {
+    pub fn new(ttl: Duration) -> Self {
+        ResponseCache {
+            ttl,
+            entries: Mutex::default(),
+        }
+    }
+
+    /// The response cached for a request body, unless it has expired
+    pub fn get(&self, request: &[u8]) -> Option<Cached> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(request)
+            .filter(|(stored, _)| stored.elapsed() < self.ttl)
+            .map(|(_, cached)| cached.clone())
+    }
+
+    /// Cache `body` as the response to `request`, returning the entry
+    pub fn insert(&self, request: Bytes, body: &[u8]) -> Cached {
+        let body = replay_body(body);
+        let cached = Cached {
+            etag: etag(&body),
+            body,
+        };
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, (stored, _)| stored.elapsed() < self.ttl);
+        if entries.len() >= MAX_ENTRIES {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, (stored, _))| *stored)
+                .map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(request, (Instant::now(), cached.clone()));
+        cached
+    }
+}
+
+/// A response body as sent from the cache: `meta` removed and `cached: true` added.
+/// Bodies that aren't JSON objects are kept as they are.
+fn replay_body(body: &[u8]) -> Bytes {
+    match serde_json::from_slice::<serde_json::Value>(body) {
+        Ok(serde_json::Value::Object(mut fields)) => {
+            fields.remove("meta");
+            fields.insert("cached".to_string(), true.into());
+            Bytes::from(serde_json::to_vec(&fields).unwrap_or_default())
+        }
+        _ => Bytes::copy_from_slice(body),
+    }
+}
+
+/// Weak `ETag` for a cached body
+fn etag(body: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    format!("W/\"{:016x}\"", hasher.finish())
+}
+
+/// Whether an `If-None-Match` value lists `etag` (or is `*`), comparing weakly: tags
+/// match whether or not either is marked `W/`
+pub fn none_match(if_none_match: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.strip_prefix("W/").unwrap_or(tag).to_string();
+    let etag = opaque(etag);
+    if_none_match
+        .split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || opaque(tag) == etag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_expire() {
+        let cache = ResponseCache::new(Duration::from_millis(20));
+        let cached = cache.insert(Bytes::from_static(b"request"), b"{}");
+        assert_eq!(cache.get(b"request").unwrap().etag, cached.etag);
+        assert!(cache.get(b"other").is_none());
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(cache.get(b"request").is_none());
+    }
+
+    #[test]
+    fn cached_bodies_are_marked_and_lose_meta() {
+        let cache = ResponseCache::new(Duration::from_secs(60));
+        let body = br#"{"summary":"Crash","meta":{"costUsd":0.01}}"#;
+        let cached = cache.insert(Bytes::from_static(b"request"), body);
+        let replayed: serde_json::Value = serde_json::from_slice(&cached.body).unwrap();
+        assert_eq!(replayed, serde_json::json!({ "summary": "Crash", "cached": true }));
+        assert_eq!(&cache.insert(Bytes::from_static(b"text"), b"[]").body[..], b"[]");
+    }
+
+    #[test]
+    fn if_none_match_lists_are_matched() {
+        let tag = etag(b"{}");
+        assert!(tag.starts_with("W/\""));
+        assert!(none_match(&tag, &tag));
+        assert!(none_match(tag.trim_start_matches("W/"), &tag));
+        assert!(none_match(&format!("\"other\", {}", tag), &tag));
+        assert!(none_match("*", &tag));
+        assert!(!none_match("\"other\"", &tag));
+    }
+}
@@ -0,0 +1,198 @@
+//! Built-in default output schemas
+//!
+//! Schemas normally come from frontend/src/prompts.js with every request. These copies
+//! are only used when `ALLOW_DEFAULT_SCHEMAS` is enabled and a request omits `schema`,
+//! so non-frontend clients (curl, scripts) can use the backend on their own. Keep them
+//! in step with `SCHEMAS` in prompts.js.
+//!
+//! `SCHEMA_OVERRIDES` is the other way round: schemas loaded from files at startup that
+//! replace whatever schema a request sends for their endpoint, for trying out changes to
+//! one endpoint's output server-side.
+
+use std::collections::BTreeMap;
+
+use serde_json::json;
+
+/// Default schema for an endpoint, keyed by the last path segment of its route
+pub fn default_schema(endpoint: &str) -> Option<serde_json::Value> {
+    let schema = match endpoint {
+        "classify" => json!({
+            "type": "object",
+            "properties": {
+                "ai_detected_str": { "type": "boolean" },
+                "ai_detected_test_attached": { "type": "boolean" },
+                "crashstack_present": { "type": "boolean" },
+                "fuzzing_testcase": { "type": "boolean" },
+                "summary": { "type": "string" },
+                "suggested_severity": {
+                    "type": "string",
+                    "enum": ["--", "S1", "S2", "S3", "S4", "N/A"]
+                },
+                "suggested_priority": {
+                    "type": "string",
+                    "enum": ["--", "P1", "P2", "P3", "P5"]
+                },
+                "suggested_actions": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "action": { "type": "string" },
+                            "reason": { "type": "string" }
+                        },
+                        "required": ["action", "reason"]
+                    }
+                },
+                "triage_reasoning": { "type": "string" },
+                "suggested_canned_id": { "type": "string" },
+                "draft_response": { "type": "string" },
+                "notes": { "type": "object" },
+                "confidence": {
+                    "type": "object",
+                    "additionalProperties": { "type": "number", "minimum": 0, "maximum": 1 }
+                }
+            },
+            "required": [
+                "ai_detected_str", "ai_detected_test_attached", "crashstack_present",
+                "fuzzing_testcase", "summary", "suggested_severity", "suggested_priority",
+                "suggested_actions", "triage_reasoning", "suggested_canned_id", "draft_response"
+            ]
+        }),
+        "suggest-response" => json!({
+            "type": "object",
+            "properties": {
+                "suggested_response_id": { "type": "string" },
+                "draft_response": { "type": "string" },
+                "reasoning": { "type": "string" },
+                "no_match": { "type": "boolean" }
+            },
+            "required": ["suggested_response_id", "draft_response"]
+        }),
+        "generate" => json!({
+            "type": "object",
+            "properties": {
+                "response_text": { "type": "string" },
+                "suggested_actions": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "action": { "type": "string" },
+                            "reason": { "type": "string" }
+                        },
+                        "required": ["action"]
+                    }
+                },
+                "used_canned_ids": { "type": "array", "items": { "type": "string" } },
+                "reasoning": { "type": "string" }
+            },
+            "required": ["response_text", "suggested_actions", "reasoning"]
+        }),
+        "refine" => json!({
+            "type": "object",
+            "properties": {
+                "refined_response": { "type": "string" },
+                "changes_made": { "type": "array", "items": { "type": "string" } }
+            },
+            "required": ["refined_response", "changes_made"]
+        }),
+        "testpage" => json!({
+            "type": "object",
+            "properties": {
+                "can_generate": { "type": "boolean" },
+                "html_content": { "type": "string" },
+                "reason": { "type": "string" }
+            },
+            "required": ["can_generate", "html_content", "reason"]
+        }),
+        "explain" => json!({
+            "type": "object",
+            "properties": {
+                "overview": { "type": "string" },
+                "field_explanations": {
+                    "type": "object",
+                    "additionalProperties": { "type": "string" }
+                }
+            },
+            "required": ["overview", "field_explanations"]
+        }),
+        "summarize-comments" => json!({
+            "type": "object",
+            "properties": {
+                "summary": { "type": "string" },
+                "key_points": { "type": "array", "items": { "type": "string" } },
+                "open_questions": { "type": "array", "items": { "type": "string" } }
+            },
+            "required": ["summary", "key_points", "open_questions"]
+        }),
+        "rewrite-summary" => json!({
+            "type": "object",
+            "properties": {
+                "suggested_summary": { "type": "string", "minLength": 1, "maxLength": 255 },
+                "reason": { "type": "string" }
+            },
+            "required": ["suggested_summary", "reason"]
+        }),
+        _ => return None,
+    };
+    Some(schema)
+}
+
+/// Parse `SCHEMA_OVERRIDES`: `endpoint:path` pairs separated by commas, each file holding
+/// a JSON schema object. Returns the schemas as compact JSON by endpoint. Endpoints
+/// without a built-in schema, unreadable files, invalid schemas and schemas too large for
+/// one CLI argument are errors.
+pub fn load_overrides(input: &str) -> Result<BTreeMap<String, String>, String> {
+    input
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (endpoint, path) = entry
+                .split_once(':')
+                .ok_or_else(|| format!("expected endpoint:path, got '{}'", entry))?;
+            let (endpoint, path) = (endpoint.trim(), path.trim());
+            if default_schema(endpoint).is_none() {
+                return Err(format!("unknown endpoint '{}'", endpoint));
+            }
+            let contents = std::fs::read_to_string(path)
+                .map_err(|e| format!("can't read {}: {}", path, e))?;
+            let schema: serde_json::Value = serde_json::from_str(&contents)
+                .map_err(|e| format!("invalid JSON in {}: {}", path, e))?;
+            if !schema.is_object() {
+                return Err(format!("{} doesn't hold a JSON schema object", path));
+            }
+            let schema = schema.to_string();
+            if schema.len() > crate::claude_cli::MAX_SCHEMA_ARG_BYTES {
+                return Err(format!("{} is too large to pass to the claude CLI", path));
+            }
+            Ok((endpoint.to_string(), schema))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overrides_are_validated() {
+        let path = std::env::temp_dir().join(format!("schema-{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(&path, r#"{ "type": "object", "properties": {} }"#).unwrap();
+        let path = path.to_str().unwrap();
+
+        let overrides = load_overrides(&format!("classify:{}", path)).unwrap();
+        assert_eq!(overrides["classify"], r#"{"properties":{},"type":"object"}"#);
+        assert!(load_overrides("").unwrap().is_empty());
+        assert!(load_overrides(&format!("nope:{}", path)).is_err());
+        assert!(load_overrides("classify:/nonexistent/schema.json").is_err());
+        assert!(load_overrides("classify").is_err());
+
+        std::fs::write(path, "[1, 2]").unwrap();
+        assert!(load_overrides(&format!("classify:{}", path)).is_err());
+        let huge = format!("{{\"description\":\"{}\"}}", "x".repeat(128 * 1024));
+        std::fs::write(path, huge).unwrap();
+        assert!(load_overrides(&format!("classify:{}", path)).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,216 @@
+//! Server-side prompt templates
+//!
+//! Prompts normally come from frontend/src/prompts.js with every request. Deployments
+//! that want to own their prompts can set `PROMPT_TEMPLATE_DIR` to a directory holding
+//! Handlebars (`<endpoint>.hbs`) or Tera (`<endpoint>.tera`) templates (`classify.hbs`,
+//! `rewrite-summary.tera`, ...); a request to an endpoint with a template that omits
+//! `prompt` gets the template rendered with its `bug` instead. Requests sending a
+//! prompt are unaffected.
+//!
+//! Templates are compiled at startup, so a syntax error stops the server rather than
+//! failing requests. Both engines get `{ "bug": <bug> }` as their context and neither
+//! HTML-escapes its output. The bug as pretty JSON is `{{json bug}}` in Handlebars and
+//! `{{ bug | json_encode(pretty=true) }}` in Tera. A missing value renders empty in
+//! Handlebars and fails the request in Tera.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use handlebars::{handlebars_helper, Handlebars};
+use tracing::warn;
+
+use crate::schemas;
+
+handlebars_helper!(json: |value: Json| serde_json::to_string_pretty(value).unwrap_or_default());
+
+/// The template engines, by file extension
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Syntax {
+    Handlebars,
+    Tera,
+}
+
+impl Syntax {
+    fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "hbs" => Some(Syntax::Handlebars),
+            "tera" => Some(Syntax::Tera),
+            _ => None,
+        }
+    }
+}
+
+/// Compiled templates, by endpoint
+#[derive(Debug)]
+pub struct Templates {
+    handlebars: Handlebars<'static>,
+    tera: tera::Tera,
+    syntaxes: BTreeMap<String, Syntax>,
+}
+
+impl Default for Templates {
+    fn default() -> Self {
+        let mut handlebars = Handlebars::new();
+        handlebars.register_escape_fn(handlebars::no_escape);
+        handlebars.register_helper("json", Box::new(json));
+        let mut tera = tera::Tera::default();
+        tera.autoescape_on(Vec::new());
+        Templates {
+            handlebars,
+            tera,
+            syntaxes: BTreeMap::new(),
+        }
+    }
+}
+
+impl Templates {
+    /// Compile `source` as the template for `endpoint`. A syntax error, or a second
+    /// template for one endpoint, is an error.
+    pub fn add(&mut self, endpoint: &str, syntax: Syntax, source: &str) -> Result<(), String> {
+        if self.syntaxes.contains_key(endpoint) {
+            return Err(format!("more than one template for {}", endpoint));
+        }
+        match syntax {
+            Syntax::Handlebars => self
+                .handlebars
+                .register_template_string(endpoint, source)
+                .map_err(|e| e.to_string())?,
+            Syntax::Tera => self
+                .tera
+                .add_raw_template(endpoint, source)
+                .map_err(tera_error)?,
+        }
+        self.syntaxes.insert(endpoint.to_string(), syntax);
+        Ok(())
+    }
+
+    /// Endpoints with a template
+    pub fn endpoints(&self) -> impl Iterator<Item = &str> {
+        self.syntaxes.keys().map(String::as_str)
+    }
+
+    /// `endpoint`'s template rendered with `bug`, or `None` when it has none
+    pub fn render(
+        &self,
+        endpoint: &str,
+        bug: &serde_json::Value,
+    ) -> Option<Result<String, String>> {
+        let context = serde_json::json!({ "bug": bug });
+        let rendered = match self.syntaxes.get(endpoint)? {
+            Syntax::Handlebars => self
+                .handlebars
+                .render(endpoint, &context)
+                .map_err(|e| e.to_string()),
+            Syntax::Tera => tera::Context::from_value(context)
+                .and_then(|context| self.tera.render(endpoint, &context))
+                .map_err(tera_error),
+        };
+        Some(rendered)
+    }
+}
+
+/// A Tera error with its causes, which hold the details (e.g. where parsing failed)
+fn tera_error(e: tera::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = std::error::Error::source(&e);
+    while let Some(cause) = source {
+        message = format!("{}: {}", message, cause);
+        source = cause.source();
+    }
+    message
+}
+
+/// Load and compile the templates in `dir`. Files that aren't `<endpoint>.<ext>` for a
+/// known endpoint are skipped with a warning; an unreadable directory or file, a
+/// template that doesn't compile, or two templates for one endpoint, is an error.
+pub fn load(dir: &Path) -> Result<Templates, String> {
+    let entries =
+        std::fs::read_dir(dir).map_err(|e| format!("can't read {}: {}", dir.display(), e))?;
+    let mut templates = Templates::default();
+    for entry in entries {
+        let path = entry
+            .map_err(|e| format!("can't read {}: {}", dir.display(), e))?
+            .path();
+        let syntax = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(Syntax::from_extension);
+        let endpoint = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .filter(|stem| schemas::default_schema(stem).is_some());
+        let (Some(syntax), Some(endpoint)) = (syntax, endpoint) else {
+            warn!("Ignoring {}: not an <endpoint>.hbs/.tera template", path.display());
+            continue;
+        };
+        let source = std::fs::read_to_string(&path)
+            .map_err(|e| format!("can't read {}: {}", path.display(), e))?;
+        templates
+            .add(endpoint, syntax, &source)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    Ok(templates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn both_syntaxes_render_the_bug() {
+        let bug = json!({ "id": 7, "summary": "<Crash>", "comments": [{ "text": "STR" }] });
+        let mut templates = Templates::default();
+        templates
+            .add(
+                "classify",
+                Syntax::Handlebars,
+                "{{#if bug.summary}}{{bug.summary}}{{/if}} ({{bug.id}}): \
+                 {{bug.comments.0.text}}{{bug.missing}}\n{{json bug}}",
+            )
+            .unwrap();
+        templates
+            .add(
+                "rewrite-summary",
+                Syntax::Tera,
+                "{{ bug.summary | upper }}{% for c in bug.comments %} {{ c.text }}{% endfor %}",
+            )
+            .unwrap();
+
+        let classify = templates.render("classify", &bug).unwrap().unwrap();
+        assert!(classify.starts_with("<Crash> (7): STR\n{"), "{}", classify);
+        assert!(classify.contains("\"summary\": \"<Crash>\""));
+        assert_eq!(templates.render("rewrite-summary", &bug).unwrap().unwrap(), "<CRASH> STR");
+        assert!(templates.render("generate", &bug).is_none());
+        // Tera doesn't render missing values
+        let mut strict = Templates::default();
+        strict.add("classify", Syntax::Tera, "{{ bug.missing }}").unwrap();
+        assert!(strict.render("classify", &bug).unwrap().is_err());
+    }
+
+    #[test]
+    fn templates_are_loaded_by_endpoint() {
+        let dir = std::env::temp_dir().join(format!("templates-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::write(dir.join("classify.hbs"), "Classify {{bug.id}}").unwrap();
+        std::fs::write(dir.join("README.md"), "notes").unwrap();
+        std::fs::write(dir.join("nope.tera"), "x").unwrap();
+
+        let templates = load(&dir).unwrap();
+        assert_eq!(templates.endpoints().collect::<Vec<_>>(), ["classify"]);
+        let rendered = templates.render("classify", &json!({ "id": 1 })).unwrap();
+        assert_eq!(rendered.unwrap(), "Classify 1");
+
+        std::fs::write(dir.join("classify.tera"), "again").unwrap();
+        assert!(load(&dir).is_err());
+        std::fs::remove_file(dir.join("classify.tera")).unwrap();
+        // Syntax errors are caught when loading
+        std::fs::write(dir.join("generate.tera"), "{% if bug.id %}").unwrap();
+        assert!(load(&dir).is_err());
+        std::fs::write(dir.join("generate.tera"), "").unwrap();
+        std::fs::write(dir.join("classify.hbs"), "{{#if bug.id}}").unwrap();
+        assert!(load(&dir).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(load(&dir).is_err());
+    }
+}
@@ -0,0 +1,2203 @@
+//! Router-level tests
+//!
+//! Requests go through the full router (middleware, handlers, CLI runner, parsing) with
+//! `tests/stub-claude` standing in for the `claude` CLI. The stub prints the fixture
+//! named by `STUB_CLAUDE_FIXTURE`, which each test sets through `CLI_WRAPPER`-style
+//! `env` wrapping so tests can run in parallel.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use serde_json::json;
+use tower::ServiceExt;
+
+use crate::{
+    actions, bugzilla, build_router, capabilities, claude_cli, headless_reason, health, history,
+    normalize_base_path, parse_env_number, parse_port, parse_provider_timeouts,
+    parse_response_headers, recent_errors, response_cache, schemas, transcript, AppState,
+};
+
+/// App state whose CLI is the stub, printing `fixture` and exiting with `exit_code`
+fn stub_state(fixture: &str, exit_code: i32) -> Arc<AppState> {
+    let dir = env!("CARGO_MANIFEST_DIR");
+    Arc::new(AppState {
+        claude_mode: "cli".to_string(),
+        anthropic_api_key: None,
+        gemini_api_key: None,
+        openai_api_key: None,
+        claude_model: "stub-model".to_string(),
+        anthropic: Default::default(),
+        cli: claude_cli::CliConfig {
+            program: format!("{}/tests/stub-claude", dir),
+            wrapper: vec![
+                "env".to_string(),
+                format!("STUB_CLAUDE_FIXTURE={}/tests/fixtures/{}", dir, fixture),
+                format!("STUB_CLAUDE_EXIT={}", exit_code),
+            ],
+            ..Default::default()
+        },
+        provider_timeouts: HashMap::new(),
+        race_max_providers: 3,
+        compare_max_concurrency: 3,
+        bugzilla: bugzilla::BugzillaConfig {
+            base_url: "http://127.0.0.1:9".to_string(),
+            api_key: None,
+            max_attachment_bytes: 1024,
+            search_max_limit: 100,
+            comments_max_page: 50,
+            actions: Arc::default(),
+        },
+        http_client: reqwest::Client::new(),
+        max_uri_bytes: 4096,
+        recent_errors: Arc::new(recent_errors::RecentErrors::new(10)),
+        refine_sessions: Arc::default(),
+        active_requests: Arc::default(),
+        admin_token: Some("test-admin-token".to_string()),
+        postprocess: Default::default(),
+        transcript: None,
+        required_fields: Arc::default(),
+        history: None,
+        response_cache: None,
+        disk: None,
+        request_limit: None,
+        http_call_limit: None,
+        max_concurrent_http_calls: 0,
+        max_body_bytes: 64 * 1024,
+        max_prompt_bytes: 0,
+        testpage_max_attachments: 10,
+        testpage_max_attachment_bytes: 256 * 1024,
+        max_cost_usd: None,
+        response_headers: Vec::new(),
+        content_security_policy: None,
+        prompt_versions: Arc::default(),
+        base_path: String::new(),
+        log_sample_rate: 0.0,
+        action_denylist: Vec::new(),
+        priority_convention: Default::default(),
+        ready: Arc::new(true.into()),
+        provider_availability: Arc::new(health::AvailabilityCache::new(
+            std::time::Duration::ZERO,
+        )),
+        started_at: std::time::SystemTime::now(),
+        frontend_dir: "/nonexistent".into(),
+    })
+}
+
+/// POST `body` to `path` and return the status and JSON response
+async fn post(
+    state: Arc<AppState>,
+    path: &str,
+    body: serde_json::Value,
+) -> (StatusCode, serde_json::Value) {
+    let request = Request::post(path)
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let response = build_router(state, "/nonexistent")
+        .oneshot(request)
+        .await
+        .unwrap();
+    let status = response.status();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap())
+}
+
+/// POST `body` to an SSE endpoint and return its `(event, data)` pairs
+async fn post_events(
+    state: Arc<AppState>,
+    path: &str,
+    body: serde_json::Value,
+) -> Vec<(String, serde_json::Value)> {
+    let request = Request::post(path)
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let response = build_router(state, "/nonexistent")
+        .oneshot(request)
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    String::from_utf8(bytes.to_vec())
+        .unwrap()
+        .split("\n\n")
+        .filter_map(|event| {
+            let mut name = None;
+            let mut data = None;
+            for line in event.lines() {
+                if let Some(value) = line.strip_prefix("event: ") {
+                    name = Some(value.to_string());
+                } else if let Some(value) = line.strip_prefix("data: ") {
+                    data = serde_json::from_str(value).ok();
+                }
+            }
+            Some((name?, data?))
+        })
+        .collect()
+}
+
+fn classify_body() -> serde_json::Value {
+    json!({
+        "provider": "claude",
+        "bug": { "id": 1, "summary": "Crash" },
+        "prompt": "Classify this bug",
+        "schema": "{}"
+    })
+}
+
+fn gzip(bytes: &[u8]) -> Vec<u8> {
+    use std::io::Write;
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(bytes).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// POST a gzip-encoded `body` to `path` and return the status and raw response body
+async fn post_gzip(state: Arc<AppState>, path: &str, body: &[u8]) -> (StatusCode, Vec<u8>) {
+    let request = Request::post(path)
+        .header("content-type", "application/json")
+        .header("content-encoding", "gzip")
+        .body(Body::from(gzip(body)))
+        .unwrap();
+    let response = build_router(state, "/nonexistent")
+        .oneshot(request)
+        .await
+        .unwrap();
+    let status = response.status();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, bytes.to_vec())
+}
+
+#[tokio::test]
+async fn gzip_request_bodies_are_decompressed() {
+    let (plain_status, plain) =
+        post(stub_state("classify.json", 0), "/api/ai/classify", classify_body()).await;
+    let (status, body) = post_gzip(
+        stub_state("classify.json", 0),
+        "/api/ai/classify",
+        classify_body().to_string().as_bytes(),
+    )
+    .await;
+
+    assert_eq!(status, plain_status);
+    assert_eq!(serde_json::from_slice::<serde_json::Value>(&body).unwrap(), plain);
+}
+
+#[tokio::test]
+async fn body_limit_applies_after_decompression() {
+    // Compresses to well under the 64 KiB test limit, but expands past it
+    let mut body = classify_body();
+    body["bug"]["description"] = json!("a".repeat(256 * 1024));
+    let body = body.to_string();
+    assert!(gzip(body.as_bytes()).len() < 64 * 1024);
+
+    let (status, _) =
+        post_gzip(stub_state("classify.json", 0), "/api/ai/classify", body.as_bytes()).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn cli_cost_over_budget_is_rejected() {
+    let mut body = classify_body();
+    body["maxCostUsd"] = json!(1.0);
+    let (status, _) = post(stub_state("costly.json", 0), "/api/ai/classify", body.clone()).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // The CLI reports $0.42 after the run; the server cap is lower than the request's
+    let mut state = Arc::into_inner(stub_state("costly.json", 0)).unwrap();
+    state.max_cost_usd = Some(0.25);
+    let (status, body) = post(Arc::new(state), "/api/ai/classify", body).await;
+    assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+    assert_eq!(body["code"], "BUDGET_EXCEEDED");
+}
+
+#[tokio::test]
+async fn cli_retries_share_the_budget() {
+    // Each run misses its structured output and reports $0.30: the second run takes the
+    // total over the budget, so there's no third
+    let mut state = Arc::into_inner(stub_state("no-structured-output-costly.json", 0)).unwrap();
+    state.cli.max_retries = 2;
+    state.max_cost_usd = Some(0.5);
+    let (status, body) = post(Arc::new(state), "/api/ai/classify", classify_body()).await;
+    assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+    assert_eq!(body["code"], "BUDGET_EXCEEDED");
+    assert!(body["details"].as_str().unwrap().contains("$0.6000"), "{}", body);
+}
+
+#[tokio::test]
+async fn classify_returns_parsed_cli_output() {
+    let (status, body) = post(
+        stub_state("classify.json", 0),
+        "/api/ai/classify",
+        classify_body(),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body,
+        json!({
+            "ai_detected_str": true,
+            "ai_detected_test_attached": false,
+            "crashstack_present": true,
+            "fuzzing_testcase": false,
+            "summary": "Crash when loading a page with WebGL",
+            "suggested_severity": "S2",
+            "suggested_priority": "P2",
+            "suggested_actions": [{
+                "id": actions::action_id("Needinfo reporter", "Need about:support"),
+                "action": "Needinfo reporter",
+                "reason": "Need about:support"
+            }],
+            "triage_reasoning": "Crash with stack",
+            "usedProvider": "claude"
+        })
+    );
+}
+
+#[tokio::test]
+async fn reasoning_is_left_out_on_request() {
+    let mut body = classify_body();
+    body["includeReasoning"] = json!(false);
+    let (status, body) = post(stub_state("classify.json", 0), "/api/ai/classify", body).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["summary"], "Crash when loading a page with WebGL");
+    assert!(body.get("triage_reasoning").is_none());
+
+    let mut body = classify_body();
+    let (_, generated) =
+        post(stub_state("classify.json", 0), "/api/ai/generate", body.clone()).await;
+    assert_eq!(generated["reasoning"], "");
+    body["includeReasoning"] = json!(false);
+    let (status, generated) = post(stub_state("classify.json", 0), "/api/ai/generate", body).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(generated.get("reasoning").is_none());
+}
+
+#[tokio::test]
+async fn oversized_prompts_are_rejected_or_truncated() {
+    let mut state = Arc::into_inner(stub_state("classify.json", 0)).unwrap();
+    state.max_prompt_bytes = 100;
+    let state = Arc::new(state);
+    let mut body = classify_body();
+    body["prompt"] = json!(format!("Classify this bug\n{}", "x".repeat(200)));
+
+    let (status, error) = post(Arc::clone(&state), "/api/ai/classify", body.clone()).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(error["code"], "PROMPT_TOO_LARGE");
+
+    body["autoTruncatePrompt"] = json!(true);
+    let (status, result) = post(Arc::clone(&state), "/api/ai/classify", body).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(result["promptTruncated"], true);
+    assert_eq!(result["summary"], "Crash when loading a page with WebGL");
+
+    let (status, result) = post(state, "/api/ai/classify", classify_body()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(result.get("promptTruncated").is_none());
+}
+
+#[tokio::test]
+async fn postprocess_cmd_rewrites_result() {
+    let mut state = Arc::into_inner(stub_state("classify.json", 0)).unwrap();
+    state.postprocess.command = ["sed", "s/WebGL/[redacted]/"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let (status, body) = post(Arc::new(state), "/api/ai/classify", classify_body()).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["summary"], "Crash when loading a page with [redacted]");
+}
+
+#[tokio::test]
+async fn postprocess_cmd_skips_oversized_results() {
+    let mut state = Arc::into_inner(stub_state("classify.json", 0)).unwrap();
+    state.postprocess.command = ["sed", "s/x/y/"].iter().map(|s| s.to_string()).collect();
+    let big = json!({ "summary": "x".repeat(crate::middleware::POSTPROCESS_BODY_LIMIT) });
+    let router = axum::Router::new()
+        .route("/api/ai/classify", axum::routing::post(move || async { axum::Json(big) }))
+        .layer(axum::middleware::from_fn_with_state(
+            Arc::new(state),
+            crate::middleware::postprocess_responses,
+        ));
+    let response = router
+        .oneshot(Request::post("/api/ai/classify").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert!(body["summary"].as_str().unwrap().starts_with("xxx"));
+}
+
+#[tokio::test]
+async fn cli_reported_cost_and_duration_are_returned_as_meta() {
+    let (status, body) = post(
+        stub_state("classify-meta.json", 0),
+        "/api/ai/classify",
+        classify_body(),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body["meta"],
+        json!({ "costUsd": 0.0123, "durationMs": 4567, "numTurns": 2 })
+    );
+}
+
+#[tokio::test]
+async fn failed_cli_runs_report_meta_too() {
+    let meta = json!({ "costUsd": 0.02, "numTurns": 3 });
+    let (status, body) = post(
+        stub_state("error-max-turns.json", 0),
+        "/api/ai/classify",
+        classify_body(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+    assert_eq!(body["meta"], meta);
+
+    let events = post_events(
+        stub_state("error-max-turns.json", 0),
+        "/api/ai/classify-stream",
+        classify_body(),
+    )
+    .await;
+    let (name, error) = events.last().unwrap();
+    assert_eq!(name, "error");
+    assert_eq!(error["code"], "CLI_MAX_TURNS");
+    assert_eq!(error["meta"], meta);
+}
+
+#[tokio::test]
+async fn ensemble_runs_are_capped_and_merged() {
+    let mut request = classify_body();
+    request["ensemble"] = json!(10);
+    let (status, body) = post(stub_state("classify-meta.json", 0), "/api/ai/classify", request)
+        .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["ensemble"]["runs"], 5);
+    assert_eq!(body["ensemble"]["failed"], 0);
+    assert_eq!(body["ensemble"]["agreement"]["suggested_severity"], 1.0);
+    // Every run is paid for
+    let cost = body["meta"]["costUsd"].as_f64().unwrap();
+    assert!((cost - 5.0 * 0.0123).abs() < 1e-9);
+}
+
+#[tokio::test]
+async fn ensemble_runs_share_the_budget() {
+    // Each run reports $0.42
+    let mut request = classify_body();
+    request["maxCostUsd"] = json!(0.6);
+    let (status, _) = post(stub_state("costly.json", 0), "/api/ai/classify", request.clone())
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    request["ensemble"] = json!(2);
+    let (status, body) = post(stub_state("costly.json", 0), "/api/ai/classify", request.clone())
+        .await;
+    assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+    assert_eq!(body["code"], "BUDGET_EXCEEDED");
+
+    request["maxCostUsd"] = json!(1.0);
+    let (status, body) = post(stub_state("costly.json", 0), "/api/ai/classify", request).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["ensemble"]["failed"], 0);
+}
+
+#[tokio::test]
+async fn cached_classify_answers_304_to_a_matching_etag() {
+    let mut state = Arc::into_inner(stub_state("classify.json", 0)).unwrap();
+    state.response_cache = Some(Arc::new(response_cache::ResponseCache::new(
+        std::time::Duration::from_secs(60),
+    )));
+    let app = build_router(Arc::new(state), "/nonexistent");
+    let classify = |if_none_match: Option<&str>| {
+        let mut request =
+            Request::post("/api/ai/classify").header("content-type", "application/json");
+        if let Some(tag) = if_none_match {
+            request = request.header("if-none-match", tag);
+        }
+        let request = request.body(Body::from(classify_body().to_string())).unwrap();
+        app.clone().oneshot(request)
+    };
+
+    let first = classify(None).await.unwrap();
+    assert_eq!(first.status(), StatusCode::OK);
+    let etag = first.headers()["etag"].to_str().unwrap().to_string();
+    let first = axum::body::to_bytes(first.into_body(), usize::MAX).await.unwrap();
+
+    let hit = classify(Some("\"stale\"")).await.unwrap();
+    assert_eq!(hit.status(), StatusCode::OK);
+    assert_eq!(hit.headers()["etag"], etag.as_str());
+    let hit = axum::body::to_bytes(hit.into_body(), usize::MAX).await.unwrap();
+    let mut expected: serde_json::Value = serde_json::from_slice(&first).unwrap();
+    assert!(expected.get("cached").is_none());
+    expected["cached"] = json!(true);
+    assert_eq!(serde_json::from_slice::<serde_json::Value>(&hit).unwrap(), expected);
+
+    let not_modified = classify(Some(&etag)).await.unwrap();
+    assert_eq!(not_modified.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(not_modified.headers()["etag"], etag.as_str());
+    let body = axum::body::to_bytes(not_modified.into_body(), usize::MAX).await.unwrap();
+    assert!(body.is_empty());
+
+    // Without the cache, no ETag
+    let response = build_router(stub_state("classify.json", 0), "/nonexistent")
+        .oneshot(
+            Request::post("/api/ai/classify")
+                .header("content-type", "application/json")
+                .body(Body::from(classify_body().to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert!(!response.headers().contains_key("etag"));
+}
+
+#[tokio::test]
+async fn classify_without_structured_output_fails_with_code() {
+    let (status, body) = post(
+        stub_state("no-structured-output.json", 0),
+        "/api/ai/classify",
+        classify_body(),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(body["code"], "STRUCTURED_OUTPUT_MISSING");
+}
+
+#[tokio::test]
+async fn fenced_json_output_is_parsed() {
+    let (status, body) = post(
+        stub_state("classify-fenced.txt", 0),
+        "/api/ai/classify",
+        classify_body(),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["summary"], "Fenced result");
+    assert_eq!(body["suggested_severity"], "S3");
+}
+
+#[tokio::test]
+async fn non_utf8_cli_output_is_reported() {
+    let (status, body) = post(
+        stub_state("non-utf8.json", 0),
+        "/api/ai/classify",
+        classify_body(),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(body["code"], "STRUCTURED_OUTPUT_MISSING");
+    let details = body["details"].as_str().unwrap();
+    assert!(
+        details.starts_with("CLI produced non-UTF8 output, 2 bytes replaced"),
+        "{}",
+        details
+    );
+}
+
+#[tokio::test]
+async fn cli_failure_maps_to_error() {
+    let (status, body) = post(
+        stub_state("classify.json", 1),
+        "/api/ai/classify",
+        classify_body(),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(body["error"], "Claude CLI execution failed");
+}
+
+#[tokio::test]
+async fn cli_error_subtypes_map_to_codes() {
+    let cases = [
+        ("error-max-turns.json", "CLI_MAX_TURNS", "error_max_turns"),
+        (
+            "error-during-execution.json",
+            "CLI_EXECUTION_ERROR",
+            "error_during_execution: Tool execution failed: permission denied",
+        ),
+        (
+            "error-unknown-subtype.json",
+            "CLI_RESULT_ERROR",
+            "error_max_structured_output_retries",
+        ),
+    ];
+    for (fixture, code, details) in cases {
+        // The CLI may exit with failure on an error result; the subtype wins either way
+        for exit_code in [0, 1] {
+            let (status, body) =
+                post(stub_state(fixture, exit_code), "/api/ai/classify", classify_body()).await;
+            assert_eq!(status, StatusCode::BAD_GATEWAY, "{}", fixture);
+            assert_eq!(body["code"], code, "{}", fixture);
+            assert_eq!(body["details"], details, "{}", fixture);
+        }
+    }
+}
+
+#[tokio::test]
+async fn rewrite_summary_returns_suggestion() {
+    let (status, body) = post(
+        stub_state("rewrite-summary.json", 0),
+        "/api/ai/rewrite-summary",
+        classify_body(),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body,
+        json!({
+            "suggestedSummary": "Crash in WebGL when loading a page with a large texture",
+            "reason": "Names the component and the trigger"
+        })
+    );
+}
+
+#[tokio::test]
+async fn summarize_comments_takes_a_page_of_comments() {
+    let mut state = Arc::into_inner(stub_state("rewrite-summary.json", 0)).unwrap();
+    state.bugzilla.comments_max_page = 2;
+    let mut templates = crate::templates::Templates::default();
+    templates
+        .add(
+            "summarize-comments",
+            crate::templates::Syntax::Tera,
+            "{% for c in bug.comments %}{{ c.text }}{% endfor %}",
+        )
+        .unwrap();
+    state.cli.prompt_templates = Arc::new(templates);
+    let state = Arc::new(state);
+    let mut request = classify_body();
+    request.as_object_mut().unwrap().remove("prompt");
+    request["bug"]["comments"] = (0..5).map(|i| json!({ "text": format!("c{}", i) })).collect();
+
+    let (status, body) =
+        post(Arc::clone(&state), "/api/ai/summarize-comments", request.clone()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["nextCommentsOffset"], 2);
+
+    request["commentsOffset"] = json!(4);
+    request["commentsLimit"] = json!(10);
+    let (status, body) =
+        post(Arc::clone(&state), "/api/ai/summarize-comments", request.clone()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["nextCommentsOffset"], serde_json::Value::Null);
+
+    // Past the end the template gets no comments, so renders nothing
+    request["commentsOffset"] = json!(5);
+    let (status, body) = post(state, "/api/ai/summarize-comments", request).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "EMPTY_PROMPT");
+}
+
+#[tokio::test]
+async fn testpage_attachments_are_truncated() {
+    let mut state = Arc::into_inner(stub_state("testpage.json", 0)).unwrap();
+    state.testpage_max_attachments = 2;
+    state.testpage_max_attachment_bytes = 8;
+    let mut request = classify_body();
+    request["bug"]["attachments"] = json!([
+        { "id": 1, "data": "c21hbGw=" },
+        { "id": 2, "data": "bGFyZ2UgYXR0YWNobWVudA==" },
+        { "id": 3, "data": "dGhpcmQ=" },
+    ]);
+    let (status, body) = post(Arc::new(state), "/api/ai/testpage", request.clone()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["can_generate"], true);
+    assert_eq!(
+        body["attachmentsTruncated"],
+        "kept the first 2 of 3 attachments; left out the data of 1 over 8 bytes"
+    );
+
+    let mut bug = request["bug"].clone();
+    crate::limit_attachments(&mut bug, 2, 8);
+    assert_eq!(bug["attachments"], json!([{ "id": 1, "data": "c21hbGw=" }, { "id": 2 }]));
+
+    // Within the limits, nothing is noted
+    let (_, body) = post(stub_state("testpage.json", 0), "/api/ai/testpage", request).await;
+    assert!(body.get("attachmentsTruncated").is_none());
+}
+
+#[tokio::test]
+async fn prompt_versions_are_reported_per_endpoint() {
+    let state = stub_state("classify.json", 0);
+    let mut request = classify_body();
+    request["promptVersion"] = json!("1.2.0");
+    post(Arc::clone(&state), "/api/ai/classify", request.clone()).await;
+    request["promptVersion"] = json!("2.0.0");
+    post(Arc::clone(&state), "/api/ai/rewrite-summary", request).await;
+
+    let response = build_router(state, "/nonexistent")
+        .oneshot(Request::get("/api/ai/prompt-version").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(body["compatibleVersions"], json!(["1"]));
+    assert_eq!(body["compatible"], false);
+    assert_eq!(body["endpoints"]["classify"]["version"], "1.2.0");
+    assert_eq!(body["endpoints"]["classify"]["compatible"], true);
+    assert_eq!(body["endpoints"]["rewrite-summary"]["compatible"], false);
+}
+
+#[tokio::test]
+async fn dry_run_estimates_a_batch() {
+    let item = json!({ "prompt": "x".repeat(3500), "schema": "{}" });
+    let (status, body) = post(
+        stub_state("classify.json", 0),
+        "/api/ai/dry-run",
+        json!({
+            "provider": "claude",
+            "model": "claude-sonnet-4-5",
+            "items": [item, item],
+        }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    let items = body["items"].as_array().unwrap();
+    assert_eq!(items.len(), 2);
+    assert_eq!(body["promptBytes"], 7000);
+    assert_eq!(body["schemaBytes"], 4);
+    let item_cost = items[0]["estimatedCostUsd"].as_f64().unwrap();
+    let total = body["estimatedCostUsd"].as_f64().unwrap();
+    assert!(item_cost > 0.0);
+    assert!((total - 2.0 * item_cost).abs() < 1e-9);
+
+    // Unknown price: tokens only
+    let (_, body) = post(
+        stub_state("classify.json", 0),
+        "/api/ai/dry-run",
+        json!({ "provider": "claude", "model": "local", "prompt": "hello" }),
+    )
+    .await;
+    assert!(body.get("estimatedCostUsd").is_none());
+    assert!(body.get("items").is_none());
+}
+
+#[tokio::test]
+async fn reparse_runs_a_captured_output_through_the_parser() {
+    let raw = json!({
+        "type": "result",
+        "subtype": "success",
+        "structured_output": { "summary": "Crash on load", "suggested_severity": "S9" },
+    });
+    let (status, body) = post(
+        stub_state("classify.json", 0),
+        "/api/ai/reparse",
+        json!({ "endpoint": "classify", "raw": raw }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["structuredOutput"]["suggested_severity"], "S9");
+    assert_eq!(body["result"]["summary"], "Crash on load");
+    assert!(body["result"].get("suggested_severity").is_none());
+    assert_eq!(body["result"]["parseWarnings"].as_array().unwrap().len(), 1);
+
+    // Parser errors come back as the endpoint would send them
+    let (status, body) = post(
+        stub_state("classify.json", 0),
+        "/api/ai/reparse",
+        json!({ "endpoint": "refine", "raw": { "changes_made": [] } }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+    assert_eq!(body["code"], "REFINED_RESPONSE_MISSING");
+
+    let (status, body) = post(
+        stub_state("classify.json", 0),
+        "/api/ai/reparse",
+        json!({ "endpoint": "nope", "raw": {} }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "UNKNOWN_ENDPOINT");
+}
+
+#[tokio::test]
+async fn selftest_checks_a_schema_against_the_parser() {
+    let selftest = |endpoint: &str, schema: serde_json::Value| {
+        let body = json!({ "endpoint": endpoint, "prompt": "Test", "schema": schema.to_string() });
+        post(stub_state("classify.json", 0), "/api/ai/selftest", body)
+    };
+
+    let (status, body) = selftest("classify", schemas::default_schema("classify").unwrap()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["problems"], json!([]));
+    assert_eq!(body["pass"], true);
+    assert_eq!(body["result"]["summary"], "example");
+
+    let schema = json!({
+        "type": "object",
+        "properties": { "suggested_severity": { "type": "string", "enum": ["whenever"] } }
+    });
+    let (_, body) = selftest("classify", schema).await;
+    assert_eq!(body["pass"], false);
+    assert!(body["problems"][0].as_str().unwrap().starts_with("Parse warning: "));
+
+    let schema = json!({ "type": "object", "properties": { "changes_made": { "type": "array" } } });
+    let (_, body) = selftest("refine", schema).await;
+    assert_eq!(body["pass"], false);
+    assert!(body["result"].is_null());
+
+    let (status, body) = selftest("nope", json!({})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "UNKNOWN_ENDPOINT");
+}
+
+#[tokio::test]
+async fn force_model_needs_the_admin_token() {
+    let dry_run = |authorization: &'static str| async move {
+        let request = Request::post("/api/ai/dry-run")
+            .header("content-type", "application/json")
+            .header("x-force-model", "claude-opus-4-1")
+            .header("authorization", authorization)
+            .body(Body::from(
+                json!({ "provider": "claude", "model": "sonnet", "prompt": "hi" }).to_string(),
+            ))
+            .unwrap();
+        let response = build_router(stub_state("classify.json", 0), "/nonexistent")
+            .oneshot(request)
+            .await
+            .unwrap();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+    };
+
+    assert_eq!(dry_run("Bearer test-admin-token").await["model"], "claude-opus-4-1");
+    assert_eq!(dry_run("Bearer wrong").await["model"], "sonnet");
+}
+
+#[tokio::test]
+async fn classify_requires_prompt() {
+    let mut request = classify_body();
+    request.as_object_mut().unwrap().remove("prompt");
+    let (status, body) = post(stub_state("classify.json", 0), "/api/ai/classify", request).await;
+
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(body["error"], "Missing prompt from frontend");
+}
+
+#[tokio::test]
+async fn classify_rejects_empty_prompt() {
+    let mut request = classify_body();
+    request["prompt"] = json!("  \n");
+    let (status, body) = post(stub_state("classify.json", 0), "/api/ai/classify", request).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "EMPTY_PROMPT");
+}
+
+#[tokio::test]
+async fn classify_rejects_oversized_schema() {
+    let mut state = Arc::into_inner(stub_state("classify.json", 0)).unwrap();
+    state.cli.max_schema_bytes = 1024;
+    let mut request = classify_body();
+    request["schema"] = json!(format!("{{\"description\":\"{}\"}}", "x".repeat(2000)));
+    let (status, body) = post(Arc::new(state), "/api/ai/classify", request).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "SCHEMA_TOO_LARGE");
+    assert_eq!(
+        body["details"],
+        "The schema is 2018 bytes, the limit is 1024 (MAX_SCHEMA_BYTES)"
+    );
+}
+
+#[tokio::test]
+async fn schemas_up_to_one_argument_reach_the_cli_whole() {
+    let args = std::env::temp_dir().join(format!("stub-args-{}", uuid::Uuid::new_v4()));
+    let classify = |schema: String| {
+        let mut state = Arc::into_inner(stub_state("classify.json", 0)).unwrap();
+        state.cli.max_schema_bytes = claude_cli::MAX_SCHEMA_ARG_BYTES;
+        state.max_body_bytes = 256 * 1024;
+        state.cli.wrapper.push(format!("STUB_CLAUDE_ARGS={}", args.display()));
+        let mut request = classify_body();
+        request["schema"] = json!(schema);
+        post(Arc::new(state), "/api/ai/classify", request)
+    };
+    let schema_arg = || {
+        let logged = std::fs::read_to_string(&args).unwrap();
+        let mut lines = logged.lines().skip_while(|line| *line != "--json-schema");
+        lines.nth(1).unwrap().to_string()
+    };
+
+    let (status, _) = classify("{}".to_string()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(schema_arg(), "{}");
+
+    // The largest schema the OS lets the CLI take as its argument
+    let padding = claude_cli::MAX_SCHEMA_ARG_BYTES - r#"{"description":""}"#.len();
+    let schema = format!("{{\"description\":\"{}\"}}", "x".repeat(padding));
+    let (status, _) = classify(schema.clone()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(schema_arg(), schema);
+    std::fs::remove_file(&args).unwrap();
+
+    let (status, body) = classify(format!("{} ", schema)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "SCHEMA_TOO_LARGE");
+}
+
+#[test]
+fn empty_model_falls_back_to_the_default() {
+    let state = stub_state("classify.json", 0);
+    assert_eq!(state.model_or_default(None), "stub-model");
+    assert_eq!(state.model_or_default(Some(String::new())), "stub-model");
+    assert_eq!(state.model_or_default(Some("  ".to_string())), "stub-model");
+    assert_eq!(state.model_or_default(Some("opus".to_string())), "opus");
+}
+
+#[test]
+fn shared_settings_are_read_from_every_request_body() {
+    let body = json!({
+        "provider": "claude",
+        "bug": { "id": 1 },
+        "seed": 7,
+        "maxRetries": 2,
+        "timeoutMs": 1000,
+        "maxCostUsd": 0.5,
+        "promptVersion": "2.1",
+        "includeReasoning": false,
+    });
+    let classify: crate::ClassifyRequest = serde_json::from_value(body.clone()).unwrap();
+    let settings = classify.settings;
+    assert_eq!(settings.seed, Some(7));
+    assert_eq!(settings.max_retries, Some(2));
+    assert_eq!(settings.timeout_ms, Some(1000));
+    assert_eq!(settings.max_cost_usd, Some(0.5));
+    assert_eq!(settings.prompt_version.as_deref(), Some("2.1"));
+    assert_eq!(settings.include_reasoning, Some(false));
+
+    let test_page: crate::TestPageRequest = serde_json::from_value(body).unwrap();
+    assert_eq!(test_page.settings.seed, Some(7));
+    assert_eq!(test_page.settings.max_retries, Some(2));
+}
+
+#[test]
+fn providers_default_to_their_own_model() {
+    let state = stub_state("classify.json", 0);
+    assert_eq!(state.model_for("claude", None), "stub-model");
+    assert_eq!(state.model_for("gemini", Some(" ".to_string())), "gemini-2.5-flash");
+    assert_eq!(state.model_for("openai", None), "gpt-4o");
+    assert_eq!(state.model_for("openai", Some("gpt-4.1".to_string())), "gpt-4.1");
+}
+
+#[tokio::test]
+async fn results_missing_required_fields_are_rejected() {
+    let classify = |fields: &[&str]| {
+        let mut state = Arc::into_inner(stub_state("classify.json", 0)).unwrap();
+        state.required_fields = Arc::new(std::collections::BTreeMap::from([(
+            "classify".to_string(),
+            fields.iter().map(|field| field.to_string()).collect(),
+        )]));
+        post(Arc::new(state), "/api/ai/classify", classify_body())
+    };
+
+    let (status, body) = classify(&["summary", "suggested_priority"]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["suggested_priority"], "P2");
+
+    let (status, body) = classify(&["summary", "draft_response", "notes"]).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["code"], "MISSING_REQUIRED_FIELD");
+    assert_eq!(body["details"], "Missing required fields: draft_response, notes");
+}
+
+#[tokio::test]
+async fn incomplete_classifications_are_not_recorded_or_streamed() {
+    let path = std::env::temp_dir().join(format!("history-{}.db", uuid::Uuid::new_v4()));
+    let mut state = Arc::into_inner(stub_state("classify-stream.jsonl", 0)).unwrap();
+    state.history = Some(Arc::new(history::History::open(&path).unwrap()));
+    state.required_fields = Arc::new(std::collections::BTreeMap::from([(
+        "classify".to_string(),
+        vec!["draft_response".to_string()],
+    )]));
+    let state = Arc::new(state);
+
+    let events = post_events(Arc::clone(&state), "/api/ai/classify-stream", classify_body()).await;
+    let (name, error) = events.last().unwrap();
+    assert_eq!(name, "error");
+    assert_eq!(error["code"], "MISSING_REQUIRED_FIELD");
+    assert!(events.iter().all(|(name, _)| name != "result"));
+
+    let (status, _) = post(Arc::clone(&state), "/api/ai/classify", classify_body()).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let history = state.history.as_ref().unwrap();
+    history.flush().await;
+    let response = build_router(Arc::clone(&state), "/nonexistent")
+        .oneshot(Request::get("/api/history/1").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(body["classifications"], json!([]));
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+    }
+}
+
+#[tokio::test]
+async fn classify_rejects_unknown_provider() {
+    let mut request = classify_body();
+    request["provider"] = json!("nope");
+    let (status, body) = post(stub_state("classify.json", 0), "/api/ai/classify", request).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "Unknown provider: nope");
+}
+
+#[tokio::test]
+async fn provider_aliases_resolve_to_canonical_names() {
+    let mut request = classify_body();
+    request["provider"] = json!(" Anthropic");
+    let (status, body) = post(stub_state("classify.json", 0), "/api/ai/classify", request).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["usedProvider"], "claude");
+}
+
+#[tokio::test]
+async fn classify_compare_reports_each_candidate() {
+    let (status, body) = post(
+        stub_state("classify.json", 0),
+        "/api/ai/classify-compare",
+        json!({
+            "bug": { "id": 1 },
+            "prompt": "Classify this bug",
+            "schema": "{}",
+            "candidates": [
+                { "provider": "claude", "model": "stub-sonnet" },
+                { "provider": "nope" }
+            ]
+        }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    let results = body["results"].as_array().unwrap();
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0]["model"], "stub-sonnet");
+    assert_eq!(results[0]["result"]["suggested_severity"], "S2");
+    assert!(results[0]["latencyMs"].is_u64());
+    assert_eq!(results[1]["model"], "stub-model");
+    assert_eq!(results[1]["error"]["error"], "Unknown provider: nope");
+}
+
+#[tokio::test]
+async fn classify_compare_defaults_models_and_takes_a_slot_per_candidate() {
+    let compare = |slots: usize, candidates: serde_json::Value| {
+        let mut state = Arc::into_inner(stub_state("classify.json", 0)).unwrap();
+        state.request_limit = Some(Arc::new(tokio::sync::Semaphore::new(slots)));
+        let body = json!({
+            "bug": { "id": 1 },
+            "prompt": "Classify this bug",
+            "schema": "{}",
+            "candidates": candidates,
+        });
+        post(Arc::new(state), "/api/ai/classify-compare", body)
+    };
+    let candidates = json!([{ "provider": "claude" }, { "provider": "openai" }]);
+
+    let (status, body) = compare(2, candidates.clone()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["results"][0]["model"], "stub-model");
+    assert_eq!(body["results"][1]["model"], "gpt-4o");
+
+    let (status, body) = compare(1, candidates).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["code"], "OVERLOADED");
+}
+
+#[tokio::test]
+async fn admin_requests_require_token() {
+    let get = |auth: Option<&str>| {
+        let mut request = Request::get("/admin/requests");
+        if let Some(auth) = auth {
+            request = request.header("authorization", auth);
+        }
+        build_router(stub_state("classify.json", 0), "/nonexistent")
+            .oneshot(request.body(Body::empty()).unwrap())
+    };
+
+    assert_eq!(get(None).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        get(Some("Bearer wrong")).await.unwrap().status(),
+        StatusCode::UNAUTHORIZED
+    );
+    let response = get(Some("Bearer test-admin-token")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(body, json!({ "requests": [] }));
+}
+
+#[tokio::test]
+async fn preflight_gets_no_content_with_cors_headers() {
+    for path in ["/api/ai/classify", "/index.html"] {
+        let request = Request::options(path)
+            .header("origin", "http://localhost:3000")
+            .header("access-control-request-method", "POST")
+            .header("access-control-request-headers", "content-type,x-provider")
+            .body(Body::empty())
+            .unwrap();
+        let response = build_router(stub_state("classify.json", 0), "/nonexistent")
+            .oneshot(request)
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT, "{}", path);
+        let headers = response.headers();
+        assert_eq!(headers["access-control-allow-origin"], "*");
+        let methods = headers["access-control-allow-methods"].to_str().unwrap();
+        assert!(methods.contains("POST"), "{}", methods);
+        let allowed = headers["access-control-allow-headers"].to_str().unwrap();
+        assert!(allowed.contains("content-type"), "{}", allowed);
+        assert!(allowed.contains("x-provider"), "{}", allowed);
+    }
+}
+
+#[tokio::test]
+async fn transcript_truncates_fields_but_not_the_response() {
+    let path = std::env::temp_dir().join(format!("transcript-{}.jsonl", uuid::Uuid::new_v4()));
+    let mut state = Arc::into_inner(stub_state("classify.json", 0)).unwrap();
+    state.transcript = Some(Arc::new(
+        transcript::Transcript::open(&path, 10, None).await.unwrap(),
+    ));
+    let state = Arc::new(state);
+    let (status, body) = post(Arc::clone(&state), "/api/ai/classify", classify_body()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["summary"], "Crash when loading a page with WebGL");
+
+    state.transcript.as_ref().unwrap().flush().await;
+    let lines = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let record: serde_json::Value = serde_json::from_str(lines.trim()).unwrap();
+    assert_eq!(record["endpoint"], "/api/ai/classify");
+    assert_eq!(record["provider"], "claude");
+    assert_eq!(record["bugId"], 1);
+    assert_eq!(record["status"], 200);
+    assert_eq!(record["prompt"], "Classify t...[truncated 7 bytes]");
+    assert!(record["result"].as_str().unwrap().contains("...[truncated "));
+}
+
+#[tokio::test]
+async fn provenance_is_opt_in() {
+    let (_, body) = post(
+        stub_state("classify.json", 0),
+        "/api/ai/classify",
+        classify_body(),
+    )
+    .await;
+    assert!(body.get("servedVia").is_none());
+
+    let (status, body) = post(
+        stub_state("classify.json", 0),
+        "/api/ai/classify?provenance=true",
+        classify_body(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["servedVia"], "cli");
+    assert_eq!(body["usedProvider"], "claude");
+}
+
+#[tokio::test]
+async fn prompt_is_included_in_errors_on_request() {
+    let mut request = classify_body();
+    request["prompt"] = json!("Classify this bug, key sk-ant-abcdefghijklmnop");
+
+    let (status, body) = post(
+        stub_state("no-structured-output.json", 0),
+        "/api/ai/classify",
+        request.clone(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(!body["details"].as_str().unwrap_or("").contains("Prompt sent"));
+
+    let (status, body) = post(
+        stub_state("no-structured-output.json", 0),
+        "/api/ai/classify?includePromptOnError=true",
+        request,
+    )
+    .await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    let details = body["details"].as_str().unwrap();
+    assert!(details.contains("Prompt sent:\nClassify this bug, key sk-ant-[REDACTED]"));
+    assert!(!details.contains("abcdefghijklmnop"));
+
+    // Errors before anything was sent are left alone
+    let (status, body) = post(
+        stub_state("classify.json", 0),
+        "/api/ai/classify?includePromptOnError=true",
+        json!({ "provider": "gemini", "bug": { "id": 1 }, "prompt": "x", "schema": "{}" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(body["error"], "GEMINI_API_KEY not configured");
+    assert_eq!(body["details"], "Available providers: claude");
+}
+
+#[tokio::test]
+async fn missing_key_suggests_configured_providers() {
+    let request = json!({ "provider": "gemini", "bug": { "id": 1 }, "prompt": "x", "schema": "{}" });
+    let mut state = Arc::into_inner(stub_state("classify.json", 0)).unwrap();
+    state.openai_api_key = Some("sk-test".to_string());
+    let (_, body) = post(Arc::new(state), "/api/ai/classify", request.clone()).await;
+    assert_eq!(body["error"], "GEMINI_API_KEY not configured");
+    assert_eq!(body["details"], "Available providers: claude, openai");
+
+    // Nothing else to switch to
+    let mut state = Arc::into_inner(stub_state("classify.json", 0)).unwrap();
+    state.claude_mode = "api".to_string();
+    let (_, body) = post(Arc::new(state), "/api/ai/classify", request).await;
+    assert_eq!(body["error"], "GEMINI_API_KEY not configured");
+    assert!(body["details"].is_null());
+}
+
+#[tokio::test]
+async fn echo_bug_is_opt_in() {
+    let classify = |echo: Option<serde_json::Value>| async move {
+        let mut request = classify_body();
+        if let Some(echo) = echo {
+            request["echoBug"] = echo;
+        }
+        post(stub_state("classify.json", 0), "/api/ai/classify", request).await
+    };
+
+    let (_, body) = classify(None).await;
+    assert!(body.get("bug").is_none());
+    assert!(body.get("bugId").is_none());
+
+    let (status, body) = classify(Some(json!(true))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["bug"], classify_body()["bug"]);
+    assert_eq!(body["usedProvider"], "claude");
+
+    let (_, body) = classify(Some(json!("id"))).await;
+    assert_eq!(body["bugId"], 1);
+    assert!(body.get("bug").is_none());
+}
+
+#[tokio::test]
+async fn echo_bug_leaves_body_limit_errors_to_the_extractor() {
+    let oversized = |echo: bool| {
+        let mut body = classify_body();
+        body["bug"]["description"] = json!("a".repeat(128 * 1024));
+        if echo {
+            body["echoBug"] = json!(true);
+        }
+        let request = Request::post("/api/ai/classify")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        build_router(stub_state("classify.json", 0), "/nonexistent").oneshot(request)
+    };
+    let with_echo = oversized(true).await.unwrap();
+    let without = oversized(false).await.unwrap();
+    assert_eq!(with_echo.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(without.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(
+        axum::body::to_bytes(with_echo.into_body(), usize::MAX).await.unwrap(),
+        axum::body::to_bytes(without.into_body(), usize::MAX).await.unwrap()
+    );
+}
+
+#[tokio::test]
+async fn body_rewrites_follow_max_body_bytes() {
+    let mut state = Arc::into_inner(stub_state("classify.json", 0)).unwrap();
+    state.max_body_bytes = 4 * 1024 * 1024;
+    let state = Arc::new(state);
+    // Over axum's 2 MiB default, under MAX_BODY_BYTES
+    let mut body = classify_body();
+    body["bug"]["description"] = json!("a".repeat(3 * 1024 * 1024));
+    let request = Request::post("/api/ai/classify")
+        .header("content-type", "application/json")
+        .header("x-provider", "claude")
+        .header("x-model", "stub-sonnet")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let response = build_router(state, "/nonexistent").oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn concurrency_limit_rejects_but_spares_probes() {
+    let mut state = Arc::into_inner(stub_state("classify.json", 0)).unwrap();
+    // No permits at all: every limited request is over the limit
+    state.request_limit = Some(Arc::new(tokio::sync::Semaphore::new(0)));
+    let state = Arc::new(state);
+
+    let (status, body) = post(Arc::clone(&state), "/api/ai/classify", classify_body()).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["code"], "OVERLOADED");
+
+    let response = build_router(state, "/nonexistent")
+        .oneshot(Request::get("/healthz").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn base_path_prefixes_every_route() {
+    let mut state = Arc::into_inner(stub_state("classify.json", 0)).unwrap();
+    state.base_path = "/triage".to_string();
+    let app = build_router(Arc::new(state), "/nonexistent");
+    let status = |path: &'static str| {
+        let app = app.clone();
+        async move {
+            app.oneshot(Request::get(path).body(Body::empty()).unwrap())
+                .await
+                .unwrap()
+                .status()
+        }
+    };
+
+    assert_eq!(status("/triage/health").await, StatusCode::OK);
+    assert_eq!(status("/health").await, StatusCode::NOT_FOUND);
+
+    let (status, body) = post(
+        Arc::new({
+            let mut state = Arc::into_inner(stub_state("classify.json", 0)).unwrap();
+            state.base_path = "/triage".to_string();
+            state
+        }),
+        "/triage/api/ai/classify",
+        classify_body(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["usedProvider"], "claude");
+}
+
+#[tokio::test]
+async fn classify_stream_sends_fields_then_result() {
+    let events = post_events(
+        stub_state("classify-stream.jsonl", 0),
+        "/api/ai/classify-stream",
+        classify_body(),
+    )
+    .await;
+
+    let field = |name: &str, value: &str| {
+        ("field".to_string(), json!({ "name": name, "value": value }))
+    };
+    assert_eq!(
+        events[..3],
+        [
+            field("summary", "Crash when loading a page"),
+            field("suggested_severity", "sev2"),
+            field("suggested_priority", "P2"),
+        ]
+    );
+    let (name, result) = &events[3];
+    assert_eq!(name, "result");
+    assert_eq!(result["suggested_severity"], "S2");
+    assert_eq!(result["usedProvider"], "claude");
+    assert_eq!(result["meta"], json!({ "costUsd": 0.004, "durationMs": 1200, "numTurns": 1 }));
+    assert_eq!(events.len(), 4);
+}
+
+#[tokio::test]
+async fn classify_stream_falls_back_to_the_whole_result() {
+    let events = post_events(
+        stub_state("classify.json", 0),
+        "/api/ai/classify-stream",
+        classify_body(),
+    )
+    .await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].0, "result");
+    assert_eq!(events[0].1["summary"], "Crash when loading a page with WebGL");
+
+    let events = post_events(
+        stub_state("classify.json", 1),
+        "/api/ai/classify-stream",
+        classify_body(),
+    )
+    .await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].0, "error");
+    assert_eq!(events[0].1["error"], "Claude CLI execution failed");
+}
+
+#[tokio::test]
+async fn bugzilla_error_bodies_are_bounded() {
+    // Every request for bug 1 fails with a 10 KB body, for bug 2 with 1 MiB
+    let bugzilla = axum::Router::new().fallback(|uri: axum::http::Uri| async move {
+        let size = if uri.path().starts_with("/rest/bug/1") { 10_000 } else { 1024 * 1024 };
+        (StatusCode::SERVICE_UNAVAILABLE, "e".repeat(size))
+    });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, bugzilla).await.unwrap() });
+    let mut state = Arc::into_inner(stub_state("classify.json", 0)).unwrap();
+    state.bugzilla.base_url = format!("http://{}", addr);
+
+    let e = bugzilla::fetch_bug(&state, 1, None).await.unwrap_err();
+    assert_eq!(e.status, StatusCode::SERVICE_UNAVAILABLE);
+    let details = e.details.unwrap();
+    assert!(details.starts_with("eeee"), "{}", details);
+    assert!(details.ends_with("...[truncated 7952 bytes]"), "{}", details);
+
+    // Bodies beyond the read limit aren't read at all
+    let e = bugzilla::fetch_bug(&state, 2, None).await.unwrap_err();
+    assert_eq!(e.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(e.details.unwrap(), "More than 65536 bytes after decompression");
+}
+
+#[tokio::test]
+async fn stream_classify_by_id_sends_the_fetched_bug_first() {
+    use axum::routing::get;
+    let bugzilla = axum::Router::new()
+        .route(
+            "/rest/bug/1",
+            get(|| async { axum::Json(json!({ "bugs": [{ "id": 1, "summary": "Crash" }] })) }),
+        )
+        .route(
+            "/rest/bug/1/attachment",
+            get(|| async { axum::Json(json!({ "bugs": { "1": [] } })) }),
+        )
+        .route(
+            "/rest/bug/1/comment",
+            get(|| async { axum::Json(json!({ "bugs": { "1": { "comments": [] } } })) }),
+        );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, bugzilla).await.unwrap() });
+
+    let mut state = Arc::into_inner(stub_state("classify-stream.jsonl", 0)).unwrap();
+    state.bugzilla.base_url = format!("http://{}", addr);
+    let state = Arc::new(state);
+    let body = json!({ "id": 1, "provider": "claude", "prompt": "Classify", "schema": "{}" });
+    let events = post_events(Arc::clone(&state), "/api/ai/stream-classify-by-id", body).await;
+
+    let names: Vec<&str> = events.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, ["fetched", "field", "field", "field", "result"]);
+    assert_eq!(events[0].1["summary"], "Crash");
+    assert_eq!(events[0].1["comments"], json!([]));
+    let result = &events[4].1;
+    assert_eq!(result["suggested_severity"], "S2");
+    assert_eq!(result["bug"]["id"], 1);
+
+    // A bug that can't be fetched ends the stream with an error
+    let body = json!({ "id": 2, "provider": "claude", "prompt": "Classify", "schema": "{}" });
+    let events = post_events(state, "/api/ai/stream-classify-by-id", body).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].0, "error");
+}
+
+#[tokio::test]
+async fn capabilities_match_the_handlers() {
+    for mode in ["cli", "api"] {
+        check_capabilities(mode).await;
+    }
+}
+
+/// Check the capability matrix against the handlers with Claude in `mode` and every
+/// provider configured, so the stubs are reached rather than a missing key
+async fn check_capabilities(mode: &str) {
+    let mut state = Arc::into_inner(stub_state("classify.json", 0)).unwrap();
+    state.claude_mode = mode.to_string();
+    state.anthropic_api_key = Some("sk-ant-test".to_string());
+    // Nothing listens here: API calls fail, but aren't rejected
+    state.anthropic.base_url = "http://127.0.0.1:9".to_string();
+    state.gemini_api_key = Some("gemini-test".to_string());
+    state.openai_api_key = Some("sk-test".to_string());
+    let state = Arc::new(state);
+    let response = build_router(Arc::clone(&state), "/nonexistent")
+        .oneshot(Request::get("/api/ai/capabilities").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let matrix: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    let claude = &matrix["providers"]["claude"];
+    assert_eq!((&claude["configured"], &claude["servedVia"]), (&json!(true), &json!(mode)));
+    let streaming = if mode == "cli" { "native" } else { "proxied" };
+    assert_eq!(claude["endpoints"]["classify-stream"], streaming);
+    assert_eq!(matrix["providers"]["gemini"]["configured"], true);
+    assert_eq!(matrix["providers"]["gemini"]["endpoints"], json!({}));
+
+    // Every combination the handlers can reach with a request body alone (the by-id
+    // endpoints need Bugzilla) is rejected exactly when the matrix leaves it out. A
+    // stub that isn't implemented yet counts as a rejection.
+    let classification = json!({
+        "ai_detected_str": false,
+        "ai_detected_test_attached": false,
+        "crashstack_present": false,
+        "fuzzing_testcase": false,
+        "summary": "",
+    });
+    for endpoint in capabilities::ENDPOINTS {
+        if endpoint.ends_with("by-id") {
+            continue;
+        }
+        for provider in capabilities::PROVIDERS {
+            let body = json!({
+                "provider": provider,
+                "candidates": [{ "provider": provider }],
+                "bug": { "id": 1 },
+                "cannedResponses": [],
+                "currentResponse": "",
+                "userInstruction": "",
+                "classification": classification,
+                "prompt": "Prompt",
+                "schema": "{}",
+            });
+            let path = format!("/api/ai/{}", endpoint);
+            let (status, error) = if endpoint == "classify-stream" {
+                let events = post_events(Arc::clone(&state), &path, body).await;
+                (None, events.last().unwrap().1["error"].clone())
+            } else {
+                let (status, response) = post(Arc::clone(&state), &path, body).await;
+                match endpoint {
+                    "classify-compare" => {
+                        (None, response["results"][0]["error"]["error"].clone())
+                    }
+                    _ => (Some(status), response["error"].clone()),
+                }
+            };
+            let error = error.as_str().unwrap_or_default();
+            let unimplemented = error.contains("not yet implemented");
+            let rejected = unimplemented
+                || error.starts_with("Only Claude provider supported")
+                || error.starts_with("Unknown provider");
+            let listed = matrix["providers"][provider]["endpoints"].get(endpoint).is_some();
+            assert_eq!(
+                rejected, !listed,
+                "{} with {} in {} mode: {:?}",
+                endpoint, provider, mode, error
+            );
+            if rejected && !unimplemented && status.is_some() {
+                assert_eq!(status, Some(StatusCode::BAD_REQUEST), "{} with {}", endpoint, provider);
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn apply_actions_rejects_unknown_actions() {
+    let (status, body) = post(
+        stub_state("classify.json", 0),
+        "/api/bugzilla/bug/1/apply-actions",
+        json!({ "actions": ["set-severity-S2", "close-as-wontfix"], "apiKey": "user-key" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "UNKNOWN_ACTION");
+    assert_eq!(body["error"], "Unknown action: close-as-wontfix");
+}
+
+#[tokio::test]
+async fn apply_actions_needs_the_callers_api_key() {
+    let mut state = Arc::into_inner(stub_state("classify.json", 0)).unwrap();
+    state.bugzilla.api_key = Some("server-key".to_string());
+    let state = Arc::new(state);
+    for body in [
+        json!({ "actions": ["set-severity-S2"] }),
+        json!({ "actions": ["set-severity-S2"], "apiKey": " " }),
+    ] {
+        let (status, body) =
+            post(Arc::clone(&state), "/api/bugzilla/bug/1/apply-actions", body).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["code"], "API_KEY_REQUIRED");
+    }
+}
+
+#[tokio::test]
+async fn api_index_lists_mounted_routes() {
+    let app = build_router(stub_state("classify.json", 0), "/nonexistent");
+    let response = app
+        .clone()
+        .oneshot(Request::get("/api").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let index: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    let endpoints = index["endpoints"].as_array().unwrap();
+    assert!(endpoints.contains(&json!({
+        "method": "POST",
+        "path": "/api/ai/classify",
+        "description": "Classify a bug"
+    })));
+
+    // Every listed route is mounted with its method (no 404 or 405)
+    for endpoint in endpoints {
+        let path = endpoint["path"].as_str().unwrap().replace("{id}", "1");
+        let request = Request::builder()
+            .method(endpoint["method"].as_str().unwrap())
+            .uri(&path)
+            .body(Body::empty())
+            .unwrap();
+        let status = app.clone().oneshot(request).await.unwrap().status();
+        assert_ne!(status, StatusCode::NOT_FOUND, "{}", path);
+        assert_ne!(status, StatusCode::METHOD_NOT_ALLOWED, "{}", path);
+    }
+}
+
+#[tokio::test]
+async fn last_cli_result_wins() {
+    let (status, body) = post(
+        stub_state("two-results.jsonl", 0),
+        "/api/ai/classify",
+        classify_body(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["summary"], "Final summary");
+    assert_eq!(body["suggested_severity"], "S2");
+}
+
+#[tokio::test]
+async fn denied_actions_are_stripped() {
+    let mut state = Arc::into_inner(stub_state("classify.json", 0)).unwrap();
+    state.action_denylist = vec!["needinfo".to_string()];
+    let (status, body) = post(Arc::new(state), "/api/ai/classify", classify_body()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.get("suggested_actions").is_none());
+    assert_eq!(
+        body["parseWarnings"],
+        json!(["1 suggested action(s) removed by ACTION_DENYLIST"])
+    );
+}
+
+#[tokio::test]
+async fn denied_actions_are_stripped_from_streamed_fields() {
+    let mut state = Arc::into_inner(stub_state("classify-stream-actions.jsonl", 0)).unwrap();
+    state.action_denylist = vec!["needinfo".to_string()];
+    let events = post_events(Arc::new(state), "/api/ai/classify-stream", classify_body()).await;
+
+    let (_, field) = events
+        .iter()
+        .find(|(name, data)| name == "field" && data["name"] == "suggested_actions")
+        .unwrap();
+    assert_eq!(field["value"], json!([{ "action": "set-severity-S2", "reason": "Crash" }]));
+    let (name, result) = events.last().unwrap();
+    assert_eq!(name, "result");
+    assert_eq!(result["suggested_actions"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn health_waits_for_readiness() {
+    let mut state = Arc::into_inner(stub_state("classify.json", 0)).unwrap();
+    state.ready = Arc::new(false.into());
+    let state = Arc::new(state);
+    let status = |path: &'static str| {
+        let app = build_router(Arc::clone(&state), "/nonexistent");
+        async move {
+            app.oneshot(Request::get(path).body(Body::empty()).unwrap())
+                .await
+                .unwrap()
+                .status()
+        }
+    };
+    assert_eq!(status("/health").await, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(status("/healthz").await, StatusCode::OK);
+
+    state.ready.store(true, std::sync::atomic::Ordering::Release);
+    assert_eq!(status("/health").await, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn client_disconnect_kills_the_cli() {
+    use tokio::io::AsyncWriteExt;
+
+    let dir = env!("CARGO_MANIFEST_DIR");
+    let pid_file = std::env::temp_dir().join(format!("stub-pid-{}", uuid::Uuid::new_v4()));
+    let mut state = Arc::into_inner(stub_state("classify.json", 0)).unwrap();
+    state.cli.program = format!("{}/tests/stub-claude-hang", dir);
+    state.cli.wrapper.push(format!("STUB_CLAUDE_PID={}", pid_file.display()));
+    let state = Arc::new(state);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(crate::serve(
+        listener,
+        build_router(Arc::clone(&state), "/nonexistent"),
+        None,
+    ));
+
+    let body = classify_body().to_string();
+    let mut client = tokio::net::TcpStream::connect(addr).await.unwrap();
+    client
+        .write_all(
+            format!(
+                "POST /api/ai/classify HTTP/1.1\r\nHost: test\r\n\
+                 Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .as_bytes(),
+        )
+        .await
+        .unwrap();
+
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    while state.cli.children.len() == 0 || !pid_file.exists() {
+        assert!(std::time::Instant::now() < deadline, "the CLI never started");
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    let pid: i32 = std::fs::read_to_string(&pid_file).unwrap().trim().parse().unwrap();
+
+    drop(client);
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    // The process is gone once it has been killed and reaped
+    while state.cli.children.len() > 0 || unsafe { libc::kill(pid, 0) } == 0 {
+        assert!(std::time::Instant::now() < deadline, "the CLI outlived its client");
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    std::fs::remove_file(&pid_file).unwrap();
+}
+
+#[tokio::test]
+async fn client_disconnect_stops_stream_classify_by_id() {
+    use tokio::io::AsyncWriteExt;
+
+    /// Set when the request it is part of is dropped
+    struct DropFlag(Arc<std::sync::atomic::AtomicBool>);
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            self.0.store(true, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+
+    // Bug 1 is fetched at once; fetching bug 2 never finishes
+    let fetch_started = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let fetch_dropped = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let flags = (Arc::clone(&fetch_started), Arc::clone(&fetch_dropped));
+    let bugzilla = axum::Router::new().fallback(move |uri: axum::http::Uri| {
+        let (started, dropped) = (Arc::clone(&flags.0), Arc::clone(&flags.1));
+        async move {
+            if uri.path().starts_with("/rest/bug/2") {
+                let _dropped = DropFlag(dropped);
+                started.store(true, std::sync::atomic::Ordering::SeqCst);
+                std::future::pending::<()>().await;
+            }
+            let body = match uri.path() {
+                "/rest/bug/1" => json!({ "bugs": [{ "id": 1, "summary": "Crash" }] }),
+                "/rest/bug/1/attachment" => json!({ "bugs": { "1": [] } }),
+                _ => json!({ "bugs": { "1": { "comments": [] } } }),
+            };
+            axum::Json(body)
+        }
+    });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let bugzilla_addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, bugzilla).await.unwrap() });
+
+    let pid_file = std::env::temp_dir().join(format!("stub-pid-{}", uuid::Uuid::new_v4()));
+    let mut state = Arc::into_inner(stub_state("classify.json", 0)).unwrap();
+    state.cli.program = format!("{}/tests/stub-claude-hang", env!("CARGO_MANIFEST_DIR"));
+    state.cli.wrapper.push(format!("STUB_CLAUDE_PID={}", pid_file.display()));
+    state.bugzilla.base_url = format!("http://{}", bugzilla_addr);
+    let state = Arc::new(state);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(crate::serve(
+        listener,
+        build_router(Arc::clone(&state), "/nonexistent"),
+        None,
+    ));
+    let connect = |id: u64| async move {
+        let body =
+            json!({ "id": id, "provider": "claude", "prompt": "Classify", "schema": "{}" })
+                .to_string();
+        let mut client = tokio::net::TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(
+                format!(
+                    "POST /api/ai/stream-classify-by-id HTTP/1.1\r\nHost: test\r\n\
+                     Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+        client
+    };
+    async fn wait_for(what: &str, done: impl Fn() -> bool) {
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while !done() {
+            assert!(std::time::Instant::now() < deadline, "{}", what);
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+    }
+
+    // Disconnecting while the CLI runs kills it
+    let client = connect(1).await;
+    wait_for("the CLI never started", || {
+        state.cli.children.len() > 0 && pid_file.exists()
+    })
+    .await;
+    let pid: i32 = std::fs::read_to_string(&pid_file).unwrap().trim().parse().unwrap();
+    drop(client);
+    // The process is gone once it has been killed and reaped
+    wait_for("the CLI outlived its client", || {
+        state.cli.children.len() == 0 && unsafe { libc::kill(pid, 0) } != 0
+    })
+    .await;
+    std::fs::remove_file(&pid_file).unwrap();
+
+    // Disconnecting while the bug is fetched abandons the fetch
+    let client = connect(2).await;
+    wait_for("the fetch never started", || {
+        fetch_started.load(std::sync::atomic::Ordering::SeqCst)
+    })
+    .await;
+    drop(client);
+    wait_for("the fetch outlived its client", || {
+        fetch_dropped.load(std::sync::atomic::Ordering::SeqCst)
+    })
+    .await;
+    assert!(state.active_requests.snapshot().is_empty());
+}
+
+#[tokio::test]
+async fn a_running_stream_holds_its_permit_and_entry() {
+    let mut state = Arc::into_inner(stub_state("classify.json", 0)).unwrap();
+    state.cli.program = format!("{}/tests/stub-claude-hang", env!("CARGO_MANIFEST_DIR"));
+    let limit = Arc::new(tokio::sync::Semaphore::new(1));
+    state.request_limit = Some(Arc::clone(&limit));
+    let state = Arc::new(state);
+    let response = build_router(Arc::clone(&state), "/nonexistent")
+        .oneshot(
+            Request::post("/api/ai/classify-stream")
+                .header("Content-Type", "application/json")
+                .body(Body::from(classify_body().to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    while state.cli.children.len() == 0 {
+        assert!(std::time::Instant::now() < deadline, "the CLI never started");
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    // The handler has returned, but the stream is still the request
+    assert_eq!(limit.available_permits(), 0);
+    let active = state.active_requests.snapshot();
+    assert_eq!(active.len(), 1);
+    let (status, _) = post(Arc::clone(&state), "/api/ai/classify", classify_body()).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+
+    assert!(state.active_requests.cancel(&active[0].request_id));
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    assert!(body.contains("event: error"), "{}", body);
+    assert!(body.contains("CANCELLED"), "{}", body);
+    assert_eq!(limit.available_permits(), 1);
+    assert!(state.active_requests.snapshot().is_empty());
+}
+
+#[tokio::test]
+async fn classifications_are_kept_in_the_history() {
+    let get_history = |state: Arc<AppState>| async move {
+        let response = build_router(state, "/nonexistent")
+            .oneshot(Request::get("/api/history/1").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice::<serde_json::Value>(&bytes).unwrap())
+    };
+    let (status, _) = get_history(stub_state("classify.json", 0)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let path = std::env::temp_dir().join(format!("history-{}.db", uuid::Uuid::new_v4()));
+    let mut state = Arc::into_inner(stub_state("classify.json", 0)).unwrap();
+    state.history = Some(Arc::new(history::History::open(&path).unwrap()));
+    let state = Arc::new(state);
+    let (status, _) = post(Arc::clone(&state), "/api/ai/classify", classify_body()).await;
+    assert_eq!(status, StatusCode::OK);
+    state.history.as_ref().unwrap().flush().await;
+
+    let (status, body) = get_history(Arc::clone(&state)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["bugId"], 1);
+    let classifications = body["classifications"].as_array().unwrap();
+    assert_eq!(classifications.len(), 1);
+    assert_eq!(classifications[0]["provider"], "claude");
+    assert_eq!(classifications[0]["model"], "stub-model");
+    assert_eq!(classifications[0]["result"]["usedProvider"], "claude");
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+    }
+}
+
+#[tokio::test]
+async fn status_json_reports_what_the_page_shows() {
+    let get = |path: &'static str| async move {
+        let response = build_router(stub_state("classify.json", 0), "/nonexistent")
+            .oneshot(Request::get(path).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    };
+
+    let status: serde_json::Value = serde_json::from_str(&get("/status.json").await).unwrap();
+    assert_eq!(status["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(status["ready"], true);
+    assert_eq!(status["mode"], "cli");
+    assert_eq!(status["claudeCli"]["available"], true);
+    assert_eq!(status["claudeCli"]["status"], "ok");
+    assert_eq!(status["claudeCli"]["version"], "0.0.0 (stub)");
+    assert!(status["endpoints"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|route| route["path"] == "/status.json" && route["method"] == "GET"));
+    assert_eq!(status["recentErrors"], json!([]));
+
+    let page = get("/status").await;
+    assert!(page.contains("0.0.0 (stub)"));
+    assert!(page.contains(status["modeDescription"].as_str().unwrap()));
+    // Relative, so they still work under BASE_PATH
+    assert!(page.contains(r#"href="status.json""#));
+    assert!(page.contains(r#"href="status/errors.json""#));
+    assert!(!page.contains(r#"href="/"#));
+}
+
+#[tokio::test]
+async fn health_reports_cli_version_probe() {
+    let health = |program: String| async move {
+        let mut state = Arc::into_inner(stub_state("classify.json", 0)).unwrap();
+        state.cli.program = program;
+        let response = build_router(Arc::new(state), "/nonexistent")
+            .oneshot(Request::get("/health").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+    };
+    let dir = env!("CARGO_MANIFEST_DIR");
+
+    let body = health(format!("{}/tests/stub-claude", dir)).await;
+    assert_eq!(body["claudeCli"], "ok");
+    assert_eq!(body["availableProviders"], json!(["claude"]));
+
+    // A wrapper that hangs on --version still counts as available
+    let started = std::time::Instant::now();
+    let body = health(format!("{}/tests/stub-claude-hang", dir)).await;
+    assert!(started.elapsed() < std::time::Duration::from_secs(10));
+    assert_eq!(body["claudeCli"], "version-unknown");
+    assert_eq!(body["availableProviders"], json!(["claude"]));
+
+    let body = health(format!("{}/tests/no-such-claude", dir)).await;
+    assert_eq!(body["claudeCli"], "not-found");
+    assert_eq!(body["availableProviders"], json!([]));
+}
+
+#[tokio::test]
+async fn anthropic_key_makes_claude_available_only_outside_cli_mode() {
+    let providers = |mode: &str| {
+        let mut state = Arc::into_inner(stub_state("classify.json", 0)).unwrap();
+        state.cli.program = format!("{}/tests/no-such-claude", env!("CARGO_MANIFEST_DIR"));
+        state.claude_mode = mode.to_string();
+        state.anthropic_api_key = Some("sk-ant-test".to_string());
+        async move {
+            let response = build_router(Arc::new(state), "/nonexistent")
+                .oneshot(Request::get("/health").body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()["availableProviders"]
+                .clone()
+        }
+    };
+
+    assert_eq!(providers("cli").await, json!([]));
+    assert_eq!(providers("api").await, json!(["claude"]));
+}
+
+#[tokio::test]
+async fn stub_providers_are_never_recommended() {
+    let mut state = Arc::into_inner(stub_state("classify.json", 0)).unwrap();
+    state.cli.program = format!("{}/tests/no-such-claude", env!("CARGO_MANIFEST_DIR"));
+    state.claude_mode = "api".to_string();
+    state.gemini_api_key = Some("gemini-test".to_string());
+    state.openai_api_key = Some("sk-test".to_string());
+    let response = build_router(Arc::new(state), "/nonexistent")
+        .oneshot(Request::get("/health").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(body["availableProviders"], json!([]));
+    assert_eq!(body["recommendedProvider"], json!(null));
+}
+
+#[tokio::test]
+async fn health_reports_http_call_utilization() {
+    let mut state = Arc::into_inner(stub_state("classify.json", 0)).unwrap();
+    state.http_call_limit = Some(Arc::new(tokio::sync::Semaphore::new(2)));
+    state.max_concurrent_http_calls = 2;
+    let state = Arc::new(state);
+    let _permit = state.http_call_permit().await;
+
+    let response = build_router(Arc::clone(&state), "/nonexistent")
+        .oneshot(Request::get("/health").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(body["httpCalls"], json!({ "inFlight": 1, "limit": 2 }));
+}
+
+#[tokio::test]
+async fn health_caches_provider_availability() {
+    let mut state = Arc::into_inner(stub_state("classify.json", 0)).unwrap();
+    state.provider_availability = Arc::new(health::AvailabilityCache::new(
+        std::time::Duration::from_secs(60),
+    ));
+    state.openai_api_key = Some("sk-test".to_string());
+    state.started_at = std::time::SystemTime::now() - std::time::Duration::from_secs(90);
+    let state = Arc::new(state);
+    let health = |uri: &'static str| {
+        let router = build_router(Arc::clone(&state), "/nonexistent");
+        async move {
+            let response = router
+                .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+        }
+    };
+
+    // The OpenAI proxy is a stub, so its key doesn't make it available
+    let body = health("/health").await;
+    assert_eq!(body["availableProviders"], json!(["claude"]));
+    assert_eq!(body["providersCheckedSecsAgo"], 0);
+    assert!(body["uptimeSecs"].as_u64().unwrap() >= 90);
+    assert!(body["startedAt"].as_str().unwrap().ends_with(" GMT"));
+    assert_eq!(body["frontendServable"], false);
+    assert_eq!(body["frontendIndex"], "/nonexistent/index.html");
+
+    // The second call is answered from the cache; refresh=true recomputes regardless
+    let checked = state.provider_availability.get(false, || async { unreachable!() }).await;
+    assert_eq!(checked.0.providers, vec!["claude"]);
+    let body = health("/health?refresh=true").await;
+    assert_eq!(body["availableProviders"], json!(["claude"]));
+}
+
+#[tokio::test]
+async fn cli_self_test_needs_structured_output() {
+    let state = stub_state("classify.json", 0);
+    assert!(claude_cli::self_test(&state.cli, "stub-model").await.is_ok());
+    let state = stub_state("no-structured-output.json", 0);
+    assert!(claude_cli::self_test(&state.cli, "stub-model").await.is_err());
+    let state = stub_state("classify.json", 1);
+    assert!(claude_cli::self_test(&state.cli, "stub-model").await.is_err());
+}
+
+#[tokio::test]
+async fn configured_headers_are_added_to_responses() {
+    let mut state = Arc::into_inner(stub_state("classify.json", 0)).unwrap();
+    state.response_headers =
+        parse_response_headers(Some(r#"{ "X-Instance-Id": "file" }"#), "x-instance-id: web-1")
+            .unwrap();
+    state.content_security_policy = Some("default-src 'self'".parse().unwrap());
+    let frontend = concat!(env!("CARGO_MANIFEST_DIR"), "/../frontend");
+    let app = build_router(Arc::new(state), frontend);
+
+    let api = app
+        .clone()
+        .oneshot(Request::get("/api").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(api.headers()["x-content-type-options"], "nosniff");
+    assert_eq!(api.headers()["x-instance-id"], "web-1");
+    assert!(!api.headers().contains_key("content-security-policy"));
+
+    let page = app
+        .oneshot(Request::get("/").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(page.status(), StatusCode::OK);
+    assert_eq!(page.headers()["x-content-type-options"], "nosniff");
+    assert_eq!(page.headers()["content-security-policy"], "default-src 'self'");
+}
+
+#[test]
+fn response_headers_are_validated() {
+    assert!(parse_response_headers(None, "").unwrap().is_empty());
+    assert!(parse_response_headers(None, "X-Instance-Id").is_err());
+    assert!(parse_response_headers(None, "Bad Name: x").is_err());
+    assert!(parse_response_headers(Some(r#"{ "X-Count": 1 }"#), "").is_err());
+}
+
+#[test]
+fn generate_options_may_be_a_json_string() {
+    let options = |options: serde_json::Value| {
+        let request = json!({ "provider": "claude", "bug": {}, "options": options });
+        serde_json::from_value::<crate::GenerateRequest>(request).unwrap().options
+    };
+    let object = json!({ "mode": "needinfo", "cannedResponses": [] });
+    assert_eq!(options(object.clone()), object);
+    assert_eq!(options(json!(object.to_string())), object);
+    // Strings that aren't an encoded object are kept
+    assert_eq!(options(json!("needinfo")), json!("needinfo"));
+    assert_eq!(options(json!("[1, 2]")), json!("[1, 2]"));
+
+    let request = json!({ "provider": "claude", "bug": {} });
+    let request: crate::GenerateRequest = serde_json::from_value(request).unwrap();
+    assert!(request.options.is_null());
+}
+
+#[test]
+fn headless_machines_are_detected() {
+    let env = |vars: &'static [(&'static str, &'static str)]| {
+        move |name: &str| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| value.to_string())
+        }
+    };
+    assert_eq!(headless_reason(env(&[("DISPLAY", ":0")])), None);
+    assert_eq!(headless_reason(env(&[("WAYLAND_DISPLAY", "wayland-0")])), None);
+    assert_eq!(
+        headless_reason(env(&[("DISPLAY", ":0"), ("SSH_CONNECTION", "10.0.0.1 22 10.0.0.2 22")])),
+        Some("SSH session")
+    );
+    assert_eq!(headless_reason(env(&[("DISPLAY", ":0"), ("CI", "true")])), Some("CI environment"));
+    assert_eq!(
+        headless_reason(env(&[("DISPLAY", ":0"), ("BROWSER", "none")])),
+        Some("BROWSER=none")
+    );
+    assert_eq!(headless_reason(env(&[("DISPLAY", ":0"), ("BROWSER", "firefox")])), None);
+    let no_display = cfg!(target_os = "linux").then_some("no display");
+    assert_eq!(headless_reason(env(&[])), no_display);
+}
+
+#[test]
+fn numeric_env_vars_are_validated() {
+    assert_eq!(parse_env_number::<usize>("MAX_PROMPT_BYTES", None), Ok(None));
+    assert_eq!(parse_env_number::<usize>("MAX_PROMPT_BYTES", Some(" ")), Ok(None));
+    assert_eq!(parse_env_number::<usize>("MAX_PROMPT_BYTES", Some(" 65536 ")), Ok(Some(65536)));
+    assert_eq!(parse_env_number::<f64>("LOG_SAMPLE_RATE", Some("0.25")), Ok(Some(0.25)));
+    assert_eq!(
+        parse_env_number::<usize>("MAX_PROMPT_BYTES", Some("64k")),
+        Err("MAX_PROMPT_BYTES must be a number, got '64k': invalid digit found in string"
+            .to_string())
+    );
+    assert!(parse_env_number::<usize>("TESTPAGE_MAX_ATTACHMENTS", Some("-1")).is_err());
+    assert!(parse_env_number::<u32>("CLI_MAX_RETRIES", Some("4294967296")).is_err());
+}
+
+#[test]
+fn provider_timeouts_are_parsed() {
+    use std::time::Duration;
+
+    let timeouts = parse_provider_timeouts(" claude:120, Anthropic:90 ,gemini:0,,").unwrap();
+    assert_eq!(timeouts.len(), 2);
+    // Names resolve like request providers; a later entry wins
+    assert_eq!(timeouts["claude"], Some(Duration::from_secs(90)));
+    assert_eq!(timeouts["gemini"], None);
+    assert!(parse_provider_timeouts("").unwrap().is_empty());
+
+    let error = |input: &str| parse_provider_timeouts(input).unwrap_err();
+    assert_eq!(error("claude:120,ollama:300"), "unknown provider in 'ollama:300'");
+    assert_eq!(error("claude"), "expected provider:seconds, got 'claude'");
+    assert_eq!(error("openai:1m"), "invalid seconds in 'openai:1m'");
+}
+
+#[tokio::test]
+async fn timeout_ms_bounds_http_provider_calls() {
+    let anthropic = axum::Router::new().route(
+        "/v1/messages",
+        axum::routing::post(|| async {
+            tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+            StatusCode::OK
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, anthropic).await.unwrap() });
+
+    let mut state = Arc::into_inner(stub_state("classify.json", 0)).unwrap();
+    state.claude_mode = "api".to_string();
+    state.anthropic_api_key = Some("sk-ant-test".to_string());
+    state.anthropic.base_url = format!("http://{}", addr);
+    let mut body = classify_body();
+    body["timeoutMs"] = json!(200);
+    let started = std::time::Instant::now();
+    let (status, body) = post(Arc::new(state), "/api/ai/classify", body).await;
+
+    assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(body["code"], "UPSTREAM_TIMEOUT");
+    assert!(started.elapsed() < std::time::Duration::from_secs(5));
+}
+
+#[test]
+fn port_is_validated() {
+    assert_eq!(parse_port(None), Ok(3000));
+    assert_eq!(parse_port(Some(" ")), Ok(3000));
+    assert_eq!(parse_port(Some("8080")), Ok(8080));
+    assert_eq!(parse_port(Some("abc")), Err("PORT must be 1-65535, got 'abc'".to_string()));
+    assert!(parse_port(Some("0")).is_err());
+    assert!(parse_port(Some("65536")).is_err());
+}
+
+#[test]
+fn base_path_is_normalized() {
+    assert_eq!(normalize_base_path(""), Ok(String::new()));
+    assert_eq!(normalize_base_path("/"), Ok(String::new()));
+    assert_eq!(normalize_base_path(" /triage/ "), Ok("/triage".to_string()));
+    assert_eq!(normalize_base_path("/app/v2//"), Ok("/app/v2".to_string()));
+    assert_eq!(normalize_base_path("app"), Err("'app' must start with '/'".to_string()));
+    assert!(normalize_base_path("/{id}").is_err());
+}
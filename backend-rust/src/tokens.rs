@@ -0,0 +1,62 @@
+//! Token estimation
+//!
+//! Cheap, tokenizer-free token estimates for keeping prompts within context limits.
+//! Each model family gets its own estimator so a real tokenizer can be dropped in for
+//! one family without touching the others.
+
+/// Estimates how many tokens a model family will count for a piece of text
+pub trait TokenEstimator: Send + Sync {
+    /// Short identifier reported alongside estimates
+    fn name(&self) -> &'static str;
+    fn estimate(&self, text: &str) -> usize;
+}
+
+/// Characters-per-token heuristic
+struct CharsPerToken {
+    name: &'static str,
+    chars_per_token: f64,
+}
+
+impl TokenEstimator for CharsPerToken {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn estimate(&self, text: &str) -> usize {
+        (text.chars().count() as f64 / self.chars_per_token).ceil() as usize
+    }
+}
+
+static CLAUDE: CharsPerToken = CharsPerToken {
+    name: "claude-chars/3.5",
+    chars_per_token: 3.5,
+};
+
+static OPENAI: CharsPerToken = CharsPerToken {
+    name: "openai-chars/4",
+    chars_per_token: 4.0,
+};
+
+static GEMINI: CharsPerToken = CharsPerToken {
+    name: "gemini-chars/4",
+    chars_per_token: 4.0,
+};
+
+static GENERIC: CharsPerToken = CharsPerToken {
+    name: "generic-chars/4",
+    chars_per_token: 4.0,
+};
+
+/// Pick the estimator for a model by its family prefix
+pub fn estimator_for(model: &str) -> &'static dyn TokenEstimator {
+    let model = model.to_ascii_lowercase();
+    if model.starts_with("claude") {
+        &CLAUDE
+    } else if model.starts_with("gpt") || model.starts_with("o1") || model.starts_with("o3") {
+        &OPENAI
+    } else if model.starts_with("gemini") {
+        &GEMINI
+    } else {
+        &GENERIC
+    }
+}
@@ -0,0 +1,274 @@
+//! JSONL transcript of AI requests
+//!
+//! With `TRANSCRIPT_PATH` set, every `/api/ai/*` request is appended to that file as one
+//! JSON line: when it ran, on which provider and model, the prompt, and the result or
+//! error. Secrets are redacted, and the prompt and result are each cut to
+//! `TRANSCRIPT_MAX_FIELD_BYTES` so one large test page can't produce a megabyte line.
+//! Only the transcript is truncated; the HTTP response is always complete.
+//!
+//! All writes go through one writer task fed by a bounded channel, so lines from
+//! concurrent requests can't interleave and handlers never wait on disk I/O. When the
+//! writer falls behind and the channel fills up, records are dropped and counted in
+//! `transcript_records_dropped_total` rather than slowing requests down. Records are
+//! dropped the same way while a [`DiskGuard`] reports the disk (nearly) full.
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{mpsc, oneshot};
+use tracing::warn;
+
+use crate::disk::DiskGuard;
+use crate::metrics;
+use crate::redact::redact;
+
+/// Records that can wait for the writer before new ones are dropped
+const QUEUE_CAPACITY: usize = 1024;
+
+/// Most records written per batch before flushing
+const MAX_BATCH: usize = 64;
+
+/// One transcript line
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Record {
+    pub request_id: String,
+    pub endpoint: String,
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    pub bug_id: Option<u64>,
+    pub status: u16,
+    pub duration_ms: u64,
+    pub prompt: Option<String>,
+    pub result: serde_json::Value,
+}
+
+enum Message {
+    Record(Record),
+    /// Answered once everything queued before it is on disk
+    Flush(oneshot::Sender<()>),
+}
+
+pub struct Transcript {
+    queue: mpsc::Sender<Message>,
+}
+
+impl Transcript {
+    /// Open `path` for appending and start its writer task; `max_field_bytes` of 0
+    /// disables truncation. Writes pause while `disk` is degraded.
+    pub async fn open(
+        path: &Path,
+        max_field_bytes: usize,
+        disk: Option<Arc<DiskGuard>>,
+    ) -> std::io::Result<Self> {
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        let (queue, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(write_records(file, receiver, max_field_bytes, disk));
+        Ok(Transcript { queue })
+    }
+
+    /// Queue `record` to be appended, redacted and with oversized fields truncated.
+    /// Never blocks: a record that doesn't fit in the queue is dropped and counted.
+    pub fn write(&self, record: Record) {
+        use mpsc::error::TrySendError;
+        match self.queue.try_send(Message::Record(record)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                metrics::inc("transcript_records_dropped_total");
+                warn!("Transcript queue full, record dropped");
+            }
+            Err(TrySendError::Closed(_)) => {
+                metrics::inc("transcript_records_dropped_total");
+                warn!("Transcript writer stopped, record dropped");
+            }
+        }
+    }
+
+    /// Wait until every record queued so far has been written
+    pub async fn flush(&self) {
+        let (done, written) = oneshot::channel();
+        if self.queue.send(Message::Flush(done)).await.is_ok() {
+            let _ = written.await;
+        }
+    }
+}
+
+/// Writer task: appends queued records in batches until every [`Transcript`] is dropped.
+/// Write failures are logged, never surfaced to the request; a full disk also trips
+/// `disk`.
+async fn write_records(
+    mut file: tokio::fs::File,
+    mut receiver: mpsc::Receiver<Message>,
+    max_field_bytes: usize,
+    disk: Option<Arc<DiskGuard>>,
+) {
+    let mut batch = Vec::with_capacity(MAX_BATCH);
+    while receiver.recv_many(&mut batch, MAX_BATCH).await > 0 {
+        let paused = disk.as_ref().is_some_and(|disk| disk.is_degraded());
+        let mut lines = Vec::new();
+        let mut flushed = Vec::new();
+        for message in batch.drain(..) {
+            match message {
+                Message::Record(_) if paused => {
+                    metrics::inc("transcript_records_dropped_total");
+                }
+                Message::Record(record) => {
+                    lines.extend(line(record, max_field_bytes).into_bytes())
+                }
+                Message::Flush(done) => flushed.push(done),
+            }
+        }
+        if !lines.is_empty() {
+            if let Err(e) = async {
+                file.write_all(&lines).await?;
+                file.flush().await
+            }
+            .await
+            {
+                warn!("Failed to write transcript: {}", e);
+                if e.kind() == std::io::ErrorKind::StorageFull {
+                    if let Some(ref disk) = disk {
+                        disk.trip();
+                    }
+                }
+            }
+        }
+        for done in flushed {
+            let _ = done.send(());
+        }
+    }
+}
+
+fn line(mut record: Record, max_field_bytes: usize) -> String {
+    record.prompt = record
+        .prompt
+        .map(|prompt| truncate_field(&redact(&prompt), max_field_bytes));
+    let result = redact(&record.result.to_string());
+    record.result = if max_field_bytes > 0 && result.len() > max_field_bytes {
+        truncate_field(&result, max_field_bytes).into()
+    } else {
+        serde_json::from_str(&result).unwrap_or(result.into())
+    };
+
+    let now = SystemTime::now();
+    let mut line = serde_json::json!({
+        "timestamp": now.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+        "time": httpdate::fmt_http_date(now),
+    });
+    if let (Some(line), serde_json::Value::Object(fields)) =
+        (line.as_object_mut(), serde_json::to_value(record).unwrap_or_default())
+    {
+        line.extend(fields);
+    }
+    let mut line = line.to_string();
+    line.push('\n');
+    line
+}
+
+/// `text` cut to at most `max_bytes` (on a char boundary) followed by
+/// `...[truncated N bytes]`, N being the bytes cut. Unchanged when it fits or
+/// `max_bytes` is 0.
+pub fn truncate_field(text: &str, max_bytes: usize) -> String {
+    if max_bytes == 0 || text.len() <= max_bytes {
+        return text.to_string();
+    }
+    let mut end = max_bytes;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}...[truncated {} bytes]", &text[..end], text.len() - end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_fields_are_kept() {
+        assert_eq!(truncate_field("hello", 10), "hello");
+        assert_eq!(truncate_field("hello", 5), "hello");
+        assert_eq!(truncate_field("hello", 0), "hello");
+    }
+
+    #[test]
+    fn long_fields_get_a_marker() {
+        assert_eq!(truncate_field("hello world", 5), "hello...[truncated 6 bytes]");
+    }
+
+    #[test]
+    fn truncation_respects_char_boundaries() {
+        // "é" is two bytes; cutting inside it backs off to before it
+        assert_eq!(truncate_field("aé", 2), "a...[truncated 2 bytes]");
+    }
+
+    fn record(request_id: &str) -> Record {
+        Record {
+            request_id: request_id.to_string(),
+            endpoint: "/api/ai/classify".to_string(),
+            provider: Some("claude".to_string()),
+            model: None,
+            bug_id: None,
+            status: 200,
+            duration_ms: 1,
+            prompt: Some("x".repeat(4096)),
+            result: serde_json::json!({ "summary": "y".repeat(4096) }),
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_writes_produce_whole_lines() {
+        let path =
+            std::env::temp_dir().join(format!("transcript-{}.jsonl", uuid::Uuid::new_v4()));
+        let transcript = Arc::new(Transcript::open(&path, 0, None).await.unwrap());
+        let writers: Vec<_> = (0..8)
+            .map(|task| {
+                let transcript = Arc::clone(&transcript);
+                tokio::spawn(async move {
+                    for i in 0..50 {
+                        transcript.write(record(&format!("{}-{}", task, i)));
+                        tokio::task::yield_now().await;
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.await.unwrap();
+        }
+        transcript.flush().await;
+
+        let lines = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(lines.lines().count(), 400);
+        for line in lines.lines() {
+            let record: serde_json::Value = serde_json::from_str(line).unwrap();
+            assert_eq!(record["prompt"].as_str().unwrap().len(), 4096);
+        }
+    }
+
+    #[tokio::test]
+    async fn records_are_dropped_while_the_disk_is_full() {
+        let path =
+            std::env::temp_dir().join(format!("transcript-{}.jsonl", uuid::Uuid::new_v4()));
+        let disk = Arc::new(DiskGuard::new(std::env::temp_dir(), 0));
+        let transcript = Transcript::open(&path, 0, Some(Arc::clone(&disk)))
+            .await
+            .unwrap();
+        transcript.write(record("kept"));
+        transcript.flush().await;
+        disk.trip();
+        transcript.write(record("dropped"));
+        transcript.flush().await;
+
+        let lines = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(lines.lines().count(), 1);
+        assert!(lines.contains("\"requestId\":\"kept\""));
+    }
+}
@@ -210,7 +210,28 @@ describe('ai module', () => {
       };
       const validation = validateSuggestResult(result);
       expect(validation.valid).toBe(false);
-      expect(validation.errors).toContain('suggested_response_id must be a string');
+      expect(validation.errors).toContain('suggested_response_id must be a string or null');
+    });
+
+    it('should accept a null suggested_response_id with no_match', () => {
+      const result = {
+        suggested_response_id: null,
+        draft_response: 'Thanks for the report.',
+        no_match: true,
+      };
+      const validation = validateSuggestResult(result);
+      expect(validation.valid).toBe(true);
+    });
+
+    it('should validate no_match type if provided', () => {
+      const result = {
+        suggested_response_id: 'needinfo',
+        draft_response: 'Thank you for the report.',
+        no_match: 'yes',
+      };
+      const validation = validateSuggestResult(result);
+      expect(validation.valid).toBe(false);
+      expect(validation.errors).toContain('no_match must be a boolean if provided');
     });
 
     it('should return invalid when draft_response is missing', () => {
@@ -387,6 +408,39 @@ describe('ai module', () => {
       expect(result.suggested_response_id).toBe('');
       expect(result.draft_response).toBe('');
       expect(result.reasoning).toBe('');
+      expect(result.noMatch).toBe(false);
+    });
+
+    it('should pass a missing canned id through as null', async () => {
+      const suggestion = (result) => ({
+        ok: true,
+        json: async () => ({
+          candidates: [{ content: { parts: [{ text: JSON.stringify(result) }] } }],
+        }),
+      });
+      const config = { provider: 'gemini', transport: 'browser', apiKey: 'test-key' };
+
+      global.fetch.mockResolvedValueOnce(
+        suggestion({ suggested_response_id: null, draft_response: 'Thanks', no_match: true })
+      );
+      let result = await suggestCannedResponse({ id: 123 }, [{ id: 'test' }], config);
+      expect(result.suggested_response_id).toBeNull();
+      expect(result.noMatch).toBe(true);
+      expect(result.draft_response).toBe('Thanks');
+
+      // no_match wins over an id sent alongside it
+      global.fetch.mockResolvedValueOnce(
+        suggestion({ suggested_response_id: 'test', draft_response: 'Thanks', no_match: true })
+      );
+      result = await suggestCannedResponse({ id: 123 }, [{ id: 'test' }], config);
+      expect(result.suggested_response_id).toBeNull();
+
+      global.fetch.mockResolvedValueOnce(
+        suggestion({ suggested_response_id: 'test', draft_response: 'Thanks' })
+      );
+      result = await suggestCannedResponse({ id: 123 }, [{ id: 'test' }], config);
+      expect(result.suggested_response_id).toBe('test');
+      expect(result.noMatch).toBe(false);
     });
   });
 
@@ -69,6 +69,7 @@ describe('prompts module', () => {
       expect(SCHEMAS.suggest.properties).toHaveProperty('suggested_response_id');
       expect(SCHEMAS.suggest.properties).toHaveProperty('draft_response');
       expect(SCHEMAS.suggest.properties).toHaveProperty('reasoning');
+      expect(SCHEMAS.suggest.properties).toHaveProperty('no_match');
     });
 
     it('should have generate schema', () => {
@@ -57,6 +57,7 @@ const DEFAULT_BACKEND_URL = 'http://localhost:3000';
  * @property {boolean} fuzzing_testcase - Fuzzing-derived testcase detected
  * @property {string} summary - 1-3 sentence brief summary
  * @property {Object} [notes] - Optional additional notes
+ * @property {Object<string, number>} [confidence] - Optional 0-1 confidence per field
  */
 
 /**
@@ -160,8 +161,9 @@ export function validateSuggestResult(result) {
     return { valid: false, errors: ['Result must be an object'] };
   }
 
-  if (typeof result.suggested_response_id !== 'string') {
-    errors.push('suggested_response_id must be a string');
+  // null when no canned response fits
+  if (result.suggested_response_id !== null && typeof result.suggested_response_id !== 'string') {
+    errors.push('suggested_response_id must be a string or null');
   }
 
   if (typeof result.draft_response !== 'string') {
@@ -173,6 +175,11 @@ export function validateSuggestResult(result) {
     errors.push('reasoning must be a string if provided');
   }
 
+  // no_match is optional
+  if (result.no_match !== undefined && typeof result.no_match !== 'boolean') {
+    errors.push('no_match must be a boolean if provided');
+  }
+
   return { valid: errors.length === 0, errors };
 }
 
@@ -407,6 +414,7 @@ async function callBackendProxy(task, payload, config) {
     body: JSON.stringify({
       provider: config.provider,
       model: config.model || DEFAULT_MODELS[config.provider],
+      promptVersion: prompts.PROMPT_VERSION,
       ...payload,
     }),
   });
@@ -455,7 +463,7 @@ export async function classifyBug(bug, providerConfig, options = {}) {
   try {
     if (transport === 'backend') {
       // Use backend proxy with centralized prompt and schema
-      const result = await callBackendProxy('classify', { bug, prompt, schema }, providerConfig);
+      const result = await callBackendProxy('classify', { bug, cannedResponses, prompt, schema }, providerConfig);
       aiLogger.completeEntry(logId, result);
       return result;
     } else {
@@ -493,6 +501,7 @@ export async function classifyBug(bug, providerConfig, options = {}) {
         suggested_canned_id: parsed.suggested_canned_id || '',
         draft_response: parsed.draft_response || '',
         notes: parsed.notes || {},
+        ...(parsed.confidence && typeof parsed.confidence === 'object' ? { confidence: parsed.confidence } : {}),
       };
       aiLogger.completeEntry(logId, { raw: responseText, parsed: result, validationErrors: validation.errors });
       return result;
@@ -511,7 +520,8 @@ export async function classifyBug(bug, providerConfig, options = {}) {
  * @param {Object} bug - Bug object
  * @param {Object[]} cannedResponses - All available canned responses
  * @param {Object} providerConfig - Provider configuration
- * @returns {Promise<Object>} { suggested_response_id, draft_response, reasoning }
+ * @returns {Promise<Object>} { suggested_response_id, draft_response, reasoning, noMatch };
+ *   suggested_response_id is null when no canned response fits
  */
 export async function suggestCannedResponse(bug, cannedResponses, providerConfig) {
   // Return empty suggestion if not configured
@@ -521,6 +531,7 @@ export async function suggestCannedResponse(bug, cannedResponses, providerConfig
       suggested_response_id: '',
       draft_response: '',
       reasoning: '',
+      noMatch: false,
     };
   }
 
@@ -566,10 +577,15 @@ export async function suggestCannedResponse(bug, cannedResponses, providerConfig
       console.warn('[ai] Suggest result validation errors:', validation.errors);
     }
 
+    // Like the backend: an explicit no_match wins over any id sent alongside it
+    const noMatch = parsed.no_match === true;
     const result = {
-      suggested_response_id: String(parsed.suggested_response_id || ''),
+      suggested_response_id: noMatch || !parsed.suggested_response_id
+        ? null
+        : String(parsed.suggested_response_id),
       draft_response: String(parsed.draft_response || ''),
       reasoning: String(parsed.reasoning || ''),
+      noMatch,
     };
 
     aiLogger.completeEntry(logId, { raw: responseText, parsed: result });
@@ -9,6 +9,12 @@
  * @module prompts
  */
 
+/**
+ * Version of these prompts and schemas, sent to the backend as `promptVersion`.
+ * Bump the major version when a schema changes in a way the backend parsers must follow.
+ */
+export const PROMPT_VERSION = '1.0.0';
+
 /**
  * JSON schemas for structured output (used by Claude CLI).
  * These schemas ensure consistent output format across all providers.
@@ -71,6 +77,11 @@ export const SCHEMAS = {
         type: 'string',
         description: 'A customized response draft based on the selected canned template, tailored for this specific bug',
       },
+      confidence: {
+        type: 'object',
+        additionalProperties: { type: 'number', minimum: 0, maximum: 1 },
+        description: 'Optional confidence from 0 to 1 for each field above, keyed by field name',
+      },
     },
     required: ['ai_detected_str', 'ai_detected_test_attached', 'crashstack_present', 'fuzzing_testcase', 'summary', 'suggested_severity', 'suggested_priority', 'suggested_actions', 'triage_reasoning', 'suggested_canned_id', 'draft_response'],
   },
@@ -90,6 +101,10 @@ export const SCHEMAS = {
         type: 'string',
         description: 'Brief explanation of why this response was chosen',
       },
+      no_match: {
+        type: 'boolean',
+        description: 'True when no canned response fits; draft_response is then a freeform draft',
+      },
     },
     required: ['suggested_response_id', 'draft_response'],
   },
@@ -385,7 +400,8 @@ Return ONLY a JSON object with this exact structure:
   ],
   "triage_reasoning": "string (2-4 sentences)",
   "suggested_canned_id": "string (ID of recommended canned response, or empty string)",
-  "draft_response": "string (customized response draft, or empty string)"
+  "draft_response": "string (customized response draft, or empty string)",
+  "confidence": {"field_name": number (optional, 0-1 confidence per field)}
 }
 \`\`\`
 
@@ -443,7 +459,7 @@ ${responsesText}
 3. Draft a customized version of that response for this specific bug
 4. Explain briefly why you chose this response
 
-If no canned response is a good fit, choose the closest match and explain the limitations in your reasoning.
+If no canned response is a good fit, set "no_match" to true, leave "suggested_response_id" empty, and write a freeform draft in "draft_response" instead.
 
 ## Output Format
 
@@ -452,7 +468,8 @@ Return ONLY a JSON object with this exact structure:
 {
   "suggested_response_id": "string (the ID of the chosen canned response)",
   "draft_response": "string (the response customized for this bug)",
-  "reasoning": "string (brief explanation of why this response was chosen)"
+  "reasoning": "string (brief explanation of why this response was chosen)",
+  "no_match": "boolean (true when no canned response fits)"
 }
 \`\`\``;
 }